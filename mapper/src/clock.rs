@@ -0,0 +1,73 @@
+use std::cell::Cell;
+use std::time::{Duration, Instant};
+
+use crate::stages::Timestamp;
+
+// recorded and generated input is replayed in frames this long, about as often as controllers report
+pub const FRAME_INTERVAL: Duration = Duration::from_millis(4);
+
+// timestamps are relative to the clock's start
+pub trait Clock {
+  fn now(&self) -> Timestamp;
+
+  // for whoever drives a mapper with recorded or generated input
+  fn as_virtual(&self) -> Option<&VirtualClock> {
+    None
+  }
+}
+
+pub struct RealClock {
+  start: Instant
+}
+
+impl RealClock {
+  pub fn new() -> Self {
+    Self { start: Instant::now() }
+  }
+}
+
+impl Clock for RealClock {
+  fn now(&self) -> Timestamp {
+    Timestamp(self.start.elapsed())
+  }
+}
+
+// only moves when told to, for fuzzing, replays and tests
+#[derive(Default)]
+pub struct VirtualClock {
+  now: Cell<Duration>
+}
+
+impl VirtualClock {
+  pub fn advance(&self, duration: Duration) {
+    self.now.set(self.now.get() + duration);
+  }
+}
+
+impl Clock for VirtualClock {
+  fn now(&self) -> Timestamp {
+    Timestamp(self.now.get())
+  }
+
+  fn as_virtual(&self) -> Option<&VirtualClock> {
+    Some(self)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  #[test]
+  fn virtual_clock() {
+    let clock = VirtualClock::default();
+    let start = clock.now();
+    assert_eq!(start, Timestamp::from_millis(0));
+
+    clock.advance(Duration::from_millis(4));
+    clock.advance(Duration::from_millis(4));
+    assert_eq!(clock.now() - start, Duration::from_millis(8));
+    assert_eq!(start - clock.now(), Duration::ZERO);
+  }
+}
@@ -35,6 +35,7 @@ pub enum Expression {
   Apply(String, Vec<(Option<String>, Expression)>, Span),
   Identifier(String, Span),
   Layer(Vec<String>, Box<Expression>, Span),
+  System(Box<Expression>, Span),
   Number(f32, Span),
   Boolean(bool, Span),
   String(String, Span),
@@ -51,6 +52,7 @@ impl Expression {
       Apply(_, _, span)     => *span,
       Identifier(_, span)   => *span,
       Layer(_, _, span)     => *span,
+      System(_, span)       => *span,
       Number(_, span)       => *span,
       Boolean(_, span)      => *span,
       String(_, span)       => *span,
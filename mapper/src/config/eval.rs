@@ -1,10 +1,13 @@
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
+use std::rc::Rc;
 
 use overlay_ipc::Knob;
+use strum::IntoEnumIterator;
 
 use super::ast::*;
 use super::util;
-use crate::config::{Axis, Button};
+use crate::config::{Axis, Button, HapticFeedbackTarget};
 use crate::mapper::LayerMask;
 use crate::output::{KeyboardKey, MouseAxis, MouseButton};
 use crate::stages::*;
@@ -15,7 +18,8 @@ pub enum Constant {
   InputButton(Button),
   MouseAxis(MouseAxis),
   MouseButton(MouseButton),
-  KeyboardKey(KeyboardKey)
+  KeyboardKey(KeyboardKey),
+  HapticTarget(HapticFeedbackTarget)
 }
 
 #[derive(Clone)]
@@ -117,7 +121,7 @@ impl std::fmt::Debug for Value {
   }
 }
 
-fn name_of_type(value: &Value) -> &'static str {
+pub fn name_of_type(value: &Value) -> &'static str {
   match value {
     Value::List(_)                => "List",
     Value::Struct(_)              => "Struct",
@@ -159,7 +163,13 @@ pub struct Context<'a> {
   pub layers:      Vec<String>,
   pub vars:        Vec<HashMap<String, Variable<'a>>>,
   pub knobs:       Vec<Knob>,
-  pub knob_values: HashMap<String, Value>
+  pub knob_values: HashMap<String, Value>,
+  pub knobs_ui:    bool,               // visible to the system section
+  pub system:      Option<Vec<Value>>, // complete pipelines from the system section
+  pub live_knobs:  HashMap<String, Rc<Cell<f32>>>,
+  pub modes:       Rc<RefCell<Vec<LayerMask>>>, // passed to mode switching functions, shared with them
+  knob_stages:     HashMap<StageId, String>,
+  in_system:       bool
 }
 
 impl<'a> Context<'a> {
@@ -172,7 +182,13 @@ impl<'a> Context<'a> {
         map
       }],
       knobs:       vec![],
-      knob_values: knob_values.unwrap_or_default()
+      knob_values: knob_values.unwrap_or_default(),
+      knobs_ui:    true,
+      system:      None,
+      live_knobs:  HashMap::new(),
+      modes:       Rc::new(RefCell::new(vec![])),
+      knob_stages: HashMap::new(),
+      in_system:   false
     }
   }
 
@@ -201,6 +217,48 @@ impl<'a> Context<'a> {
     scope.insert(name.to_string(), Variable::NativeFun(std::rc::Rc::new(fun)));
   }
 
+  // number knobs are live pipelines, a knob that ends up as a plain number can only be changed by reloading the config,
+  // the same goes for arithmetic on knobs and constants (e.g. `smooth(k * 2)`), which is frozen at its current value
+  fn freeze_knobs<'v>(&mut self, values: impl Iterator<Item = &'v mut Value>) -> bool {
+    let mut frozen = false;
+    for value in values {
+      let pipeline = if let Value::Pipeline1D(p) = value { Rc::clone(p) } else { continue };
+
+      let mut stages = HashMap::new();
+      pipeline.borrow().inspect(&mut stages);
+
+      let knobs_only = stages.values().all(|stage| {
+        self.knob_stages.contains_key(&stage.id) || (matches!(stage.name, "scale" | "offset") && !stage.inputs.is_empty())
+      });
+
+      if knobs_only {
+        for id in stages.keys() {
+          if let Some(name) = self.knob_stages.get(id) {
+            self.live_knobs.remove(name);
+          }
+        }
+        *value = Value::Number(current_value(&pipeline));
+        frozen = true;
+      }
+    }
+    frozen
+  }
+
+  // "foo | bar"
+  fn mode_name(&self, mask: LayerMask) -> String {
+    mask.layer_names(&self.layers).join(" | ")
+  }
+
+  // a value of the wrong type is an error rather than a silent reset to the default
+  fn stored_knob_value<T>(&self, name: &str, expected: &str, location: Option<Span>, extract: impl Fn(&Value) -> Option<T>) -> Result<Option<T>, EvalError> {
+    match self.knob_values.get(name) {
+      Some(value) => extract(value).map(Some).ok_or_else(|| {
+        EvalError(format!("Knob {} expects {} in the knob file, got {} {:?}", name, expected, name_of_type(value), value), location)
+      }),
+      None => Ok(None)
+    }
+  }
+
   fn lookup(&self, name: &str) -> Option<Variable<'a>> {
     for scope in (self.vars).iter().rev() {
       if scope.contains_key(name) {
@@ -237,7 +295,14 @@ impl<'a> Context<'a> {
       match var {
         Variable::Value(v) => Err(EvalError(format!("Can't invoke value {:?} as function", v), location)),
         Variable::NativeFun(fun) => {
-          fun(posit_args.as_slice(), named_args).map_err(|str| {
+          let mut result = fun(posit_args.as_slice(), named_args.clone());
+          if result.is_err() {
+            let (mut posit_args, mut named_args) = (posit_args.clone(), named_args);
+            if self.freeze_knobs(posit_args.iter_mut().chain(named_args.values_mut())) {
+              result = fun(posit_args.as_slice(), named_args);
+            }
+          }
+          result.map_err(|str| {
             if let Some(str) = str {
               EvalError(format!("{}: {}", name, str), location)
             } else {
@@ -279,54 +344,136 @@ impl<'a> Context<'a> {
         Variable::KnobFun => {
           let args = posit_args.as_slice();
           let opts = named_args;
+          if let Some(Value::String(name)) = args.first() {
+            if self.knobs.iter().any(|k| k.name() == *name) {
+              return Err(EvalError(format!("Knob {} is already registered", name), location));
+            }
+          }
           match args {
             [Value::String(name), Value::Boolean(default)] => {
-              if self.knobs.iter().any(|k| k.name() == *name) {
-                return Err(EvalError(format!("Knob {} is already registered", name), location));
-              }
-              let value = if let Some(Value::Boolean(value)) = self.knob_values.get(name) { *value } else { *default };
+              let value = self.stored_knob_value(name, "a Boolean", location, |value| match value {
+                Value::Boolean(value) => Some(*value),
+                _ => None
+              })?.unwrap_or(*default);
               self.knobs.push(Knob::Flag { name: name.clone(), value });
               Ok(Value::Boolean(*default))
             },
             [Value::String(name), Value::String(default), Value::List(options)] => {
-              if self.knobs.iter().any(|k| k.name() == *name) {
-                return Err(EvalError(format!("Knob {} is already registered", name), location));
-              }
-              let value = if let Some(Value::String(value)) = self.knob_values.get(name) {
-                if let Some(options) = util::strings(options) {
-                  if options.contains(value) {
-                    value
-                  } else {
-                    default
-                  }
-                } else {
-                  return Err(EvalError("Options should only contain string values".to_string(), location));
+              let options = util::strings(options)
+                .ok_or_else(|| EvalError("Options should only contain string values".to_string(), location))?;
+              let value = self.stored_knob_value(name, "a String", location, string_value)?
+                .filter(|value| options.contains(value))
+                .unwrap_or_else(|| default.clone());
+              let index = options.iter().position(|opt| *opt == value).unwrap_or(0);
+              self.knobs.push(Knob::Enum { name: name.clone(), index, options });
+              Ok(Value::String(value))
+            },
+            [Value::String(name), Value::Number(default)] if opts.contains_key("step") => {
+              if let (Some(Value::Number(min_value)), Some(Value::Number(max_value)), Some(Value::Number(step))) =
+                (opts.get("min_value"), opts.get("max_value"), opts.get("step"))
+              {
+                let integers = [*default, *min_value, *max_value, *step];
+                if integers.iter().any(|n| n.fract() != 0.0) || *step < 1.0 || min_value >= max_value {
+                  return Err(EvalError("Integer knob expects integer values with min_value < max_value and step >= 1".to_string(), location));
                 }
+                let value = self.stored_knob_value(name, "an integer Number", location, |value| match value {
+                  Value::Number(value) if value.fract() == 0.0 => Some(*value),
+                  _ => None
+                })?.map_or(*default, |value| value.clamp(*min_value, *max_value));
+                self.knobs.push(Knob::Integer {
+                  name:      name.clone(),
+                  value:     value as i32,
+                  min_value: *min_value as i32,
+                  max_value: *max_value as i32,
+                  step:      *step as i32
+                });
+                Ok(Value::Number(value))
               } else {
-                default
-              };
-              if let Some(options) = util::strings(options) {
-                let index = options.iter().position(|opt| opt == value).unwrap_or(0);
-                self.knobs.push(Knob::Enum { name: name.clone(), index, options });
-              } else {
-                return Err(EvalError("Options should only contain string values".to_string(), location));
+                Err(EvalError("min_value/max_value/step should be specified".to_string(), location))
               }
-              Ok(Value::String(value.clone()))
             },
             [Value::String(name), Value::Number(default)] => {
-              if self.knobs.iter().any(|k| k.name() == *name) {
-                return Err(EvalError(format!("Knob {} is already registered", name), location));
-              }
               if let (Some(Value::Number(ref min_value)), Some(Value::Number(max_value))) =
                 (opts.get("min_value"), opts.get("max_value"))
               {
-                let value = if let Some(Value::Number(value)) = self.knob_values.get(name) { *value } else { *default };
+                if min_value >= max_value {
+                  return Err(EvalError("Number knob expects min_value < max_value".to_string(), location));
+                }
+                let value = self.stored_knob_value(name, "a Number", location, |value| match value {
+                  Value::Number(value) => Some(*value),
+                  _ => None
+                })?.map_or(*default, |value| value.clamp(*min_value, *max_value));
                 self.knobs.push(Knob::Number { name: name.clone(), value, min_value: *min_value, max_value: *max_value });
-                Ok(Value::Number(value))
+
+                let cell     = Rc::new(Cell::new(value));
+                let pipeline = knob_input(name, cell.clone());
+                self.live_knobs.insert(name.clone(), cell);
+                self.knob_stages.insert(pipeline.borrow().stage_id(), name.clone());
+                Ok(Value::Pipeline1D(pipeline))
               } else {
                 Err(EvalError("min_value/max_value should be specified".to_string(), location))
               }
             },
+            [Value::String(name), Value::Constant(Constant::KeyboardKey(default)), options @ ..] if options.len() <= 1 => {
+              let keys = match options {
+                [Value::List(options)] => {
+                  let mut keys = vec![];
+                  for option in options {
+                    if let Value::Constant(Constant::KeyboardKey(key)) = option {
+                      keys.push(*key);
+                    } else {
+                      return Err(EvalError("Options should only contain keyboard keys".to_string(), location));
+                    }
+                  }
+                  keys
+                },
+                [] => KeyboardKey::iter().collect(),
+                _ => return Err(EvalError("Unknown knob format".to_string(), location))
+              };
+              if keys.is_empty() {
+                return Err(EvalError("Options should not be empty".to_string(), location));
+              }
+              let default = keys.iter().position(|key| key == default)
+                .ok_or_else(|| EvalError(format!("Default key {} should be one of the options", default), location))?;
+              let options = keys.iter().map(|key| key.to_string()).collect::<Vec<_>>();
+              let index = self.stored_knob_value(name, "a String", location, string_value)?
+                .and_then(|value| options.iter().position(|opt| *opt == value))
+                .unwrap_or(default);
+              self.knobs.push(Knob::Key { name: name.clone(), index, options });
+              Ok(Value::Constant(Constant::KeyboardKey(keys[index])))
+            },
+            [Value::String(name), Value::LayerMask(default), Value::List(options)] => {
+              let mut modes = vec![];
+              for option in options {
+                if let Value::LayerMask(mask) = option {
+                  modes.push(*mask);
+                } else {
+                  return Err(EvalError("Options should only contain layers or their combinations".to_string(), location));
+                }
+              }
+              if modes.is_empty() {
+                return Err(EvalError("Options should not be empty".to_string(), location));
+              }
+              let options = modes.iter().map(|mask| self.mode_name(*mask)).collect::<Vec<_>>();
+              let index = self.stored_knob_value(name, "a String", location, string_value)?
+                .and_then(|value| options.iter().position(|opt| *opt == value))
+                .unwrap_or_else(|| modes.iter().position(|mask| mask == default).unwrap_or(0));
+              self.knobs.push(Knob::Layer { name: name.clone(), index, options });
+              Ok(Value::LayerMask(modes[index]))
+            },
+            [Value::String(name), Value::List(default)] => {
+              if let Some(default) = util::curve_points(default) {
+                let points = self.stored_knob_value(name, "a List of {x, y} pairs", location, |value| match value {
+                  Value::List(value) => util::curve_points(value),
+                  _ => None
+                })?.unwrap_or(default);
+                let value = util::curve_value(&points);
+                self.knobs.push(Knob::Curve { name: name.clone(), points });
+                Ok(value)
+              } else {
+                Err(EvalError("Curve points should be {x, y} pairs in [0, 1] sorted by x from 0 to 1".to_string(), location))
+              }
+            },
             _ => Err(EvalError("Unknown knob format".to_string(), location))
           }
         }
@@ -345,11 +492,148 @@ impl<'a> Context<'a> {
   }
 }
 
+// knobs don't depend on the controller state
+fn string_value(value: &Value) -> Option<String> {
+  match value {
+    Value::String(value) => Some(value.clone()),
+    _ => None
+  }
+}
+
+fn current_value(pipeline: &PipelineRef<f32>) -> f32 {
+  let state = crate::controllers::ControllerState::empty();
+  let ctx = crate::stages::Context {
+    state: &state, time: Timestamp(std::time::Duration::ZERO), layers: LayerMask::EMPTY, probe_values: &HashMap::new(), overlay: &OverlayStatus::default()
+  };
+
+  let mut pipeline = pipeline.borrow_mut();
+  pipeline.reset();
+  let value = pipeline.apply(&ctx, &mut vec![]);
+  pipeline.reset();
+  value
+}
+
+// arithmetic on 1D pipelines (e.g. number knobs) builds more pipelines
+fn apply_op(op: &Operation, lhs: &Value, rhs: &Value, span: Span) -> Result<Value, EvalError> {
+
+  use Operation::*;
+
+  match (lhs, rhs) {
+    (Value::Number(x), Value::Number(y)) => match op {
+      Add => Ok(Value::Number(x + y)),
+      Sub => Ok(Value::Number(x - y)),
+      Mul => Ok(Value::Number(x * y)),
+      Div => Ok(Value::Number(x / y)),
+      Eq  => Ok(Value::Boolean(x == y)),
+      _ => Err(EvalError(format!("Can't apply {:?} to numeric operands", op), Some(span)))
+    },
+    (Value::Pipeline1D(p), Value::Number(n)) => match op {
+      Add => Ok(Value::Pipeline1D(offset(Rc::clone(p),  *n))),
+      Sub => Ok(Value::Pipeline1D(offset(Rc::clone(p), -*n))),
+      Mul => Ok(Value::Pipeline1D(scale(Rc::clone(p),   *n))),
+      Div => Ok(Value::Pipeline1D(scale(Rc::clone(p), 1.0 / *n))),
+      _ => Err(EvalError(format!("Can't apply {:?} to pipeline and numeric operands", op), Some(span)))
+    },
+    (Value::Number(n), Value::Pipeline1D(p)) => match op {
+      Add => Ok(Value::Pipeline1D(offset(Rc::clone(p), *n))),
+      Sub => Ok(Value::Pipeline1D(offset(scale(Rc::clone(p), -1.0), *n))),
+      Mul => Ok(Value::Pipeline1D(scale(Rc::clone(p), *n))),
+      _ => Err(EvalError(format!("Can't apply {:?} to numeric and pipeline operands", op), Some(span)))
+    },
+    (Value::Pipeline1D(p1), Value::Pipeline1D(p2)) => match op {
+      Add => Ok(Value::Pipeline1D(offset_by_axis(Rc::clone(p1), Rc::clone(p2)))),
+      Sub => Ok(Value::Pipeline1D(offset_by_axis(Rc::clone(p1), scale(Rc::clone(p2), -1.0)))),
+      Mul => Ok(Value::Pipeline1D(scale_by_axis(Rc::clone(p1), Rc::clone(p2)))),
+      _ => Err(EvalError(format!("Can't apply {:?} to pipeline operands", op), Some(span)))
+    },
+    (Value::LayerMask(x), Value::LayerMask(y)) => match op {
+      BitOr => Ok(Value::LayerMask(*x | *y)),
+      _ => Err(EvalError(format!("Can't apply {:?} to layer operands", op), Some(span)))
+    },
+    (Value::String(lhs), Value::String(rhs)) => match op {
+      Eq => Ok(Value::Boolean(lhs == rhs)),
+      _  => Err(EvalError(format!("Can't apply {:?} to string operands", op), Some(span)))
+    },
+    (a, b) => Err(EvalError(format!("Can't apply {:?} to operands {:?} and {:?}", op, a, b), Some(span)))
+  }
+}
+
+// statements and expressions of a scope, evaluated in the current one
+fn eval_scope(statements: Vec<Statement>, expressions: Vec<Expression>, ctx: &mut Context, allow_layer_exprs: bool) -> Result<Vec<Value>, EvalError> {
+
+  use Expression::*;
+  use Statement::*;
+
+  for expr in &expressions {
+    // system layers are predefined
+    if ctx.in_system {
+      break;
+    }
+
+    if let Layer(names, _, span) = expr {
+      for name in names {
+        let index = if !ctx.layers.contains(name) {
+          ctx.layers.push(name.clone());
+          ctx.layers.len() - 1
+        } else {
+          ctx.layers.iter().position(|n| n == name).unwrap()
+        };
+
+        let mask = LayerMask::user_layer(index).map_err(|_| {
+          EvalError(format!("Too many layers, at most {} are supported", LayerMask::MAX_USER_LAYERS), Some(*span))
+        })?;
+
+        // layers are visible to statements unless shadowed by them
+        let shadowed = statements.iter().any(|st| match st {
+          Let(ids, _, _)   => ids.contains(name),
+          Def(id, _, _, _) => id == name
+        });
+
+        if !shadowed && !ctx.exists(name) {
+          ctx.insert_var(name, Value::LayerMask(mask)); // ?
+        }
+      }
+    }
+  }
+
+  for st in statements {
+    match st {
+      Let(ids, body, span) => {
+        let result = eval(*body, ctx, false)?;
+
+        if ids.len() > 1 {
+          if let Value::List(v) = result {
+            if ids.len() == v.len() {
+              for i in 0..v.len() {
+                ctx.insert_var(&ids[i], v[i].clone());
+              }
+            } else {
+              return Err(EvalError(format!("Expected {} vars", v.len()), Some(span))) //TODO: more specific span
+            }
+          } else {
+            return Err(EvalError(format!("Expected list, got {:?}", result), Some(span))) //TODO: more specific span
+          }
+        } else {
+          ctx.insert_var(ids.first().unwrap(), result);
+        }
+      },
+      Def(name, args, body, _) => {
+        ctx.insert_fun(&name, args, *body);
+      }
+    };
+  }
+
+  let mut result = vec![];
+  for expr in expressions {
+    result.push(eval(expr, ctx, allow_layer_exprs)?);
+  }
+  Ok(result)
+}
+
 fn eval(expr: Expression, ctx: &mut Context, allow_layer_exprs: bool) -> Result<Value, EvalError> {
 
   use Expression::*;
   use Operation::*;
-  use Statement::*;
 
   match expr {
     Identifier(id, span) => ctx.get_value(&id, Some(span)),
@@ -434,77 +718,16 @@ fn eval(expr: Expression, ctx: &mut Context, allow_layer_exprs: bool) -> Result<
 
       ctx.apply_fun(fun, posit_args, named_args, Some(span))
     },
-    OpExpr(op, lhs, rhs, span) => match (eval(*lhs, ctx, false)?, eval(*rhs, ctx, false)?) {
-      (Value::Number(x), Value::Number(y)) => match op {
-        Add => Ok(Value::Number(x + y)),
-        Sub => Ok(Value::Number(x - y)),
-        Mul => Ok(Value::Number(x * y)),
-        Div => Ok(Value::Number(x / y)),
-        Eq  => Ok(Value::Boolean(x == y)),
-        _ => Err(EvalError(format!("Can't apply {:?} to numeric operands", op), Some(span)))
-      },
-      (Value::LayerMask(x), Value::LayerMask(y)) => match op {
-        BitOr => Ok(Value::LayerMask(x | y)),
-        _ => Err(EvalError(format!("Can't apply {:?} to layer operands", op), Some(span)))
-      },
-      (Value::String(lhs), Value::String(rhs)) => match op {
-        Eq => Ok(Value::Boolean(lhs == rhs)),
-        _  => Err(EvalError(format!("Can't apply {:?} to string operands", op), Some(span)))
-      },
-      (a, b) => Err(EvalError(format!("Can't apply {:?} to operands {:?} and {:?}", op, a, b), Some(span)))
+    OpExpr(op, lhs, rhs, span) => {
+      let mut operands = [eval(*lhs, ctx, false)?, eval(*rhs, ctx, false)?];
+      match apply_op(&op, &operands[0], &operands[1], span) {
+        Err(_) if ctx.freeze_knobs(operands.iter_mut()) => apply_op(&op, &operands[0], &operands[1], span),
+        result => result
+      }
     },
     Scope(statements, expressions, _) => {
       ctx.new_scope();
-
-      for st in statements {
-        match st {
-          Let(ids, body, span) => {
-            let result = eval(*body, ctx, false)?;
-
-            if ids.len() > 1 {
-              if let Value::List(v) = result {
-                if ids.len() == v.len() {
-                  for i in 0..v.len() {
-                    ctx.insert_var(&ids[i], v[i].clone());
-                  }
-                } else {
-                  return Err(EvalError(format!("Expected {} vars", v.len()), Some(span))) //TODO: more specific span
-                }
-              } else {
-                return Err(EvalError(format!("Expected list, got {:?}", result), Some(span))) //TODO: more specific span
-              }
-            } else {
-              ctx.insert_var(ids.first().unwrap(), result);
-            }
-          },
-          Def(name, args, body, _) => {
-            ctx.insert_fun(&name, args, *body);
-          }
-        };
-      }
-
-      for expr in &expressions {
-        if let Layer(names, _, _) = expr {
-          for name in names {
-            let index = if !ctx.layers.contains(name) {
-              ctx.layers.push(name.clone());
-              ctx.layers.len() - 1
-            } else {
-              ctx.layers.iter().position(|n| n == name).unwrap()
-            };
-
-            if !ctx.exists(name) {
-              ctx.insert_var(name, Value::LayerMask(LayerMask::user_layer(index).unwrap())); // ?
-            }
-          }
-        }
-      }
-
-      let mut result = vec![];
-      for expr in expressions {
-        result.push(eval(expr, ctx, allow_layer_exprs)?);
-      }
-
+      let mut result = eval_scope(statements, expressions, ctx, allow_layer_exprs)?;
       ctx.drop_scope();
 
       if result.len() == 1 {
@@ -522,8 +745,15 @@ fn eval(expr: Expression, ctx: &mut Context, allow_layer_exprs: bool) -> Result<
       let mut mask = LayerMask::EMPTY;
 
       for name in names {
-        let index = ctx.layers.iter().position(|n| n == &name).unwrap();
-        mask = mask | LayerMask::user_layer(index).unwrap(); // ?
+        if ctx.in_system {
+          match ctx.lookup(&name) {
+            Some(Variable::Value(Value::LayerMask(layer))) => mask = mask | layer,
+            _ => return Err(EvalError(format!("Unknown layer {}", name), Some(span)))
+          }
+        } else {
+          let index = ctx.layers.iter().position(|n| n == &name).unwrap();
+          mask = mask | LayerMask::user_layer(index).unwrap(); // ?
+        }
       }
 
       let mut res = util::flatten(eval((*expr).clone(), ctx, false)?);
@@ -537,6 +767,41 @@ fn eval(expr: Expression, ctx: &mut Context, allow_layer_exprs: bool) -> Result<
 
       Ok(Value::List(res))
     },
+    System(expr, span) => {
+
+      if !allow_layer_exprs || ctx.in_system {
+        return Err(EvalError("System bindings must be declared at the top level of config file".to_string(), Some(span)));
+      }
+
+      if ctx.system.is_some() {
+        return Err(EvalError("System bindings are already declared".to_string(), Some(span)));
+      }
+
+      ctx.new_scope();
+      ctx.in_system = true;
+      ctx.insert_var("knobs_ui",   Value::Boolean(ctx.knobs_ui));
+      ctx.insert_var("knobs_menu", Value::LayerMask(LayerMask::internal_layer(0).unwrap()));
+
+      let result = eval(*expr, ctx, true);
+
+      ctx.in_system = false;
+      ctx.drop_scope();
+
+      // unlike user pipelines, these are active in every mode unless told otherwise
+      let mut res = util::flatten(result?);
+      for p in &mut res {
+        if let Value::CompletePipeline(layers, _) = p {
+          if *layers == LayerMask::EMPTY {
+            *layers = LayerMask::ALL;
+          }
+        } else {
+          return Err(EvalError(format!("Expected closed pipeline, got {:?}", p), Some(span)));
+        }
+      }
+
+      ctx.system = Some(res);
+      Ok(Value::List(vec![]))
+    },
     IfElse(condition, branch1, branch2, _) => {
       let span = condition.span();
       if let Value::Boolean(value) = eval(*condition, ctx, false)? {
@@ -556,6 +821,34 @@ pub fn eval_config(config: Expression, context: &mut Context) -> Result<Value, E
   eval(config, context, true)
 }
 
+// a line of the repl, its variables stay in the root scope for the next lines;
+// returns values of new variables (by their names in the source) followed by values of expressions
+pub fn eval_line(line: Expression, context: &mut Context) -> Result<Vec<(Option<String>, Value)>, EvalError> {
+  let Expression::Scope(statements, expressions, _) = line else {
+    unreachable!("repl lines are parsed as scopes")
+  };
+
+  let names = statements.iter()
+    .flat_map(|st| match st {
+      Statement::Let(ids, _, _) => ids.clone(),
+      Statement::Def(_, _, _, _) => vec![]
+    })
+    .collect::<Vec<_>>();
+
+  // scopes opened by a failed expression are never dropped
+  let depth  = context.vars.len();
+  let result = eval_scope(statements, expressions, context, true);
+  context.vars.truncate(depth);
+
+  let mut values = vec![];
+  for name in names {
+    let value = context.get_value(&name, None)?;
+    values.push((Some(name.split('$').next().unwrap().to_string()), value));
+  }
+  values.extend(result?.into_iter().map(|value| (None, value)));
+  Ok(values)
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -579,6 +872,18 @@ mod tests {
     }
   }
 
+  #[test]
+  fn repl_lines() {
+    let mut parser = super::super::parser::LineParser::default();
+    let mut ctx = Context::new(None);
+    let mut eval = |line: &str| eval_line(parser.parse(line).unwrap(), &mut ctx).unwrap();
+
+    assert_eq!(eval("let x = 1; def inc(v) = v + 1"), vec![(Some("x".to_string()), Value::Number(1.0))]);
+    assert_eq!(eval("let x = inc(x);"), vec![(Some("x".to_string()), Value::Number(2.0))]);
+    assert_eq!(eval("x * 2, x"), vec![(None, Value::Number(4.0)), (None, Value::Number(2.0))]);
+    assert_eq!(eval(""), vec![]);
+  }
+
   #[test]
   fn math() {
     let code = r#"
@@ -723,6 +1028,22 @@ mod tests {
     }
   }
 
+  #[test]
+  fn too_many_layers() {
+    let code = (0..=LayerMask::MAX_USER_LAYERS)
+      .map(|i| format!("layer l{} {{ 1 }}", i))
+      .collect::<Vec<String>>()
+      .join(",\n");
+    if let Ok(p) = parse_config(&code) {
+      match eval_config(p, &mut Context::new(None)) {
+        Err(EvalError(message, Some(_))) => assert!(message.starts_with("Too many layers")),
+        _ => panic!()
+      }
+    } else {
+      panic!();
+    }
+  }
+
   #[test]
   fn strings() {
     let code = r#"
@@ -734,4 +1055,194 @@ mod tests {
       panic!();
     }
   }
+
+  #[test]
+  fn system_section() {
+    let code = r#"
+      layer foo { bind(1) },
+      system {
+        bind(2),
+        layer knobs_menu | foo { bind(3) },
+        if (knobs_ui) bind(4) else bind(5)
+      }
+    "#;
+
+    let mut ctx = Context::new(None);
+    ctx.knobs_ui = false;
+    ctx.register_fun("bind", |_, _| {
+      Ok(Value::CompletePipeline(LayerMask::EMPTY, std::rc::Rc::new(keyboard_key_press(dummy_button_input(false), KeyboardKey::A))))
+    });
+
+    let config = eval_config(parse_config(code).unwrap(), &mut ctx).unwrap();
+    assert!(matches!(util::flatten(config).as_slice(), [Value::CompletePipeline(mask, _)] if *mask == LayerMask::user_layer(0).unwrap()));
+
+    let system = ctx.system.unwrap().iter().map(|p| match p {
+      Value::CompletePipeline(mask, _) => *mask,
+      _ => panic!()
+    }).collect::<Vec<_>>();
+    assert_eq!(system, vec![LayerMask::ALL, LayerMask::internal_layer(0).unwrap() | LayerMask::user_layer(0).unwrap(), LayerMask::ALL]);
+
+    match eval_config(parse_config("let x = system { 1 }; x").unwrap(), &mut Context::new(None)) {
+      Err(EvalError(message, Some(_))) => assert!(message.starts_with("System bindings must be declared at the top level")),
+      _ => panic!()
+    }
+  }
+
+  #[test]
+  fn live_knobs() {
+    let code = r#"
+      let speed = knob("speed", 2, min_value = 0, max_value = 10);
+      let delay = knob("delay", 3, min_value = 0, max_value = 10);
+      speed * 2 + 1, plain(delay)
+    "#;
+
+    let mut ctx = Context::new(None);
+    ctx.register_fun("plain", |args, _| match args {
+      [Value::Number(n)] => Ok(Value::Number(*n)),
+      _ => Err(None)
+    });
+
+    let result = eval_config(parse_config(code).unwrap(), &mut ctx).unwrap();
+    let pipeline = match &result {
+      Value::List(values) if values[1] == Value::Number(3.0) => match &values[0] {
+        Value::Pipeline1D(p) => p.clone(),
+        _ => panic!()
+      },
+      _ => panic!()
+    };
+
+    // delay is only known at evaluation time
+    assert_eq!(ctx.live_knobs.keys().collect::<Vec<_>>(), vec!["speed"]);
+
+    let state = crate::controllers::ControllerState::empty();
+    let apply = || {
+      let ctx = crate::stages::Context {
+        state: &state, time: Timestamp::from_millis(0), layers: LayerMask::EMPTY, probe_values: &HashMap::new(), overlay: &OverlayStatus::default()
+      };
+      pipeline.borrow_mut().reset();
+      pipeline.borrow_mut().apply(&ctx, &mut vec![])
+    };
+
+    assert_eq!(apply(), 5.0);
+    ctx.live_knobs["speed"].set(4.0);
+    assert_eq!(apply(), 9.0);
+  }
+
+  #[test]
+  fn frozen_knob_arithmetic() {
+    let code = r#"
+      let speed = knob("speed", 2, min_value = 0, max_value = 10);
+      let delay = knob("delay", 3, min_value = 0, max_value = 10);
+      let gain  = knob("gain",  4, min_value = 0, max_value = 10);
+      let j = 2 * speed;
+      plain(delay / 2 + 1), checked(gain - 1), if (j == 4) { 1 } else { 0 }
+    "#;
+
+    let mut ctx = Context::new(None);
+    ctx.register_fun("plain", |args, _| match args {
+      [Value::Number(n)] => Ok(Value::Number(*n)),
+      _ => Err(None)
+    });
+    // builtins validating their arguments report pipelines with an error message
+    ctx.register_fun("checked", |args, _| match args {
+      [Value::Number(n)] => Ok(Value::Number(*n)),
+      _ => Err(Some("expected a number".to_string()))
+    });
+
+    let result = eval_config(parse_config(code).unwrap(), &mut ctx).unwrap();
+    assert!(matches!(&result, Value::List(values) if values[..] == [Value::Number(2.5), Value::Number(3.0), Value::Number(1.0)]));
+    assert!(ctx.live_knobs.is_empty());
+
+    // anything depending on the controller can't be frozen
+    let code = r#"
+      let speed = knob("speed", 2, min_value = 0, max_value = 10);
+      plain(speed * axis())
+    "#;
+
+    let mut ctx = Context::new(None);
+    ctx.register_fun("plain", |args, _| match args {
+      [Value::Number(n)] => Ok(Value::Number(*n)),
+      _ => Err(None)
+    });
+    ctx.register_fun("axis", |_, _| Ok(Value::Pipeline1D(axis_input(Axis::LPadX))));
+    assert!(eval_config(parse_config(code).unwrap(), &mut ctx).is_err());
+  }
+
+  #[test]
+  fn knob_types() {
+    let code = r#"
+      let speed = knob("speed", 1, min_value = 0, max_value = 2);
+      layer foo { nothing() },
+      layer bar { nothing() },
+      knob("mode", foo, {foo, bar, foo | bar}),
+      knob("key", key),
+      knob("count", 2, min_value = 1, max_value = 5, step = 1),
+      knob("curve", {{0, 0}, {0.5, 0.25}, {1, 1}})
+    "#;
+
+    let point = |x, y| Value::List(vec![Value::Number(x), Value::Number(y)]);
+    let mut knob_values = HashMap::new();
+    knob_values.insert("mode".to_string(),  Value::String("foo | bar".to_string()));
+    knob_values.insert("key".to_string(),   Value::String("B".to_string()));
+    knob_values.insert("count".to_string(), Value::Number(7.0));
+    knob_values.insert("curve".to_string(), Value::List(vec![point(0.0, 0.0), point(1.0, 0.5)]));
+    knob_values.insert("speed".to_string(), Value::Number(-1.0));
+
+    let mut ctx = Context::new(Some(knob_values));
+    ctx.insert_var("key", Value::Constant(Constant::KeyboardKey(KeyboardKey::A)));
+    ctx.register_fun("nothing", |_, _| Ok(Value::List(vec![])));
+
+    let result = util::flatten(eval_config(parse_config(code).unwrap(), &mut ctx).unwrap());
+    let foo_bar = LayerMask::user_layer(0).unwrap() | LayerMask::user_layer(1).unwrap();
+    assert_eq!(result, vec![
+      Value::LayerMask(foo_bar),
+      Value::Constant(Constant::KeyboardKey(KeyboardKey::B)),
+      Value::Number(5.0),
+      Value::Number(0.0), Value::Number(0.0), Value::Number(1.0), Value::Number(0.5)
+    ]);
+    assert!(matches!(&ctx.knobs[1], Knob::Layer { index: 2, options, .. } if options[2] == "foo | bar"));
+
+    // numbers are clamped like integers
+    assert!(matches!(&ctx.knobs[0], Knob::Number { value, .. } if *value == 0.0));
+  }
+
+  #[test]
+  fn knob_file_type_mismatch() {
+    let eval_error = |code: &str, value: Value| {
+      let mut knob_values = HashMap::new();
+      knob_values.insert("foo".to_string(), value);
+      let mut ctx = Context::new(Some(knob_values));
+      match eval_config(parse_config(code).unwrap(), &mut ctx) {
+        Err(EvalError(message, Some(_))) => message,
+        _ => panic!("expected an error")
+      }
+    };
+
+    assert_eq!(eval_error(r#"knob("foo", 1, min_value = 0, max_value = 2)"#, Value::String("fast".to_string())),
+      "Knob foo expects a Number in the knob file, got String fast");
+    assert_eq!(eval_error(r#"knob("foo", 1, min_value = 0, max_value = 4, step = 1)"#, Value::Number(1.5)),
+      "Knob foo expects an integer Number in the knob file, got Number 1.5");
+    assert_eq!(eval_error(r#"knob("foo", "a", {"a", "b"})"#, Value::Boolean(true)),
+      "Knob foo expects a String in the knob file, got Boolean true");
+    assert_eq!(eval_error(r#"knob("foo", {{0, 0}, {1, 1}})"#, Value::List(vec![Value::Number(1.0)])),
+      "Knob foo expects a List of {x, y} pairs in the knob file, got List [1.0]");
+  }
+
+  #[test]
+  fn key_knob_options() {
+    let eval_error = |code: &str| {
+      let mut ctx = Context::new(None);
+      ctx.insert_var("a", Value::Constant(Constant::KeyboardKey(KeyboardKey::A)));
+      ctx.insert_var("b", Value::Constant(Constant::KeyboardKey(KeyboardKey::B)));
+      ctx.insert_var("c", Value::Constant(Constant::KeyboardKey(KeyboardKey::C)));
+      ctx.register_fun("nothing", |_, _| Ok(Value::List(vec![])));
+      match eval_config(parse_config(code).unwrap(), &mut ctx) {
+        Err(EvalError(message, Some(_))) => message,
+        _ => panic!("{} should not evaluate", code)
+      }
+    };
+
+    assert_eq!(eval_error(r#"knob("key", a, nothing())"#), "Options should not be empty");
+    assert_eq!(eval_error(r#"knob("key", a, {b, c})"#), "Default key A should be one of the options");
+  }
 }
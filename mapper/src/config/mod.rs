@@ -3,22 +3,230 @@ mod eval;
 mod parser;
 mod util;
 
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
 use std::rc::Rc;
 
 use eval::Constant;
-pub use eval::{EvalError, Value};
+pub use eval::{name_of_type, EvalError, Value};
+pub use util::curve_points;
 use overlay_ipc::Knob;
 
-use crate::controllers::{Axis, Button};
+use crate::controllers::{Axis, Button, HapticFeedbackEffect, HapticFeedbackTarget};
 use crate::mapper::LayerMask;
 use crate::output::{KeyboardKey, MouseAxis, MouseButton};
 use crate::stages::*;
 
+type Bindings = Vec<(LayerMask, Box<dyn Pipeline<()>>)>;
+
 pub struct Config {
-  pub pipelines: Vec<(LayerMask, Box<dyn Pipeline<()>>)>,
-  pub layers:    Vec<String>,
-  pub knobs:     Vec<Knob>
+  pub pipelines:  Bindings,
+  pub system:     Bindings, // only used with an overlay
+  pub layers:     Vec<String>,
+  pub knobs:      Vec<Knob>,
+  pub live_knobs: HashMap<String, Rc<Cell<f32>>>, // number knobs which can be changed without reloading the config
+  pub modes:      Vec<LayerMask>                  // every mode the bindings can switch to
+}
+
+// used unless the config has its own system section, and only if the overlay has the knobs menu
+const DEFAULT_SYSTEM_BINDINGS: &str = r#"
+system {
+  def rad(deg) = deg * 3.14159265358979323846264338327950288 / 180.0;
+  def sector(pad, direction) =
+    pad.as_ring_sector_button(
+      direction    = rad(direction),
+      angle        = rad(120),
+      inner_radius = 0.25,
+      outer_radius = 1.2,
+      margin       = 0.1);
+
+  let left_pad  = merge(input(LPadX), input(LPadY));
+  let right_pad = merge(input(RPadX), input(RPadY));
+
+  input(Steam).flip_mode(knobs_menu),
+  mode_is(knobs_menu).overlay_menu("open"),
+  invert(mode_is(knobs_menu)).overlay_menu("close"),
+
+  layer knobs_menu {
+    input(X).toggle_overlay_ui(),
+
+    sector(left_pad,   90).overlay_menu("prev_item"),
+    sector(left_pad,  -90).overlay_menu("next_item"),
+    sector(right_pad, 180).overlay_menu("prev_value"),
+    sector(right_pad,   0).overlay_menu("next_value"),
+
+    input(DPadUp   ).overlay_menu("prev_item"),
+    input(DPadDown ).overlay_menu("next_item"),
+    input(DPadLeft ).overlay_menu("prev_value"),
+    input(DPadRight).overlay_menu("next_value"),
+
+    input(LBump).overlay_menu("prev_preset"),
+    input(RBump).overlay_menu("next_preset")
+  }
+}
+"#;
+
+fn touch_menu_haptics(xy: &PipelineRef<(f32, f32)>, opts: &HashMap<String, Value>) -> Result<TouchMenuHaptics, Option<String>> {
+
+  let mut haptics = TouchMenuHaptics::for_position(xy);
+
+  match opts.get("haptics") {
+    Some(Value::Constant(Constant::HapticTarget(target))) => haptics.targets = vec![*target],
+    Some(Value::String(s)) if s == "none" => haptics.targets = vec![],
+    Some(_) => return Err(Some("haptics should be either a Haptic.* constant or \"none\"".to_string())),
+    None => ()
+  }
+
+  match opts.get("haptic_intensity") {
+    Some(Value::Number(intensity)) if *intensity >= 0.0 => haptics.intensity = *intensity,
+    Some(_) => return Err(Some("haptic_intensity should be a non-negative number".to_string())),
+    None => ()
+  }
+
+  Ok(haptics)
+}
+
+// x1, y1, x2, y2 in pixels
+fn screen_probe_bounds(opts: &HashMap<String, Value>) -> Result<overlay_ipc::Rect, Option<String>> {
+  if let (
+    Some(Value::Number(x1)),
+    Some(Value::Number(y1)),
+    Some(Value::Number(x2)),
+    Some(Value::Number(y2))
+  ) = (
+    opts.get("x1"),
+    opts.get("y1"),
+    opts.get("x2"),
+    opts.get("y2")
+  ) {
+    if x1 < x2 && y1 < y2 {
+      Ok(overlay_ipc::Rect {
+        min: overlay_ipc::Point { x: overlay_ipc::Length::px(*x1), y: overlay_ipc::Length::px(*y1) },
+        max: overlay_ipc::Point { x: overlay_ipc::Length::px(*x2), y: overlay_ipc::Length::px(*y2) }
+      })
+    } else {
+      Err(Some("probe area should satisfy x1 < x2 and y1 < y2".to_string()))
+    }
+  } else {
+    Err(None)
+  }
+}
+
+fn screen_probe_image(opts: &HashMap<String, Value>) -> Result<overlay_ipc::Image, Option<String>> {
+  match opts.get("file") {
+    Some(Value::String(path)) => overlay_ipc::Image::load(path).map_err(|e| Some(format!("Can't load reference image: {}", e))),
+    Some(_) => Err(Some("file should be a path to a PNG or binary PPM image".to_string())),
+    None => Err(None)
+  }
+}
+
+fn screen_probe_threshold(opts: &HashMap<String, Value>, name: &str, default: f32) -> Result<f32, Option<String>> {
+  match opts.get(name) {
+    Some(Value::Number(n)) => Ok(*n),
+    Some(_) => Err(Some(format!("{} should be a number", name))),
+    None => Ok(default)
+  }
+}
+
+// pulse parameters go to the controller as they are
+fn pulse_param(value: f32, name: &str) -> Result<u16, Option<String>> {
+  if value >= 0.0 && value <= u16::MAX as f32 && value.fract() == 0.0 {
+    Ok(value as u16)
+  } else {
+    Err(Some(format!("haptic {} should be a whole number between 0 and {}", name, u16::MAX)))
+  }
+}
+
+fn touch_menu_items(values: &[Value], bindings: &mut Vec<Option<Value>>) -> Result<Vec<TouchMenuItem>, Option<String>> {
+
+  let mut items = vec![];
+
+  for value in values {
+    match value {
+      Value::String(label) => {
+        items.push(TouchMenuItem::new(label.clone()));
+        bindings.push(None);
+      },
+      Value::Struct(fields) if fields.contains_key("label") => {
+        let mut item = TouchMenuItem::new(match &fields["label"] {
+          Value::String(label) => label.clone(),
+          _ => return Err(Some("item labels should be strings".to_string()))
+        });
+
+        if let Some(Value::String(icon)) = fields.get("icon") {
+          item.icon = Some(icon.clone());
+        }
+
+        if let Some(Value::String(color)) = fields.get("color") {
+          item.color = util::color(color);
+        }
+
+        match fields.get("submenu") {
+          Some(Value::List(submenu)) if !submenu.is_empty() => {
+            // leaves are numbered in depth-first order, so submenu items go right there
+            item.submenu = touch_menu_items(submenu, bindings)?;
+          },
+          _ => bindings.push(fields.get("bind").cloned())
+        }
+
+        items.push(item);
+      },
+      _ => return Err(Some("items should only contain string values or item(...) definitions".to_string()))
+    }
+  }
+
+  Ok(items)
+}
+
+// returns a button per menu item or a complete pipeline for items with bindings
+fn touch_menu_fun(args: &[Value], opts: &HashMap<String, Value>, layout: TouchMenuOpts) -> Result<Value, Option<String>> {
+
+  let sticky = match opts.get("sticky") {
+    Some(Value::Boolean(sticky)) => *sticky,
+    Some(_) => return Err(Some("sticky should be a boolean".to_string())),
+    None => false
+  };
+
+  let (xy, toggle, select, items) = match args {
+    [Value::Pipeline2D(xy), Value::PipelineB(toggle), Value::List(items)] => {
+      (xy, Rc::clone(toggle), invert(Rc::clone(toggle)), items)
+    },
+    [Value::Pipeline2D(xy), Value::PipelineB(toggle), Value::PipelineB(select), Value::List(items)] => {
+      (xy, Rc::clone(toggle), Rc::clone(select), items)
+    },
+    _ => return Err(None)
+  };
+
+  let mut bindings = vec![];
+  let items = touch_menu_items(items, &mut bindings)?;
+  let menu  = touch_menu(xy.clone(), toggle, select, items, layout, touch_menu_haptics(xy, opts)?, sticky).map_err(Some)?;
+
+  let mut values = vec![];
+  for (i, binding) in bindings.into_iter().enumerate() {
+    let button = menu_item(Rc::clone(&menu), i as u8);
+    values.push(match binding {
+      None => Value::PipelineB(button),
+      Some(Value::Constant(Constant::KeyboardKey(key))) => {
+        Value::CompletePipeline(LayerMask::EMPTY, Rc::new(keyboard_key_press(button, key)))
+      },
+      Some(Value::Constant(Constant::MouseButton(btn))) => {
+        Value::CompletePipeline(LayerMask::EMPTY, Rc::new(mouse_button_press(button, btn)))
+      },
+      Some(Value::LayerMask(mask)) => {
+        Value::CompletePipeline(LayerMask::EMPTY, Rc::new(switch_mode(button, mask)))
+      },
+      Some(_) => return Err(Some("item bindings should be a key, a mouse button or a mode".to_string()))
+    });
+  }
+
+  Ok(Value::List(values))
+}
+
+fn declare_mode(modes: &RefCell<Vec<LayerMask>>, mask: LayerMask) {
+  let mut modes = modes.borrow_mut();
+  if !modes.contains(&mask) {
+    modes.push(mask);
+  }
 }
 
 fn register_defaults(ctx: &mut eval::Context) {
@@ -68,6 +276,7 @@ fn register_defaults(ctx: &mut eval::Context) {
   ctx.insert_var("DPadLeft",    Value::Constant(Constant::InputButton(Button::DPadLeft)));
   ctx.insert_var("DPadDown",    Value::Constant(Constant::InputButton(Button::DPadDown)));
   ctx.insert_var("DPadRight",   Value::Constant(Constant::InputButton(Button::DPadRight)));
+  ctx.insert_var("Steam",       Value::Constant(Constant::InputButton(Button::Steam)));
 
   let mut ms = HashMap::new();
 
@@ -90,6 +299,14 @@ fn register_defaults(ctx: &mut eval::Context) {
 
   ctx.insert_var("Kb", Value::Struct(kb));
 
+  let mut hf = HashMap::new();
+
+  for target in HapticFeedbackTarget::iter() {
+    hf.insert(format!("{:?}", target), Value::Constant(Constant::HapticTarget(target)));
+  }
+
+  ctx.insert_var("Haptic", Value::Struct(hf));
+
   ctx.register_fun("as_axis", move |args, opts| match args {
     [Value::PipelineB(p)] => {
       if let (Some(Value::Number(value)), Some(Value::Boolean(repeat))) = (opts.get("value"), opts.get("repeat")) {
@@ -162,11 +379,38 @@ fn register_defaults(ctx: &mut eval::Context) {
     _ => Err(None)
   });
 
+  ctx.register_fun("color_probe", move |args, opts| match args {
+    [] => {
+      let bounds = screen_probe_bounds(&opts)?;
+      let color  = match opts.get("color") {
+        Some(Value::String(color)) => util::color(color).ok_or_else(|| Some("color should look like \"#rrggbb\"".to_string()))?,
+        _ => return Err(None)
+      };
+      let tolerance = screen_probe_threshold(&opts, "tolerance", 0.1)?;
+
+      let target = overlay_ipc::ScreenScrapingArea { bounds, detector: overlay_ipc::ScreenScrapingDetector::MeanColor };
+      Ok(Value::PipelineB(screen_probe(target, ScreenProbeCondition::ColorWithin(color, tolerance))))
+    },
+    _ => Err(None)
+  });
+
+  ctx.register_fun("curve", move |args, _| match args {
+    [Value::Pipeline1D(p), Value::List(points)] => {
+      if let Some(points) = util::curve_points(points) {
+        Ok(Value::Pipeline1D(curve(Rc::clone(p), points)))
+      } else {
+        Err(Some("curve points should be {x, y} pairs in [0, 1] sorted by x from 0 to 1".to_string()))
+      }
+    },
+    _ => Err(None)
+  });
+
   ctx.register_fun("cutoff", move |args, _| match args {
     [Value::Pipeline1D(p), Value::Number(n)] => Ok(Value::Pipeline1D(cutoff(Rc::clone(p), *n))),
     _ => Err(None)
   });
 
+  let modes = Rc::clone(&ctx.modes);
   ctx.register_fun("cycle_modes", move |args, _| match args {
     [Value::PipelineB(p), Value::List(list)] => {
       let mut masks = vec![];
@@ -177,6 +421,9 @@ fn register_defaults(ctx: &mut eval::Context) {
           return Err(None);
         }
       }
+      for mask in &masks {
+        declare_mode(&modes, *mask);
+      }
       let p = cycle_modes(Rc::clone(p), masks);
       Ok(Value::CompletePipeline(LayerMask::EMPTY, Rc::new(p)))
     },
@@ -204,38 +451,53 @@ fn register_defaults(ctx: &mut eval::Context) {
     _ => Err(None)
   });
 
-  ctx.register_fun("hex_grid_menu", move |args, _| {
-    let default_menu_opts = TouchMenuOpts::HexGrid {
-      margin: 0.015 // ?
-    };
+  ctx.register_fun("grid_menu", move |args, opts| match opts.get("columns") {
+    Some(Value::Number(columns)) if *columns >= 1.0 && columns.fract() == 0.0 => {
+      touch_menu_fun(args, &opts, TouchMenuOpts::Grid { columns: *columns as usize, margin: 0.03 /* ? */ })
+    },
+    _ => Err(Some("columns should be a positive integer".to_string()))
+  });
 
+  let modes = Rc::clone(&ctx.modes);
+  ctx.register_fun("flip_mode", move |args, _| match args {
+    [Value::PipelineB(p), Value::LayerMask(mask)] => {
+      declare_mode(&modes, *mask);
+      Ok(Value::CompletePipeline(LayerMask::EMPTY, Rc::new(flip_mode(Rc::clone(p), *mask))))
+    },
+    _ => Err(None)
+  });
+
+  ctx.register_fun("haptic", move |args, _| match args {
+    [Value::PipelineB(button), Value::Constant(Constant::HapticTarget(target))] => {
+      Ok(Value::CompletePipeline(LayerMask::EMPTY, Rc::new(haptic_pulse(Rc::clone(button), *target, HapticFeedbackEffect::SlightBump))))
+    },
+    [Value::PipelineB(button), Value::Constant(Constant::HapticTarget(target)), Value::Number(amplitude), Value::Number(period), Value::Number(count)] => {
+      let effect = HapticFeedbackEffect::Pulse {
+        amplitude: pulse_param(*amplitude, "amplitude")?, period: pulse_param(*period, "period")?, count: pulse_param(*count, "count")?
+      };
+      Ok(Value::CompletePipeline(LayerMask::EMPTY, Rc::new(haptic_pulse(Rc::clone(button), *target, effect))))
+    },
+    _ => Err(None)
+  });
+
+  ctx.register_fun("hex_grid_menu", move |args, opts| {
     // TODO: doesn't work when items.len() == 1
-    let (menu, number_of_items) = match args {
-      [Value::Pipeline2D(xy), Value::PipelineB(toggle), Value::List(items)] => {
-        if let Some(items) = util::strings(items) {
-          let n = items.len();
-          (touch_menu(xy.clone(), Rc::clone(toggle), invert(Rc::clone(toggle)), items, default_menu_opts), n)
-        } else {
-          return Err(Some("items should only contain string values".to_string()));
-        }
-      },
-      [Value::Pipeline2D(xy), Value::PipelineB(toggle), Value::PipelineB(select), Value::List(items)] => {
-        if let Some(items) = util::strings(items) {
-          let n = items.len();
-          (touch_menu(xy.clone(), Rc::clone(toggle), Rc::clone(select), items, default_menu_opts), n)
-        } else {
-          return Err(Some("items should only contain string values".to_string()));
-        }
-      },
-      _ => return Err(None)
-    };
+    touch_menu_fun(args, &opts, TouchMenuOpts::HexGrid {
+      margin: 0.015 // ?
+    })
+  });
 
-    let mut buttons = vec![];
-    for i in 0..number_of_items {
-      buttons.push(Value::PipelineB(menu_item(Rc::clone(&menu), i as u8)));
-    }
 
-    Ok(Value::List(buttons))
+  ctx.register_fun("histogram_probe", move |args, opts| match args {
+    [] => {
+      let bounds    = screen_probe_bounds(&opts)?;
+      let reference = screen_probe_image(&opts)?.histogram();
+      let threshold = screen_probe_threshold(&opts, "threshold", 0.2)?;
+
+      let target = overlay_ipc::ScreenScrapingArea { bounds, detector: overlay_ipc::ScreenScrapingDetector::Histogram(reference) };
+      Ok(Value::PipelineB(screen_probe(target, ScreenProbeCondition::MaxDistance(threshold))))
+    },
+    _ => Err(None)
   });
 
   ctx.register_fun("input", move |args, _| match args {
@@ -251,6 +513,37 @@ fn register_defaults(ctx: &mut eval::Context) {
     _ => Err(None)
   });
 
+  let modes = Rc::clone(&ctx.modes);
+  ctx.register_fun("item", move |args, opts| match args {
+    [Value::String(label)] => {
+      let mut item = HashMap::new();
+      item.insert("label".to_string(), Value::String(label.clone()));
+
+      for (name, value) in opts {
+        match (name.as_str(), &value) {
+          ("icon", Value::String(_)) | ("submenu", Value::List(_)) => (),
+          ("color", Value::String(color)) => {
+            if util::color(color).is_none() {
+              return Err(Some(format!("Unable to parse color {:?}, expected #rrggbb or #rrggbbaa", color)));
+            }
+          },
+          ("bind", Value::Constant(Constant::KeyboardKey(_)))
+          | ("bind", Value::Constant(Constant::MouseButton(_))) => (),
+          ("bind", Value::LayerMask(mask)) => declare_mode(&modes, *mask),
+          _ => return Err(Some(format!("Unexpected item option {} = {:?}", name, value)))
+        }
+        item.insert(name, value);
+      }
+
+      if item.contains_key("bind") && item.contains_key("submenu") {
+        return Err(Some("Items with submenus can't be bound".to_string()));
+      }
+
+      Ok(Value::Struct(item))
+    },
+    _ => Err(None)
+  });
+
   ctx.register_fun("left_trigger_bump", move |args, _| match args {
     [Value::PipelineB(button)] => {
       Ok(Value::CompletePipeline(LayerMask::EMPTY, Rc::new(trigger_bump(Rc::clone(button), true))))
@@ -258,8 +551,16 @@ fn register_defaults(ctx: &mut eval::Context) {
     _ => Err(None)
   });
 
+  ctx.register_fun("list_menu", move |args, opts| {
+    touch_menu_fun(args, &opts, TouchMenuOpts::Grid { columns: 1, margin: 0.03 /* ? */ })
+  });
+
   ctx.register_fun("memory_probe", move |args, _| match args {
-    [Value::String(spec)] => Ok(Value::PipelineB(memory_probe(spec).expect("memory_probe"))),
+    [Value::String(spec)] => match memory_probe(spec) {
+      Ok(MemoryProbe::Value(p))     => Ok(Value::Pipeline1D(p)),
+      Ok(MemoryProbe::Condition(p)) => Ok(Value::PipelineB(p)),
+      Err(e) => Err(Some(e))
+    },
     _ => Err(None)
   });
 
@@ -268,6 +569,11 @@ fn register_defaults(ctx: &mut eval::Context) {
     _ => Err(None)
   });
 
+  ctx.register_fun("mode_is", move |args, _| match args {
+    [Value::LayerMask(mask)] => Ok(Value::PipelineB(mode_is(*mask))),
+    _ => Err(None)
+  });
+
   ctx.register_fun("offset", move |args, _| match args {
     [Value::Pipeline1D(p), Value::Number(addend)] => {
       Ok(Value::Pipeline1D(offset(Rc::clone(p), *addend)))
@@ -278,6 +584,33 @@ fn register_defaults(ctx: &mut eval::Context) {
     _ => Err(None)
   });
 
+  ctx.register_fun("overlay_menu", move |args, _| match args {
+    [Value::PipelineB(p), Value::String(command)] => {
+      let command = match command.as_str() {
+        "open"        => OverlayMenuCommand::OpenKnobsMenu,
+        "close"       => OverlayMenuCommand::CloseKnobsMenu,
+        "prev_item"   => OverlayMenuCommand::SelectPrevMenuItem,
+        "next_item"   => OverlayMenuCommand::SelectNextMenuItem,
+        "prev_value"  => OverlayMenuCommand::SelectPrevValue,
+        "next_value"  => OverlayMenuCommand::SelectNextValue,
+        "prev_preset" => OverlayMenuCommand::SelectPrevPreset,
+        "next_preset" => OverlayMenuCommand::SelectNextPreset,
+        _ => return Err(Some(format!("unknown menu command {:?}", command)))
+      };
+      Ok(Value::CompletePipeline(LayerMask::EMPTY, Rc::new(overlay_menu_command(Rc::clone(p), command))))
+    },
+    _ => Err(None)
+  });
+
+  ctx.register_fun("overlay_status", move |args, _| match args {
+    [Value::String(name)] => match OverlayProperty::from_name(name) {
+      Some(property) if property.is_boolean() => Ok(Value::PipelineB(overlay_status_button(property))),
+      Some(property) => Ok(Value::Pipeline1D(overlay_status_axis(property))),
+      None => Err(Some(format!("unknown property {:?}", name)))
+    },
+    _ => Err(None)
+  });
+
   ctx.register_fun("polar", move |args, _| match args {
     [Value::Pipeline2D(p)] => Ok(Value::Pipeline2D(polar(Rc::clone(p)))),
     _ => Err(None)
@@ -299,41 +632,15 @@ fn register_defaults(ctx: &mut eval::Context) {
     _ => Err(None)
   });
 
-  ctx.register_fun("radial_menu", move |args, _| {
-    let default_menu_opts = TouchMenuOpts::Radial {
+  ctx.register_fun("radial_menu", move |args, opts| {
+    touch_menu_fun(args, &opts, TouchMenuOpts::Radial {
       inner_radius: 0.25, // ?
       outer_radius: 1.0,  // ?
       margin:       0.03  // ?
-    };
-
-    let (menu, number_of_items) = match args {
-      [Value::Pipeline2D(xy), Value::PipelineB(toggle), Value::List(items)] => {
-        if let Some(items) = util::strings(items) {
-          let n = items.len();
-          (touch_menu(xy.clone(), Rc::clone(toggle), invert(Rc::clone(toggle)), items, default_menu_opts), n)
-        } else {
-          return Err(Some("items should only contain string values".to_string()));
-        }
-      },
-      [Value::Pipeline2D(xy), Value::PipelineB(toggle), Value::PipelineB(select), Value::List(items)] => {
-        if let Some(items) = util::strings(items) {
-          let n = items.len();
-          (touch_menu(xy.clone(), Rc::clone(toggle), Rc::clone(select), items, default_menu_opts), n)
-        } else {
-          return Err(Some("items should only contain string values".to_string()));
-        }
-      },
-      _ => return Err(None)
-    };
-
-    let mut buttons = vec![];
-    for i in 0..number_of_items {
-      buttons.push(Value::PipelineB(menu_item(Rc::clone(&menu), i as u8)));
-    }
-
-    Ok(Value::List(buttons))
+    })
   });
 
+
   ctx.register_fun("relative", move |args, _| match args {
     [Value::Pipeline1D(axis), Value::PipelineB(button)] => {
       Ok(Value::Pipeline1D(relative(Rc::clone(axis), Rc::clone(button))))
@@ -358,6 +665,13 @@ fn register_defaults(ctx: &mut eval::Context) {
     _ => Err(None)
   });
 
+  ctx.register_fun("rumble", move |args, _| match args {
+    [Value::Pipeline1D(intensity), Value::Constant(Constant::HapticTarget(target))] => {
+      Ok(Value::CompletePipeline(LayerMask::EMPTY, Rc::new(rumble(Rc::clone(intensity), *target))))
+    },
+    _ => Err(None)
+  });
+
   ctx.register_fun("scale", move |args, _| match args {
     [Value::Pipeline1D(p), Value::Number(factor)] => {
       Ok(Value::Pipeline1D(scale(Rc::clone(p), *factor)))
@@ -409,19 +723,21 @@ fn register_defaults(ctx: &mut eval::Context) {
         assert!(*threshold2 > 0.0);
 
         let target = overlay_ipc::ScreenScrapingArea {
-          bounds:  overlay_ipc::Rect {
+          bounds:   overlay_ipc::Rect {
             min: overlay_ipc::Point { x: overlay_ipc::Length::px(*x1), y: overlay_ipc::Length::px(*y1) },
             max: overlay_ipc::Point { x: overlay_ipc::Length::px(*x2), y: overlay_ipc::Length::px(*y2) }
           },
-          min_hue: *min_hue,
-          max_hue: *max_hue,
-          min_sat: *min_sat,
-          max_sat: *max_sat,
-          min_val: *min_val,
-          max_val: *max_val
+          detector: overlay_ipc::ScreenScrapingDetector::HsvRange {
+            min_hue: *min_hue,
+            max_hue: *max_hue,
+            min_sat: *min_sat,
+            max_sat: *max_sat,
+            min_val: *min_val,
+            max_val: *max_val
+          }
         };
 
-        Ok(Value::PipelineB(screen_probe(target, (*threshold1, *threshold2))))
+        Ok(Value::PipelineB(screen_probe(target, ScreenProbeCondition::Thresholds(*threshold1, *threshold2))))
       } else {
         Err(None)
       }
@@ -429,8 +745,10 @@ fn register_defaults(ctx: &mut eval::Context) {
     _ => Err(None)
   });
 
+  let modes = Rc::clone(&ctx.modes);
   ctx.register_fun("set_mode", move |args, _| match args {
     [Value::PipelineB(p), Value::LayerMask(mask)] => {
+      declare_mode(&modes, *mask);
       Ok(Value::CompletePipeline(LayerMask::EMPTY, Rc::new(switch_mode(Rc::clone(p), *mask))))
     },
     _ => Err(None)
@@ -450,42 +768,181 @@ fn register_defaults(ctx: &mut eval::Context) {
     _ => Err(None)
   });
 
+  ctx.register_fun("template_probe", move |args, opts| match args {
+    [] => {
+      let bounds    = screen_probe_bounds(&opts)?;
+      let image     = screen_probe_image(&opts)?;
+      let threshold = screen_probe_threshold(&opts, "threshold", 0.8)?;
+
+      let target = overlay_ipc::ScreenScrapingArea { bounds, detector: overlay_ipc::ScreenScrapingDetector::Template(image) };
+      Ok(Value::PipelineB(screen_probe(target, ScreenProbeCondition::MinCorrelation(threshold))))
+    },
+    _ => Err(None)
+  });
+
+  ctx.register_fun("toggle_overlay_ui", move |args, _| match args {
+    [Value::PipelineB(p)] => Ok(Value::CompletePipeline(LayerMask::EMPTY, Rc::new(toggle_overlay_ui(Rc::clone(p))))),
+    _ => Err(None)
+  });
+
   ctx.register_fun("twitch_joymouse", move |args, _| match args {
     [Value::Pipeline2D(joystick)] => Ok(Value::Pipeline2D(twitch_joymouse(Rc::clone(joystick)))),
     _ => Err(None)
   });
 }
 
-#[cfg(not(test))]
-pub fn load_config(script: &str, knob_values: Option<HashMap<String, Value>>) -> Result<Config, String> {
+fn complete_pipelines(values: Vec<Value>) -> Result<Bindings, String> {
+  let mut pipelines = vec![];
+
+  for res in values {
+    match res {
+      Value::CompletePipeline(mask, p) => {
+        let mask = if mask == LayerMask::EMPTY { LayerMask::ALL_USER_BITS } else { mask };
+        pipelines.push((mask, std::rc::Rc::try_unwrap(p).unwrap_or_else(|_| panic!("Binding already consumed"))));
+      },
+      whatever => {
+        return Err(format!("Expected closed pipeline, got {:?}", whatever));
+      }
+    }
+  }
+
+  Ok(pipelines)
+}
+
+fn system_pipelines(context: &mut eval::Context, defaults: &str) -> Result<Bindings, String> {
+  if context.system.is_none() && context.knobs_ui {
+    let config = parser::parse_config(defaults).map_err(|err| format!("Default system bindings: {}", err))?;
+    eval::eval_config(config, context).map_err(|err| format!("Default system bindings: {}", describe_error(err, defaults)))?;
+  }
+
+  complete_pipelines(context.system.take().unwrap_or_default())
+}
+
+// no user pipelines, but the default system bindings are still there
+pub fn empty_config(knobs_ui: bool) -> Config {
+  let mut context = eval::Context::new(None);
+  context.knobs_ui = knobs_ui;
+  register_defaults(&mut context);
+
+  let system = system_pipelines(&mut context, DEFAULT_SYSTEM_BINDINGS).unwrap();
+  let modes = context.modes.take();
+  Config { pipelines: vec![], system, layers: vec![], knobs: vec![], live_knobs: HashMap::new(), modes }
+}
+
+fn describe_error(err: EvalError, source: &str) -> String {
+  match err {
+    EvalError(message, Some(location)) => {
+      format!("{}\n{} at {}:{}", location.show_in_source(source), message, location.0.0, location.0.1)
+    },
+    EvalError(message, None) => message
+  }
+}
+
+// knobs_ui tells the system section whether the overlay can show the knobs menu
+pub fn load_config(script: &str, knob_values: Option<HashMap<String, Value>>, knobs_ui: bool) -> Result<Config, String> {
   match parser::parse_config(script) {
     Ok(config) => {
       let mut context = eval::Context::new(knob_values);
+      context.knobs_ui = knobs_ui;
       register_defaults(&mut context);
 
-      let config = eval::eval_config(config, &mut context).map_err(|err| match err {
-        EvalError(message, Some(location)) => {
-          format!("{}\n{} at {}:{}", location.show_in_source(script), message, location.0.0, location.0.1)
-        },
-        EvalError(message, None) => message
-      })?;
+      let config = eval::eval_config(config, &mut context).map_err(|err| describe_error(err, script))?;
 
-      let mut pipelines = vec![];
+      let pipelines = complete_pipelines(util::flatten(config))?;
 
-      for res in util::flatten(config) {
-        match res {
-          Value::CompletePipeline(mask, p) => {
-            let mask = if mask == LayerMask::EMPTY { LayerMask::ALL_USER_BITS } else { mask };
-            pipelines.push((mask, std::rc::Rc::try_unwrap(p).unwrap_or_else(|_| panic!("Binding already consumed"))));
-          },
-          whatever => {
-            return Err(format!("Expected closed pipeline, got {:?}", whatever));
-          }
-        }
-      }
+      let system = system_pipelines(&mut context, DEFAULT_SYSTEM_BINDINGS)?;
 
-      Ok(Config { pipelines, layers: context.layers, knobs: context.knobs })
+      let modes = context.modes.take();
+      Ok(Config { pipelines, system, layers: context.layers, knobs: context.knobs, live_knobs: context.live_knobs, modes })
     },
     Err(err) => Err(err)
   }
 }
+
+// evaluates code line by line for `repl`, variables are kept between lines
+pub struct Repl<'a> {
+  parser:  parser::LineParser,
+  context: eval::Context<'a>
+}
+
+impl Default for Repl<'_> {
+
+  fn default() -> Self {
+    let mut context = eval::Context::new(None);
+    register_defaults(&mut context);
+    Self { parser: parser::LineParser::default(), context }
+  }
+}
+
+impl Repl<'_> {
+
+  // values of new variables by name, then values of expressions
+  pub fn eval(&mut self, line: &str) -> Result<Vec<(Option<String>, Value)>, String> {
+    let previous = self.parser.clone();
+    let result = self.parser.parse(line)
+      .and_then(|ast| eval::eval_line(ast, &mut self.context).map_err(|err| describe_error(err, line)));
+    if result.is_err() {
+      self.parser.restore(previous);
+    }
+    result
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  fn load_error(script: &str) -> String {
+    load_config(script, None, false).err().expect("config should not load")
+  }
+
+  #[test]
+  fn haptic_pulse_params() {
+    assert!(load_config("haptic(input(A), Haptic.LeftSide, 100, 2, 25)", None, false).is_ok());
+    assert!(load_error("haptic(input(A), Haptic.LeftSide, -1, 2, 25)").contains("haptic amplitude should be a whole number"));
+    assert!(load_error("haptic(input(A), Haptic.LeftSide, 100, 70000, 25)").contains("haptic period should be a whole number"));
+    assert!(load_error("haptic(input(A), Haptic.LeftSide, 100, 2, 2.5)").contains("haptic count should be a whole number"));
+  }
+
+  #[test]
+  fn grid_menu_columns() {
+    let script = |columns: &str| format!(r#"
+      let a, _ = grid_menu(merge(input(RPadX), input(RPadY)), input(RPadTouch), {{"1", "2"}}, columns = {});
+      a.bind(Kb._1)
+    "#, columns);
+    assert!(load_config(&script("2"), None, false).is_ok());
+    assert!(load_error(&script("1.5")).contains("columns should be a positive integer"));
+    assert!(load_error(&script("0")).contains("columns should be a positive integer"));
+  }
+
+  #[test]
+  fn system_bindings() {
+    assert_eq!(empty_config(true).system.len(), 14);
+    assert!(empty_config(false).system.is_empty());
+
+    // a system section replaces the defaults
+    let config = load_config("input(A).bind(Kb.A), system { input(Back).flip_mode(knobs_menu) }", None, true).unwrap();
+    assert_eq!(config.system.len(), 1);
+
+    let mut context = eval::Context::new(None);
+    register_defaults(&mut context);
+    let error = system_pipelines(&mut context, "system { input(Foo).flip_mode(knobs_menu) }").err().unwrap();
+    assert!(error.starts_with("Default system bindings: "), "{}", error);
+    assert!(system_pipelines(&mut context, "system {").is_err());
+  }
+
+  #[test]
+  fn knob_arithmetic_in_builtins() {
+    let config = load_config(r#"
+      let k = knob("k", 2, min_value = 0, max_value = 4);
+      let j = 2 * k;
+      input(LPadX).smooth(k * 2).deadzone(k / 10).scale(k).bind(Ms.X),
+      if (j == 4) { input(A).bind(Kb.A) } else { input(A).bind(Kb.B) }
+    "#, None, false).unwrap();
+
+    // k is frozen by smooth and deadzone, changing it reloads the config
+    assert_eq!(config.pipelines.len(), 2);
+    assert!(config.live_knobs.is_empty());
+  }
+}
@@ -76,7 +76,7 @@ fn into_typed_ast(pairs: &Pairs<Rule>) -> ast::Expression {
         ast::Expression::Apply(ident.as_str().to_string(), args, pair.as_span().into())
       },
 
-      Rule::scope => {
+      Rule::config | Rule::scope | Rule::repl_line => {
         let mut statements  = vec![];
         let mut expressions = vec![];
 
@@ -174,6 +174,13 @@ fn into_typed_ast(pairs: &Pairs<Rule>) -> ast::Expression {
         )
       },
 
+      Rule::system_expr => {
+        let scope = pair.clone().into_inner();
+        assert_eq!(scope.peek().unwrap().as_rule(), Rule::scope);
+
+        ast::Expression::System(Box::new(into_typed_ast(&scope)), pair.as_span().into())
+      },
+
       whatever => unreachable!("What do we have there?\n\t{:?}", whatever)
     })
     .map_infix(|lhs: ast::Expression, op: Pair<Rule>, rhs: ast::Expression| {
@@ -195,7 +202,6 @@ fn into_typed_ast(pairs: &Pairs<Rule>) -> ast::Expression {
 fn tag_variables(node: &mut ast::Expression, scopes: &mut Vec<HashMap<String, usize>>, counter: &mut usize) {
 
   use ast::Expression::*;
-  use ast::Statement::*;
 
   match node {
     Identifier(ref mut name, _) => {
@@ -229,42 +235,11 @@ fn tag_variables(node: &mut ast::Expression, scopes: &mut Vec<HashMap<String, us
 
     Scope(statements, expressions, _) => {
       scopes.push(HashMap::new());
-      for st in statements {
-        match st {
-          Let(ref mut names, body, _) => {
-            tag_variables(body, scopes, counter);
-            for name in names {
-              scopes.last_mut().unwrap().insert(name.clone(), *counter);
-              *name = format!("{}${}", name, *counter);
-              *counter += 1;
-            }
-          },
-
-          Def(ref mut name, args, body, _) => {
-            scopes.push(HashMap::new());
-            for arg in args {
-              scopes.last_mut().unwrap().insert(arg.clone(), *counter);
-              *arg = format!("{}${}", arg, *counter);
-              *counter += 1;
-            }
-
-            tag_variables(body, scopes, counter);
-
-            scopes.remove(scopes.len() - 1);
-
-            scopes.last_mut().unwrap().insert(name.clone(), *counter);
-            *name = format!("{}${}", name, *counter);
-            *counter += 1;
-          }
-        };
-      }
-      for expr in expressions {
-        tag_variables(expr, scopes, counter);
-      }
+      tag_scope(statements, expressions, scopes, counter);
       scopes.remove(scopes.len() - 1);
     },
 
-    Layer(_, expr, _) => {
+    Layer(_, expr, _) | System(expr, _) => {
       tag_variables(expr, scopes, counter);
     },
 
@@ -276,8 +251,47 @@ fn tag_variables(node: &mut ast::Expression, scopes: &mut Vec<HashMap<String, us
   };
 }
 
+// variables go into the innermost scope
+fn tag_scope(statements: &mut [ast::Statement], expressions: &mut [ast::Expression], scopes: &mut Vec<HashMap<String, usize>>, counter: &mut usize) {
+
+  use ast::Statement::*;
+
+  for st in statements {
+    match st {
+      Let(ref mut names, body, _) => {
+        tag_variables(body, scopes, counter);
+        for name in names {
+          scopes.last_mut().unwrap().insert(name.clone(), *counter);
+          *name = format!("{}${}", name, *counter);
+          *counter += 1;
+        }
+      },
+
+      Def(ref mut name, args, body, _) => {
+        scopes.push(HashMap::new());
+        for arg in args {
+          scopes.last_mut().unwrap().insert(arg.clone(), *counter);
+          *arg = format!("{}${}", arg, *counter);
+          *counter += 1;
+        }
+
+        tag_variables(body, scopes, counter);
+
+        scopes.remove(scopes.len() - 1);
+
+        scopes.last_mut().unwrap().insert(name.clone(), *counter);
+        *name = format!("{}${}", name, *counter);
+        *counter += 1;
+      }
+    };
+  }
+  for expr in expressions {
+    tag_variables(expr, scopes, counter);
+  }
+}
+
 pub fn parse_config(config: &str) -> Result<ast::Expression, String> {
-  match ConfigParser::parse(Rule::scope, config) {
+  match ConfigParser::parse(Rule::config, config) {
     Ok(pairs) => {
       let mut ast = into_typed_ast(&pairs);
       tag_variables(&mut ast, &mut vec![HashMap::new()], &mut 1);
@@ -286,3 +300,39 @@ pub fn parse_config(config: &str) -> Result<ast::Expression, String> {
     Err(err) => Err(format!("{}", err))
   }
 }
+
+// variables of previous lines stay visible, a repeated let shadows the previous one
+#[derive(Clone)]
+pub struct LineParser {
+  scopes:  Vec<HashMap<String, usize>>,
+  counter: usize
+}
+
+impl Default for LineParser {
+
+  fn default() -> Self {
+    Self { scopes: vec![HashMap::new()], counter: 1 }
+  }
+}
+
+impl LineParser {
+
+  // forgets variables of the last line (e.g. it failed to evaluate), their numbers aren't reused
+  pub fn restore(&mut self, previous: LineParser) {
+    self.scopes = previous.scopes;
+  }
+
+  // always returns a scope, which is empty for an empty line
+  pub fn parse(&mut self, line: &str) -> Result<ast::Expression, String> {
+    match ConfigParser::parse(Rule::repl_line, line) {
+      Ok(pairs) => {
+        let mut ast = into_typed_ast(&pairs);
+        if let ast::Expression::Scope(statements, expressions, _) = &mut ast {
+          tag_scope(statements, expressions, &mut self.scopes, &mut self.counter);
+        }
+        Ok(ast)
+      },
+      Err(err) => Err(format!("{}", err))
+    }
+  }
+}
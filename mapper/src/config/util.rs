@@ -29,3 +29,44 @@ pub fn strings(list: &Vec<Value>) -> Option<Vec<String>> {
   }
   Some(v)
 }
+
+// {{x1, y1}, {x2, y2}, ...} with x1 = 0 < x2 < ... < xn = 1 and y in [0, 1]
+pub fn curve_points(list: &[Value]) -> Option<Vec<(f32, f32)>> {
+  let mut points = vec![];
+  for item in list {
+    match item {
+      Value::List(point) => match point.as_slice() {
+        [Value::Number(x), Value::Number(y)] if (0.0..=1.0).contains(y) => points.push((*x, *y)),
+        _ => return None
+      },
+      _ => return None
+    }
+  }
+
+  let sorted = points.windows(2).all(|w| w[0].0 < w[1].0);
+  if points.len() >= 2 && sorted && points[0].0 == 0.0 && points[points.len() - 1].0 == 1.0 {
+    Some(points)
+  } else {
+    None
+  }
+}
+
+pub fn curve_value(points: &[(f32, f32)]) -> Value {
+  Value::List(points.iter().map(|(x, y)| Value::List(vec![Value::Number(*x), Value::Number(*y)])).collect())
+}
+
+// "#rrggbb" or "#rrggbbaa"
+pub fn color(s: &str) -> Option<overlay_ipc::Color> {
+  let hex = s.strip_prefix('#')?;
+
+  if !(hex.len() == 6 || hex.len() == 8) || !hex.is_ascii() {
+    return None;
+  }
+
+  let mut components = vec![];
+  for i in (0..hex.len()).step_by(2) {
+    components.push(u8::from_str_radix(&hex[i..(i + 2)], 16).ok()? as f32 / 255.0);
+  }
+
+  Some(overlay_ipc::Color::rgba(components[0], components[1], components[2], *components.get(3).unwrap_or(&1.0)))
+}
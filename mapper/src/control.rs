@@ -0,0 +1,202 @@
+use std::sync::{mpsc, Mutex};
+
+use futures::channel::mpsc::{unbounded, UnboundedSender};
+use futures::StreamExt;
+use serde_json::json;
+use zbus::fdo::Error::InvalidArgs;
+use zbus::{interface, ConnectionBuilder, SignalContext};
+
+use overlay_ipc::Knob;
+
+use crate::controllers::ControllerState;
+use crate::frontend::{self, Link, Shared, Snapshot};
+use crate::knob_file;
+use crate::log::log;
+use crate::mapper::{Frontend, FrontendRequest, Mode};
+
+const OBJECT_PATH: &str = "/mapper";
+
+// what State() reports, read from zbus' threads
+#[derive(Default)]
+struct ControlState {
+  snapshot: Snapshot,
+  script:   Option<String>,
+  preset:   String
+}
+
+impl AsMut<Snapshot> for ControlState {
+
+  fn as_mut(&mut self) -> &mut Snapshot {
+    &mut self.snapshot
+  }
+}
+
+// the stwgs.Mapper object, method calls only validate and queue requests for the mapper thread
+struct SCMapper {
+  state:    Shared<ControlState>,
+  requests: Mutex<mpsc::Sender<FrontendRequest>>
+}
+
+impl SCMapper {
+
+  fn send(&self, request: FrontendRequest) -> zbus::fdo::Result<()> {
+    self.requests.lock().unwrap().send(request).map_err(|e| zbus::fdo::Error::Failed(format!("{}", e)))
+  }
+}
+
+#[interface(name = "stwgs.Mapper")]
+impl SCMapper {
+
+  fn pid(&self) -> u32 {
+    std::process::id()
+  }
+
+  // { "script": ..., "preset": ..., "layers": [...], "mode": [...], "knobs": { <knob values> } }
+  fn state(&self) -> String {
+    let state = self.state.lock().unwrap();
+    json!({
+      "script": state.script,
+      "preset": state.preset,
+      "layers": state.snapshot.layers,
+      "mode":   state.snapshot.mode,
+      "knobs":  knob_file::serialize_knobs(&state.snapshot.knobs)
+    }).to_string()
+  }
+
+  fn set_mode(&self, layers: Vec<String>) -> zbus::fdo::Result<()> {
+    let known = &self.state.lock().unwrap().snapshot.layers;
+    if let Some(layer) = layers.iter().find(|layer| !known.contains(layer)) {
+      return Err(InvalidArgs(format!("unknown layer \"{}\"", layer)));
+    }
+    if layers.is_empty() {
+      return Err(InvalidArgs("expected at least one layer".to_string()));
+    }
+    self.send(FrontendRequest::SetMode(layers))
+  }
+
+  // the value is JSON, same as in knob files
+  fn set_knob(&self, name: String, value: String) -> zbus::fdo::Result<()> {
+    let mut knob = self.state.lock().unwrap().snapshot.knobs.iter().find(|knob| knob.name() == name).cloned()
+      .ok_or_else(|| InvalidArgs(format!("unknown knob \"{}\"", name)))?;
+    let value = serde_json::from_str(&value).map_err(|e| InvalidArgs(format!("{}", e)))?;
+    knob_file::set_knob_value(&mut knob, &value).map_err(InvalidArgs)?;
+    self.send(FrontendRequest::SetKnob(knob))
+  }
+
+  // rereads the script, keeping the current mode and knob values
+  fn reload(&self) -> zbus::fdo::Result<()> {
+    self.send(FrontendRequest::Reload)
+  }
+
+  #[zbus(signal)]
+  async fn mode_changed(ctxt: &SignalContext<'_>, layers: Vec<String>) -> zbus::Result<()>;
+}
+
+// keeps the bus name for as long as the mapper runs, across config reloads
+pub struct ControlFrontend {
+  _connection:  zbus::Connection,
+  link:         Link<ControlState>,
+  mode_changes: UnboundedSender<Vec<String>>
+}
+
+// registers stwgs.Mapper<pid> on the session bus
+pub fn start() -> Result<ControlFrontend, String> {
+  let (state, sender, link) = frontend::link(ControlState::default());
+  let mapper_dbus_object = SCMapper { state, requests: Mutex::new(sender) };
+
+  let connection = futures::executor::block_on(
+    ConnectionBuilder::session().map_err(|e| format!("{}", e))?
+      .name(format!("stwgs.Mapper{}", std::process::id())).map_err(|e| format!("{}", e))?
+      .serve_at(OBJECT_PATH, mapper_dbus_object).map_err(|e| format!("{}", e))?
+      .build())
+    .map_err(|e| format!("{}", e))?;
+
+  // signals are emitted by zbus' own executor, in order, without blocking the mapper
+  let (mode_changes, mut mode_receiver) = unbounded();
+  let signal_connection = connection.clone();
+  connection.executor().spawn(async move {
+    let ctxt = SignalContext::new(&signal_connection, OBJECT_PATH).unwrap();
+    while let Some(layers) = mode_receiver.next().await {
+      if let Err(e) = SCMapper::mode_changed(&ctxt, layers).await {
+        log!("Can't emit ModeChanged: {}", e);
+      }
+    }
+  }, "mode changes").detach();
+
+  Ok(ControlFrontend { _connection: connection, link, mode_changes })
+}
+
+impl ControlFrontend {
+
+  // State() reports the script and preset the running config was loaded from
+  pub fn set_config(&mut self, script: Option<String>, preset: String, layers: Vec<String>) {
+    let mut state = self.link.state.lock().unwrap();
+    state.script          = script;
+    state.preset          = preset;
+    state.snapshot.layers = layers;
+  }
+}
+
+impl Frontend for ControlFrontend {
+
+  fn update(&mut self, state: &ControllerState, mode: &Mode, knobs: &[Knob], knobs_edited: &[Knob]) {
+    if self.link.update(state, mode, knobs, knobs_edited) {
+      let _ = self.mode_changes.unbounded_send(mode.layers().to_vec());
+    }
+  }
+
+  fn requests(&mut self) -> Vec<FrontendRequest> {
+    self.link.requests()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  fn sc_mapper() -> (SCMapper, mpsc::Receiver<FrontendRequest>) {
+    let (sender, receiver) = mpsc::channel();
+    let snapshot = Snapshot {
+      layers: vec!["foo".to_string(), "bar".to_string()],
+      knobs:  vec![Knob::Flag { name: "invert y".to_string(), value: false }],
+      ..Snapshot::default()
+    };
+    let state = ControlState { snapshot, ..ControlState::default() };
+    (SCMapper { state: std::sync::Arc::new(Mutex::new(state)), requests: Mutex::new(sender) }, receiver)
+  }
+
+  #[test]
+  fn set_mode() {
+    let (mapper, requests) = sc_mapper();
+
+    assert!(mapper.set_mode(vec!["foo".to_string(), "bar".to_string()]).is_ok());
+    assert!(matches!(requests.try_recv(), Ok(FrontendRequest::SetMode(layers)) if layers == ["foo", "bar"]));
+
+    assert!(matches!(mapper.set_mode(vec!["baz".to_string()]), Err(InvalidArgs(message)) if message == "unknown layer \"baz\""));
+    assert!(matches!(mapper.set_mode(vec![]), Err(InvalidArgs(_))));
+    assert!(requests.try_recv().is_err());
+  }
+
+  #[test]
+  fn set_knob() {
+    let (mapper, requests) = sc_mapper();
+
+    // a single request, other pending edits aren't committed along with it
+    assert!(mapper.set_knob("invert y".to_string(), "true".to_string()).is_ok());
+    assert!(matches!(requests.try_recv(), Ok(FrontendRequest::SetKnob(Knob::Flag { value: true, .. }))));
+    assert!(requests.try_recv().is_err());
+
+    assert!(matches!(mapper.set_knob("foo".to_string(), "true".to_string()), Err(InvalidArgs(message)) if message == "unknown knob \"foo\""));
+    assert!(matches!(mapper.set_knob("invert y".to_string(), "yes".to_string()), Err(InvalidArgs(_))));
+    assert!(matches!(mapper.set_knob("invert y".to_string(), "1".to_string()), Err(InvalidArgs(_))));
+    assert!(requests.try_recv().is_err());
+  }
+
+  #[test]
+  fn reload() {
+    let (mapper, requests) = sc_mapper();
+    assert!(mapper.reload().is_ok());
+    assert!(matches!(requests.try_recv(), Ok(FrontendRequest::Reload)));
+  }
+}
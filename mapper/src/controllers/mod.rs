@@ -3,7 +3,7 @@ mod sdlgc;
 mod steam;
 
 use std::sync::mpsc::{Receiver, Sender};
-use strum_macros::EnumIter;
+use strum_macros::{EnumCount, EnumIter};
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, EnumIter)]
 pub enum Button {
@@ -232,6 +232,62 @@ impl ControllerState {
     }
   }
 
+  pub fn set_button(&mut self, button: Button, value: bool) {
+    match button {
+      Button::LStick    => self.buttons.lstick = value,
+      Button::RStick    => self.buttons.rstick = value,
+      Button::RPadTouch => self.buttons.rpad_touch = value,
+      Button::LPadTouch => self.buttons.lpad_touch = value,
+      Button::RPad      => self.buttons.rpad_press = value,
+      Button::LPad      => self.buttons.lpad_press = value,
+      Button::RGrip     => self.buttons.rgrip = value,
+      Button::LGrip     => self.buttons.lgrip = value,
+      Button::Start     => self.buttons.start = value,
+      Button::Steam     => self.buttons.steam = value,
+      Button::Back      => self.buttons.back = value,
+      Button::DPadDown  => self.buttons.dpad_down = value,
+      Button::DPadLeft  => self.buttons.dpad_left = value,
+      Button::DPadRight => self.buttons.dpad_right = value,
+      Button::DPadUp    => self.buttons.dpad_up = value,
+      Button::A         => self.buttons.a = value,
+      Button::X         => self.buttons.x = value,
+      Button::B         => self.buttons.b = value,
+      Button::Y         => self.buttons.y = value,
+      Button::LBump     => self.buttons.lbump = value,
+      Button::RBump     => self.buttons.rbump = value,
+      Button::LTrig     => self.buttons.ltrig = value,
+      Button::RTrig     => self.buttons.rtrig = value
+    }
+  }
+
+  pub fn set_axis(&mut self, axis: Axis, value: f32) {
+    match axis {
+      Axis::LTrig    => self.axes.ltrig = value,
+      Axis::RTrig    => self.axes.rtrig = value,
+      Axis::LJoyX    => self.axes.ljoy_x = value,
+      Axis::LJoyY    => self.axes.ljoy_y = value,
+      Axis::RJoyX    => self.axes.rjoy_x = value,
+      Axis::RJoyY    => self.axes.rjoy_y = value,
+      Axis::LPadX    => self.axes.lpad_x = value,
+      Axis::LPadY    => self.axes.lpad_y = value,
+      Axis::RPadX    => self.axes.rpad_x = value,
+      Axis::RPadY    => self.axes.rpad_y = value,
+      Axis::AX       => self.axes.ax = value,
+      Axis::AY       => self.axes.ay = value,
+      Axis::AZ       => self.axes.az = value,
+      Axis::Pitch    => self.axes.pitch = value,
+      Axis::Roll     => self.axes.roll = value,
+      Axis::Yaw      => self.axes.yaw = value,
+      Axis::Q0       => self.axes.q0 = value as i16,
+      Axis::Q1       => self.axes.q1 = value as i16,
+      Axis::Q2       => self.axes.q2 = value as i16,
+      Axis::Q3       => self.axes.q3 = value as i16,
+      Axis::AbsPitch => self.axes.a_pitch = value,
+      Axis::AbsRoll  => self.axes.a_roll = value,
+      Axis::AbsYaw   => self.axes.a_yaw = value
+    }
+  }
+
   pub fn random<R: ::rand::Rng>(rng: &mut R) -> Self {
     Self {
       buttons: ControllerButtons {
@@ -288,7 +344,7 @@ impl ControllerState {
   }
 }
 
-#[derive(Copy, Clone, Debug, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq, EnumCount, EnumIter)]
 pub enum HapticFeedbackTarget {
   LeftSide,
   RightSide,
@@ -299,12 +355,31 @@ pub enum HapticFeedbackTarget {
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum HapticFeedbackEffect {
   SlightBump,
-  ModerateBump
+  ModerateBump,
+  Pulse { amplitude: u16, period: u16, count: u16 }
+}
+
+impl HapticFeedbackEffect {
+
+  // amplitude, period and count in the Steam Controller's units
+  pub fn pulse_params(self) -> (u16, u16, u16) {
+    match self {
+      HapticFeedbackEffect::SlightBump   => (100, 2, 25),
+      HapticFeedbackEffect::ModerateBump => (100, 2, 50),
+      HapticFeedbackEffect::Pulse { amplitude, period, count } => (amplitude, period, count)
+    }
+  }
+
+  pub fn scaled(self, factor: f32) -> Self {
+    let (amplitude, period, count) = self.pulse_params();
+    HapticFeedbackEffect::Pulse { amplitude: (amplitude as f32 * factor.max(0.0)) as u16, period, count }
+  }
 }
 
 #[derive(Copy, Clone)]
 pub enum ControllerCommand {
-  HapticFeedback(HapticFeedbackTarget, HapticFeedbackEffect)
+  HapticFeedback(HapticFeedbackTarget, HapticFeedbackEffect),
+  Rumble(HapticFeedbackTarget, f32) // intensity in the 0..=1 range, 0 stops rumbling
 }
 
 pub trait Controller {
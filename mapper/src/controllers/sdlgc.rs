@@ -1,4 +1,5 @@
 use std::sync::{Arc, Mutex};
+use strum::{EnumCount, IntoEnumIterator};
 
 use crate::controllers::*;
 
@@ -119,6 +120,11 @@ impl Controller for SDL2Controller {
     let mut events = controller.subsystem().sdl().event_pump()?;
     let mut state  = ControllerState::empty();
 
+    let mut rumble             = [0_u16; HapticFeedbackTarget::COUNT];
+    let mut pulses             = [(0_u16, std::time::Instant::now()); HapticFeedbackTarget::COUNT];
+    let mut rumble_changed     = false;
+    let mut last_rumble_update = std::time::Instant::now();
+
     let mut prev_gyro_data = [0.0; 3];
     let mut gyro_bias      = [0.0; 3];
     let mut gyro_average   = Average::<500>::new();
@@ -285,32 +291,71 @@ impl Controller for SDL2Controller {
 
       sender.send(state).map_err(|e| format!("{}", e))?;
 
+      // rumble intensities are sent every frame they change, so the queue is drained
       if let Some(receiver) = &receiver {
-        if let Ok(command) = receiver.try_recv() {
+        while let Ok(command) = receiver.try_recv() {
           match command {
             ControllerCommand::HapticFeedback(target, effect) => {
 
               let (amplitude, duration) = match effect {
                 HapticFeedbackEffect::SlightBump   => (3275, 50),
-                HapticFeedbackEffect::ModerateBump => (6550, 50)
+                HapticFeedbackEffect::ModerateBump => (6550, 50),
+                // no pulse trains in SDL, so we approximate them with a single rumble of the same total length
+                HapticFeedbackEffect::Pulse { amplitude, period, count } =>
+                  (amplitude.saturating_mul(PULSE_AMPLITUDE_SCALE), (period as u64 * count as u64).max(1))
               };
 
-              let _ = match target {
-                HapticFeedbackTarget::LeftSide     => controller.set_rumble(amplitude, 0, duration),
-                HapticFeedbackTarget::RightSide    => controller.set_rumble(0, amplitude, duration),
-                HapticFeedbackTarget::LeftTrigger  => controller.set_rumble_triggers(amplitude, 0, duration),
-                HapticFeedbackTarget::RightTrigger => controller.set_rumble_triggers(0, amplitude, duration)
-              };
+              pulses[target as usize] = (amplitude, std::time::Instant::now() + std::time::Duration::from_millis(duration));
+              rumble_changed = true;
+            },
+            ControllerCommand::Rumble(target, intensity) => {
+              rumble[target as usize] = (intensity.clamp(0.0, 1.0) * u16::MAX as f32) as u16;
+              rumble_changed = true;
             }
           }
         }
       }
 
+      // SDL sets both motors of a pair at once and stops them after the given duration,
+      // so we combine pulses with continuous rumble and keep refreshing the latter
+      if rumble_changed || (rumble.iter().any(|r| *r > 0) && last_rumble_update.elapsed() >= RUMBLE_REFRESH_INTERVAL) {
+
+        let now = std::time::Instant::now();
+
+        let mut duration = if rumble.iter().any(|r| *r > 0) { RUMBLE_REFRESH_INTERVAL * 2 } else { std::time::Duration::ZERO };
+        let mut effective = rumble;
+
+        for target in HapticFeedbackTarget::iter() {
+          let (amplitude, until) = pulses[target as usize];
+          if until > now {
+            effective[target as usize] = effective[target as usize].max(amplitude);
+            duration = duration.max(until - now);
+          }
+        }
+
+        let duration = duration.as_millis() as u32;
+        let _ = controller.set_rumble(
+          effective[HapticFeedbackTarget::LeftSide as usize],
+          effective[HapticFeedbackTarget::RightSide as usize],
+          duration);
+        let _ = controller.set_rumble_triggers(
+          effective[HapticFeedbackTarget::LeftTrigger as usize],
+          effective[HapticFeedbackTarget::RightTrigger as usize],
+          duration);
+        last_rumble_update = std::time::Instant::now();
+        rumble_changed     = false;
+      }
+
       std::thread::sleep(std::time::Duration::from_millis(8)); // what interval should that be?
     } // loop
   }
 }
 
+const RUMBLE_REFRESH_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
+
+// pulse amplitudes are in the Steam Controller's units, where a slight bump is 100 (3275 here)
+const PULSE_AMPLITUDE_SCALE: u16 = 32;
+
 fn is_gyro_steady(v1: [f32; 3], v2: [f32; 3]) -> bool {
   const THRESHOLD: f32 = 0.0174533; // one 1 deg in rads
   (v1[0] - v2[0]).abs() <= THRESHOLD && (v1[1] - v2[1]).abs() <= THRESHOLD && (v1[2] - v2[2]).abs() <= THRESHOLD
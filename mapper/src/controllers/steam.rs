@@ -166,6 +166,43 @@ fn prepare_controller<T: UsbContext>(handle: &rusb::DeviceHandle<T>, index: u16)
   Ok(())
 }
 
+// rumble is a slight bump every interval, with the amplitude scaled by the intensity
+const RUMBLE_PULSE_INTERVAL: Duration = Duration::from_millis(25);
+const RUMBLE_MAX_AMPLITUDE:  f32      = 1000.0;
+const RUMBLE_PULSE_PERIOD:   u16      = 2;
+const RUMBLE_PULSE_COUNT:    u16      = 25;
+
+fn touchpad_for_target(target: HapticFeedbackTarget) -> Option<u8> {
+  match target {
+    HapticFeedbackTarget::LeftSide  => Some(1),
+    HapticFeedbackTarget::RightSide => Some(0),
+    _ => None
+  }
+}
+
+fn send_haptic_pulse<T: UsbContext>(handle: &rusb::DeviceHandle<T>, touchpad: u8, amplitude: u16, period: u16, count: u16) -> Result<(), String> {
+  let amplitude = amplitude.to_le_bytes();
+  let period    = period.to_le_bytes();
+  let count     = count.to_le_bytes();
+
+  let buffer = [
+    0x8f,
+    0x07,
+    touchpad,
+    amplitude[0],
+    amplitude[1],
+    period[0],
+    period[1],
+    count[0],
+    count[1]
+  ];
+
+  let request_type = rusb::request_type(Direction::Out, RequestType::Class, Recipient::Interface);
+  let transferred  = handle.write_control(request_type, 0x09, 0x0300, 2, &buffer, Duration::new(0, 0)).map_err(libusb_err_to_string)?;
+  assert_eq!(transferred, buffer.len());
+  Ok(())
+}
+
 impl Controller for SteamController {
 
   fn name(&self) -> String {
@@ -200,6 +237,9 @@ impl Controller for SteamController {
     let mut buffer = [0_u8; 64];
     let mut state  = ControllerState::empty();
 
+    let mut rumble            = [0.0_f32; 2];
+    let mut last_rumble_pulse = std::time::Instant::now();
+
     let accel_scale_factor = 1f32 / 32768.0 * 2.0 * 9.80665;
     let  gyro_scale_factor = 1f32 / 32768.0 * (2000.0 * std::f32::consts::PI / 180.0);
     let   pad_scale_factor = 1f32 / 32768.0;
@@ -319,45 +359,34 @@ impl Controller for SteamController {
         // ?
       }
 
+      // rumble intensities are sent every frame they change, so the queue is drained
       if let Some(receiver) = &receiver {
-        if let Ok(command) = receiver.try_recv() {
+        while let Ok(command) = receiver.try_recv() {
           match command {
             ControllerCommand::HapticFeedback(target, effect) => {
-
-              let touchpad = match target {
-                HapticFeedbackTarget::LeftSide  => Some(1),
-                HapticFeedbackTarget::RightSide => Some(0),
-                _ => None
-              };
-
-              if let Some(touchpad) = touchpad {
-
-                let (amplitude, period, count) = match effect {
-                  HapticFeedbackEffect::SlightBump   => (100, 2, 25),
-                  HapticFeedbackEffect::ModerateBump => (100, 2, 50),
-                };
-
-                //TODO: is this really amplitude, period, count?
-                let buffer = [
-                  0x8f,
-                  0x07,
-                  touchpad,
-                  (amplitude % 0xff) as u8,
-                  (amplitude / 0xff) as u8,
-                  (period    % 0xff) as u8,
-                  (period    / 0xff) as u8,
-                  (count     % 0xff) as u8,
-                  (count     / 0xff) as u8
-                ];
-
-                let request_type = rusb::request_type(Direction::Out, RequestType::Class, Recipient::Interface);
-                let transferred  = handle.write_control(request_type, 0x09, 0x0300, 2, &buffer, Duration::new(0, 0)).map_err(libusb_err_to_string)?;
-                assert_eq!(transferred, buffer.len());
+              if let Some(touchpad) = touchpad_for_target(target) {
+                let (amplitude, period, count) = effect.pulse_params();
+                send_haptic_pulse(&handle, touchpad, amplitude, period, count)?;
+              }
+            },
+            ControllerCommand::Rumble(target, intensity) => {
+              if let Some(touchpad) = touchpad_for_target(target) {
+                rumble[touchpad as usize] = intensity.clamp(0.0, 1.0);
               }
             }
           }
         }
       }
+
+      // the Steam Controller has no rumble motors, so we emulate rumble with a stream of haptic pulses
+      if last_rumble_pulse.elapsed() >= RUMBLE_PULSE_INTERVAL {
+        for (touchpad, intensity) in rumble.iter().enumerate() {
+          if *intensity > 0.0 {
+            send_haptic_pulse(&handle, touchpad as u8, (intensity * RUMBLE_MAX_AMPLITUDE) as u16, RUMBLE_PULSE_PERIOD, RUMBLE_PULSE_COUNT)?;
+          }
+        }
+        last_rumble_pulse = std::time::Instant::now();
+      }
     } // loop
   }
 }
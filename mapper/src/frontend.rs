@@ -0,0 +1,71 @@
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use overlay_ipc::Knob;
+
+use crate::controllers::ControllerState;
+use crate::mapper::{FrontendRequest, Mode};
+
+// frontends run on other threads and only see copies, made at most this often
+pub const UPDATE_INTERVAL: Duration = Duration::from_millis(30);
+
+// the mapper state as last copied for a frontend
+pub struct Snapshot {
+  pub layers:       Vec<String>, // reported by the config, not by the mapper
+  pub mode:         Vec<String>,
+  pub knobs:        Vec<Knob>,
+  pub knobs_edited: Vec<Knob>,
+  pub controller:   ControllerState
+}
+
+impl Default for Snapshot {
+
+  fn default() -> Self {
+    Snapshot { layers: vec![], mode: vec![], knobs: vec![], knobs_edited: vec![], controller: ControllerState::empty() }
+  }
+}
+
+pub type Shared<S> = Arc<Mutex<S>>;
+
+// the mapper end of a frontend: copies snapshots into the shared state, collects requests
+pub struct Link<S> {
+  pub state:   Shared<S>,
+  requests:    mpsc::Receiver<FrontendRequest>,
+  last_update: Option<Instant>
+}
+
+// the frontend end keeps the shared state and the sender
+pub fn link<S>(state: S) -> (Shared<S>, mpsc::Sender<FrontendRequest>, Link<S>) {
+  let state = Arc::new(Mutex::new(state));
+  let (sender, receiver) = mpsc::channel();
+  (state.clone(), sender, Link { state, requests: receiver, last_update: None })
+}
+
+impl<S: AsMut<Snapshot>> Link<S> {
+
+  pub fn set_layers(&self, layers: Vec<String>) {
+    self.state.lock().unwrap().as_mut().layers = layers;
+  }
+
+  // mode changes aren't throttled so that short-lived modes aren't missed, returns whether the mode changed
+  pub fn update(&mut self, controller: &ControllerState, mode: &Mode, knobs: &[Knob], knobs_edited: &[Knob]) -> bool {
+    let mut state = self.state.lock().unwrap();
+    let snapshot = state.as_mut();
+
+    let mode_changed = snapshot.mode != mode.layers();
+    if !mode_changed && self.last_update.map(|t| t.elapsed() < UPDATE_INTERVAL).unwrap_or(false) {
+      return false;
+    }
+    self.last_update = Some(Instant::now());
+
+    snapshot.mode         = mode.layers().to_vec();
+    snapshot.knobs        = knobs.to_vec();
+    snapshot.knobs_edited = knobs_edited.to_vec();
+    snapshot.controller   = *controller;
+    mode_changed
+  }
+
+  pub fn requests(&mut self) -> Vec<FrontendRequest> {
+    self.requests.try_iter().collect()
+  }
+}
@@ -0,0 +1,394 @@
+// Checks config invariants against random input, with probe conditions switched on and off at random:
+//  - keys and mouse buttons pressed along the way are released once the input returns to neutral,
+//  - nothing is output while all user layers are disengaged (e.g. while the knobs menu is open),
+//  - mode switches only end in modes named by the config's bindings.
+// Runs are recordings (see preview.rs), failing ones are shrunk and written into the current dir,
+// `test <script> --replay <file>` checks them again, `dot <script> --input <file>` shows what stages were up to.
+
+use std::cell::RefCell;
+use std::time::Duration;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use strum::IntoEnumIterator;
+
+use crate::controllers::{Axis, Button};
+use crate::mapper::LayerMask;
+use crate::preview::{InputChange, InputStep};
+
+// long enough for hold and double tap timers to run out
+const SETTLE_TIME: Duration = Duration::from_secs(3);
+
+const RUN_LENGTH:      usize = 200; // input steps
+const MAX_SHRINK_RUNS: usize = 2000;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Violation {
+  StuckOutput(Vec<String>),
+  OutputWhileDisengaged(Duration, String),
+  UndeclaredMode(Duration, String),
+  BadRecording(String)
+}
+
+impl std::fmt::Display for Violation {
+
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Violation::StuckOutput(held)                 => write!(f, "still held after returning to neutral input: {}", held.join(", ")),
+      Violation::OutputWhileDisengaged(time, out)  => write!(f, "output at {} ms with all layers disengaged: {}", time.as_millis(), out),
+      Violation::UndeclaredMode(time, mode)        => write!(f, "switched to undeclared mode at {} ms: {}", time.as_millis(), mode),
+      Violation::BadRecording(e)                   => write!(f, "{}", e)
+    }
+  }
+}
+
+// recordings that don't parse or don't fit the config
+impl From<String> for Violation {
+  fn from(e: String) -> Self {
+    Violation::BadRecording(e)
+  }
+}
+
+impl Violation {
+
+  fn same_kind(&self, other: &Violation) -> bool {
+    std::mem::discriminant(self) == std::mem::discriminant(other)
+  }
+}
+
+// held keys and buttons, presses and moves since the last frame (releases aren't output as such)
+#[derive(Default)]
+struct OutputLog {
+  held:   Vec<String>,
+  events: Vec<String>
+}
+
+impl OutputLog {
+
+  // see RecordingOutput
+  fn record(&mut self, event: String) {
+    if let Some(released) = event.strip_suffix(" up") {
+      self.held.retain(|held| held != released);
+    } else {
+      if let Some(pressed) = event.strip_suffix(" down") {
+        self.held.push(pressed.to_string());
+      }
+      self.events.push(event);
+    }
+  }
+}
+
+fn describe_mode(layers: &[String], mask: LayerMask) -> String {
+  format!("{{{}}} (mask {})", mask.layer_names(layers).join(" | "), mask)
+}
+
+// replays the steps on a fresh mapper, then keeps the input neutral until everything settles,
+// returns keys and mouse buttons still held at the end
+fn run(load: &dyn Fn() -> crate::config::Config, steps: &[InputStep]) -> Result<Vec<String>, Violation> {
+
+  use crate::clock::VirtualClock;
+  use crate::controllers::ControllerState;
+  use crate::mapper::Mapper;
+  use crate::output::{DummyOutput, RecordingOutput};
+
+  // as loaded with an overlay, so the knobs menu can disengage user layers
+  let mut config = load();
+  config.pipelines.append(&mut config.system);
+
+  let layers = config.layers.clone();
+  let mut declared = config.modes.clone();
+
+  let log = RefCell::new(OutputLog::default());
+  let mut output = RecordingOutput::new(Box::new(DummyOutput), |event| log.borrow_mut().record(event));
+  let clock = VirtualClock::default();
+  let mut mapper = Mapper::new(None, None, None, config, &mut output, &clock, 0);
+  declared.push(mapper.curr_layer_mask());
+
+  crate::stages::clear_forced_probes();
+
+  // the mode the frame started in
+  let mut mask = mapper.curr_layer_mask();
+  let mut frame = |mapper: &Mapper, time: Duration| {
+    let engaged = mask & LayerMask::ALL_USER_BITS != LayerMask::EMPTY;
+    mask = mapper.curr_layer_mask();
+
+    let events = std::mem::take(&mut log.borrow_mut().events);
+    if !engaged && !events.is_empty() {
+      return Err(Violation::OutputWhileDisengaged(time, events.join(", ")));
+    }
+
+    if !declared.contains(&mask) {
+      return Err(Violation::UndeclaredMode(time, describe_mode(&layers, mask)));
+    }
+    Ok(())
+  };
+
+  let mut time = crate::preview::replay_input(&mut mapper, steps, &mut frame, |_, _| Ok(()))?;
+
+  // probes go back to whatever their values say
+  crate::stages::clear_forced_probes();
+  let settled = time + SETTLE_TIME;
+  crate::preview::step_until(&mut mapper, &ControllerState::empty(), &mut time, settled, &mut frame)?;
+
+  let held = log.borrow().held.clone();
+  Ok(held)
+}
+
+// some output might be held at neutral input from the start, e.g. bound to invert(input(A))
+fn check_run(load: &dyn Fn() -> crate::config::Config, neutral: &[String], steps: &[InputStep]) -> Result<(), Violation> {
+  let stuck = run(load, steps)?.into_iter().filter(|held| !neutral.contains(held)).collect::<Vec<_>>();
+  if stuck.is_empty() {
+    Ok(())
+  } else {
+    Err(Violation::StuckOutput(stuck))
+  }
+}
+
+fn random_change(rng: &mut StdRng, probe_count: usize) -> InputChange {
+  // raw quaternions aren't used by configs
+  let axes = Axis::iter().filter(|axis| !matches!(axis, Axis::Q0 | Axis::Q1 | Axis::Q2 | Axis::Q3)).collect::<Vec<_>>();
+  let buttons = Button::iter().collect::<Vec<_>>();
+
+  match rng.gen_range(0..if probe_count > 0 { 3 } else { 2 }) {
+    0 => InputChange::Button(buttons[rng.gen_range(0..buttons.len())], rng.gen()),
+    1 => {
+      let axis  = axes[rng.gen_range(0..axes.len())];
+      let value = match axis {
+        Axis::LTrig | Axis::RTrig                     => rng.gen_range(0.0..=1.0),
+        Axis::AbsPitch | Axis::AbsRoll | Axis::AbsYaw => rng.gen_range(-std::f32::consts::PI..=std::f32::consts::PI),
+        _                                             => rng.gen_range(-1.0..=1.0)
+      };
+      // short enough to read in a recording
+      InputChange::Axis(axis, (value * 100.0).round() / 100.0)
+    },
+    _ => InputChange::Probe(rng.gen_range(0..probe_count), rng.gen())
+  }
+}
+
+fn random_run(seed: u64, probe_count: usize) -> Vec<InputStep> {
+  let mut rng  = StdRng::seed_from_u64(seed);
+  let mut time = Duration::ZERO;
+
+  (0..RUN_LENGTH).map(|_| {
+    time += Duration::from_millis(rng.gen_range(0..=400));
+    let changes = (0..rng.gen_range(1..=3)).map(|_| random_change(&mut rng, probe_count)).collect();
+    InputStep::At(time, changes)
+  }).collect()
+}
+
+// drops chunks of items, then single items, as long as fails keeps returning true
+fn shrink_list<T: Clone>(mut items: Vec<T>, fails: &mut impl FnMut(&[T]) -> bool) -> Vec<T> {
+  let mut chunk = (items.len() / 2).max(1);
+  loop {
+    let mut shrunk = false;
+    let mut i = 0;
+    while i < items.len() {
+      let candidate = items[..i].iter().chain(items.iter().skip(i + chunk)).cloned().collect::<Vec<_>>();
+      if fails(&candidate) {
+        items  = candidate;
+        shrunk = true;
+      } else {
+        i += chunk;
+      }
+    }
+
+    if chunk > 1 {
+      chunk /= 2;
+    } else if !shrunk {
+      return items;
+    }
+  }
+}
+
+// keeps the violation kind, so that shrinking doesn't wander off into a different failure
+fn shrink(load: &dyn Fn() -> crate::config::Config, neutral: &[String], steps: Vec<InputStep>, violation: Violation) -> (Vec<InputStep>, Violation) {
+  let mut runs      = 0;
+  let mut violation = violation;
+
+  let mut fails = |steps: &[InputStep]| {
+    if runs >= MAX_SHRINK_RUNS {
+      return false;
+    }
+    runs += 1;
+    match check_run(load, neutral, steps) {
+      Err(v) if v.same_kind(&violation) => {
+        violation = v;
+        true
+      },
+      _ => false
+    }
+  };
+
+  let mut steps = shrink_list(steps, &mut fails);
+
+  // starting right away is easier to read
+  if let Some(InputStep::At(start, _)) = steps.first().cloned() {
+    let shifted = steps.iter().map(|step| match step {
+      InputStep::At(time, changes) => InputStep::At(*time - start, changes.clone()),
+      snapshot => snapshot.clone()
+    }).collect::<Vec<_>>();
+    if fails(&shifted) {
+      steps = shifted;
+    }
+  }
+
+  for i in 0..steps.len() {
+    let InputStep::At(time, changes) = steps[i].clone() else {
+      continue;
+    };
+    let changes = shrink_list(changes, &mut |changes: &[InputChange]| {
+      let mut candidate = steps.clone();
+      candidate[i] = InputStep::At(time, changes.to_vec());
+      fails(&candidate)
+    });
+    steps[i] = InputStep::At(time, changes);
+  }
+
+  (steps, violation)
+}
+
+// runs are numbered from the seed up, failing ones are written to <name>-fuzz-<seed>.input, returns the number of failures
+pub fn check(name: &str, load: &dyn Fn() -> crate::config::Config, seed: u64, runs: u64) -> usize {
+
+  use crate::clock::VirtualClock;
+  use crate::mapper::Mapper;
+  use crate::output::DummyOutput;
+
+  let probe_count = {
+    let clock = VirtualClock::default();
+    Mapper::new(None, None, None, load(), &mut DummyOutput {}, &clock, 0).probe_ids().len()
+  };
+
+  let neutral = match run(load, &[]) {
+    Ok(held) => held,
+    Err(violation) => {
+      println!("neutral input: {}", violation);
+      return 1;
+    }
+  };
+
+  let mut failures = 0;
+  for seed in seed..seed + runs {
+    let steps = random_run(seed, probe_count);
+    let Err(violation) = check_run(load, &neutral, &steps) else {
+      continue;
+    };
+    failures += 1;
+
+    let (steps, violation) = shrink(load, &neutral, steps, violation);
+    let path = format!("{}-fuzz-{}.input", name, seed);
+    let recording = format!("# seed {}: {}\n{}", seed, violation, crate::preview::format_recording(&steps));
+
+    match std::fs::write(&path, recording) {
+      Ok(()) => println!("seed {}: {}, written to {}", seed, violation, path),
+      Err(e) => println!("seed {}: {}, can't write {}: {}", seed, violation, path, e)
+    }
+  }
+
+  failures
+}
+
+pub fn replay(load: &dyn Fn() -> crate::config::Config, steps: &[InputStep]) -> Result<(), Violation> {
+  let neutral = run(load, &[])?;
+  check_run(load, &neutral, steps)
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  #[test]
+  fn shrink_list_test() {
+    let items = (0..100).collect::<Vec<_>>();
+    assert_eq!(shrink_list(items, &mut |items: &[i32]| items.contains(&17) && items.contains(&42)), vec![17, 42]);
+    assert_eq!(shrink_list(vec![1, 2, 3], &mut |_: &[i32]| true), Vec::<i32>::new());
+  }
+
+  #[test]
+  fn random_runs_are_reproducible() {
+    assert_eq!(random_run(7, 2), random_run(7, 2));
+    assert_ne!(random_run(7, 2), random_run(8, 2));
+    assert!(random_run(7, 0).iter().all(|step| match step {
+      InputStep::At(_, changes) => changes.iter().all(|change| !matches!(change, InputChange::Probe(..))),
+      InputStep::Snapshot(_)    => false
+    }));
+  }
+
+  fn load(script: &str) -> crate::config::Config {
+    crate::config::load_config(script, None, true).unwrap()
+  }
+
+  fn recording(recording: &str) -> Vec<InputStep> {
+    crate::preview::parse_recording(recording).unwrap()
+  }
+
+  // starts in base, C stays held once X switches to foo
+  const STUCK: &str = "layer base { input(B).bind(Kb.B) }, layer foo { input(Y).bind(Kb.Y) }, input(X).set_mode(foo), mode_is(foo).bind(Kb.C)";
+
+  #[test]
+  fn stuck_key() {
+    let load = || load(STUCK);
+    let neutral = run(&load, &[]).unwrap();
+    assert!(neutral.is_empty());
+
+    let steps = recording("100 A=true\n200 B=true LJoyX=0.5\n300 X=true\n400 A=false Y=true\n500 X=false");
+    let violation = check_run(&load, &neutral, &steps).unwrap_err();
+    assert_eq!(violation, Violation::StuckOutput(vec!["key C".to_string()]));
+
+    let (steps, violation) = shrink(&load, &neutral, steps, violation);
+    assert_eq!(crate::preview::format_recording(&steps), "0     X=true\n");
+    assert_eq!(violation.to_string(), "still held after returning to neutral input: key C");
+  }
+
+  #[test]
+  fn undeclared_mode() {
+    let load = || {
+      let mut config = load("layer base { input(B).bind(Kb.B) }, layer foo { input(Y).bind(Kb.Y) }, input(X).set_mode(foo)");
+      config.modes.retain(|mode| *mode != LayerMask::user_layer(1).unwrap());
+      config
+    };
+    let neutral = run(&load, &[]).unwrap();
+
+    let steps = recording("100 A=true\n200 B=true\n250 B=false X=true\n400 A=false Y=true");
+    let violation = check_run(&load, &neutral, &steps).unwrap_err();
+    assert_eq!(violation, Violation::UndeclaredMode(Duration::from_millis(250), "{foo} (mask 2)".to_string()));
+
+    let (steps, violation) = shrink(&load, &neutral, steps, violation);
+    assert_eq!(crate::preview::format_recording(&steps), "0     X=true\n");
+    assert_eq!(violation, Violation::UndeclaredMode(Duration::ZERO, "{foo} (mask 2)".to_string()));
+  }
+
+  #[test]
+  fn output_while_disengaged() {
+    let load = || load("input(A).bind(Kb.A), system { input(Steam).flip_mode(knobs_menu), layer knobs_menu { input(X).bind(Kb.X) } }");
+    let neutral = run(&load, &[]).unwrap();
+
+    // the user layer's A is disengaged along with it
+    assert_eq!(check_run(&load, &neutral, &recording("0 Steam=true\n100 A=true\n200 A=false")), Ok(()));
+
+    let steps = recording("0 A=true\n100 Steam=true A=false\n200 X=true");
+    let violation = check_run(&load, &neutral, &steps).unwrap_err();
+    assert_eq!(violation, Violation::OutputWhileDisengaged(Duration::from_millis(200), "key X down".to_string()));
+  }
+
+  // whatever the seed finds is written out shrunk and fails again when replayed
+  #[test]
+  fn check_writes_recordings() {
+    let dir = std::env::temp_dir().join(format!("stwgs-fuzz-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let name = dir.join("stuck");
+
+    let load = || load(STUCK);
+    assert_eq!(check(name.to_str().unwrap(), &load, 0, 1), 1);
+
+    let written = std::fs::read_to_string(dir.join("stuck-fuzz-0.input")).unwrap();
+    assert!(written.starts_with("# seed 0: still held after returning to neutral input: key C\n"));
+
+    let steps = recording(&written);
+    assert!(steps.len() <= 5);
+    assert_eq!(replay(&load, &steps), Err(Violation::StuckOutput(vec!["key C".to_string()])));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+}
@@ -0,0 +1,204 @@
+// The config's stage graph for `dot`, either as a Graphviz digraph or as JSON:
+//   { "layers": [...], "mode": [...], "stages": [{ "id", "name", "opts", "inputs", "mask", "layers", "value" }, ...] }
+// "mode" and "value" are only there for snapshots taken while replaying recorded input.
+
+use std::collections::HashMap;
+
+use serde_json::{json, Value};
+
+use crate::mapper::LayerMask;
+use crate::stages::{Pipeline, PipelineStageDescription, StageId};
+
+const MAX_LABEL_LENGTH: usize = 25;
+
+pub struct Graph {
+  layers: Vec<String>,
+  stages: Vec<PipelineStageDescription>, // sorted by id
+  masks:  HashMap<StageId, LayerMask>    // every layer a stage is active in
+}
+
+impl Graph {
+
+  pub fn new(pipelines: &[(LayerMask, Box<dyn Pipeline<()>>)], layers: &[String]) -> Self {
+    let mut meta = HashMap::new();
+
+    for (_, pipeline) in pipelines {
+      pipeline.inspect(&mut meta);
+    }
+
+    fn scan(
+      masks: &mut HashMap<StageId, LayerMask>,
+      mask:  LayerMask,
+      stage: &PipelineStageDescription,
+      meta:  &HashMap<StageId, PipelineStageDescription>
+    ) {
+      let entry = masks.entry(stage.id).or_insert(LayerMask::EMPTY);
+      *entry = *entry | mask;
+
+      for input_stage_id in &stage.inputs {
+        scan(masks, mask, &meta[input_stage_id], meta);
+      }
+    }
+
+    let mut masks = HashMap::new();
+    for (mask, pipeline) in pipelines {
+      scan(&mut masks, *mask, &meta[&pipeline.stage_id()], &meta);
+    }
+
+    let mut stages = meta.into_values().collect::<Vec<_>>();
+    stages.sort_by_key(|stage| stage.id);
+
+    Self { layers: layers.to_vec(), stages, masks }
+  }
+
+  // values are stage outputs as traced by stages::trace
+  pub fn to_dot(&self, values: &HashMap<StageId, String>) -> String {
+    let mut out = String::new();
+    out.push_str("digraph {\n");
+    out.push_str("  rankdir=LR;\n");
+    out.push_str("  node [shape=box];\n");
+
+    let mut masks = self.masks.values().copied().collect::<Vec<_>>();
+    masks.sort();
+    masks.dedup();
+
+    for mask in masks {
+      out.push_str(&format!("  subgraph cluster_{} {{\n", mask));
+      out.push_str(&format!("    label = \"{}\";\n", mask.layer_names(&self.layers).join(" | ")));
+
+      for node in self.stages.iter().filter(|stage| self.masks[&stage.id] == mask) {
+        let label = if node.opts.is_empty() {
+          node.name.to_string()
+        } else {
+          format!("{}({})", node.name, node.opts)
+        };
+        let label = label.replace('"', "\\\"");
+
+        let mut short_label = if label.chars().count() > MAX_LABEL_LENGTH {
+          format!("{}...", label.chars().take(MAX_LABEL_LENGTH).collect::<String>())
+        } else {
+          label.clone()
+        };
+
+        // firing buttons stand out
+        let mut style = String::new();
+        if let Some(value) = values.get(&node.id) {
+          short_label.push_str(&format!("\\n= {}", value));
+          if value == "true" {
+            style.push_str(", style=filled, fillcolor=palegreen");
+          }
+        }
+
+        out.push_str(&format!("    {:4} [label=\"{}\", tooltip=\"{}: {}\"{}];\n", node.id, short_label, node.id, label, style));
+      }
+
+      out.push_str("  };\n");
+    }
+
+    let mut edges = vec![];
+    for stage in &self.stages {
+      for input_stage_id in &stage.inputs {
+        edges.push((*input_stage_id, stage.id));
+      }
+    }
+    edges.sort();
+    edges.dedup();
+
+    for (source, target) in edges {
+      out.push_str(&format!("  {:4} -> {:4};\n", source, target));
+    }
+
+    out.push_str("}\n");
+    out
+  }
+
+  pub fn to_json(&self, values: &HashMap<StageId, String>, mode: Option<&[String]>) -> Value {
+    let stages = self.stages.iter().map(|stage| {
+      let mask = self.masks[&stage.id];
+      let mut node = json!({
+        "id":     stage.id,
+        "name":   stage.name,
+        "opts":   stage.opts,
+        "inputs": stage.inputs,
+        "mask":   mask.to_string(),
+        "layers": mask.layer_names(&self.layers)
+      });
+      // numbers and booleans stay as they are, tuples and such are kept as strings
+      if let Some(value) = values.get(&stage.id) {
+        node["value"] = serde_json::from_str(value).unwrap_or_else(|_| json!(value));
+      }
+      node
+    }).collect::<Vec<_>>();
+
+    let mut graph = json!({ "layers": self.layers, "stages": stages });
+    if let Some(mode) = mode {
+      graph["mode"] = json!(mode);
+    }
+    graph
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  fn graph() -> Graph {
+    let config = crate::config::load_config(r#"
+      layer foo {
+        input(A).bind(Kb.A)
+      },
+      layer foo | bar {
+        input(B).bind(Kb.B)
+      }
+    "#, None, false).unwrap();
+    Graph::new(&config.pipelines, &config.layers)
+  }
+
+  // stage ids are global, so expectations are relative to the first one
+  #[test]
+  fn dot_test() {
+    let graph  = graph();
+    let id     = graph.stages[0].id;
+    let values = HashMap::from([(id, "true".to_string()), (id + 2, "false".to_string())]);
+
+    assert_eq!(graph.to_dot(&values), format!(r#"digraph {{
+  rankdir=LR;
+  node [shape=box];
+  subgraph cluster_1 {{
+    label = "foo";
+    {a:4} [label="input(A)\n= true", tooltip="{a}: input(A)", style=filled, fillcolor=palegreen];
+    {b:4} [label="key(A)", tooltip="{b}: key(A)"];
+  }};
+  subgraph cluster_3 {{
+    label = "foo | bar";
+    {c:4} [label="input(B)\n= false", tooltip="{c}: input(B)"];
+    {d:4} [label="key(B)", tooltip="{d}: key(B)"];
+  }};
+  {a:4} -> {b:4};
+  {c:4} -> {d:4};
+}}
+"#, a = id, b = id + 1, c = id + 2, d = id + 3));
+  }
+
+  #[test]
+  fn json_test() {
+    let graph  = graph();
+    let id     = graph.stages[0].id;
+    let values = HashMap::from([(id, "true".to_string()), (id + 1, "(0.5, 1.0)".to_string())]);
+    let mode   = ["foo".to_string()];
+
+    let json = graph.to_json(&values, Some(&mode));
+    assert_eq!(json["layers"], json!(["foo", "bar"]));
+    assert_eq!(json["mode"], json!(["foo"]));
+    assert_eq!(json["stages"][0], json!({
+      "id": id, "name": "input", "opts": "A", "inputs": [], "mask": "1", "layers": ["foo"], "value": true
+    }));
+    assert_eq!(json["stages"][1]["value"], json!("(0.5, 1.0)"));
+    assert_eq!(json["stages"][3]["inputs"], json!([id + 2]));
+    assert_eq!(json["stages"][3]["layers"], json!(["foo", "bar"]));
+    assert!(json["stages"][3].get("value").is_none());
+
+    assert!(graph.to_json(&HashMap::new(), None).get("mode").is_none());
+  }
+}
@@ -0,0 +1,231 @@
+use std::collections::{BTreeMap, HashMap};
+
+use serde_json::{json, Map, Value};
+
+use overlay_ipc::Knob;
+
+use crate::config;
+
+// used by flat knob files, which predate presets
+pub const DEFAULT_PRESET: &str = "default";
+
+// either a flat object with knob values or
+// { "preset": "<active preset>", "presets": { "<name>": { <knob values> }, ... } }
+#[derive(Clone, Debug, PartialEq)]
+pub struct KnobFile {
+  pub preset: String,
+  presets:    BTreeMap<String, Map<String, Value>>
+}
+
+impl Default for KnobFile {
+
+  fn default() -> Self {
+    Self { preset: DEFAULT_PRESET.to_string(), presets: BTreeMap::new() }
+  }
+}
+
+impl KnobFile {
+
+  pub fn parse(json: &str) -> Result<Self, String> {
+    let mut object = match serde_json::from_str(json).map_err(|e| format!("{}", e))? {
+      Value::Object(object) => object,
+      _ => return Err("expected an object with knob values".to_string())
+    };
+
+    // knob values are never objects, so there is no ambiguity
+    let flat = !matches!(object.get("presets"), Some(Value::Object(_)));
+
+    let file = if let Some(Value::Object(presets)) = object.get("presets") {
+      let preset = match object.get("preset") {
+        Some(Value::String(preset)) => preset.clone(),
+        None                        => DEFAULT_PRESET.to_string(),
+        Some(value)                 => return Err(format!("unexpected value of \"preset\": {}", value))
+      };
+
+      let mut file = Self { preset, presets: BTreeMap::new() };
+      for (name, values) in presets {
+        match values {
+          Value::Object(values) => file.presets.insert(name.clone(), values.clone()),
+          _ => return Err(format!("unexpected value of preset \"{}\": {}", name, values))
+        };
+      }
+      file
+    } else {
+      let mut file = Self::default();
+      file.presets.insert(DEFAULT_PRESET.to_string(), std::mem::take(&mut object));
+      file
+    };
+
+    for (name, values) in &file.presets {
+      knob_values(values).map_err(|e| if flat { e } else { format!("preset \"{}\": {}", name, e) })?;
+    }
+
+    Ok(file)
+  }
+
+  pub fn load(path: &str) -> Result<Self, String> {
+    let json = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    Self::parse(&json).map_err(|e| format!("{}: {}", path, e))
+  }
+
+  // a missing file is the same as an empty one
+  pub fn load_or_default(path: &str) -> Result<Self, String> {
+    if std::path::Path::new(path).exists() {
+      Self::load(path)
+    } else {
+      Ok(Self::default())
+    }
+  }
+
+  pub fn save(&self, path: &str) -> Result<(), String> {
+    std::fs::write(path, self.serialize()).map_err(|e| format!("{}: {}", path, e))
+  }
+
+  fn is_flat(&self) -> bool {
+    self.preset == DEFAULT_PRESET && self.presets.keys().all(|name| name == DEFAULT_PRESET)
+  }
+
+  pub fn serialize(&self) -> String {
+    let value = if self.is_flat() {
+      Value::Object(self.presets.get(DEFAULT_PRESET).cloned().unwrap_or_default())
+    } else {
+      json!({ "preset": self.preset, "presets": self.presets })
+    };
+    serde_json::to_string_pretty(&value).unwrap()
+  }
+
+  // sorted by name, the active preset is included even if it has no values yet
+  pub fn presets(&self) -> Vec<String> {
+    let mut names = self.presets.keys().cloned().collect::<Vec<_>>();
+    if !self.presets.contains_key(&self.preset) {
+      names.push(self.preset.clone());
+      names.sort();
+    }
+    names
+  }
+
+  // wraps around, e.g. select_next(-1) for the previous preset
+  pub fn select_next(&mut self, offset: isize) {
+    let names = self.presets();
+    let index = names.iter().position(|name| *name == self.preset).unwrap() as isize;
+    self.preset = names[(index + offset).rem_euclid(names.len() as isize) as usize].clone();
+  }
+
+  // missing values fall back to script defaults
+  pub fn values(&self) -> HashMap<String, config::Value> {
+    self.presets.get(&self.preset).map(|values| knob_values(values).unwrap()).unwrap_or_default()
+  }
+
+  pub fn set_values(&mut self, knobs: &[Knob]) {
+    self.presets.insert(self.preset.clone(), serialize_knobs(knobs));
+  }
+}
+
+pub fn serialize_knob(knob: &Knob) -> Value {
+  match knob {
+    Knob::Enum    { index, options, .. } => json!(options[*index]),
+    Knob::Flag    { value, .. }          => json!(value),
+    Knob::Number  { value, .. }          => json!(value),
+    Knob::Integer { value, .. }          => json!(value),
+    Knob::Key     { index, options, .. } => json!(options[*index]),
+    Knob::Layer   { index, options, .. } => json!(options[*index]),
+    Knob::Curve   { points, .. }         => json!(points.iter().map(|(x, y)| [x, y]).collect::<Vec<_>>())
+  }
+}
+
+pub fn serialize_knobs(knobs: &[Knob]) -> Map<String, Value> {
+  knobs.iter().map(|knob| (knob.name(), serialize_knob(knob))).collect()
+}
+
+// the other way around, for values coming from elsewhere (e.g. the control interface)
+pub fn set_knob_value(knob: &mut Knob, json: &Value) -> Result<(), String> {
+  let error = format!("unexpected value of knob \"{}\": {}", knob.name(), json);
+  match knob {
+    Knob::Flag { value, .. } => {
+      *value = json.as_bool().ok_or(error)?;
+    },
+    Knob::Enum { index, options, .. } | Knob::Key { index, options, .. } | Knob::Layer { index, options, .. } => {
+      *index = json.as_str().and_then(|v| options.iter().position(|option| option == v)).ok_or(error)?;
+    },
+    Knob::Number { value, min_value, max_value, .. } => {
+      *value = (json.as_f64().ok_or(error)? as f32).clamp(*min_value, *max_value);
+    },
+    Knob::Integer { value, min_value, max_value, .. } => {
+      *value = json.as_i64().and_then(|v| i32::try_from(v).ok()).ok_or(error)?.clamp(*min_value, *max_value);
+    },
+    Knob::Curve { points, .. } => {
+      *points = match knob_value(json) {
+        Some(config::Value::List(list)) => config::curve_points(&list).ok_or(error)?,
+        _ => return Err(error)
+      };
+    }
+  }
+  Ok(())
+}
+
+// curves are lists of {x, y} pairs
+fn knob_value(value: &Value) -> Option<config::Value> {
+  match value {
+    Value::Bool(v)   => Some(config::Value::Boolean(*v)),
+    Value::Number(v) => v.as_f64().map(|v| config::Value::Number(v as f32)),
+    Value::String(v) => Some(config::Value::String(v.clone())),
+    Value::Array(v)  => v.iter().map(knob_value).collect::<Option<Vec<_>>>().map(config::Value::List),
+    _ => None
+  }
+}
+
+fn knob_values(values: &Map<String, Value>) -> Result<HashMap<String, config::Value>, String> {
+  let mut map = HashMap::new();
+  for (key, value) in values {
+    match knob_value(value) {
+      Some(v) => map.insert(key.clone(), v),
+      None => return Err(format!("unexpected value of knob \"{}\": {}", key, value))
+    };
+  }
+  Ok(map)
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  #[test]
+  fn knob_file_test() {
+    let flat = KnobFile::parse(r#"{ "speed": 0.5 }"#).unwrap();
+    assert_eq!(flat.values()["speed"], config::Value::Number(0.5));
+    assert_eq!(KnobFile::parse(&flat.serialize()).unwrap(), flat);
+    assert!(!flat.serialize().contains("presets"));
+
+    let mut file = KnobFile::parse(r#"{
+      "preset": "sniper",
+      "presets": { "default": { "speed": 0.5 }, "sniper": { "speed": 0.2 } }
+    }"#).unwrap();
+    assert_eq!(file.values()["speed"], config::Value::Number(0.2));
+
+    file.select_next(1);
+    assert_eq!(file.preset, "default");
+    file.select_next(-1);
+    assert_eq!(file.preset, "sniper");
+
+    file.preset = "driving".to_string();
+    assert!(file.values().is_empty());
+    assert_eq!(file.presets(), vec!["default", "driving", "sniper"]);
+    file.set_values(&[Knob::Flag { name: "invert y".to_string(), value: true }]);
+    assert_eq!(KnobFile::parse(&file.serialize()).unwrap(), file);
+
+    let err = KnobFile::parse(r#"{ "presets": { "default": { "speed": {} } } }"#).unwrap_err();
+    assert_eq!(err, "preset \"default\": unexpected value of knob \"speed\": {}");
+    let err = KnobFile::parse(r#"{ "speed": null }"#).unwrap_err();
+    assert_eq!(err, "unexpected value of knob \"speed\": null");
+
+    let mut knob = Knob::Number { name: "speed".to_string(), value: 0.5, min_value: 0.0, max_value: 1.0 };
+    set_knob_value(&mut knob, &json!(2)).unwrap();
+    assert_eq!(serialize_knob(&knob), json!(1.0));
+    let mut knob = Knob::Curve { name: "trigger".to_string(), points: vec![(0.0, 0.0), (1.0, 1.0)] };
+    set_knob_value(&mut knob, &json!([[0, 0], [0.5, 0.25], [1, 1]])).unwrap();
+    assert_eq!(serialize_knob(&knob), json!([[0.0, 0.0], [0.5, 0.25], [1.0, 1.0]]));
+    let err = set_knob_value(&mut knob, &json!([[1, 1], [0, 0]])).unwrap_err();
+    assert_eq!(err, "unexpected value of knob \"trigger\": [[1,1],[0,0]]");
+  }
+}
@@ -0,0 +1,24 @@
+// messages printed while the mapper runs, the terminal UI shows them instead of stderr (which would garble its screen)
+
+use std::sync::Mutex;
+
+pub type Sink = Box<dyn Fn(String) + Send>;
+
+static SINK: Mutex<Option<Sink>> = Mutex::new(None);
+
+pub fn set_sink(sink: Option<Sink>) {
+  *SINK.lock().unwrap() = sink;
+}
+
+pub fn emit(message: String) {
+  match &*SINK.lock().unwrap() {
+    Some(sink) => sink(message),
+    None       => eprintln!("{}", message)
+  }
+}
+
+macro_rules! log {
+  ($($arg:tt)*) => { $crate::log::emit(format!($($arg)*)) }
+}
+
+pub(crate) use log;
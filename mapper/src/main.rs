@@ -1,8 +1,22 @@
+mod clock;
 mod config;
+mod control;
 mod controllers;
+mod frontend;
+mod fuzz;
+mod graph;
+mod knob_file;
+mod log;
 mod mapper;
 mod output;
+mod preview;
+mod probe_test;
+mod process_memory;
+mod profiles;
+mod repl;
 mod stages;
+#[cfg(feature = "tui")]
+mod tui;
 
 use std::collections::HashMap;
 use std::path::Path;
@@ -11,10 +25,8 @@ use std::{io, thread};
 
 use clap::{Parser, Subcommand, ValueEnum};
 
-use controllers::{available_controllers, find_controller, Axis, Button};
-use mapper::LayerMask;
+use controllers::{available_controllers, find_controller};
 use overlay_ipc::Knob;
-use stages::{PipelineStageDescription, StageId};
 
 #[derive(Parser)]
 struct Cli {
@@ -40,23 +52,105 @@ enum Command {
   Load {
     script:     String,
     knobs:      Option<String>,
+    /// knob preset, the one active last time by default
+    #[arg(long)]
+    preset:     Option<String>,
     #[arg(short, long)]
     controller: Option<String>,
     #[arg(short, long)]
-    output:     Option<OutputBackend>
+    output:     Option<OutputBackend>,
+    /// read memory probes directly from this process (pid or name) instead of the overlay
+    #[arg(short, long)]
+    process:    Option<process_memory::ProcessSelector>,
+    /// connect to this overlay (pid, overlay name or executable name) instead of the first one found
+    #[arg(long)]
+    overlay:    Option<String>,
+    /// show knobs, layers, controller state and output in the terminal, e.g. for games without the overlay
+    #[arg(long)]
+    tui:        bool
+  },
+  /// run configs from a profile directory, switching between them as the focused window changes
+  Supervise {
+    profiles:   String,
+    #[arg(short, long)]
+    controller: Option<String>,
+    #[arg(short, long)]
+    output:     Option<OutputBackend>,
+    /// match profiles against running processes instead of the focused window
+    #[arg(long)]
+    processes:  bool,
+    /// read memory probes directly from this process (pid or name) instead of the overlay
+    #[arg(short, long)]
+    process:    Option<process_memory::ProcessSelector>
   },
   /// run config with dummy input
   Test {
-    script: String
+    script: String,
+    /// check invariants (stuck keys, output with all layers disengaged, undeclared modes) instead of measuring speed
+    #[arg(long)]
+    check:  bool,
+    /// number of random runs to check
+    #[arg(long, default_value_t = 100)]
+    runs:   u64,
+    /// seed of the first run
+    #[arg(long, default_value_t = 0)]
+    seed:   u64,
+    /// check a recorded run again (e.g. one written by --check)
+    #[arg(long)]
+    replay: Option<String>
   },
   /// export config into DOT graph
   Dot {
-    script: String
+    script: String,
+    /// export JSON with full stage options instead
+    #[arg(long)]
+    json:   bool,
+    /// replay recorded input (see mapper/src/preview.rs), writing a graph with stage outputs for every snapshot
+    #[arg(short, long)]
+    input:  Option<String>
+  },
+  /// evaluate config's screen probes against a screenshot (PNG or binary PPM)
+  ProbeTest {
+    script:     String,
+    screenshot: String,
+    /// write a copy of the screenshot with probe areas and matching pixels highlighted (PNG, or PPM for .ppm paths)
+    #[arg(short, long)]
+    debug:      Option<String>
+  },
+  /// render overlay shapes (menus) into PNG images without a GPU
+  Preview {
+    script:     String,
+    knobs:      Option<String>,
+    #[arg(long)]
+    preset:     Option<String>,
+    /// replay recorded input (see mapper/src/preview.rs) instead of reading the controller
+    #[arg(short, long)]
+    input:      Option<String>,
+    /// live preview file, rewritten as the controller input changes the shapes
+    #[arg(short, long, default_value = "preview.png")]
+    output:     String,
+    #[arg(short, long)]
+    controller: Option<String>,
+    #[arg(long, default_value_t = 1920)]
+    width:      u32,
+    #[arg(long, default_value_t = 1080)]
+    height:     u32
   },
   /// list config parameters
+  #[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
   Knobs {
-    script: String,
-    knobs:  Option<String>
+    #[command(subcommand)]
+    command: Option<KnobsCommand>,
+    #[arg(required = true)]
+    script:  Option<String>,
+    knobs:   Option<String>,
+    #[arg(long)]
+    preset:  Option<String>
+  },
+  /// evaluate config code line by line, `.watch <expr>` shows a pipeline's output
+  Repl {
+    #[arg(short, long)]
+    controller: Option<String>
   },
   /// list connected controllers
   List {
@@ -67,11 +161,22 @@ enum Command {
   }
 }
 
+#[derive(Subcommand)]
+enum KnobsCommand {
+  /// show knob values differing from the script defaults
+  Diff {
+    script: String,
+    knobs:  String,
+    #[arg(long)]
+    preset: Option<String>
+  }
+}
+
 #[cfg(not(test))]
 fn main() {
 
   fn load_config(script: &str, knob_values: Option<HashMap<String, config::Value>>) -> config::Config {
-    match config::load_config(script, knob_values) {
+    match config::load_config(script, knob_values, true) {
       Ok(config) => config,
       Err(err) => {
         eprintln!("Can't load config:\n{}", err);
@@ -85,386 +190,576 @@ fn main() {
     load_config(&std::fs::read_to_string(path).unwrap(), knob_values)
   }
 
-  fn load_knobs_from_file(path: &str) -> HashMap<String, config::Value> {
-
-    use serde_json::Value;
-
-    let settings = std::fs::read_to_string(path).unwrap();
-
-    if let Value::Object(settings) = serde_json::from_str(&settings).unwrap() {
-      let mut map = HashMap::new();
+  fn try_load_config_from_file(
+    path:        &str,
+    knob_values: Option<HashMap<String, config::Value>>,
+    knobs_ui:    bool
+  ) -> Result<config::Config, String> {
+    let script = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    config::load_config(&script, knob_values, knobs_ui)
+  }
 
-      for (key, value) in settings {
-        match value {
-          Value::Bool(v)   => map.insert(key.clone(), config::Value::Boolean(v)),
-          Value::Number(v) => map.insert(key.clone(), config::Value::Number(v.as_f64().unwrap() as f32)),
-          Value::String(v) => map.insert(key.clone(), config::Value::String(v)),
-          _ => panic!()
-        };
+  fn load_knobs(path: &str, preset: Option<String>) -> HashMap<String, config::Value> {
+    match knob_file::KnobFile::load(path) {
+      Ok(mut file) => {
+        if let Some(preset) = preset {
+          file.preset = preset;
+        }
+        file.values()
+      },
+      Err(err) => {
+        eprintln!("Can't load knobs: {}", err);
+        std::process::exit(1);
       }
-
-      map
-    } else {
-      panic!();
     }
   }
 
-  fn serialize_knobs(knobs: &Vec<Knob>) -> String {
+  fn save_knobs(knobs_path: &Option<String>, file: &knob_file::KnobFile) {
+    if let Some(knobs_path) = knobs_path {
+      if let Err(e) = file.save(knobs_path) {
+        log::log!("Unable to save knob values: {}", e);
+      }
+    }
+  }
 
-    use serde_json::{json, Value};
+  fn knob_values_of(knobs: &[Knob]) -> HashMap<String, config::Value> {
+    let mut knob_values = HashMap::new();
 
-    let mut h = serde_json::map::Map::with_capacity(knobs.len());
     for knob in knobs {
-      h.insert(
-        knob.name().clone(),
-        match knob {
-          Knob::Enum   { index, options, .. } => json!(options[*index]),
-          Knob::Flag   { value, .. }          => json!(value),
-          Knob::Number { value, .. }          => json!(value)
+      match knob {
+        Knob::Enum { name, index, options } | Knob::Key { name, index, options } | Knob::Layer { name, index, options } => {
+          knob_values.insert(name.clone(), config::Value::String(options[*index].clone()));
+        },
+        Knob::Flag { name, value } => {
+          knob_values.insert(name.clone(), config::Value::Boolean(*value));
+        },
+        Knob::Number { name, value, .. } => {
+          knob_values.insert(name.clone(), config::Value::Number(*value));
+        },
+        Knob::Integer { name, value, .. } => {
+          knob_values.insert(name.clone(), config::Value::Number(*value as f32));
+        },
+        Knob::Curve { name, points } => {
+          let points = points.iter().map(|(x, y)| config::Value::List(vec![config::Value::Number(*x), config::Value::Number(*y)]));
+          knob_values.insert(name.clone(), config::Value::List(points.collect()));
         }
-      );
+      }
     }
 
-    serde_json::to_string_pretty(&Value::Object(h)).unwrap()
+    knob_values
   }
 
-  let cli = Cli::parse();
+  // a broken file is left alone rather than overwritten with defaults
+  fn load_knob_file(knobs_path: &mut Option<String>, preset: Option<String>) -> knob_file::KnobFile {
+    let mut file = match knobs_path.as_deref().map(knob_file::KnobFile::load_or_default) {
+      Some(Ok(file)) => file,
+      Some(Err(e)) => {
+        log::log!("Can't load knobs, using defaults (changes won't be saved): {}", e);
+        *knobs_path = None;
+        knob_file::KnobFile::default()
+      },
+      None => knob_file::KnobFile::default()
+    };
+    if let Some(preset) = preset {
+      file.preset = preset;
+    }
+    file
+  }
 
-  match cli.command {
-    Some(Command::Check { script }) => {
-      let config = load_config_from_file(&script, None);
-      for (mask, p) in config.pipelines {
-        println!("{:?} -> {}", mask, p.desc());
+  #[allow(clippy::too_many_arguments)]
+  fn run(
+    mut script:             Option<String>,
+    mut knobs_path:         Option<String>,
+    preset:                 Option<String>,
+    serial_or_partial_path: Option<String>,
+    output:                 Option<OutputBackend>,
+    process:                Option<process_memory::ProcessSelector>,
+    overlay_selector:       Option<String>,
+    terminal_ui:            bool,
+    profile_receiver:       Option<mpsc::Receiver<Option<profiles::Profile>>>
+  ) {
+
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[cfg(feature = "tui")]
+    let (tui, mut frontend) = if terminal_ui {
+      let (tui, frontend) = tui::tui();
+      log::set_sink(Some(frontend.log_sink()));
+      (Some(tui), Some(frontend))
+    } else {
+      (None, None)
+    };
+
+    #[cfg(not(feature = "tui"))]
+    if terminal_ui {
+      eprintln!("Terminal UI is not supported by this build");
+      std::process::exit(1);
+    }
+
+    let (controller_state_sender,   controller_state_receiver)   = mpsc::channel();
+    let (controller_command_sender, controller_command_receiver) = mpsc::channel();
+
+    //TODO: consider getting rid of thread + channel here
+    //TODO: wait for the controller appearance if it's not connected?
+    thread::spawn(move || {
+      if let Some(controller) = find_controller(serial_or_partial_path).unwrap() {
+        controller.run_polling_loop(controller_state_sender, Some(controller_command_receiver)).unwrap();
+      } else {
+        eprintln!("No controllers found.");
+        std::process::exit(1);
       }
-    },
-    Some(Command::Load { script, knobs: knobs_path, controller: serial_or_partial_path, output }) => {
+    });
+
+    thread::spawn(move || {
+
+      let available_backends = vec![
+        #[cfg(feature = "x11")]
+        OutputBackend::X11,
+        #[cfg(feature = "evdev")]
+        OutputBackend::Evdev
+      ];
+
+      assert_ne!(available_backends.len(), 0);
+
+      let mut output: Box<dyn output::MapperIO> = match output.unwrap_or(available_backends[0]) {
+        #[cfg(feature = "x11")]
+        OutputBackend::X11 => match output::xcb::XcbKeyboardAndMouse::new() {
+          Ok(xtest_out) => Box::new(xtest_out),
+          Err(e) => {
+            eprintln!("Can't initialize xtest keyboard and mouse: {}", e);
+            std::process::exit(1);
+          }
+        },
+        #[cfg(feature = "evdev")]
+        OutputBackend::Evdev => match output::evdev::UInputKeyboardAndMouse::new() {
+          Ok(uinput_out) => Box::new(uinput_out),
+          Err(e) => {
+            eprintln!("Can't initialize uinput keyboard and mouse: {}", e);
+            std::process::exit(1);
+          }
+        }
+      };
 
-      let (controller_state_sender,   controller_state_receiver)   = mpsc::channel();
-      let (controller_command_sender, controller_command_receiver) = mpsc::channel();
+      #[cfg(feature = "tui")]
+      if let Some(frontend) = &frontend {
+        output = frontend.record_output(output);
+      }
 
-      //TODO: consider getting rid of thread + channel here
-      //TODO: wait for the controller appearance if it's not connected?
-      thread::spawn(move || {
-        if let Some(controller) = find_controller(serial_or_partial_path).unwrap() {
-          controller.run_polling_loop(controller_state_sender, Some(controller_command_receiver)).unwrap();
-        } else {
-          eprintln!("No controllers found.");
-          std::process::exit(1);
+      let connect_to_overlay = || match overlay_ipc::connect_to_overlay(overlay_selector.as_deref(), &log::emit) {
+        Ok(overlay) => overlay,
+        Err(e) => {
+          log::log!("Can't connect to overlay: {}", e);
+          None
         }
-      });
+      };
 
-      thread::spawn(move || {
+      let mut overlay = connect_to_overlay();
+      let mut overlay_required = false;
 
-        let available_backends = vec![
-          #[cfg(feature = "x11")]
-          OutputBackend::X11,
-          #[cfg(feature = "evdev")]
-          OutputBackend::Evdev
-        ];
-
-        assert_ne!(available_backends.len(), 0);
-
-        let mut output: Box<dyn output::MapperIO> = match output.unwrap_or(available_backends[0]) {
-          #[cfg(feature = "x11")]
-          OutputBackend::X11 => match output::xcb::XcbKeyboardAndMouse::new() {
-            Ok(xtest_out) => Box::new(xtest_out),
-            Err(e) => {
-              eprintln!("Can't initialize xtest keyboard and mouse: {}", e);
-              std::process::exit(1);
-            }
-          },
-          #[cfg(feature = "evdev")]
-          OutputBackend::Evdev => match output::evdev::UInputKeyboardAndMouse::new() {
-            Ok(uinput_out) => Box::new(uinput_out),
-            Err(e) => {
-              eprintln!("Can't initialize uinput keyboard and mouse: {}", e);
-              std::process::exit(1);
-            }
-          }
-        };
+      let mut process_memory = process.map(process_memory::ProcessMemory::new);
 
-        let mut overlay = overlay_ipc::connect_to_overlay().unwrap();
-        let mut overlay_required = false;
+      // shared by all mapper instances, stage timers don't jump back on reload
+      let clock = clock::RealClock::new();
 
-        let mut knob_values = knobs_path
-          .clone()
-          .and_then(|path| {
-            if Path::new(&path).exists() {
-              Some(load_knobs_from_file(&path))
-            } else {
-              None
+      let knob_file       = Rc::new(RefCell::new(load_knob_file(&mut knobs_path, preset)));
+      let mut knob_values = knob_file.borrow().values();
+      let mut mode        = None;
+
+      let mut control = match control::start() {
+        Ok(control) => Some(control),
+        Err(e) => {
+          log::log!("Can't register D-Bus control interface: {}", e);
+          None
+        }
+      };
+
+      loop {
+        if overlay_required && overlay.is_none() {
+          log::log!("Waiting for overlay...");
+          loop {
+            overlay = connect_to_overlay();
+            if overlay.is_some() {
+              break;
             }
-          })
-          .unwrap_or_default();
-
-        loop {
-          if overlay_required && overlay.is_none() {
-            eprintln!("Waiting for overlay...");
-            loop {
-              overlay = overlay_ipc::connect_to_overlay().unwrap();
-              if overlay.is_some() {
-                break;
-              }
-              thread::sleep(std::time::Duration::from_secs(1));
+            thread::sleep(std::time::Duration::from_secs(1));
+            if !terminal_ui {
               eprint!("*");
             }
           }
+        }
 
-          let mut config = load_config_from_file(&script, Some(knob_values.clone()));
+        let knobs_ui = overlay.as_ref().map(|overlay| overlay.capabilities.knobs_ui).unwrap_or(false);
+
+        let mut config = match script.as_ref().map(|script| try_load_config_from_file(script, Some(knob_values.clone()), knobs_ui)) {
+          Some(Ok(config)) => config,
+          // a broken profile shouldn't take the whole supervisor down
+          Some(Err(err)) if profile_receiver.is_some() => {
+            log::log!("Can't load config:\n{}", err);
+            config::empty_config(knobs_ui)
+          },
+          Some(Err(err)) => {
+            eprintln!("Can't load config:\n{}", err);
+            std::process::exit(1);
+          },
+          None => config::empty_config(knobs_ui)
+        };
+        if !terminal_ui {
           for (mask, p) in &config.pipelines {
             println!("{:?} -> {}", mask, p.desc());
           }
+        }
+
+        if overlay.is_some() {
+          config.pipelines.append(&mut config.system);
+        }
 
-          if overlay.is_some() {
+        #[cfg(feature = "tui")]
+        if let Some(frontend) = &mut frontend {
+          frontend.set_layers(config.layers.clone());
+        }
 
-            use stages::*;
+        if let Some(control) = &mut control {
+          control.set_config(script.clone(), knob_file.borrow().preset.clone(), config.layers.clone());
+        }
 
-            fn toggle_overlay_ui(pipeline: PipelineRef<bool>) -> Box<dyn Pipeline<()>> {
+        let log_level = if terminal_ui { 0 } else { 1 };
+        let mut mapper = mapper::Mapper::new(Some(&controller_command_sender), overlay.as_ref(), process_memory.as_mut(), config, &mut *output, &clock, log_level);
 
-              let mut bstate = to_button_state();
+        #[cfg(feature = "tui")]
+        if let Some(frontend) = &mut frontend {
+          mapper.attach_frontend(frontend);
+        }
 
-              let fun = Box::new(move |pressed, _, _, actions: &mut Vec<Action>| {
-                if bstate(pressed) == ButtonState::Pressed {
-                  actions.push(Action::ToggleOverlayUI);
-                }
-              });
+        if let Some(control) = &mut control {
+          mapper.attach_frontend(control);
+        }
 
-              Box::new(FnStage::from("toggle_overlay_ui", "".to_string(), pipeline, fun))
-            }
+        if let Some(mode) = mode.take() {
+          mapper.restore_mode(&mode);
+        }
 
-            config.pipelines.push((LayerMask::ALL_INTERNAL_BITS, toggle_overlay_ui(button_input(Button::X))));
+        let (live_knobs_path, live_knob_file) = (knobs_path.clone(), knob_file.clone());
+        mapper.on_live_knobs_changed(move |knobs| {
+          live_knob_file.borrow_mut().set_values(knobs);
+          save_knobs(&live_knobs_path, &live_knob_file.borrow());
+        });
 
-            fn overlay_menu_command(pipeline: PipelineRef<bool>, command: OverlayMenuCommand) -> Box<dyn Pipeline<()>> {
+        let exit_reason = mapper.run(&controller_state_receiver, profile_receiver.as_ref());
 
-              let mut bstate = to_button_state();
+        // live knob changes don't stop the mapper, but the next config should see them as well
+        knob_values = knob_values_of(mapper.knobs());
 
-              let fun = Box::new(move |pressed, _, _, actions: &mut Vec<Action>| {
-                if bstate(pressed) == ButtonState::Pressed {
-                  eprintln!("command: {:?}", command);
-                  actions.push(Action::SendOverlayMenuCommand(command));
-                }
-              });
+        match exit_reason {
 
-              Box::new(FnStage::from("overlay_menu_command", format!("{:?}", command), pipeline, fun))
-            }
+          Ok(mapper::ExitReason::KnobsChanged(knobs, last_mode)) => {
+            log::log!("reconfiguring with knobs {:?}", knobs);
+            knob_values = knob_values_of(&knobs);
+            knob_file.borrow_mut().set_values(&knobs);
+            save_knobs(&knobs_path, &knob_file.borrow());
+            mode = Some(last_mode);
+          },
 
-            let knobs_menu_layer = LayerMask::internal_layer(0).unwrap();
+          Ok(mapper::ExitReason::KnobPresetSwitched(knobs, offset, last_mode)) => {
+            let mut file = knob_file.borrow_mut();
+            file.set_values(&knobs);
+            file.select_next(offset);
+            log::log!("switching to knob preset {:?}", file.preset);
+            knob_values = file.values();
+            save_knobs(&knobs_path, &file);
+            mode = Some(last_mode);
+          },
 
-            //TODO: switch to the last active overlay on Steam button press
-            config.pipelines.push((LayerMask::ALL, flip_mode(button_input(Button::Steam), knobs_menu_layer)));
-            config.pipelines.push((LayerMask::ALL, overlay_menu_command(       mode_is(knobs_menu_layer),  OverlayMenuCommand::OpenKnobsMenu)));
-            config.pipelines.push((LayerMask::ALL, overlay_menu_command(invert(mode_is(knobs_menu_layer)), OverlayMenuCommand::CloseKnobsMenu)));
+          Ok(mapper::ExitReason::ReloadRequested(last_mode)) => {
+            log::log!("reloading {:?}", script);
+            mode = Some(last_mode);
+          },
 
-            fn deg_to_rad(deg: f32) -> f32 {
-              deg * std::f32::consts::PI / 180.0
-            }
+          Ok(mapper::ExitReason::OverlayRequired) => {
+            overlay_required = true;
+          },
 
-            let left_pad  = merge(axis_input(Axis::LPadX), axis_input(Axis::LPadY));
-            let right_pad = merge(axis_input(Axis::RPadX), axis_input(Axis::RPadY));
-
-            let mut opts = RingSectorButtonOpts {
-              direction:    0.0,
-              angle:        deg_to_rad(120.0),
-              inner_radius: 0.25,
-              outer_radius: 1.2,
-              margin:       0.1
-            };
-
-            opts.direction = deg_to_rad( 90.0);
-            let up    = ring_sector_button(left_pad .clone(), opts);
-            opts.direction = deg_to_rad(-90.0);
-            let down  = ring_sector_button(left_pad .clone(), opts);
-            opts.direction = deg_to_rad(180.0);
-            let left  = ring_sector_button(right_pad.clone(), opts);
-            opts.direction = deg_to_rad(  0.0);
-            let right = ring_sector_button(right_pad.clone(), opts);
-
-            config.pipelines.push((knobs_menu_layer, overlay_menu_command(up,    OverlayMenuCommand::SelectPrevMenuItem)));
-            config.pipelines.push((knobs_menu_layer, overlay_menu_command(down,  OverlayMenuCommand::SelectNextMenuItem)));
-            config.pipelines.push((knobs_menu_layer, overlay_menu_command(left,  OverlayMenuCommand::SelectPrevValue)));
-            config.pipelines.push((knobs_menu_layer, overlay_menu_command(right, OverlayMenuCommand::SelectNextValue)));
-
-            config.pipelines.push((knobs_menu_layer, overlay_menu_command(button_input(Button::DPadUp),    OverlayMenuCommand::SelectPrevMenuItem)));
-            config.pipelines.push((knobs_menu_layer, overlay_menu_command(button_input(Button::DPadDown),  OverlayMenuCommand::SelectNextMenuItem)));
-            config.pipelines.push((knobs_menu_layer, overlay_menu_command(button_input(Button::DPadLeft),  OverlayMenuCommand::SelectPrevValue)));
-            config.pipelines.push((knobs_menu_layer, overlay_menu_command(button_input(Button::DPadRight), OverlayMenuCommand::SelectNextValue)));
-          }
+          Ok(mapper::ExitReason::OverlayLost) => {
+            // the game has exited, wait for it to come back if the overlay was explicitly selected
+            overlay          = None;
+            overlay_required = overlay_required || overlay_selector.is_some();
+          },
 
-          let mut mapper = mapper::Mapper::new(Some(&controller_command_sender), overlay.as_ref(), config, &mut *output, 1);
-
-          match mapper.run(&controller_state_receiver) {
-
-            Ok(mapper::ExitReason::KnobsChanged(knobs)) => {
-              eprintln!("reconfiguring with knobs {:?}", knobs);
-              knob_values.clear();
-
-              for knob in &knobs {
-                match knob {
-                  Knob::Enum { name, index, options } => {
-                    knob_values.insert(name.clone(), config::Value::String(options[*index].clone()));
-                  },
-                  Knob::Flag { name, value } => {
-                    knob_values.insert(name.clone(), config::Value::Boolean(*value));
-                  },
-                  Knob::Number { name, value, .. } => {
-                    knob_values.insert(name.clone(), config::Value::Number(*value));
-                  }
-                }
-              }
-
-              if let Some(knobs_path) = &knobs_path {
-                if let Err(e) = std::fs::write(knobs_path, serialize_knobs(&knobs)) {
-                  eprintln!("Unable to save knob values: {}", e);
-                }
-              }
-            },
-
-            Ok(mapper::ExitReason::OverlayRequired) => {
-              overlay_required = true;
-            },
-
-            Err(e) => {
-              eprintln!("exiting on error: {}", e);
-              //TODO: enable lizard mode
-              std::process::exit(1);
-            }
+          Ok(mapper::ExitReason::ProfileChanged(profile)) => {
+            log::log!("switching to profile {:?}", profile);
+            script      = profile.as_ref().map(|p| p.script.clone());
+            knobs_path  = profile.map(|p| p.knobs);
+            *knob_file.borrow_mut() = load_knob_file(&mut knobs_path, None);
+            knob_values = knob_file.borrow().values();
+          },
+
+          Err(e) => {
+            eprintln!("exiting on error: {}", e);
+            //TODO: enable lizard mode
+            std::process::exit(1);
           }
         }
+      }
+    });
+
+    #[cfg(feature = "tui")]
+    if let Some(tui) = tui {
+      if let Err(e) = tui.run() {
+        eprintln!("Terminal UI failed: {}", e);
+      }
+      return;
+    }
+
+    let _ = io::stdin().read_line(&mut String::new());
+  }
+
+  let cli = Cli::parse();
+
+  match cli.command {
+    Some(Command::Check { script }) => {
+      let config = load_config_from_file(&script, None);
+      for (mask, p) in config.pipelines {
+        println!("{:?} -> {}", mask, p.desc());
+      }
+      for (mask, p) in config.system {
+        println!("system {:?} -> {}", mask, p.desc());
+      }
+    },
+    Some(Command::Load { script, knobs: knobs_path, preset, controller: serial_or_partial_path, output, process, overlay, tui }) => {
+      run(Some(script), knobs_path, preset, serial_or_partial_path, output, process, overlay, tui, None);
+    },
+    Some(Command::Supervise { profiles, controller: serial_or_partial_path, output, processes, process }) => {
+
+      let source = if processes {
+        profiles::ProfileSource::Processes
+      } else {
+        #[cfg(feature = "x11")]
+        { profiles::ProfileSource::FocusedWindow }
+        #[cfg(not(feature = "x11"))]
+        { profiles::ProfileSource::Processes }
+      };
+
+      let (profile_sender, profile_receiver) = mpsc::channel();
+
+      thread::spawn(move || {
+        if let Err(e) = profiles::watch_profiles(profiles.into(), source, profile_sender) {
+          eprintln!("Can't watch profiles: {}", e);
+          std::process::exit(1);
+        }
       });
 
-      let _ = io::stdin().read_line(&mut String::new());
+      let profile = profile_receiver.recv().unwrap();
+      run(
+        profile.as_ref().map(|p| p.script.clone()),
+        profile.map(|p| p.knobs),
+        None,
+        serial_or_partial_path,
+        output,
+        process,
+        None,
+        false,
+        Some(profile_receiver));
     },
-    Some(Command::Test { script }) => {
+    Some(Command::Test { script, check, runs, seed, replay }) => {
+
+      let load = || load_config_from_file(&script, None);
+
+      if let Some(path) = replay {
+        let steps = match std::fs::read_to_string(&path).map_err(|e| format!("{}: {}", path, e)).and_then(|s| preview::parse_recording(&s)) {
+          Ok(steps) => steps,
+          Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+          }
+        };
+        match fuzz::replay(&load, &steps) {
+          Ok(()) => println!("ok"),
+          Err(violation) => {
+            println!("{}", violation);
+            std::process::exit(1);
+          }
+        }
+        return;
+      }
+
+      if check {
+        let name = Path::new(&script).file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or(script.clone());
+        let failures = fuzz::check(&name, &load, seed, runs);
+        println!("{} of {} runs failed", failures, runs);
+        if failures > 0 {
+          std::process::exit(1);
+        }
+        return;
+      }
 
       let mut output = output::DummyOutput {};
 
       let config = load_config_from_file(&script, None);
-      let mut mapper = mapper::Mapper::new(None, None, config, &mut output, 0);
+      let clock  = clock::VirtualClock::default();
+      let mut mapper = mapper::Mapper::new(None, None, None, config, &mut output, &clock, 0);
 
       let iterations = 1_000_000;
       let start      = std::time::Instant::now();
 
-      mapper.fuzz(iterations);
+      if let Err(e) = mapper.fuzz(clock::FRAME_INTERVAL, iterations) {
+        eprintln!("{}", e);
+        return;
+      }
       println!("{} iterations in {} ms: {} per ms", iterations, start.elapsed().as_millis(), iterations as u128 / start.elapsed().as_millis());
     },
-    Some(Command::Dot { script }) => {
-      let config::Config { pipelines, layers, .. } = load_config_from_file(&script, None);
-
-      let mut meta = HashMap::new();
+    Some(Command::Dot { script, json, input }) => {
+      let config = load_config_from_file(&script, None);
+      let graph  = graph::Graph::new(&config.pipelines, &config.layers);
 
-      for (_, pipeline) in &pipelines {
-        pipeline.inspect(&mut meta);
-      }
+      let render = |values: &HashMap<stages::StageId, String>, mode: Option<&[String]>| if json {
+        format!("{}\n", serde_json::to_string_pretty(&graph.to_json(values, mode)).unwrap())
+      } else {
+        graph.to_dot(values)
+      };
 
-      let masks = {
-        let mut masks = HashMap::new();
-
-        fn scan(
-          masks: &mut HashMap<StageId, LayerMask>,
-          mask:  LayerMask,
-          stage: &PipelineStageDescription,
-          meta:  &HashMap<StageId, PipelineStageDescription>
-        ) {
-          match masks.entry(stage.id) {
-            std::collections::hash_map::Entry::Vacant(e) => {
-              e.insert(mask);
-            },
-            std::collections::hash_map::Entry::Occupied(mut e) => {
-              *e.get_mut() = *e.get() | mask;
-            }
-          }
+      let Some(path) = input else {
+        print!("{}", render(&HashMap::new(), None));
+        return;
+      };
 
-          for input_stage_id in &stage.inputs {
-            scan(masks, mask, &meta[input_stage_id], meta);
-          }
+      let steps = match std::fs::read_to_string(&path).map_err(|e| format!("{}: {}", path, e)).and_then(|s| preview::parse_recording(&s)) {
+        Ok(steps) => steps,
+        Err(e) => {
+          eprintln!("{}", e);
+          std::process::exit(1);
         }
+      };
+      let dir = Path::new(&path).parent().unwrap_or(Path::new("."));
 
-        for (mask, pipeline) in pipelines {
-          scan(&mut masks, mask, &meta[&pipeline.stage_id()], &meta);
-        }
+      stages::enable_tracing();
 
-        masks
-      };
+      let clock = clock::VirtualClock::default();
+      let mut dummy_output = output::DummyOutput {};
+      let mut mapper = mapper::Mapper::new(None, None, None, config, &mut dummy_output, &clock, 0);
 
-      let edges = {
-        let mut edges = vec![];
-        for stage in meta.values() {
-          for input_stage_id in &stage.inputs {
-            edges.push((input_stage_id, stage.id));
-          }
+      let result = preview::replay_input::<String>(&mut mapper, &steps, |_, _| Ok(()), |mapper, snapshot| {
+        let out = render(&stages::traced_values(), Some(mapper.mode().layers()));
+        std::fs::write(dir.join(snapshot), out).map_err(|e| format!("{}: {}", snapshot, e))?;
+        println!("{}", snapshot);
+        Ok(())
+      });
+
+      if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+      }
+    },
+    Some(Command::ProbeTest { script, screenshot, debug }) => {
+
+      let config = load_config_from_file(&script, None);
+
+      let screen = match overlay_ipc::Image::load(&screenshot) {
+        Ok(screen) => screen,
+        Err(e) => {
+          eprintln!("Can't load screenshot: {}", e);
+          std::process::exit(1);
         }
-        edges.sort();
-        edges.dedup();
-        edges
       };
 
-      let nodes_by_layer = {
-        let mut nodes_by_layer: HashMap<LayerMask, Vec<PipelineStageDescription>> = HashMap::new();
+      let test = probe_test::probe_test(&config, &screen);
 
-        for stage in meta.values() {
-          let mask = masks[&stage.id];
-          nodes_by_layer.entry(mask).or_insert_with(Vec::new);
-          nodes_by_layer.get_mut(&mask).unwrap().push(stage.clone());
-        }
+      for (id, opts, result) in &test.results {
+        println!("{:4}: {}", id, probe_test::describe(result));
+        println!("      {}", opts);
+      }
 
-        for nodes in nodes_by_layer.values_mut() {
-          nodes.sort_by_key(|stage| stage.id);
+      if let Some(path) = debug {
+        if let Err(e) = test.debug.save(&path) {
+          eprintln!("Can't write debug image: {}", e);
+          std::process::exit(1);
         }
+      }
+    },
+    Some(Command::Preview { script, knobs, preset, input, output, controller: serial_or_partial_path, width, height }) => {
 
-        let mut nodes_by_layer = nodes_by_layer.iter()
-          .map(|(mask, stages)| (*mask, stages.clone()))
-          .collect::<Vec<(LayerMask, Vec<PipelineStageDescription>)>>();
-        nodes_by_layer.sort_by_key(|(mask, _)| *mask);
-        nodes_by_layer
-      };
+      use overlay_ipc::headless::HeadlessOverlay;
 
-      println!("digraph {{");
-      println!("  rankdir=LR;");
-      println!("  node [shape=box];");
+      let knob_values = knobs.map(|path| load_knobs(&path, preset));
+      let config = load_config_from_file(&script, knob_values);
 
-      for (mask, nodes) in nodes_by_layer {
-        println!("  subgraph cluster_{} {{", mask);
+      let (overlay, server_name) = HeadlessOverlay::serve(overlay_ipc::Capabilities::ALL);
+      overlay.resize(width, height);
 
-        let mut v = vec![];
-        #[allow(clippy::needless_range_loop)]
-        for i in 0..layers.len() {
-          if mask & LayerMask::user_layer(i).unwrap() != LayerMask::EMPTY {
-            v.push(layers[i].clone());
-          }
+      let client = match overlay_ipc::connect_to_ipc_server(server_name) {
+        Ok(client) => client,
+        Err(e) => {
+          eprintln!("Can't connect to headless overlay: {}", e);
+          std::process::exit(1);
         }
-        println!("    label = \"{}\";", v.join(" | "));
-
-        for node in nodes {
-          let label = if node.opts.is_empty() {
-            node.name.to_string()
-          } else {
-            format!("{}({})", node.name, node.opts)
-          };
-
-          print!("    {:4} [label=\"", node.id);
-          if label.len() > 25 {
-            print!("{}...", &label[..25]);
-          } else {
-            print!("{}", label);
-          }
-          println!("\", tooltip=\"{}: {}\"];", node.id, label);
+      };
+
+      // recorded input runs in virtual time, as fast as snapshots can be taken
+      let virtual_clock = clock::VirtualClock::default();
+      let real_clock    = clock::RealClock::new();
+      let clock: &dyn clock::Clock = if input.is_some() { &virtual_clock } else { &real_clock };
+
+      let mut dummy_output = output::DummyOutput {};
+      let mut mapper = mapper::Mapper::new(None, Some(&client), None, config, &mut dummy_output, clock, 0);
+      mapper.init_probes();
+
+      let result = match input {
+        Some(path) => match std::fs::read_to_string(&path).map_err(|e| format!("{}: {}", path, e)).and_then(|s| preview::parse_recording(&s)) {
+          Ok(steps) => {
+            let dir = Path::new(&path).parent().unwrap_or(Path::new("."));
+            preview::replay(&mut mapper, &overlay, &client, &steps, dir)
+          },
+          Err(e) => Err(e)
+        },
+        None => {
+          let (controller_state_sender, controller_state_receiver) = mpsc::channel();
+
+          thread::spawn(move || {
+            if let Some(controller) = find_controller(serial_or_partial_path).unwrap() {
+              controller.run_polling_loop(controller_state_sender, None).unwrap();
+            } else {
+              eprintln!("No controllers found.");
+              std::process::exit(1);
+            }
+          });
+
+          preview::run_live(&mut mapper, &overlay, &client, &controller_state_receiver, Path::new(&output))
         }
+      };
 
-        println!("  }};");
+      if let Err(e) = result {
+        eprintln!("Can't render preview: {}", e);
+        std::process::exit(1);
       }
-
-      for (source, target) in edges {
-        println!("  {:4} -> {:4};", source, target);
+    },
+    Some(Command::Knobs { command: Some(KnobsCommand::Diff { script, knobs: knobs_path, preset }), .. }) => {
+      let knob_values = load_knobs(&knobs_path, preset);
+      let config::Config { knobs: defaults, .. } = load_config_from_file(&script, None);
+      let config::Config { knobs, .. }           = load_config_from_file(&script, Some(knob_values.clone()));
+
+      // knobs declared conditionally might only exist with non-default values
+      for knob in &knobs {
+        match defaults.iter().find(|default| default.name() == knob.name()) {
+          Some(default) if default.compare_value(knob) => {},
+          Some(default) => println!("{}: {} -> {}", knob.name(), knob_file::serialize_knob(default), knob_file::serialize_knob(knob)),
+          None          => println!("{}: -> {}", knob.name(), knob_file::serialize_knob(knob))
+        }
       }
 
-      println!("}}");
+      let mut unused = knob_values.keys().filter(|name| !knobs.iter().any(|knob| knob.name() == **name)).collect::<Vec<_>>();
+      unused.sort();
+      for name in unused {
+        println!("{}: not used by the script", name);
+      }
+    },
+    Some(Command::Knobs { script, knobs, preset, .. }) => {
+      let knob_values = knobs.map(|path| load_knobs(&path, preset));
+      let config::Config { knobs, .. } = load_config_from_file(&script.unwrap(), knob_values);
+      println!("{}", serde_json::to_string_pretty(&knob_file::serialize_knobs(&knobs)).unwrap());
     },
-    Some(Command::Knobs { script, knobs }) => {
-      let knob_values = knobs.map(|path| load_knobs_from_file(&path));
-      let config::Config { knobs, .. } = load_config_from_file(&script, knob_values);
-      println!("{}", serialize_knobs(&knobs));
+    Some(Command::Repl { controller: serial_or_partial_path }) => {
+      repl::run(serial_or_partial_path);
     },
-    Some(Command::List { controllers: show_controllers, overlays: _ }) => {
+    Some(Command::List { controllers: show_controllers, overlays: show_overlays }) => {
       if show_controllers.unwrap_or(true) {
         let controllers = available_controllers().unwrap();
         if !controllers.is_empty() {
@@ -479,7 +774,18 @@ fn main() {
           eprintln!("No controllers found.");
         }
       }
-      //TODO: list overlays
+      if show_overlays.unwrap_or(true) {
+        match overlay_ipc::list_overlays() {
+          Ok(overlays) if !overlays.is_empty() => {
+            println!("Overlays: ");
+            for overlay in overlays {
+              println!("  {} [{}] @ {}", overlay.overlay_name, overlay.pid, overlay.executable);
+            }
+          },
+          Ok(_)  => eprintln!("No overlays found."),
+          Err(e) => eprintln!("Can't list overlays: {}", e)
+        }
+      }
     },
     None => {}
   }
@@ -5,13 +5,16 @@ use strum::{EnumCount, IntoEnumIterator};
 
 use overlay_ipc::Knob;
 
+use crate::clock::*;
 use crate::config::Config;
-use crate::controllers::{ControllerCommand, ControllerState};
+use crate::controllers::{ControllerCommand, ControllerState, HapticFeedbackTarget};
+use crate::log::log;
 use crate::output::{KeyboardKey, MapperIO, MouseAxis, MouseButton};
+use crate::process_memory::ProcessMemory;
 use crate::stages::*;
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Ord, PartialOrd)]
-pub struct LayerMask(u32);
+pub struct LayerMask(u128);
 
 impl std::fmt::Display for LayerMask {
 
@@ -56,16 +59,16 @@ impl std::ops::Not for LayerMask {
 
 impl LayerMask {
 
-  pub const MAX_LAYERS:          usize = 32;
-  pub const MAX_USER_LAYERS:     usize = 24;
+  pub const MAX_LAYERS:          usize = 128;
+  pub const MAX_USER_LAYERS:     usize = 120;
   pub const MAX_INTERNAL_LAYERS: usize = Self::MAX_LAYERS - Self::MAX_USER_LAYERS;
   pub const EMPTY:               Self  = Self(0);
   pub const ALL_USER_BITS:       Self  = Self((1 << Self::MAX_USER_LAYERS) - 1);
-  pub const ALL_INTERNAL_BITS:   Self  = Self(u32::MAX & !((1 << Self::MAX_USER_LAYERS) - 1));
-  pub const ALL:                 Self  = Self(u32::MAX);
+  pub const ALL_INTERNAL_BITS:   Self  = Self(u128::MAX & !((1 << Self::MAX_USER_LAYERS) - 1));
+  pub const ALL:                 Self  = Self(u128::MAX);
 
   pub fn user_layer(n: usize) -> Result<Self, ()> {
-    if n <= Self::MAX_USER_LAYERS {
+    if n < Self::MAX_USER_LAYERS {
       Ok(Self(1 << n))
     } else {
       Err(())
@@ -73,12 +76,20 @@ impl LayerMask {
   }
 
   pub fn internal_layer(n: usize) -> Result<Self, ()> {
-    if n <= Self::MAX_INTERNAL_LAYERS {
+    if n < Self::MAX_INTERNAL_LAYERS {
       Ok(Self(1 << (Self::MAX_USER_LAYERS + n)))
     } else {
       Err(())
     }
   }
+
+  // names of the user layers in the mask, layers are named in user layer order
+  pub fn layer_names<S: AsRef<str>>(self, layers: &[S]) -> Vec<String> {
+    layers.iter().enumerate()
+      .filter(|(i, _)| self & Self::user_layer(*i).unwrap() != Self::EMPTY)
+      .map(|(_, layer)| layer.as_ref().to_string())
+      .collect()
+  }
 }
 
 lazy_static! {
@@ -89,11 +100,38 @@ pub struct Layer {
   name: String
 }
 
+impl AsRef<str> for Layer {
+  fn as_ref(&self) -> &str {
+    &self.name
+  }
+}
+
+type KnobsListener = Box<dyn FnMut(&[Knob])>;
+
+pub enum FrontendRequest {
+  EditKnob(Knob),
+  CommitKnobs,
+  SetKnob(Knob), // edits and commits a single knob, other edits stay pending
+  SetMode(Vec<String>), // layer names
+  Reload
+}
+
+// a way to watch and drive the mapper without the overlay (e.g. the terminal UI)
+pub trait Frontend {
+  // called on every frame
+  fn update(&mut self, state: &ControllerState, mode: &Mode, knobs: &[Knob], knobs_edited: &[Knob]);
+  fn requests(&mut self) -> Vec<FrontendRequest>;
+}
+
 pub struct Mapper<'m> {
 
   controller: Option<&'m std::sync::mpsc::Sender<ControllerCommand>>,
   overlay:    Option<&'m overlay_ipc::CommandSender>,
+  process:    Option<&'m mut ProcessMemory>,
   output:     &'m mut dyn MapperIO,
+  clock:      &'m dyn Clock,
+
+  overlay_lost: bool,
 
   curr_layer_mask: LayerMask,
   next_layer_mask: Option<LayerMask>,
@@ -106,9 +144,10 @@ pub struct Mapper<'m> {
 
   probes:        HashMap<StageId, Probe>,
   probe_values:  HashMap<StageId, ProbeValue>,
-  //TODO: we should probably use a single receiver for all probes or at least refactor them to use a single return type
-  probe_ssr_rcv: HashMap<StageId, overlay_ipc::ipc::IpcReceiver<overlay_ipc::ScreenScrapingResult>>,
-  probe_u64_rcv: HashMap<StageId, overlay_ipc::ipc::IpcReceiver<u64>>,
+  probe_rcv:     Option<overlay_ipc::ipc::IpcReceiver<overlay_ipc::ProbeReport>>,
+
+  overlay_status: OverlayStatus,
+  event_rcv:      Option<overlay_ipc::ipc::IpcReceiver<overlay_ipc::OverlayEvent>>,
 
   shapes:           HashMap<StageId, Vec<Vec<overlay_ipc::Shape>>>,
   curr_shape_state: HashMap<StageId, Vec<u64>>,
@@ -119,33 +158,65 @@ pub struct Mapper<'m> {
   curr_mouse_button_state: [bool; MouseButton::COUNT],
   next_mouse_button_state: [bool; MouseButton::COUNT],
 
+  curr_rumble_state: [f32; HapticFeedbackTarget::COUNT],
+  next_rumble_state: [f32; HapticFeedbackTarget::COUNT],
+
   rel_mouse_x: f32,
   rel_mouse_y: f32,
 
   log_level: u8,
 
   knobs: Vec<Knob>,
-  knobs_changed: bool
+  knobs_edited: Vec<Knob>, // in the overlay menu
+  knobs_changed: bool,
+  preset_offset: isize, // requested knob preset switch, relative to the current one
+  live_knobs: HashMap<String, std::rc::Rc<std::cell::Cell<f32>>>,
+  live_knobs_listener: Option<KnobsListener>,
+
+  frontends:        Vec<&'m mut dyn Frontend>,
+  reload_requested: bool
+}
+
+// active layers by name, so that the mode survives reloading a config with a different set of layers
+pub struct Mode {
+  layers:   Vec<String>,
+  internal: LayerMask
+}
+
+impl Mode {
+  pub fn layers(&self) -> &[String] {
+    &self.layers
+  }
 }
 
 pub enum ExitReason {
-  KnobsChanged(Vec<Knob>),
-  OverlayRequired
+  KnobsChanged(Vec<Knob>, Mode),
+  KnobPresetSwitched(Vec<Knob>, isize, Mode), // knob values include unsaved menu edits
+  ReloadRequested(Mode),
+  OverlayLost,
+  OverlayRequired,
+  ProfileChanged(Option<crate::profiles::Profile>)
 }
 
 impl<'m> Mapper<'m> {
 
   pub fn new(controller: Option<&'m std::sync::mpsc::Sender<ControllerCommand>>,
              overlay:    Option<&'m overlay_ipc::CommandSender>,
+             process:    Option<&'m mut ProcessMemory>,
              config: Config,
              output:     &'m mut dyn MapperIO,
+             clock:      &'m dyn Clock,
              log_level:  u8
   ) -> Self {
 
     let mut m = Self {
       controller,
       overlay,
+      process,
       output,
+      clock,
+
+      overlay_lost: false,
 
       curr_layer_mask: LayerMask::user_layer(0).unwrap(),
       next_layer_mask: None,
@@ -159,8 +230,10 @@ impl<'m> Mapper<'m> {
       //TODO: extract probes into a separate object?
       probes:        HashMap::new(),
       probe_values:  HashMap::new(),
-      probe_ssr_rcv: HashMap::new(),
-      probe_u64_rcv: HashMap::new(),
+      probe_rcv:     None,
+
+      overlay_status: OverlayStatus::default(),
+      event_rcv:      None,
 
       shapes:           HashMap::new(),
       curr_shape_state: HashMap::new(),
@@ -171,19 +244,29 @@ impl<'m> Mapper<'m> {
       curr_mouse_button_state: [false; MouseButton::COUNT],
       next_mouse_button_state: [false; MouseButton::COUNT],
 
+      curr_rumble_state: [0.0; HapticFeedbackTarget::COUNT],
+      next_rumble_state: [0.0; HapticFeedbackTarget::COUNT],
+
       rel_mouse_x: 0.0,
       rel_mouse_y: 0.0,
 
       log_level,
 
+      knobs_edited: config.knobs.clone(),
       knobs: config.knobs,
-      knobs_changed: false
+      knobs_changed: false,
+      preset_offset: 0,
+      live_knobs: config.live_knobs,
+      live_knobs_listener: None,
+
+      frontends:        Vec::new(),
+      reload_requested: false
     };
 
     //TODO: we should probably accept the mask number there as well
     fn register_layer(mapper: &mut Mapper, name: Option<String>) -> LayerMask {
       let i = mapper.layers.len();
-      assert!(i < std::mem::size_of::<LayerMask>() * 8);
+      assert!(i < LayerMask::MAX_USER_LAYERS);
 
       mapper.layers.push(Layer { name: name.unwrap_or_else(|| "???".to_string()) });
       LayerMask::user_layer(i).unwrap()
@@ -192,7 +275,7 @@ impl<'m> Mapper<'m> {
     for name in config.layers {
       let mask = register_layer(&mut m, Some(name.clone()));
       if log_level > 0 {
-        eprintln!("layer {:?}: {}", name, mask);
+        log!("layer {:?}: {}", name, mask);
       }
     }
 
@@ -207,7 +290,7 @@ impl<'m> Mapper<'m> {
 
       if let Some(probe) = &stage_description.probe {
         m.probes.insert(stage_id, probe.clone());
-        m.probe_values.insert(stage_id, ProbeValue { u64: 0 }); // ?
+        m.probe_values.insert(stage_id, ProbeValue { fff32: (0.0, 0.0, 0.0) }); // ?
       }
 
       let layer_count = stage_description.shapes.len();
@@ -219,7 +302,7 @@ impl<'m> Mapper<'m> {
           if shape_count <= 64 {
             v.push(stage_description.shapes[i].clone());
           } else {
-            eprintln!("too many shapes ({}) in stage {} (id: {}) layer {}, truncated",
+            log!("too many shapes ({}) in stage {} (id: {}) layer {}, truncated",
               shape_count, stage_description.name, stage_id, i);
             v.push(stage_description.shapes[i][0..=63].to_vec());
           }
@@ -231,91 +314,285 @@ impl<'m> Mapper<'m> {
     m
   }
 
-  fn init_probes(&mut self) -> bool {
-
-    //TODO: rename init_probes to init or move overlay layer registration to some other place
-    if let Some(overlay) = &self.overlay {
-      overlay.send(overlay_ipc::OverlayCommand::ResetOverlay).unwrap();
+  // the overlay goes away together with the game, which shouldn't take the mapper down
+  fn send_to_overlay(&mut self, command: overlay_ipc::OverlayCommand) {
+    if let Some(overlay) = self.overlay {
+      if overlay.send(command).is_err() {
+        log!("lost connection to overlay");
+        self.overlay      = None;
+        self.overlay_lost = true;
+        self.overlay_status.connected = false;
+      }
     }
+  }
 
-    for (id, probe) in &self.probes {
-      match probe {
+  pub fn init_probes(&mut self) -> bool {
 
-        Probe::Screen { target } => {
+    //TODO: rename init_probes to init or move overlay layer registration to some other place
+    self.send_to_overlay(overlay_ipc::OverlayCommand::ResetOverlay);
 
-          let (sender, receiver) = overlay_ipc::ipc::channel().unwrap();
+    if self.overlay.is_some() {
+      let (sender, receiver) = overlay_ipc::ipc::channel().unwrap();
+      self.send_to_overlay(overlay_ipc::OverlayCommand::SubscribeToEvents(sender));
+      self.event_rcv = Some(receiver);
+      self.overlay_status.connected = true;
+    }
 
-          if let Some(overlay) = &self.overlay {
-            overlay.send(overlay_ipc::OverlayCommand::AddScreenScrapingArea(target.clone(), sender)).unwrap();
-            self.probe_ssr_rcv.insert(*id, receiver);
-          } else {
-            eprintln!("Probe {:?} requires overlay to be present", probe);
-            return false;
-          }
-        },
+    // memory probes are read directly from the target process if one is specified
+    let overlay_probes = self.probes.iter()
+      .filter(|(_, probe)| !(matches!(probe, Probe::Memory { .. }) && self.process.is_some()))
+      .collect::<Vec<_>>();
 
-        Probe::Memory {usize, address, offsets } => {
+    if !overlay_probes.is_empty() {
 
-          let (sender, receiver) = overlay_ipc::ipc::channel().unwrap();
+      if self.overlay.is_none() {
+        log!("Probe {:?} requires overlay to be present", overlay_probes[0].1);
+        return false;
+      }
 
-          if let Some(overlay) = &self.overlay {
-            overlay.send(overlay_ipc::OverlayCommand::AddMemoryCheck(*usize, *address, offsets.clone(), sender)).unwrap();
-            self.probe_u64_rcv.insert(*id, receiver);
-          } else {
-            eprintln!("Probe {:?} requires overlay to be present", probe);
-            return false;
-          }
+      let capabilities = self.overlay.unwrap().capabilities;
+      let (sender, receiver) = overlay_ipc::ipc::channel().unwrap();
+
+      // unsupported probes are left at their initial values
+      let commands = overlay_probes.into_iter().filter_map(|(id, probe)| match probe {
+        Probe::Screen { target } if capabilities.screen_probes =>
+          Some(overlay_ipc::OverlayCommand::AddScreenScrapingArea(*id as u64, target.clone(), sender.clone())),
+        Probe::Memory { usize, module, address, offsets } if capabilities.memory_probes =>
+          Some(overlay_ipc::OverlayCommand::AddMemoryCheck(*id as u64, *usize, module.clone(), *address, offsets.clone(), sender.clone())),
+        _ => {
+          log!("Probe {:?} is not supported by the overlay, disabled", probe);
+          None
         }
+      }).collect::<Vec<_>>();
+
+      for command in commands {
+        self.send_to_overlay(command);
       }
+
+      self.probe_rcv = Some(receiver);
     }
 
     //TODO: rename init_probes to init or move shape registration to some other place
     if !self.shapes.is_empty() {
-      if let Some(overlay) = &self.overlay {
-        for (id, shapes) in &self.shapes {
-          overlay.send(overlay_ipc::OverlayCommand::RegisterShapes { stage_id: *id as u64, shapes: shapes.clone() }).unwrap();
-          self.curr_shape_state.insert(*id, vec![0; shapes.len()]);
-          self.next_shape_state.insert(*id, vec![0; shapes.len()]);
+      if let Some(overlay) = self.overlay {
+        if !overlay.capabilities.shapes {
+          log!("Menus are not supported by the overlay, hidden");
+        }
+        for (id, shapes) in self.shapes.clone() {
+          if overlay.capabilities.shapes {
+            self.send_to_overlay(overlay_ipc::OverlayCommand::RegisterShapes { stage_id: id as u64, shapes: shapes.clone() });
+          }
+          self.curr_shape_state.insert(id, vec![0; shapes.len()]);
+          self.next_shape_state.insert(id, vec![0; shapes.len()]);
         }
       } else {
-        eprintln!("Menus require overlay to be present");
+        log!("Menus require overlay to be present");
         return false;
       }
     }
 
-    if let Some(overlay) = &self.overlay {
-      overlay.send(overlay_ipc::OverlayCommand::SetLayerNames(self.layers.iter().map(|layer| layer.name.clone()).collect())).unwrap();
-    }
-
-    if let Some(overlay) = &self.overlay {
-      overlay.send(overlay_ipc::OverlayCommand::RegisterKnobs(self.knobs.clone())).unwrap();
+    self.send_to_overlay(overlay_ipc::OverlayCommand::SetLayerNames(self.layers.iter().map(|layer| layer.name.clone()).collect()));
+    if self.overlay.map(|overlay| overlay.capabilities.knobs_ui).unwrap_or(false) {
+      self.send_to_overlay(overlay_ipc::OverlayCommand::RegisterKnobs(self.knobs.clone()));
     }
 
     true
   }
 
   fn poll_probes(&mut self) {
-    for (id, probe) in &self.probes {
-      match probe {
-        Probe::Screen { .. } => {
-          if let Ok(result) = self.probe_ssr_rcv[id].try_recv() {
-            self.probe_values.insert(*id, ProbeValue { ff32: (result.pixels_in_range, result.uniformity_score) });
+    if let Some(receiver) = &self.probe_rcv {
+      // keep only the latest value of each probe
+      while let Ok((id, result)) = receiver.try_recv() {
+        let value = match result {
+          overlay_ipc::ProbeResult::Screen(result) => match result {
+            overlay_ipc::ScreenScrapingResult::HsvRange { pixels_in_range, uniformity_score } =>
+              ProbeValue { ff32: (pixels_in_range, uniformity_score) },
+            overlay_ipc::ScreenScrapingResult::Template { correlation } =>
+              ProbeValue { f32: correlation },
+            overlay_ipc::ScreenScrapingResult::MeanColor(color) =>
+              ProbeValue { fff32: (color.r, color.g, color.b) },
+            overlay_ipc::ScreenScrapingResult::Histogram { distance } =>
+              ProbeValue { f32: distance }
+          },
+          overlay_ipc::ProbeResult::Memory(result) => ProbeValue { u64: result }
+        };
+        self.probe_values.insert(id as StageId, value);
+      }
+    }
+
+    if let Some(process) = &mut self.process {
+      for (id, probe) in &self.probes {
+        if let Probe::Memory { usize, module, address, offsets } = probe {
+          // keep the last known value while the process is unavailable
+          if let Some(value) = process.read_pointer_chain(*usize, module, *address, offsets) {
+            self.probe_values.insert(*id, ProbeValue { u64: value });
           }
+        }
+      }
+    }
+  }
+
+  fn poll_events(&mut self) {
+    let mut commit_knobs = false;
+
+    if let Some(receiver) = &self.event_rcv {
+      while let Ok(event) = receiver.try_recv() {
+        match event {
+          overlay_ipc::OverlayEvent::FocusChanged(focused) => {
+            self.overlay_status.focused = focused;
+          },
+          overlay_ipc::OverlayEvent::FramePresented { frame_time } => {
+            self.overlay_status.frame_time = frame_time;
+          },
+          overlay_ipc::OverlayEvent::KnobChanged(knob) => {
+            if let Some(edited) = self.knobs_edited.iter_mut().find(|edited| edited.name() == knob.name()) {
+              *edited = knob;
+            }
+          },
+          overlay_ipc::OverlayEvent::SwapchainResized { width, height } => {
+            self.overlay_status.screen_width  = width;
+            self.overlay_status.screen_height = height;
+          },
+          overlay_ipc::OverlayEvent::UIVisibilityChanged { hud, knobs_menu } => {
+            // all knob edits made in the menu have already arrived by the time it is reported closed
+            commit_knobs = commit_knobs || (self.overlay_status.knobs_menu_visible && !knobs_menu);
+
+            self.overlay_status.hud_visible        = hud;
+            self.overlay_status.knobs_menu_visible = knobs_menu;
+          }
+        }
+      }
+    }
+
+    if commit_knobs {
+      self.commit_knobs();
+    }
+  }
+
+  fn commit_knobs(&mut self) {
+    self.set_knobs(self.knobs_edited.clone());
+  }
+
+  // only knobs read at evaluation time (e.g. in `if`) require reloading the config
+  fn set_knobs(&mut self, knobs: Vec<Knob>) {
+    let mut changed = self.knobs.iter().zip(&knobs).filter(|(knob, edited)| !knob.compare_value(edited)).peekable();
+    if changed.peek().is_none() {
+      return;
+    }
+
+    if changed.any(|(knob, _)| !self.live_knobs.contains_key(&knob.name())) {
+      self.knobs_changed = true;
+    } else {
+      for knob in &knobs {
+        if let (Knob::Number { name, value, .. }, Some(cell)) = (knob, self.live_knobs.get(&knob.name())) {
+          if self.log_level > 0 {
+            log!("knob {:?}: {}", name, value);
+          }
+          cell.set(*value);
+        }
+      }
+      if let Some(listener) = &mut self.live_knobs_listener {
+        listener(&knobs);
+      }
+    }
+
+    self.knobs = knobs;
+  }
+
+  pub fn knobs(&self) -> &[Knob] {
+    &self.knobs
+  }
+
+  // called with all knob values after live knobs are changed, structural changes end up in ExitReason::KnobsChanged
+  pub fn on_live_knobs_changed(&mut self, listener: impl FnMut(&[Knob]) + 'static) {
+    self.live_knobs_listener = Some(Box::new(listener));
+  }
+
+  pub fn attach_frontend(&mut self, frontend: &'m mut dyn Frontend) {
+    self.frontends.push(frontend);
+  }
+
+  // knob edits are handled the same way as the ones coming from the overlay menu
+  fn poll_frontends(&mut self, state: &ControllerState) {
+    if self.frontends.is_empty() {
+      return;
+    }
+
+    let requests = self.frontends.iter_mut().flat_map(|frontend| frontend.requests()).collect::<Vec<_>>();
+    for request in requests {
+      match request {
+        FrontendRequest::EditKnob(knob) => {
+          if let Some(edited) = self.knobs_edited.iter_mut().find(|edited| edited.name() == knob.name()) {
+            *edited = knob;
+          }
+        },
+        FrontendRequest::CommitKnobs => {
+          self.commit_knobs();
+        },
+        FrontendRequest::SetKnob(knob) => {
+          let mut knobs = self.knobs.clone();
+          for other in knobs.iter_mut().chain(self.knobs_edited.iter_mut()) {
+            if other.name() == knob.name() {
+              *other = knob.clone();
+            }
+          }
+          self.set_knobs(knobs);
         },
-        Probe::Memory { .. } => {
-          if let Ok(result) = self.probe_u64_rcv[id].try_recv() {
-            self.probe_values.insert(*id, ProbeValue { u64: result});
+        FrontendRequest::SetMode(layers) => {
+          // same as set_mode in the config, applied on the next frame
+          let mask = self.layer_mask(&layers);
+          if mask != LayerMask::EMPTY {
+            self.next_layer_mask = Some(mask | (self.curr_layer_mask & LayerMask::ALL_INTERNAL_BITS));
           }
+        },
+        FrontendRequest::Reload => {
+          self.reload_requested = true;
         }
       }
     }
+
+    let mode = self.mode();
+    for frontend in &mut self.frontends {
+      frontend.update(state, &mode, &self.knobs, &self.knobs_edited);
+    }
+  }
+
+  pub fn mode(&self) -> Mode {
+    let layers = self.curr_layer_mask.layer_names(&self.layers);
+    Mode { layers, internal: self.curr_layer_mask & LayerMask::ALL_INTERNAL_BITS }
+  }
+
+  // layers which no longer exist are dropped, the default mode is kept if none are left
+  pub fn restore_mode(&mut self, mode: &Mode) {
+    let mut mask = self.layer_mask(&mode.layers);
+    if mask == LayerMask::EMPTY {
+      mask = self.curr_layer_mask & LayerMask::ALL_USER_BITS;
+    }
+
+    // reported to the overlay on the next frame
+    self.curr_layer_mask = mask | mode.internal;
+    self.next_layer_mask = Some(self.curr_layer_mask);
   }
 
-  //TODO: we need to fuzz probe's on/off states instead of raw input data
-  fn randomize_probe_values<R: ::rand::Rng>(&mut self, rng: &mut R) {
-    for id in self.probes.keys() {
-      self.probe_values.insert(*id, ProbeValue { u64: rng.gen() });
+  fn layer_mask(&self, names: &[String]) -> LayerMask {
+    let mut mask = LayerMask::EMPTY;
+    for (i, layer) in self.layers.iter().enumerate() {
+      if names.contains(&layer.name) {
+        mask = mask | LayerMask::user_layer(i).unwrap();
+      }
     }
+    mask
+  }
+
+  pub fn curr_layer_mask(&self) -> LayerMask {
+    self.curr_layer_mask
+  }
+
+  // sorted by stage id, so that recordings can refer to probes by index
+  pub fn probe_ids(&self) -> Vec<StageId> {
+    let mut ids = self.probes.keys().copied().collect::<Vec<_>>();
+    ids.sort();
+    ids
   }
 
   fn apply_action(&mut self, i: usize) {
@@ -361,13 +638,14 @@ impl<'m> Mapper<'m> {
       },
 
       Action::ToggleShapes { stage_id, layer, mask } => {
-        self.next_shape_state.get_mut(&stage_id).unwrap()[layer as usize] = mask;
+        // shapes are only registered with an overlay, there is nothing to toggle in dot or fuzz runs
+        if let Some(state) = self.next_shape_state.get_mut(&stage_id) {
+          state[layer as usize] = mask;
+        }
       },
 
       Action::ToggleOverlayUI => {
-        if let Some(overlay) = self.overlay {
-          overlay.send(overlay_ipc::OverlayCommand::ToggleUI).unwrap();
-        }
+        self.send_to_overlay(overlay_ipc::OverlayCommand::ToggleUI);
       },
 
       Action::HapticFeedback(target, effect) => {
@@ -376,36 +654,29 @@ impl<'m> Mapper<'m> {
         }
       },
 
-      Action::SendOverlayMenuCommand(command) => {
-        if let Some(overlay) = self.overlay {
-          overlay.send(overlay_ipc::OverlayCommand::MenuCommand(command)).unwrap();
-          if command == OverlayMenuCommand::CloseKnobsMenu {
-
-            let (sender, receiver) = overlay_ipc::ipc::channel().unwrap();
-            overlay.send(overlay_ipc::OverlayCommand::GetKnobs(sender)).unwrap();
-
-            let knobs = receiver.recv().unwrap(); // it's ok to block here
-            assert_eq!(self.knobs.len(), knobs.len());
-
-            #[allow(clippy::needless_range_loop)]
-            for i in 0..self.knobs.len() {
-              if !self.knobs[i].compare_value(&knobs[i]) {
-                self.knobs_changed = true;
-                break;
-              }
-            }
+      Action::Rumble(target, intensity) => {
+        let state = &mut self.next_rumble_state[target as usize];
+        *state = state.max(intensity);
+      },
 
-            if self.knobs_changed {
-              self.knobs = knobs;
-            }
-          }
-        }
+      Action::SendOverlayMenuCommand(OverlayMenuCommand::SelectPrevPreset) => {
+        self.preset_offset -= 1;
+      },
+
+      Action::SendOverlayMenuCommand(OverlayMenuCommand::SelectNextPreset) => {
+        self.preset_offset += 1;
+      },
+
+      Action::SendOverlayMenuCommand(command) => {
+        self.send_to_overlay(overlay_ipc::OverlayCommand::MenuCommand(command));
       }
     }
   }
 
   fn apply_actions(&mut self, state: &ControllerState, now: Timestamp) {
 
+    crate::stages::clear_trace();
+
     self.actions.clear();
     self.discarded_actions.clear();
 
@@ -417,6 +688,10 @@ impl<'m> Mapper<'m> {
       self.next_mouse_button_state[btn as usize] = false;
     }
 
+    for target in HapticFeedbackTarget::iter() {
+      self.next_rumble_state[target as usize] = 0.0;
+    }
+
     for (_, masks) in self.next_shape_state.iter_mut() {
       for mask in masks {
         *mask = 0;
@@ -433,7 +708,7 @@ impl<'m> Mapper<'m> {
     for &mut (mask, ref mut pipeline, ref mut should_apply_empty_state) in &mut self.pipelines {
       if *should_apply_empty_state {
         assert_eq!(mask & self.curr_layer_mask, LayerMask::EMPTY);
-        let ctx = Context { state: &EMPTY_STATE, time: now, layers: self.curr_layer_mask, probe_values: &self.probe_values };
+        let ctx = Context { state: &EMPTY_STATE, time: now, layers: self.curr_layer_mask, probe_values: &self.probe_values, overlay: &self.overlay_status };
         pipeline.apply(&ctx, &mut self.discarded_actions);
         *should_apply_empty_state = false;
       }
@@ -451,7 +726,7 @@ impl<'m> Mapper<'m> {
 
     for &mut (mask, ref mut pipeline, _) in &mut self.pipelines {
       if mask & self.curr_layer_mask != LayerMask::EMPTY {
-        let ctx = Context { state, time: now, layers: self.curr_layer_mask, probe_values: &self.probe_values };
+        let ctx = Context { state, time: now, layers: self.curr_layer_mask, probe_values: &self.probe_values, overlay: &self.overlay_status };
         pipeline.apply(&ctx, &mut self.actions);
       }
     }
@@ -462,12 +737,10 @@ impl<'m> Mapper<'m> {
 
     if let Some(next_mask) = self.next_layer_mask {
 
-      if let Some(overlay) = self.overlay {
-        overlay.send(overlay_ipc::OverlayCommand::SetMode(next_mask.0 as u64)).unwrap();
-      }
+      self.send_to_overlay(overlay_ipc::OverlayCommand::SetMode(next_mask.0));
 
       if self.log_level > 0 {
-        eprintln!("switch to mode: {}", next_mask);
+        log!("switch to mode: {}", next_mask);
       }
 
       //TODO: should probably think of something more intelligent for resetting double press timers, etc.
@@ -509,59 +782,148 @@ impl<'m> Mapper<'m> {
 
     self.output.syn();
 
-    if let Some(overlay) = self.overlay {
+    for target in HapticFeedbackTarget::iter() {
+      let intensity = self.next_rumble_state[target as usize];
+      if intensity != self.curr_rumble_state[target as usize] {
+        if let Some(controller) = self.controller {
+          controller.send(ControllerCommand::Rumble(target, intensity)).unwrap();
+        }
+        self.curr_rumble_state[target as usize] = intensity;
+      }
+    }
+
+    if self.overlay.map(|overlay| overlay.capabilities.shapes).unwrap_or(false) {
+      let mut commands = vec![];
       for (stage_id, masks) in self.next_shape_state.iter() {
         #[allow(clippy::needless_range_loop)]
         for i in 0..masks.len() {
           if masks[i] != self.curr_shape_state[stage_id][i] {
-            overlay.send(overlay_ipc::OverlayCommand::ToggleShapes { stage_id: *stage_id as u64, layer: i as u8, mask: masks[i] }).unwrap();
+            commands.push(overlay_ipc::OverlayCommand::ToggleShapes { stage_id: *stage_id as u64, layer: i as u8, mask: masks[i] });
             self.curr_shape_state.get_mut(stage_id).unwrap()[i] = masks[i];
           }
         }
       }
+
+      for command in commands {
+        self.send_to_overlay(command);
+      }
     }
   }
 
+  // None for real time
+  pub fn virtual_clock(&self) -> Option<&'m VirtualClock> {
+    self.clock.as_virtual()
+  }
+
+  // a single frame at the clock's current time, for driving the mapper without run (e.g. with recorded input)
+  pub fn step(&mut self, state: &ControllerState) {
+    self.apply_actions(state, self.clock.now());
+    self.poll_probes();
+    self.poll_events();
+    self.poll_frontends(state);
+  }
+
   // TODO: Sender<ControllerCommand> vs Receiver<ControllerState> set up
   #[cfg(not(test))]
-  pub fn run(&mut self, controller_state_receiver: &'m std::sync::mpsc::Receiver<ControllerState>) -> Result<ExitReason, String> {
+  pub fn run(&mut self,
+    controller_state_receiver: &'m std::sync::mpsc::Receiver<ControllerState>,
+    profile_receiver:          Option<&'m std::sync::mpsc::Receiver<Option<crate::profiles::Profile>>>
+  ) -> Result<ExitReason, String> {
 
     if !self.init_probes() {
       return Ok(ExitReason::OverlayRequired);
     }
 
-    // make sure LOGO (GUIDE) button is not pressed
+    // make sure LOGO (GUIDE) button is not pressed, same for bumpers switching knob presets by default
     loop {
       let state = controller_state_receiver.recv().map_err(|e| format!("{}", e))?;
-      if !state.buttons.steam {
+      if !state.buttons.steam && !state.buttons.lbump && !state.buttons.rbump {
         break;
       }
     }
 
     loop {
       let state = controller_state_receiver.recv().map_err(|e| format!("{}", e))?;
-      let now   = std::time::Instant::now(); //TODO: put timestamp into ControllerState
-      self.apply_actions(&state, Timestamp(now));
-      self.poll_probes();
+      self.step(&state); //TODO: put timestamp into ControllerState
 
       if self.knobs_changed {
-        return Ok(ExitReason::KnobsChanged(self.knobs.clone()));
+        self.release_all();
+        return Ok(ExitReason::KnobsChanged(self.knobs.clone(), self.mode()));
+      }
+
+      if self.preset_offset != 0 {
+        self.release_all();
+        return Ok(ExitReason::KnobPresetSwitched(self.knobs_edited.clone(), self.preset_offset, self.mode()));
+      }
+
+      if self.reload_requested {
+        self.release_all();
+        return Ok(ExitReason::ReloadRequested(self.mode()));
+      }
+
+      if self.overlay_lost {
+        self.release_all();
+        return Ok(ExitReason::OverlayLost);
+      }
+
+      if let Some(profile) = profile_receiver.and_then(|receiver| receiver.try_iter().last()) {
+        self.release_all();
+        return Ok(ExitReason::ProfileChanged(profile));
+      }
+    }
+  }
+
+  // the next config might not know about keys pressed by this one
+  #[cfg(not(test))]
+  fn release_all(&mut self) {
+    for key in KeyboardKey::iter() {
+      if self.curr_keyboard_key_state[key as usize] {
+        self.output.keyboard_key_up(key);
+        self.curr_keyboard_key_state[key as usize] = false;
+      }
+    }
+
+    for btn in MouseButton::iter() {
+      if self.curr_mouse_button_state[btn as usize] {
+        self.output.mouse_button_up(btn);
+        self.curr_mouse_button_state[btn as usize] = false;
+      }
+    }
+
+    self.output.syn();
+
+    for target in HapticFeedbackTarget::iter() {
+      if self.curr_rumble_state[target as usize] != 0.0 {
+        if let Some(controller) = self.controller {
+          controller.send(ControllerCommand::Rumble(target, 0.0)).unwrap();
+        }
+        self.curr_rumble_state[target as usize] = 0.0;
       }
     }
   }
 
+  // every iteration is a frame later than the previous one
   #[cfg(not(test))]
-  pub fn fuzz(&mut self, max_iterations: usize) {
+  pub fn fuzz(&mut self, frame_interval: std::time::Duration, max_iterations: usize) -> Result<(), String> {
+
+    use rand::Rng;
+
+    let clock = self.virtual_clock().ok_or("Fuzzing needs a virtual clock")?;
     let mut rng: rand::rngs::StdRng = rand::SeedableRng::from_seed([42; 32]);
     for i in 1..max_iterations {
       let state = ControllerState::random(&mut rng);
-      self.randomize_probe_values(&mut rng);
-      self.apply_actions(&state, Timestamp(std::time::Instant::now()));
+      // probe conditions are either on or off, whatever raw values are behind them
+      for id in self.probe_ids() {
+        force_probe(id, rng.gen());
+      }
+      clock.advance(frame_interval);
+      self.apply_actions(&state, self.clock.now());
       if i % 10_000 == 0 {
         eprint!("*"); // ?
       }
     }
     eprintln!();
+    Ok(())
   }
 }
 
@@ -604,7 +966,19 @@ mod tests {
   }
 
   fn config(pipelines: Vec<(LayerMask, Box<dyn Pipeline<()>>)>) -> Config {
-    Config { pipelines, layers: vec![], knobs: vec![] }
+    Config { pipelines, system: vec![], layers: vec![], knobs: vec![], live_knobs: HashMap::new(), modes: vec![] }
+  }
+
+  // for things arriving from other threads
+  fn until(timeout: std::time::Duration, mut condition: impl FnMut() -> bool) -> bool {
+    let started_at = std::time::Instant::now();
+    while !condition() {
+      if started_at.elapsed() > timeout {
+        return false;
+      }
+      std::thread::sleep(std::time::Duration::from_millis(1));
+    }
+    true
   }
 
   /*#[test]
@@ -615,30 +989,31 @@ mod tests {
       (LayerMask(0b10), switch_mode(button_input(Button::X), LayerMask(0b01)))
     ]);
 
-    let mut mapper = Mapper::new(None, None, config, &crate::output::DummyOutput, 0);
+    let clock = VirtualClock::default();
+    let mut mapper = Mapper::new(None, None, None, config, &crate::output::DummyOutput, &clock, 0);
     let mut state  = crate::controllers::ControllerState::empty();
 
     assert_eq!(mapper.curr_layer_mask, LayerMask(0b01));
 
     state.buttons.x = true;
-    mapper.apply_actions(&state, Timestamp(1));
+    mapper.apply_actions(&state, Timestamp::from_millis(1));
     assert_eq!(mapper.curr_layer_mask, LayerMask(0b10));
 
-    mapper.apply_actions(&state, Timestamp(2));
+    mapper.apply_actions(&state, Timestamp::from_millis(2));
     assert_eq!(mapper.curr_layer_mask, LayerMask(0b10));
 
     state.buttons.x = false;
-    mapper.apply_actions(&state, Timestamp(3));
+    mapper.apply_actions(&state, Timestamp::from_millis(3));
     assert_eq!(mapper.curr_layer_mask, LayerMask(0b10));
 
-    mapper.apply_actions(&state, Timestamp(4));
+    mapper.apply_actions(&state, Timestamp::from_millis(4));
     assert_eq!(mapper.curr_layer_mask, LayerMask(0b10));
 
     state.buttons.x = true;
-    mapper.apply_actions(&state, Timestamp(5));
+    mapper.apply_actions(&state, Timestamp::from_millis(5));
     assert_eq!(mapper.curr_layer_mask, LayerMask(0b01));
 
-    mapper.apply_actions(&state, Timestamp(6));
+    mapper.apply_actions(&state, Timestamp::from_millis(6));
     assert_eq!(mapper.curr_layer_mask, LayerMask(0b01));
   }*/
 
@@ -652,17 +1027,18 @@ mod tests {
     ]);
 
     let mut output = DummyOutput2 { keys: Cell::new(vec![]) };
-    let mut mapper = Mapper::new(None, None, config, &mut output, 0);
+    let clock = VirtualClock::default();
+    let mut mapper = Mapper::new(None, None, None, config, &mut output, &clock, 0);
     let mut state  = crate::controllers::ControllerState::empty();
 
     state.buttons.a = true;
-    mapper.apply_actions(&state, Timestamp(0));
+    mapper.apply_actions(&state, Timestamp::from_millis(0));
 
     state.buttons.x = true;
-    mapper.apply_actions(&state, Timestamp(1));
+    mapper.apply_actions(&state, Timestamp::from_millis(1));
 
     state.buttons.b = true;
-    mapper.apply_actions(&state, Timestamp(2));
+    mapper.apply_actions(&state, Timestamp::from_millis(2));
 
     assert_eq!(output.keys.take(), vec![(true, KeyboardKey::A), (false, KeyboardKey::A), (true, KeyboardKey::B)])
   }
@@ -677,17 +1053,306 @@ mod tests {
     ]);
 
     let mut output = DummyOutput2 { keys: Cell::new(vec![]) };
-    let mut mapper = Mapper::new(None, None, config, &mut output, 0);
+    let clock = VirtualClock::default();
+    let mut mapper = Mapper::new(None, None, None, config, &mut output, &clock, 0);
     let mut state  = crate::controllers::ControllerState::empty();
 
-    mapper.apply_actions(&state, Timestamp(0));
+    mapper.apply_actions(&state, Timestamp::from_millis(0));
 
     state.buttons.x = true;
-    mapper.apply_actions(&state, Timestamp(1));
+    mapper.apply_actions(&state, Timestamp::from_millis(1));
 
     state.buttons.b = true;
-    mapper.apply_actions(&state, Timestamp(2));
+    mapper.apply_actions(&state, Timestamp::from_millis(2));
 
     assert_eq!(output.keys.take(), vec![(true, KeyboardKey::A), (false, KeyboardKey::A), (true, KeyboardKey::B)])
   }
+
+  #[test]
+  fn rumble_is_sent_on_change_only() {
+
+    let config = config(vec![
+      (LayerMask(0b01), rumble(axis_input(crate::controllers::Axis::LTrig), HapticFeedbackTarget::LeftTrigger))
+    ]);
+
+    let (sender, receiver) = std::sync::mpsc::channel();
+
+    let mut output = DummyOutput2 { keys: Cell::new(vec![]) };
+    let clock = VirtualClock::default();
+    let mut mapper = Mapper::new(Some(&sender), None, None, config, &mut output, &clock, 0);
+    let mut state  = crate::controllers::ControllerState::empty();
+
+    mapper.apply_actions(&state, Timestamp::from_millis(0));
+
+    state.axes.ltrig = 0.5;
+    mapper.apply_actions(&state, Timestamp::from_millis(1));
+    mapper.apply_actions(&state, Timestamp::from_millis(2));
+
+    state.axes.ltrig = 0.0;
+    mapper.apply_actions(&state, Timestamp::from_millis(3));
+
+    let commands = receiver.try_iter().map(|command| match command {
+      ControllerCommand::Rumble(target, intensity) => (target, intensity),
+      _ => unreachable!()
+    }).collect::<Vec<_>>();
+
+    assert_eq!(commands, vec![(HapticFeedbackTarget::LeftTrigger, 0.5), (HapticFeedbackTarget::LeftTrigger, 0.0)]);
+  }
+
+  #[test]
+  fn headless_overlay_test() {
+
+    use std::time::Duration;
+    use overlay_ipc::headless::HeadlessOverlay;
+    use overlay_ipc::{OverlayCommand, OverlayMenuCommand, ProbeResult};
+
+    let in_vehicle = match memory_probe("u32[32: 0x1000] == 5").unwrap() {
+      MemoryProbe::Condition(pipeline) => pipeline,
+      MemoryProbe::Value(_)            => unreachable!()
+    };
+
+    let menu = touch_menu(
+      merge(axis_input(crate::controllers::Axis::LPadX), axis_input(crate::controllers::Axis::LPadY)),
+      button_input(Button::LPadTouch),
+      dummy_button_input(true),
+      vec![TouchMenuItem::new("A".to_string()), TouchMenuItem::new("B".to_string())],
+      TouchMenuOpts::Radial { inner_radius: 0.25, outer_radius: 1.0, margin: 0.03 },
+      TouchMenuHaptics { targets: vec![], intensity: 1.0 },
+      false).unwrap();
+
+    let mut config = config(vec![
+      (LayerMask(0b01), keyboard_key_press(in_vehicle, KeyboardKey::A)),
+      (LayerMask(0b01), keyboard_key_press(menu_item(menu.clone(), 0), KeyboardKey::B)),
+      (LayerMask(0b01), keyboard_key_press(menu_item(menu, 1), KeyboardKey::C))
+    ]);
+    config.knobs = vec![overlay_ipc::Knob::Flag { name: "invert y".to_string(), value: false }];
+
+    let (overlay, server_name) = HeadlessOverlay::serve(overlay_ipc::Capabilities::ALL);
+    let client = overlay_ipc::connect_to_ipc_server(server_name).unwrap();
+
+    let mut output = DummyOutput2 { keys: Cell::new(vec![]) };
+    let clock = VirtualClock::default();
+    let mut mapper = Mapper::new(None, Some(&client), None, config, &mut output, &clock, 0);
+    let mut state  = crate::controllers::ControllerState::empty();
+
+    assert!(mapper.init_probes());
+    assert!(overlay.wait_for(Duration::from_secs(5), |state| !state.knobs.is_empty()));
+
+    let probe_id = overlay.state().commands.iter().find_map(|command| match command {
+      OverlayCommand::AddMemoryCheck(probe_id, 32, None, 0x1000, offsets, _) if offsets.is_empty() => Some(*probe_id),
+      _ => None
+    }).unwrap();
+
+    // memory probes
+    overlay.set_probe_result(probe_id, ProbeResult::Memory(5));
+    assert!(until(Duration::from_secs(5), || {
+      mapper.poll_probes();
+      unsafe { mapper.probe_values[&(probe_id as StageId)].u64 == 5 }
+    }));
+
+    mapper.apply_actions(&state, Timestamp::from_millis(0));
+    assert!(mapper.curr_keyboard_key_state[KeyboardKey::A as usize]);
+
+    // shape toggling
+    let stage_id = *overlay.state().shapes.keys().next().unwrap();
+    state.buttons.lpad_touch = true;
+    mapper.apply_actions(&state, Timestamp::from_millis(1));
+    assert!(overlay.wait_for(Duration::from_secs(5), |state| state.shapes[&stage_id][0].1 == u64::MAX));
+
+    // knobs menu
+    overlay.menu_command(OverlayMenuCommand::OpenKnobsMenu);
+    overlay.menu_command(OverlayMenuCommand::SelectNextValue);
+    overlay.menu_command(OverlayMenuCommand::CloseKnobsMenu);
+    assert!(until(Duration::from_secs(5), || { mapper.poll_events(); mapper.knobs_changed }));
+
+    assert!(matches!(mapper.knobs[..], [overlay_ipc::Knob::Flag { value: true, .. }]));
+    assert_eq!(mapper.overlay_status.screen_width, 1920);
+  }
+
+  #[test]
+  fn overlay_status_test() {
+
+    use std::time::Duration;
+    use overlay_ipc::headless::HeadlessOverlay;
+
+    let frame_time = overlay_status_axis(OverlayProperty::FrameTime);
+    let config = config(vec![
+      (LayerMask(0b01), keyboard_key_press(overlay_status_button(OverlayProperty::Focused), KeyboardKey::A))
+    ]);
+
+    let (overlay, server_name) = HeadlessOverlay::serve(overlay_ipc::Capabilities::ALL);
+    let client = overlay_ipc::connect_to_ipc_server(server_name).unwrap();
+
+    let mut output = DummyOutput2 { keys: Cell::new(vec![]) };
+    let clock = VirtualClock::default();
+    let mut mapper = Mapper::new(None, Some(&client), None, config, &mut output, &clock, 0);
+    let state = crate::controllers::ControllerState::empty();
+
+    assert!(mapper.init_probes());
+    assert!(mapper.overlay_status.connected);
+    assert!(until(Duration::from_secs(5), || { mapper.poll_events(); mapper.overlay_status.focused }));
+
+    mapper.apply_actions(&state, Timestamp::from_millis(0));
+    assert!(mapper.curr_keyboard_key_state[KeyboardKey::A as usize]);
+
+    overlay.set_focused(false);
+    overlay.present_frame(0.02);
+    assert!(until(Duration::from_secs(5), || { mapper.poll_events(); mapper.overlay_status.frame_time == 0.02 }));
+    assert!(!mapper.overlay_status.focused);
+
+    mapper.apply_actions(&state, Timestamp::from_millis(1));
+    assert!(!mapper.curr_keyboard_key_state[KeyboardKey::A as usize]);
+
+    let ctx = Context {
+      state: &state, time: Timestamp::from_millis(1), layers: LayerMask::EMPTY, probe_values: &HashMap::new(), overlay: &mapper.overlay_status
+    };
+    assert_eq!(frame_time.borrow_mut().apply(&ctx, &mut vec![]), 0.02);
+  }
+
+  #[test]
+  fn knobs_test() {
+
+    use overlay_ipc::Knob;
+
+    let speed = std::rc::Rc::new(std::cell::Cell::new(1.0));
+
+    let mut first_config = config(vec![]);
+    first_config.layers = vec!["foo".to_string(), "bar".to_string()];
+    first_config.knobs  = vec![
+      Knob::Number { name: "speed".to_string(), value: 1.0, min_value: 0.0, max_value: 2.0 },
+      Knob::Flag   { name: "invert y".to_string(), value: false }
+    ];
+    first_config.live_knobs.insert("speed".to_string(), speed.clone());
+
+    let mut output = DummyOutput2 { keys: Cell::new(vec![]) };
+    let clock = VirtualClock::default();
+    let mut mapper = Mapper::new(None, None, None, first_config, &mut output, &clock, 0);
+
+    // number knobs are updated in place
+    mapper.knobs_edited[0].select_next_value();
+    mapper.commit_knobs();
+    assert!(!mapper.knobs_changed);
+    assert!((speed.get() - 1.1).abs() < f32::EPSILON);
+
+    // everything else requires reloading the config
+    mapper.knobs_edited[1].select_next_value();
+    mapper.commit_knobs();
+    assert!(mapper.knobs_changed);
+
+    mapper.curr_layer_mask = LayerMask(0b10);
+    let mode = mapper.mode();
+
+    // the next config has a different set of layers
+    let mut next_config = config(vec![]);
+    next_config.layers = vec!["bar".to_string()];
+    let clock = VirtualClock::default();
+    let mut mapper = Mapper::new(None, None, None, next_config, &mut output, &clock, 0);
+    mapper.restore_mode(&mode);
+    assert_eq!(mapper.curr_layer_mask, LayerMask(0b01));
+  }
+
+  struct RequestFrontend(Vec<FrontendRequest>);
+
+  impl Frontend for RequestFrontend {
+    fn update(&mut self, _: &crate::controllers::ControllerState, _: &Mode, _: &[Knob], _: &[Knob]) {}
+
+    fn requests(&mut self) -> Vec<FrontendRequest> {
+      std::mem::take(&mut self.0)
+    }
+  }
+
+  #[test]
+  fn set_knob_request_test() {
+
+    let speed = std::rc::Rc::new(std::cell::Cell::new(1.0));
+
+    let mut config = config(vec![]);
+    config.knobs = vec![
+      Knob::Number { name: "speed".to_string(), value: 1.0, min_value: 0.0, max_value: 2.0 },
+      Knob::Flag   { name: "invert y".to_string(), value: false }
+    ];
+    config.live_knobs.insert("speed".to_string(), speed.clone());
+
+    let mut frontend = RequestFrontend(vec![
+      FrontendRequest::SetKnob(Knob::Number { name: "speed".to_string(), value: 1.5, min_value: 0.0, max_value: 2.0 })
+    ]);
+
+    let mut output = DummyOutput2 { keys: Cell::new(vec![]) };
+    let clock = VirtualClock::default();
+    let mut mapper = Mapper::new(None, None, None, config, &mut output, &clock, 0);
+
+    // edited in the menu, but not committed yet
+    mapper.knobs_edited[1].select_next_value();
+
+    mapper.attach_frontend(&mut frontend);
+    mapper.step(&crate::controllers::ControllerState::empty());
+
+    assert_eq!(speed.get(), 1.5);
+    assert!(!mapper.knobs_changed);
+    assert!(matches!(mapper.knobs[..], [Knob::Number { value: 1.5, .. }, Knob::Flag { value: false, .. }]));
+    assert!(matches!(mapper.knobs_edited[..], [Knob::Number { value: 1.5, .. }, Knob::Flag { value: true, .. }]));
+  }
+
+  #[test]
+  fn pulse_golden_test() {
+    let config = crate::config::load_config("input(A).pulse(10, 0.5).bind(Kb.A)", None, false).unwrap();
+
+    let events = std::cell::RefCell::new(vec![]);
+    let mut output = crate::output::RecordingOutput::new(Box::new(crate::output::DummyOutput), |event| events.borrow_mut().push(event));
+    let clock = VirtualClock::default();
+    let mut mapper = Mapper::new(None, None, None, config, &mut output, &clock, 0);
+    let mut state  = crate::controllers::ControllerState::empty();
+
+    let mut transcript = vec![];
+    let clock = mapper.virtual_clock().unwrap();
+    while clock.now() < Timestamp::from_millis(300) {
+      state.buttons.a = clock.now() < Timestamp::from_millis(230);
+      mapper.step(&state);
+      for event in events.take() {
+        transcript.push(format!("{} ms: {}", clock.now().0.as_millis(), event));
+      }
+      clock.advance(FRAME_INTERVAL);
+    }
+
+    assert_eq!(transcript, vec![
+      "0 ms: key A down",
+      "52 ms: key A up",
+      "104 ms: key A down",
+      "156 ms: key A up",
+      "208 ms: key A down",
+      "232 ms: key A up"
+    ]);
+  }
+
+  #[test]
+  fn replay_golden_test() {
+    let config = crate::config::load_config("input(A).pulse(10, 0.5).bind(Kb.A), input(B).bind(Kb.B)", None, false).unwrap();
+
+    let events = std::cell::RefCell::new(vec![]);
+    let mut output = crate::output::RecordingOutput::new(Box::new(crate::output::DummyOutput), |event| events.borrow_mut().push(event));
+    let clock = VirtualClock::default();
+    let mut mapper = Mapper::new(None, None, None, config, &mut output, &clock, 0);
+
+    let steps = crate::preview::parse_recording("
+      0   A=true
+      snapshot pressed
+      120 B=true
+      snapshot flipped
+      200 A=false B=false
+      snapshot released
+    ").unwrap();
+
+    let mut transcript = vec![];
+    crate::preview::replay_input::<String>(&mut mapper, &steps, |_, _| Ok(()), |mapper, name| {
+      let time = mapper.virtual_clock().unwrap().now().0.as_millis();
+      transcript.push(format!("{} ms, {}: {}", time, name, events.take().join(", ")));
+      Ok(())
+    }).unwrap();
+
+    assert_eq!(transcript, vec![
+      "0 ms, pressed: key A down",
+      "120 ms, flipped: key A up, key A down, key B down",
+      "200 ms, released: key A up, key B up"
+    ]);
+  }
 }
@@ -106,3 +106,52 @@ impl MapperIO for DummyOutput {
   fn mouse_wheel_rel(&mut self, _value: i32) {}
   fn syn(&mut self) {}
 }
+
+// passes everything on, describing each event to the sink first: "key A down", "mouse button Left up", "mouse move 3 -1"
+pub struct RecordingOutput<'o> {
+  inner: Box<dyn MapperIO + 'o>,
+  sink:  Box<dyn FnMut(String) + 'o>
+}
+
+impl<'o> RecordingOutput<'o> {
+  pub fn new(inner: Box<dyn MapperIO + 'o>, sink: impl FnMut(String) + 'o) -> Self {
+    Self { inner, sink: Box::new(sink) }
+  }
+}
+
+impl MapperIO for RecordingOutput<'_> {
+
+  fn keyboard_key_down(&mut self, key: KeyboardKey) {
+    (self.sink)(format!("key {} down", key));
+    self.inner.keyboard_key_down(key);
+  }
+
+  fn keyboard_key_up(&mut self, key: KeyboardKey) {
+    (self.sink)(format!("key {} up", key));
+    self.inner.keyboard_key_up(key);
+  }
+
+  fn mouse_button_down(&mut self, btn: MouseButton) {
+    (self.sink)(format!("mouse button {} down", btn));
+    self.inner.mouse_button_down(btn);
+  }
+
+  fn mouse_button_up(&mut self, btn: MouseButton) {
+    (self.sink)(format!("mouse button {} up", btn));
+    self.inner.mouse_button_up(btn);
+  }
+
+  fn mouse_cursor_rel_xy(&mut self, x: i32, y: i32) {
+    (self.sink)(format!("mouse move {} {}", x, y));
+    self.inner.mouse_cursor_rel_xy(x, y);
+  }
+
+  fn mouse_wheel_rel(&mut self, value: i32) {
+    (self.sink)(format!("mouse wheel {}", value));
+    self.inner.mouse_wheel_rel(value);
+  }
+
+  fn syn(&mut self) {
+    self.inner.syn();
+  }
+}
@@ -0,0 +1,369 @@
+// Renders overlay shapes (menus) on the CPU, the mapper talks to a headless overlay instead of the Vulkan one.
+//
+// Recorded input is a list of timestamped input changes and snapshots, one per line:
+//   0    LPadTouch=true LPadX=0 LPadY=0.8
+//   600
+//   snapshot menu-locked.png
+//   700  LPadTouch=false
+// Inputs keep their values until changed, frames in between are filled in at FRAME_INTERVAL.
+// Probe<n>=true|false forces the config's n-th probe condition (in stage id order) on or off.
+
+use std::path::Path;
+use std::time::Duration;
+
+use strum::IntoEnumIterator;
+
+use overlay_ipc::headless::{HeadlessOverlay, HeadlessState};
+use overlay_ipc::{raster, CommandSender, Image};
+
+use crate::clock::FRAME_INTERVAL;
+use crate::controllers::{Axis, Button, ControllerState};
+use crate::stages::StageId;
+
+// live previews are rewritten at most this often
+const RENDER_INTERVAL: Duration = Duration::from_millis(100);
+
+const SYNC_TIMEOUT: Duration = Duration::from_secs(5);
+
+const BACKGROUND: [u8; 3] = [64, 64, 64];
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum InputChange {
+  Button(Button, bool),
+  Axis(Axis, f32),
+  Probe(usize, bool)
+}
+
+impl std::fmt::Display for InputChange {
+
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      InputChange::Button(button, value) => write!(f, "{:?}={}", button, value),
+      InputChange::Axis(axis, value)     => write!(f, "{:?}={}", axis, value),
+      InputChange::Probe(index, value)   => write!(f, "Probe{}={}", index, value)
+    }
+  }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum InputStep {
+  At(Duration, Vec<InputChange>),
+  Snapshot(String)
+}
+
+fn parse_bool(name: &str, value: &str) -> Result<bool, String> {
+  match value {
+    "true"  | "1" => Ok(true),
+    "false" | "0" => Ok(false),
+    _ => Err(format!("{} should be either true or false", name))
+  }
+}
+
+fn parse_input_change(s: &str) -> Result<InputChange, String> {
+  let (name, value) = s.split_once('=').ok_or_else(|| format!("expected <input>=<value>, got {}", s))?;
+
+  if let Some(Ok(index)) = name.strip_prefix("Probe").map(|index| index.parse::<usize>()) {
+    parse_bool(name, value).map(|value| InputChange::Probe(index, value))
+  } else if let Some(button) = Button::iter().find(|button| format!("{:?}", button) == name) {
+    parse_bool(name, value).map(|value| InputChange::Button(button, value))
+  } else if let Some(axis) = Axis::iter().find(|axis| format!("{:?}", axis) == name) {
+    value.parse::<f32>().map(|value| InputChange::Axis(axis, value)).map_err(|_| format!("{} should be a number", name))
+  } else {
+    Err(format!("unknown input: {}", name))
+  }
+}
+
+pub fn parse_recording(recording: &str) -> Result<Vec<InputStep>, String> {
+  let mut steps = vec![];
+  let mut last_time = Duration::ZERO;
+
+  for (i, line) in recording.lines().enumerate() {
+    let line = line.split('#').next().unwrap();
+    let mut tokens = line.split_whitespace();
+
+    let step = match tokens.next() {
+      None => continue,
+      Some("snapshot") => match (tokens.next(), tokens.next()) {
+        (Some(path), None) => InputStep::Snapshot(path.to_string()),
+        _ => return Err(format!("line {}: expected snapshot <file>", i + 1))
+      },
+      Some(time) => {
+        let time = time.parse::<u64>().map(Duration::from_millis)
+          .map_err(|_| format!("line {}: expected time in milliseconds, got {}", i + 1, time))?;
+        if time < last_time {
+          return Err(format!("line {}: time goes backwards", i + 1));
+        }
+        last_time = time;
+
+        let changes = tokens.map(parse_input_change).collect::<Result<Vec<_>, _>>()
+          .map_err(|e| format!("line {}: {}", i + 1, e))?;
+        InputStep::At(time, changes)
+      }
+    };
+
+    steps.push(step);
+  }
+
+  Ok(steps)
+}
+
+pub fn format_recording(steps: &[InputStep]) -> String {
+  let mut recording = String::new();
+  for step in steps {
+    match step {
+      InputStep::At(time, changes) => {
+        recording.push_str(&format!("{:<5}", time.as_millis()));
+        for change in changes {
+          recording.push_str(&format!(" {}", change));
+        }
+        recording.push('\n');
+      },
+      InputStep::Snapshot(path) => {
+        recording.push_str(&format!("snapshot {}\n", path));
+      }
+    }
+  }
+  recording
+}
+
+// probe indices are relative to the mapper's probe_ids
+pub fn apply_input_change(state: &mut ControllerState, probe_ids: &[StageId], change: InputChange) -> Result<(), String> {
+  match change {
+    InputChange::Button(button, value) => state.set_button(button, value),
+    InputChange::Axis(axis, value)     => state.set_axis(axis, value),
+    InputChange::Probe(index, value)   => {
+      let id = probe_ids.get(index).ok_or_else(|| format!("Probe{}: the config has only {} probes", index, probe_ids.len()))?;
+      crate::stages::force_probe(*id, value);
+    }
+  }
+  Ok(())
+}
+
+// stage ids are random, but the drawing order should be stable
+pub fn render(overlay: &HeadlessState) -> Image {
+  let (width, height) = overlay.screen_size;
+  let mut image = Image::new(width, height, BACKGROUND);
+
+  let mut stage_ids = overlay.shapes.keys().collect::<Vec<_>>();
+  stage_ids.sort();
+
+  for stage_id in stage_ids {
+    raster::draw_shape_layers(&mut image, &overlay.shapes[stage_id]);
+  }
+
+  image
+}
+
+fn shape_masks(overlay: &HeadlessState) -> Vec<(u64, Vec<u64>)> {
+  let mut masks = overlay.shapes.iter()
+    .map(|(stage_id, layers)| (*stage_id, layers.iter().map(|(_, mask)| *mask).collect()))
+    .collect::<Vec<_>>();
+  masks.sort();
+  masks
+}
+
+fn write_snapshot(overlay: &HeadlessOverlay, client: &CommandSender, path: &Path) -> Result<(), String> {
+  if !overlay.sync(client, SYNC_TIMEOUT) {
+    return Err("overlay didn't catch up with the mapper".to_string());
+  }
+  let image = render(&overlay.state());
+  std::fs::write(path, image.to_png()).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+// snapshot paths are relative to dir
+#[cfg(not(test))]
+pub fn replay(
+  mapper:  &mut crate::mapper::Mapper,
+  overlay: &HeadlessOverlay,
+  client:  &CommandSender,
+  steps:   &[InputStep],
+  dir:     &Path
+) -> Result<(), String> {
+
+  replay_input::<String>(mapper, steps, |_, _| Ok(()), |_, path| {
+    write_snapshot(overlay, client, &dir.join(path))?;
+    println!("{}", path);
+    Ok(())
+  })?;
+  Ok(())
+}
+
+// frame is called after every mapper step with the time of the step, e.g. to check what was output,
+// snapshots are taken by the caller, e.g. dot graphs with stage outputs, returns the time of the last step
+pub fn replay_input<E: From<String>>(
+  mapper:       &mut crate::mapper::Mapper,
+  steps:        &[InputStep],
+  mut frame:    impl FnMut(&crate::mapper::Mapper, Duration) -> Result<(), E>,
+  mut snapshot: impl FnMut(&crate::mapper::Mapper, &str) -> Result<(), E>
+) -> Result<Duration, E> {
+
+  let probe_ids = mapper.probe_ids();
+  let mut time  = Duration::ZERO;
+  let mut state = ControllerState::empty();
+
+  for step in steps {
+    match step {
+      InputStep::At(next_time, changes) => {
+        step_until(mapper, &state, &mut time, next_time.saturating_sub(FRAME_INTERVAL), &mut frame)?;
+
+        for change in changes {
+          apply_input_change(&mut state, &probe_ids, *change)?;
+        }
+        step_at(mapper, &state, &mut time, *next_time, &mut frame)?;
+      },
+      InputStep::Snapshot(path) => {
+        snapshot(mapper, path)?;
+      }
+    }
+  }
+
+  Ok(time)
+}
+
+// a step every FRAME_INTERVAL while there's time left before the end
+pub fn step_until<E: From<String>>(
+  mapper: &mut crate::mapper::Mapper,
+  state:  &ControllerState,
+  time:   &mut Duration,
+  end:    Duration,
+  frame:  &mut impl FnMut(&crate::mapper::Mapper, Duration) -> Result<(), E>
+) -> Result<(), E> {
+  while *time < end {
+    step_at(mapper, state, time, *time + FRAME_INTERVAL, frame)?;
+  }
+  Ok(())
+}
+
+fn step_at<E: From<String>>(
+  mapper: &mut crate::mapper::Mapper,
+  state:  &ControllerState,
+  time:   &mut Duration,
+  at:     Duration,
+  frame:  &mut impl FnMut(&crate::mapper::Mapper, Duration) -> Result<(), E>
+) -> Result<(), E> {
+  let clock = mapper.virtual_clock().ok_or_else(|| "Replaying input needs a virtual clock".to_string())?;
+  clock.advance(at.saturating_sub(*time));
+  *time = at;
+  mapper.step(state);
+  frame(mapper, at)
+}
+
+// rewrites the output file whenever shapes change, most image viewers can pick that up
+#[cfg(not(test))]
+pub fn run_live(
+  mapper:   &mut crate::mapper::Mapper,
+  overlay:  &HeadlessOverlay,
+  client:   &CommandSender,
+  receiver: &std::sync::mpsc::Receiver<ControllerState>,
+  output:   &Path
+) -> Result<(), String> {
+
+  let mut last_render = std::time::Instant::now();
+  let mut last_masks  = None;
+
+  loop {
+    let state = receiver.recv().map_err(|e| format!("{}", e))?;
+    mapper.step(&state);
+
+    if last_render.elapsed() >= RENDER_INTERVAL {
+      let masks = Some(shape_masks(&overlay.state()));
+      if masks != last_masks {
+        write_snapshot(overlay, client, output)?;
+        last_masks = masks;
+      }
+      last_render = std::time::Instant::now();
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  #[test]
+  fn parse_recording_test() {
+    let recording = "
+      0   LPadTouch=true LPadX=0 LPadY=-0.8 # top
+      600
+      snapshot locked.png
+      700 LPadTouch=0
+    ";
+
+    assert_eq!(parse_recording(recording), Ok(vec![
+      InputStep::At(Duration::ZERO, vec![
+        InputChange::Button(Button::LPadTouch, true),
+        InputChange::Axis(Axis::LPadX, 0.0),
+        InputChange::Axis(Axis::LPadY, -0.8)
+      ]),
+      InputStep::At(Duration::from_millis(600), vec![]),
+      InputStep::Snapshot("locked.png".to_string()),
+      InputStep::At(Duration::from_millis(700), vec![InputChange::Button(Button::LPadTouch, false)])
+    ]));
+
+    let steps = vec![
+      InputStep::At(Duration::ZERO, vec![InputChange::Axis(Axis::LPadY, -0.8), InputChange::Probe(1, true)]),
+      InputStep::At(Duration::from_millis(600), vec![]),
+      InputStep::Snapshot("locked.png".to_string())
+    ];
+    assert_eq!(parse_recording(&format_recording(&steps)), Ok(steps));
+
+    assert_eq!(parse_recording("10\n5"), Err("line 2: time goes backwards".to_string()));
+    assert_eq!(parse_recording("0 LPad=yes"), Err("line 1: LPad should be either true or false".to_string()));
+    assert_eq!(parse_recording("0 Foo=1"), Err("line 1: unknown input: Foo".to_string()));
+  }
+
+  // compares against src/fixtures/<name>, STWGS_UPDATE_SNAPSHOTS=1 rewrites the fixture instead
+  fn assert_snapshot(image: &Image, name: &str) {
+    let path = format!("{}/src/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name);
+    if std::env::var_os("STWGS_UPDATE_SNAPSHOTS").is_some() {
+      image.save(&path).unwrap();
+    }
+    assert!(Image::load(&path).unwrap() == *image, "{} doesn't match the rendered snapshot", name);
+  }
+
+  #[test]
+  fn touch_menu_snapshot_test() {
+    let config = crate::config::load_config(r#"
+      let items = {"1", "2", "3", "4", "5", "6"};
+      {
+        let a, _, _, _, _, _ = radial_menu(merge(input(LPadX), input(LPadY)), input(LPadTouch), items);
+        a.bind(Kb._1)
+      },
+      {
+        let a, _, _, _, _, _ = hex_grid_menu(merge(input(RPadX), input(RPadY)), input(RPadTouch), items);
+        a.bind(Kb._2)
+      }
+    "#, None, false).unwrap();
+
+    let (overlay, server_name) = HeadlessOverlay::serve(overlay_ipc::Capabilities::ALL);
+    overlay.resize(240, 135);
+    let client = overlay_ipc::connect_to_ipc_server(server_name).unwrap();
+
+    let clock = crate::clock::VirtualClock::default();
+    let mut output = crate::output::DummyOutput;
+    let mut mapper = crate::mapper::Mapper::new(None, Some(&client), None, config, &mut output, &clock, 0);
+    assert!(mapper.init_probes());
+
+    let steps = parse_recording("
+      0    LPadTouch=true LPadX=0 LPadY=0.8
+      100
+      snapshot touch-menu-radial.png
+      200  LPadTouch=false RPadTouch=true RPadX=0.3 RPadY=-0.3
+      300
+      snapshot touch-menu-hex.png
+    ").unwrap();
+
+    let mut snapshots = vec![];
+    replay_input::<String>(&mut mapper, &steps, |_, _| Ok(()), |_, name| {
+      assert!(overlay.sync(&client, SYNC_TIMEOUT));
+      snapshots.push((name.to_string(), render(&overlay.state())));
+      Ok(())
+    }).unwrap();
+
+    assert_eq!(snapshots.len(), 2);
+    for (name, image) in &snapshots {
+      assert_snapshot(image, name);
+    }
+  }
+}
@@ -0,0 +1,91 @@
+// Evaluates a config's screen probes against a screenshot, without an overlay, for `probe-test`.
+
+use std::collections::HashMap;
+
+use overlay_ipc::{detectors, Image, ScreenScrapingResult};
+
+use crate::config::Config;
+use crate::stages::{Probe, StageId};
+
+pub struct ProbeTest {
+  pub results: Vec<(StageId, String, ScreenScrapingResult)>, // stage id, probe options, result
+  pub debug:   Image // the screenshot with probe areas and matching pixels highlighted
+}
+
+pub fn probe_test(config: &Config, screen: &Image) -> ProbeTest {
+
+  let mut meta = HashMap::new();
+
+  for (_, pipeline) in &config.pipelines {
+    pipeline.inspect(&mut meta);
+  }
+
+  let mut probes = meta.values()
+    .filter_map(|stage| match &stage.probe {
+      Some(Probe::Screen { target }) => Some((stage.id, stage.opts.clone(), target.clone())),
+      _ => None
+    })
+    .collect::<Vec<_>>();
+  probes.sort_by_key(|(id, _, _)| *id);
+
+  let targets        = probes.iter().map(|(_, _, target)| target.clone()).collect::<Vec<_>>();
+  let (packed, data) = detectors::pack(&targets, screen.width, screen.height);
+  let results        = detectors::unpack(&targets, &detectors::compute_on_cpu(screen, &packed, &data));
+
+  ProbeTest {
+    results: probes.into_iter().zip(results).map(|((id, opts, _), result)| (id, opts, result)).collect(),
+    debug:   detectors::debug_image(screen, &packed)
+  }
+}
+
+pub fn describe(result: &ScreenScrapingResult) -> String {
+  match result {
+    ScreenScrapingResult::HsvRange { pixels_in_range, uniformity_score } =>
+      format!("pixels_in_range = {:.5}, uniformity_score = {:.5}", pixels_in_range, uniformity_score),
+    ScreenScrapingResult::Template { correlation } =>
+      format!("correlation = {:.5}", correlation),
+    ScreenScrapingResult::MeanColor(color) =>
+      format!("mean color = ({:.3}, {:.3}, {:.3})", color.r, color.g, color.b),
+    ScreenScrapingResult::Histogram { distance } =>
+      format!("distance = {:.5}", distance)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  #[test]
+  fn png_screenshot_test() {
+    let dir = std::env::temp_dir().join(format!("stwgs-probe-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    // a red square in the top left corner of a grey screen
+    let mut screen = Image::new(16, 16, [128, 128, 128]);
+    for y in 0..8 {
+      for x in 0..8 {
+        screen.set_rgb(x, y, [255, 0, 0]);
+      }
+    }
+
+    let path = dir.join("screen.png");
+    screen.save(path.to_str().unwrap()).unwrap();
+    let screen = Image::load(path.to_str().unwrap()).unwrap();
+
+    let config = crate::config::load_config(r##"
+      bind(color_probe(x1 = 0, y1 = 0, x2 = 8, y2 = 8, color = "#ff0000"), Kb.A),
+      bind(color_probe(x1 = 8, y1 = 8, x2 = 16, y2 = 16, color = "#ff0000"), Kb.B)
+    "##, None, false).unwrap();
+
+    let test = probe_test(&config, &screen);
+    let described = test.results.iter().map(|(_, _, result)| describe(result)).collect::<Vec<_>>();
+    assert_eq!(described, vec!["mean color = (1.000, 0.000, 0.000)", "mean color = (0.502, 0.502, 0.502)"]);
+
+    let debug = dir.join("debug.png");
+    test.debug.save(debug.to_str().unwrap()).unwrap();
+    assert_eq!(Image::load(debug.to_str().unwrap()).unwrap(), test.debug);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+  }
+}
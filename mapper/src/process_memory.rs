@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::os::unix::fs::FileExt;
+use std::time::{Duration, Instant};
+
+use crate::log::log;
+
+// don't rescan /proc on every frame while the target process is missing
+const ATTACH_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ProcessSelector {
+  Pid(u32),
+  Name(String)
+}
+
+impl std::str::FromStr for ProcessSelector {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    if s.is_empty() {
+      Err("empty process name".to_string())
+    } else if let Ok(pid) = s.parse::<u32>() {
+      Ok(ProcessSelector::Pid(pid))
+    } else {
+      Ok(ProcessSelector::Name(s.to_string()))
+    }
+  }
+}
+
+struct Attachment {
+  pid:          u32,
+  mem:          File,
+  module_bases: HashMap<String, u64>,
+  read_failed:  bool
+}
+
+// reads memory of another process through /proc/<pid>/mem,
+// which requires ptrace access (same user with kernel.yama.ptrace_scope = 0 or CAP_SYS_PTRACE)
+pub struct ProcessMemory {
+  selector:     ProcessSelector,
+  attachment:   Option<Attachment>,
+  last_attempt: Option<Instant>
+}
+
+// comm is truncated to 15 characters, so argv[0] is checked as well
+fn find_process(name: &str) -> Option<u32> {
+  let mut pids = std::fs::read_dir("/proc").ok()?
+    .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<u32>().ok())
+    .collect::<Vec<_>>();
+  pids.sort();
+
+  pids.into_iter().find(|pid| {
+    let comm    = std::fs::read_to_string(format!("/proc/{}/comm", pid)).unwrap_or_default();
+    let cmdline = std::fs::read(format!("/proc/{}/cmdline", pid)).unwrap_or_default();
+    let argv0   = String::from_utf8_lossy(cmdline.split(|&c| c == 0).next().unwrap_or_default()).to_string();
+    // Wine processes might report Windows paths
+    let argv0   = argv0.rsplit(['/', '\\']).next().unwrap().to_string();
+    comm.trim_end().eq_ignore_ascii_case(name) || argv0.eq_ignore_ascii_case(name)
+  })
+}
+
+impl ProcessMemory {
+
+  pub fn new(selector: ProcessSelector) -> Self {
+    Self { selector, attachment: None, last_attempt: None }
+  }
+
+  fn attach(&mut self) -> Result<(), String> {
+    let pid = match &self.selector {
+      ProcessSelector::Pid(pid)   => *pid,
+      ProcessSelector::Name(name) => find_process(name).ok_or_else(|| format!("process {} not found", name))?
+    };
+
+    let path = format!("/proc/{}/mem", pid);
+    let mem  = File::open(&path).map_err(|e| format!("{}: {}", path, e))?;
+
+    log!("attached to process {}", pid);
+    self.attachment = Some(Attachment { pid, mem, module_bases: HashMap::new(), read_failed: false });
+    Ok(())
+  }
+
+  fn module_base(attachment: &mut Attachment, module: &str) -> Result<u64, String> {
+    if let Some(base) = attachment.module_bases.get(module) {
+      return Ok(*base);
+    }
+
+    let path = format!("/proc/{}/maps", attachment.pid);
+    let maps = std::fs::read_to_string(&path).map_err(|e| format!("{}: {}", path, e))?;
+    let base = overlay_ipc::proc_maps::module_base(&maps, module)
+      .ok_or_else(|| format!("module {} is not loaded", module))?;
+
+    attachment.module_bases.insert(module.to_string(), base);
+    Ok(base)
+  }
+
+  fn read(attachment: &Attachment, address: u64, usize: u8) -> Result<u64, String> {
+    let mut buf = [0u8; 8];
+    let len = usize as usize / 8;
+    attachment.mem.read_exact_at(&mut buf[0..len], address).map_err(|e| format!("0x{:x}: {}", address, e))?;
+    Ok(u64::from_le_bytes(buf))
+  }
+
+  // same semantics as the overlay's follow_pointer_chain
+  fn follow_pointer_chain(attachment: &mut Attachment, usize: u8, module: &Option<String>, address: u64, offsets: &[i32]) -> Result<u64, String> {
+    let mut p = match module {
+      Some(module) => Self::module_base(attachment, module)?.checked_add(address)
+        .ok_or_else(|| format!("{} + 0x{:x} overflows the address space", module, address))?,
+      None         => address
+    };
+
+    for offset in offsets {
+      p = Self::read(attachment, p, usize)?;
+      if p == 0 {
+        return Ok(0);
+      }
+      p = p.wrapping_add_signed(*offset as i64);
+    }
+
+    Self::read(attachment, p, usize)
+  }
+
+  // None means the target process isn't available (yet)
+  pub fn read_pointer_chain(&mut self, usize: u8, module: &Option<String>, address: u64, offsets: &[i32]) -> Option<u64> {
+
+    if self.attachment.is_none() {
+      if self.last_attempt.map(|t| t.elapsed() < ATTACH_INTERVAL).unwrap_or(false) {
+        return None;
+      }
+
+      self.last_attempt = Some(Instant::now());
+      if let Err(e) = self.attach() {
+        log!("{}", e);
+        return None;
+      }
+    }
+
+    let attachment = self.attachment.as_mut().unwrap();
+    match Self::follow_pointer_chain(attachment, usize, module, address, offsets) {
+      Ok(value) => Some(value),
+      Err(e) => {
+        // the process is gone or the pointer chain is not valid at the moment
+        if !std::path::Path::new(&format!("/proc/{}", attachment.pid)).exists() {
+          log!("process {} exited", attachment.pid);
+          self.attachment = None;
+        } else if !attachment.read_failed {
+          // pointer chains are often invalid for a while (e.g. during loading), report only once
+          log!("can't read memory: {}", e);
+          attachment.read_failed = true;
+        }
+        None
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  #[test]
+  fn process_memory_test() {
+    assert_eq!("1234".parse::<ProcessSelector>(), Ok(ProcessSelector::Pid(1234)));
+    assert_eq!("gta-sa.exe".parse::<ProcessSelector>(), Ok(ProcessSelector::Name("gta-sa.exe".to_string())));
+
+    static VALUE:   u32 = 0xdeadbeef;
+    static POINTER: &u32 = &VALUE;
+
+    let mut memory = ProcessMemory::new(ProcessSelector::Pid(std::process::id()));
+    let usize = (core::mem::size_of::<usize>() * 8) as u8;
+
+    let address = &VALUE as *const u32 as u64;
+    assert_eq!(memory.read_pointer_chain(32, &None, address, &[]), Some(0xdeadbeef));
+
+    let address = &POINTER as *const &u32 as u64;
+    assert_eq!(memory.read_pointer_chain(usize, &None, address, &[0]).map(|v| v as u32), Some(0xdeadbeef));
+
+    let exe = std::env::current_exe().unwrap().file_name().unwrap().to_string_lossy().to_string();
+    assert_eq!(memory.read_pointer_chain(usize, &Some(exe), u64::MAX, &[]), None);
+  }
+}
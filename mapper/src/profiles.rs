@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+const POLLING_INTERVAL: Duration = Duration::from_secs(1);
+
+// used when nothing else matches
+const DEFAULT_PROFILE: &str = "default";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Profile {
+  pub name:   String,
+  pub script: String,
+  pub knobs:  String
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ProfileSource {
+  #[cfg(feature = "x11")]
+  FocusedWindow,
+  Processes
+}
+
+// <dir>/<name>.cfg, keyed by lowercase name
+fn list_profiles(dir: &Path) -> Result<HashMap<String, Profile>, String> {
+  let mut profiles = HashMap::new();
+
+  for entry in std::fs::read_dir(dir).map_err(|e| format!("{}: {}", dir.display(), e))? {
+    let path = entry.map_err(|e| format!("{}", e))?.path();
+    if path.extension().map(|ext| ext == "cfg").unwrap_or(false) {
+      if let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) {
+        profiles.insert(name.to_lowercase(), Profile {
+          name:   name.to_string(),
+          script: path.to_string_lossy().to_string(),
+          knobs:  path.with_extension("json").to_string_lossy().to_string()
+        });
+      }
+    }
+  }
+
+  Ok(profiles)
+}
+
+// candidates are expected to be sorted by priority
+fn match_profile(profiles: &HashMap<String, Profile>, candidates: &[String]) -> Option<Profile> {
+  candidates.iter()
+    .find_map(|name| profiles.get(&name.to_lowercase()))
+    .or_else(|| profiles.get(DEFAULT_PROFILE))
+    .cloned()
+}
+
+fn process_names(pid: Option<u32>) -> Result<Vec<String>, String> {
+  let mut ps = std::process::Command::new("ps");
+  if let Some(pid) = pid {
+    ps.args(["-o", "comm=", "-p", &pid.to_string()]);
+  } else {
+    ps.args(["-ax", "-o", "comm="]);
+  }
+
+  let output = ps.output().map_err(|e| format!("Can't run ps: {}", e))?;
+
+  Ok(String::from_utf8_lossy(&output.stdout)
+    .lines()
+    .map(|line| line.trim())
+    .filter(|line| !line.is_empty())
+    // comm might be a full path on some systems
+    .map(|line| line.rsplit('/').next().unwrap().to_string())
+    .collect())
+}
+
+#[cfg(feature = "x11")]
+struct FocusedWindowWatcher {
+  connection:        xcb::Connection,
+  root:              xcb::x::Window,
+  net_active_window: xcb::x::Atom,
+  net_wm_pid:        xcb::x::Atom
+}
+
+#[cfg(feature = "x11")]
+impl FocusedWindowWatcher {
+
+  fn new() -> Result<Self, String> {
+    let (connection, screen_num) = xcb::Connection::connect(None).map_err(|e| format!("{}", e))?;
+
+    let root = connection.get_setup().roots().nth(screen_num as usize).ok_or("No screen found")?.root();
+
+    let intern_atom = |name: &str| -> Result<xcb::x::Atom, String> {
+      let cookie = connection.send_request(&xcb::x::InternAtom { only_if_exists: true, name: name.as_bytes() });
+      let atom   = connection.wait_for_reply(cookie).map_err(|e| format!("{}", e))?.atom();
+      if atom == xcb::x::ATOM_NONE {
+        Err(format!("{} is not supported by the window manager", name))
+      } else {
+        Ok(atom)
+      }
+    };
+
+    let net_active_window = intern_atom("_NET_ACTIVE_WINDOW")?;
+    let net_wm_pid        = intern_atom("_NET_WM_PID")?;
+
+    Ok(Self { connection, root, net_active_window, net_wm_pid })
+  }
+
+  fn get_property(&self, window: xcb::x::Window, property: xcb::x::Atom, r#type: xcb::x::Atom) -> Result<xcb::x::GetPropertyReply, String> {
+    let cookie = self.connection.send_request(&xcb::x::GetProperty {
+      delete: false,
+      window,
+      property,
+      r#type,
+      long_offset: 0,
+      long_length: 1024
+    });
+    self.connection.wait_for_reply(cookie).map_err(|e| format!("{}", e))
+  }
+
+  // WM_CLASS instance, WM_CLASS class, process name
+  fn names(&self) -> Result<Vec<String>, String> {
+    let mut names = vec![];
+
+    let reply = self.get_property(self.root, self.net_active_window, xcb::x::ATOM_WINDOW)?;
+    let window = match reply.value::<xcb::x::Window>().first() {
+      Some(&window) if !xcb::Xid::is_none(&window) => window,
+      _ => return Ok(names) // nothing focused
+    };
+
+    // the window might be gone by now, that's no names as well
+    if let Ok(reply) = self.get_property(window, xcb::x::ATOM_WM_CLASS, xcb::x::ATOM_STRING) {
+      for name in reply.value::<u8>().split(|&c| c == 0) {
+        if !name.is_empty() {
+          names.push(String::from_utf8_lossy(name).to_string());
+        }
+      }
+    }
+
+    if let Ok(reply) = self.get_property(window, self.net_wm_pid, xcb::x::ATOM_CARDINAL) {
+      if let Some(&pid) = reply.value::<u32>().first() {
+        names.extend(process_names(Some(pid))?);
+      }
+    }
+
+    Ok(names)
+  }
+}
+
+pub fn watch_profiles(dir: PathBuf, source: ProfileSource, sender: Sender<Option<Profile>>) -> Result<(), String> {
+
+  #[cfg(feature = "x11")]
+  let watcher = match source {
+    ProfileSource::FocusedWindow => Some(FocusedWindowWatcher::new()?),
+    ProfileSource::Processes     => None
+  };
+
+  let mut current    = None;
+  let mut last_error = None;
+
+  loop {
+    let names = match source {
+      #[cfg(feature = "x11")]
+      ProfileSource::FocusedWindow => watcher.as_ref().unwrap().names(),
+      ProfileSource::Processes     => process_names(None)
+    };
+
+    match names.and_then(|names| Ok(match_profile(&list_profiles(&dir)?, &names))) {
+      Ok(profile) => {
+        last_error = None;
+        if current.as_ref() != Some(&profile) {
+          eprintln!("profile: {}", profile.as_ref().map(|p| p.name.as_str()).unwrap_or("none"));
+          if sender.send(profile.clone()).is_err() {
+            return Ok(());
+          }
+          current = Some(profile);
+        }
+      },
+      // nothing to keep running before the first profile, later on we keep the current one and try again
+      Err(e) if current.is_none() => return Err(e),
+      Err(e) => {
+        if last_error.as_ref() != Some(&e) {
+          eprintln!("Can't check profiles, keeping the current one: {}", e);
+        }
+        last_error = Some(e);
+      }
+    }
+
+    std::thread::sleep(POLLING_INTERVAL);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  #[test]
+  fn profile_matching_test() {
+    let profile = |name: &str| Profile {
+      name:   name.to_string(),
+      script: format!("{}.cfg",  name),
+      knobs:  format!("{}.json", name)
+    };
+
+    let mut profiles = HashMap::new();
+    profiles.insert("doom".to_string(), profile("Doom"));
+    profiles.insert("quake".to_string(), profile("quake"));
+
+    let names = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+    assert_eq!(match_profile(&profiles, &names(&["gzdoom", "Doom"])).map(|p| p.name), Some("Doom".to_string()));
+    assert_eq!(match_profile(&profiles, &names(&["Quake", "Doom"])).map(|p| p.name), Some("quake".to_string()));
+    assert_eq!(match_profile(&profiles, &names(&["xterm"])), None);
+
+    profiles.insert(DEFAULT_PROFILE.to_string(), profile(DEFAULT_PROFILE));
+    assert_eq!(match_profile(&profiles, &names(&["xterm"])).map(|p| p.name), Some(DEFAULT_PROFILE.to_string()));
+  }
+}
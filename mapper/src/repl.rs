@@ -0,0 +1,213 @@
+// Evaluates config code line by line:
+//   > let pad = merge(input(LPadX), input(LPadY))
+//   pad: Pipeline2D = [input(LPadX) + input(LPadY)]
+//   > .watch pad.as_ring_sector_button(direction = 0, angle = 2, inner_radius = 0.25, outer_radius = 1.2)
+// `.watch <expr>` prints the pipeline's output as the controller's input changes, until Enter is pressed.
+
+use std::collections::HashMap;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+use crate::config::{self, Value};
+use crate::controllers::ControllerState;
+use crate::stages::{ProbeValue, StageId};
+
+fn describe(name: Option<&str>, value: &Value) -> String {
+  match name {
+    Some(name) => format!("{}: {} = {:?}", name, config::name_of_type(value), value),
+    None       => format!("{} = {:?}", config::name_of_type(value), value)
+  }
+}
+
+// only the latest state is kept, nobody reads it between watches
+struct ControllerInput {
+  latest: Arc<Mutex<Option<ControllerState>>>,
+  thread: thread::JoinHandle<()>
+}
+
+fn connect_controller(serial_or_partial_path: Option<String>) -> ControllerInput {
+  let latest = Arc::new(Mutex::new(None));
+  let slot   = latest.clone();
+
+  let thread = thread::spawn(move || {
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || match crate::controllers::find_controller(serial_or_partial_path) {
+      Ok(Some(controller)) => {
+        if let Err(e) = controller.run_polling_loop(sender, None) {
+          eprintln!("{}", e);
+        }
+      },
+      Ok(None) => eprintln!("No controllers found."),
+      Err(e) => eprintln!("{}", e)
+    });
+
+    for state in receiver {
+      *slot.lock().unwrap() = Some(state);
+    }
+  });
+
+  ControllerInput { latest, thread }
+}
+
+// there is no overlay or game to probe, so probes read zeros as they do before the first results come in
+fn probe_values<R: Copy>(pipeline: &crate::stages::PipelineRef<R>) -> HashMap<StageId, ProbeValue> {
+  let mut meta = HashMap::new();
+  pipeline.borrow().inspect(&mut meta);
+
+  meta.into_values()
+    .filter(|stage| stage.probe.is_some())
+    .map(|stage| (stage.id, ProbeValue { fff32: (0.0, 0.0, 0.0) }))
+    .collect()
+}
+
+// returns false if the controller is gone
+#[cfg(not(test))]
+fn watch<R: Copy + std::fmt::Debug>(pipeline: &crate::stages::PipelineRef<R>, input: &ControllerInput) -> bool {
+
+  use crate::mapper::LayerMask;
+  use crate::clock::{Clock, RealClock, FRAME_INTERVAL};
+  use crate::stages::{Context, OverlayStatus};
+
+  // the stdin thread below would take the next line otherwise
+  while input.latest.lock().unwrap().is_none() {
+    if input.thread.is_finished() {
+      return false;
+    }
+    thread::sleep(FRAME_INTERVAL);
+  }
+
+  let (stop_sender, stop_receiver) = mpsc::channel();
+  thread::spawn(move || {
+    let _ = std::io::stdin().read_line(&mut String::new());
+    let _ = stop_sender.send(());
+  });
+
+  let clock        = RealClock::new();
+  let probe_values = probe_values(pipeline);
+  let overlay      = OverlayStatus::default();
+  let mut last     = None;
+
+  while stop_receiver.try_recv().is_err() {
+    if input.thread.is_finished() {
+      eprintln!("Controller disconnected, press Enter");
+      let _ = stop_receiver.recv();
+      return false;
+    }
+
+    thread::sleep(FRAME_INTERVAL);
+    let Some(state) = *input.latest.lock().unwrap() else {
+      continue;
+    };
+
+    let ctx = Context {
+      state:        &state,
+      time:         clock.now(),
+      layers:       LayerMask::user_layer(0).unwrap(),
+      probe_values: &probe_values,
+      overlay:      &overlay
+    };
+
+    let mut pipeline = pipeline.borrow_mut();
+    pipeline.reset();
+    let value = format!("{:?}", pipeline.apply(&ctx, &mut vec![]));
+
+    if last.as_ref() != Some(&value) {
+      println!("{}", value);
+      last = Some(value);
+    }
+  }
+
+  true
+}
+
+#[cfg(not(test))]
+pub fn run(serial_or_partial_path: Option<String>) {
+
+  use std::io::{BufRead, Write};
+
+  let mut repl  = config::Repl::default();
+  let mut input = None;
+
+  loop {
+    print!("> ");
+    let _ = std::io::stdout().flush();
+
+    let mut line = String::new();
+    match std::io::stdin().lock().read_line(&mut line) {
+      Ok(0) | Err(_) => break,
+      Ok(_) => ()
+    }
+    // the newline ends a trailing // comment
+    let line = line.trim_start();
+
+    let Some(expr) = line.strip_prefix(".watch") else {
+      match repl.eval(line) {
+        Ok(values) => {
+          for (name, value) in values {
+            println!("{}", describe(name.as_deref(), &value));
+          }
+        },
+        Err(e) => eprintln!("{}", e)
+      }
+      continue;
+    };
+
+    let value = match repl.eval(expr).map(|mut values| values.pop()) {
+      Ok(Some((None, value))) => value,
+      Ok(_) => {
+        eprintln!("Expected .watch <expression>");
+        continue;
+      },
+      Err(e) => {
+        eprintln!("{}", e);
+        continue;
+      }
+    };
+
+    let controller = input.get_or_insert_with(|| connect_controller(serial_or_partial_path.clone()));
+    let connected = match &value {
+      Value::Pipeline1D(p) => watch(p, controller),
+      Value::Pipeline2D(p) => watch(p, controller),
+      Value::PipelineB(p)  => watch(p, controller),
+      other => {
+        eprintln!("Can't watch {}, expected a pipeline", config::name_of_type(other));
+        true
+      }
+    };
+
+    // try again on the next watch
+    if !connected {
+      input = None;
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+  use crate::mapper::LayerMask;
+  use crate::stages::{Context, OverlayStatus, Timestamp};
+
+  #[test]
+  fn watch_probes_test() {
+    let mut repl = config::Repl::default();
+    let mut values = repl.eval(r##"memory_probe("u16[32: 0x00C0FEE0 +0x22] in {460, 464}").gate(color_probe(x1 = 0, y1 = 0, x2 = 8, y2 = 8, color = "#ff0000"))"##).unwrap();
+    let Some((None, Value::PipelineB(pipeline))) = values.pop() else {
+      panic!("expected a bool pipeline");
+    };
+
+    let probe_values = probe_values(&pipeline);
+    assert_eq!(probe_values.len(), 2);
+
+    let ctx = Context {
+      state:        &ControllerState::default(),
+      time:         Timestamp::from_millis(0),
+      layers:       LayerMask::user_layer(0).unwrap(),
+      probe_values: &probe_values,
+      overlay:      &OverlayStatus::default()
+    };
+    assert!(!pipeline.borrow_mut().apply(&ctx, &mut vec![]));
+  }
+}
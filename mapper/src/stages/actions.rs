@@ -129,3 +129,51 @@ pub fn trigger_bump(button: PipelineRef<bool>, left: bool) -> Box<dyn Pipeline<(
 
   Box::new(FnStage::from("trigger_bump", "".to_string(), button, fun))
 }
+
+pub fn haptic_pulse(button: PipelineRef<bool>, target: HapticFeedbackTarget, effect: HapticFeedbackEffect) -> Box<dyn Pipeline<()>> {
+
+  let mut bstate = to_button_state();
+
+  let fun = Box::new(move |pressed, _, _, actions: &mut Vec<Action>| {
+    if (bstate)(pressed) == ButtonState::Pressed {
+      actions.push(Action::HapticFeedback(target, effect));
+    }
+  });
+
+  Box::new(FnStage::from("haptic", format!("{:?}, {:?}", target, effect), button, fun))
+}
+
+pub fn rumble(pipeline: PipelineRef<f32>, target: HapticFeedbackTarget) -> Box<dyn Pipeline<()>> {
+  let fun = Box::new(move |intensity: f32, _, _, actions: &mut Vec<Action>| {
+    if intensity > 0.0 {
+      actions.push(Action::Rumble(target, intensity.min(1.0)));
+    }
+  });
+  Box::new(FnStage::from("rumble", format!("{:?}", target), pipeline, fun))
+}
+
+pub fn toggle_overlay_ui(pipeline: PipelineRef<bool>) -> Box<dyn Pipeline<()>> {
+
+  let mut bstate = to_button_state();
+
+  let fun = Box::new(move |pressed, _, _, actions: &mut Vec<Action>| {
+    if bstate(pressed) == ButtonState::Pressed {
+      actions.push(Action::ToggleOverlayUI);
+    }
+  });
+
+  Box::new(FnStage::from("toggle_overlay_ui", "".to_string(), pipeline, fun))
+}
+
+pub fn overlay_menu_command(pipeline: PipelineRef<bool>, command: OverlayMenuCommand) -> Box<dyn Pipeline<()>> {
+
+  let mut bstate = to_button_state();
+
+  let fun = Box::new(move |pressed, _, _, actions: &mut Vec<Action>| {
+    if bstate(pressed) == ButtonState::Pressed {
+      actions.push(Action::SendOverlayMenuCommand(command));
+    }
+  });
+
+  Box::new(FnStage::from("overlay_menu_command", format!("{:?}", command), pipeline, fun))
+}
@@ -0,0 +1,15 @@
+use super::*;
+
+// piecewise linear response curve applied to the absolute value, the sign is preserved
+pub fn curve(pipeline: PipelineRef<f32>, points: Vec<(f32, f32)>) -> PipelineRef<f32> {
+  assert!(points.len() >= 2);
+  let desc = points.iter().map(|(x, y)| format!("{}:{}", x, y)).collect::<Vec<_>>().join(" ");
+  let fun  = Box::new(move |value: f32, _, _, _: &mut Vec<Action>| {
+    let x = value.abs().min(1.0);
+    let i = points.iter().position(|(px, _)| *px >= x).unwrap_or(points.len() - 1).max(1);
+    let ((x1, y1), (x2, y2)) = (points[i - 1], points[i]);
+    (y1 + (y2 - y1) * (x - x1) / (x2 - x1)).copysign(value)
+  });
+  let p = FnStage::from("curve", desc, pipeline, fun);
+  std::rc::Rc::new(std::cell::RefCell::new(p))
+}
@@ -59,7 +59,7 @@ mod tests {
       joy.borrow_mut().reset();
       state.axes.ljoy_x = *joy_x;
       state.axes.ljoy_y = *joy_y;
-      let ctx = Context { state: &state, time: Timestamp(0), layers: LayerMask::EMPTY, probe_values: &HashMap::new() };
+      let ctx = Context { state: &state, time: Timestamp::from_millis(0), layers: LayerMask::EMPTY, probe_values: &HashMap::new(), overlay: &OverlayStatus::default() };
       let (x, y) = joy.borrow_mut().apply(&ctx, &mut actions);
       assert_eq!(x.round(), *expected_x);
       assert_eq!(y.round(), *expected_y);
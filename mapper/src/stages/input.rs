@@ -23,12 +23,18 @@ impl Pipeline<f32> for AxisInputStage {
     format!("{:?}", self.axis)
   }
 
+  fn axis(&self) -> Option<Axis> {
+    Some(self.axis)
+  }
+
   fn inspect(&self, out: &mut HashMap<StageId, PipelineStageDescription>) {
     insert_stage_description(out, self);
   }
 
   fn apply(&mut self, ctx: &Context, _: &mut Vec<Action>) -> f32 {
-    ctx.state.read_axis(self.axis)
+    let value = ctx.state.read_axis(self.axis);
+    trace(self.stage_id, &value);
+    value
   }
 
   fn reset(&mut self) {}
@@ -66,7 +72,9 @@ impl Pipeline<bool> for ButtonInputStage {
   }
 
   fn apply(&mut self, ctx: &Context, _: &mut Vec<Action>) -> bool {
-    ctx.state.read_button(self.button)
+    let value = ctx.state.read_button(self.button);
+    trace(self.stage_id, &value);
+    value
   }
 
   fn reset(&mut self) {}
@@ -152,20 +160,21 @@ pub fn constant_input(value: f32) -> PipelineRef<f32> {
   std::rc::Rc::new(std::cell::RefCell::new(ConstantInputStage { stage_id: generate_stage_id(), value }))
 }
 
-struct ScreenScrapingInputStage {
-  stage_id:  StageId,
-  target:    overlay_ipc::ScreenScrapingArea,
-  threshold: (f32, f32)
+// a number knob, the mapper updates the value as it is edited in the overlay menu
+struct KnobInputStage {
+  stage_id: StageId,
+  name:     String,
+  value:    std::rc::Rc<std::cell::Cell<f32>>
 }
 
-impl Pipeline<bool> for ScreenScrapingInputStage {
+impl Pipeline<f32> for KnobInputStage {
 
   fn stage_id(&self) -> StageId {
     self.stage_id
   }
 
   fn name(&self) -> &'static str {
-    "screen_probe"
+    "knob"
   }
 
   fn desc(&self) -> String {
@@ -173,47 +182,50 @@ impl Pipeline<bool> for ScreenScrapingInputStage {
   }
 
   fn opts(&self) -> String {
-    format!("{:?}, thresholds = {:?}", self.target, self.threshold)
-  }
-
-  fn probe(&self) -> Option<Probe> {
-    Some(Probe::Screen { target: self.target.clone() })
+    format!("{:?}", self.name)
   }
 
   fn inspect(&self, out: &mut HashMap<StageId, PipelineStageDescription>) {
     insert_stage_description(out, self);
   }
 
-  fn apply(&mut self, ctx: &Context, _: &mut Vec<Action>) -> bool {
-    let (pixels_in_range, uniformity_score): (f32, f32) = unsafe { ctx.probe_values[&self.stage_id].ff32 };
-    pixels_in_range >= self.threshold.0 || uniformity_score >= self.threshold.1
+  fn apply(&mut self, _: &Context, _: &mut Vec<Action>) -> f32 {
+    let value = self.value.get();
+    trace(self.stage_id, &value);
+    value
   }
 
   fn reset(&mut self) {}
 }
 
-pub fn screen_probe(target: overlay_ipc::ScreenScrapingArea, threshold: (f32, f32)) -> PipelineRef<bool> {
-  std::rc::Rc::new(std::cell::RefCell::new(ScreenScrapingInputStage { stage_id: generate_stage_id(), target, threshold }))
+pub fn knob_input(name: &str, value: std::rc::Rc<std::cell::Cell<f32>>) -> PipelineRef<f32> {
+  std::rc::Rc::new(std::cell::RefCell::new(KnobInputStage { stage_id: generate_stage_id(), name: name.to_string(), value }))
 }
 
-struct MemoryInputStage {
-  stage_id: StageId,
-  usize:    u8,
-  address:  u64,
-  offsets:  Vec<i32>,
-  relation: Relation,
-  var_type: VarType,
-  values:   Vec<u64>
+#[derive(Clone, Debug)]
+pub enum ScreenProbeCondition {
+  // either of pixels_in_range, uniformity_score reaches the threshold
+  Thresholds(f32, f32),
+  MinCorrelation(f32),
+  // no channel of the mean color is further away than the tolerance
+  ColorWithin(overlay_ipc::Color, f32),
+  MaxDistance(f32)
+}
+
+struct ScreenScrapingInputStage {
+  stage_id:  StageId,
+  target:    overlay_ipc::ScreenScrapingArea,
+  condition: ScreenProbeCondition
 }
 
-impl Pipeline<bool> for MemoryInputStage {
+impl Pipeline<bool> for ScreenScrapingInputStage {
 
   fn stage_id(&self) -> StageId {
     self.stage_id
   }
 
   fn name(&self) -> &'static str {
-    "memory_probe"
+    "screen_probe"
   }
 
   fn desc(&self) -> String {
@@ -221,12 +233,11 @@ impl Pipeline<bool> for MemoryInputStage {
   }
 
   fn opts(&self) -> String {
-    format!("{}, 0x{:x}, {:?}, {:?}, {:?}, {:?}",
-      self.usize, self.address, self.offsets, self.relation, self.var_type, self.values)
+    format!("{:?}, {:?}", self.target, self.condition)
   }
 
   fn probe(&self) -> Option<Probe> {
-    Some(Probe::Memory { usize: self.usize, address: self.address, offsets: self.offsets.clone() })
+    Some(Probe::Screen { target: self.target.clone() })
   }
 
   fn inspect(&self, out: &mut HashMap<StageId, PipelineStageDescription>) {
@@ -234,177 +245,161 @@ impl Pipeline<bool> for MemoryInputStage {
   }
 
   fn apply(&mut self, ctx: &Context, _: &mut Vec<Action>) -> bool {
+    let value = forced_probe(self.stage_id).unwrap_or_else(|| self.check(&ctx.probe_values[&self.stage_id]));
+    trace(self.stage_id, &value);
+    value
+  }
+
+  fn reset(&mut self) {}
+}
 
-    fn compare_u<T>(rel: &Relation, var: T, values: &Vec<u64>) -> bool where u64: From<T> {
-      match rel {
-        Relation::Eq =>  values.contains(&u64::from(var)),
-        Relation::Ne => !values.contains(&u64::from(var)),
-        Relation::Gt => {
-          assert_eq!(values.len(), 1);
-          values[0] > u64::from(var)
-        },
-        Relation::Lt => {
-          assert_eq!(values.len(), 1);
-          values[0] < u64::from(var)
-        },
-        Relation::Ge => {
-          assert_eq!(values.len(), 1);
-          values[0] >= u64::from(var)
-        },
-        Relation::Le => {
-          assert_eq!(values.len(), 1);
-          values[0] <= u64::from(var)
-        }
+impl ScreenScrapingInputStage {
+
+  fn check(&self, value: &ProbeValue) -> bool {
+    match &self.condition {
+      ScreenProbeCondition::Thresholds(threshold1, threshold2) => {
+        let (pixels_in_range, uniformity_score): (f32, f32) = unsafe { value.ff32 };
+        pixels_in_range >= *threshold1 || uniformity_score >= *threshold2
+      },
+      ScreenProbeCondition::MinCorrelation(threshold) => {
+        let correlation: f32 = unsafe { value.f32 };
+        correlation >= *threshold
+      },
+      ScreenProbeCondition::ColorWithin(color, tolerance) => {
+        let (r, g, b): (f32, f32, f32) = unsafe { value.fff32 };
+        (r - color.r).abs() <= *tolerance && (g - color.g).abs() <= *tolerance && (b - color.b).abs() <= *tolerance
+      },
+      ScreenProbeCondition::MaxDistance(threshold) => {
+        let distance: f32 = unsafe { value.f32 };
+        distance <= *threshold
       }
     }
+  }
+}
 
-    fn compare_s<T: Copy>(rel: &Relation, var: T, values: &Vec<u64>) -> bool where i64: From<T> {
-      match rel {
-        Relation::Eq =>  values.iter().any(|val| *val as i64 == i64::from(var)),
-        Relation::Ne => !values.iter().all(|val| *val as i64 != i64::from(var)),
-        Relation::Gt => {
-          assert_eq!(values.len(), 1);
-          (values[0] as i64) > i64::from(var)
-        },
-        Relation::Lt => {
-          assert_eq!(values.len(), 1);
-          (values[0] as i64) < i64::from(var)
-        },
-        Relation::Ge => {
-          assert_eq!(values.len(), 1);
-          (values[0] as i64) >= i64::from(var)
-        },
-        Relation::Le => {
-          assert_eq!(values.len(), 1);
-          (values[0] as i64) <= i64::from(var)
-        }
-      }
+pub fn screen_probe(target: overlay_ipc::ScreenScrapingArea, condition: ScreenProbeCondition) -> PipelineRef<bool> {
+  std::rc::Rc::new(std::cell::RefCell::new(ScreenScrapingInputStage { stage_id: generate_stage_id(), target, condition }))
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum OverlayProperty {
+  Connected,
+  Focused,
+  HudVisible,
+  KnobsMenuVisible,
+  ScreenWidth,
+  ScreenHeight,
+  FrameTime
+}
+
+impl OverlayProperty {
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name {
+      "connected"          => Some(OverlayProperty::Connected),
+      "focused"            => Some(OverlayProperty::Focused),
+      "hud_visible"        => Some(OverlayProperty::HudVisible),
+      "knobs_menu_visible" => Some(OverlayProperty::KnobsMenuVisible),
+      "screen_width"       => Some(OverlayProperty::ScreenWidth),
+      "screen_height"      => Some(OverlayProperty::ScreenHeight),
+      "frame_time"         => Some(OverlayProperty::FrameTime),
+      _ => None
     }
+  }
 
-    let probe_value = unsafe { ctx.probe_values[&self.stage_id].u64 };
-
-    match self.var_type {
-      VarType::I8  => compare_s(&self.relation, probe_value as  i8, &self.values),
-      VarType::U8  => compare_u(&self.relation, probe_value as  u8, &self.values),
-      VarType::I16 => compare_s(&self.relation, probe_value as i16, &self.values),
-      VarType::U16 => compare_u(&self.relation, probe_value as u16, &self.values),
-      VarType::I32 => compare_s(&self.relation, probe_value as i32, &self.values),
-      VarType::U32 => compare_u(&self.relation, probe_value as u32, &self.values),
-      VarType::I64 => compare_s(&self.relation, probe_value as i64, &self.values),
-      VarType::U64 => compare_u(&self.relation, probe_value,        &self.values)
+  fn read(&self, status: &OverlayStatus) -> f32 {
+    match self {
+      OverlayProperty::Connected        => status.connected          as u8 as f32,
+      OverlayProperty::Focused          => status.focused            as u8 as f32,
+      OverlayProperty::HudVisible       => status.hud_visible        as u8 as f32,
+      OverlayProperty::KnobsMenuVisible => status.knobs_menu_visible as u8 as f32,
+      OverlayProperty::ScreenWidth      => status.screen_width  as f32,
+      OverlayProperty::ScreenHeight     => status.screen_height as f32,
+      OverlayProperty::FrameTime        => status.frame_time
     }
   }
 
+  pub fn is_boolean(&self) -> bool {
+    matches!(self,
+      OverlayProperty::Connected | OverlayProperty::Focused | OverlayProperty::HudVisible | OverlayProperty::KnobsMenuVisible)
+  }
+}
+
+struct OverlayStatusAxisStage {
+  stage_id: StageId,
+  property: OverlayProperty
+}
+
+impl Pipeline<f32> for OverlayStatusAxisStage {
+
+  fn stage_id(&self) -> StageId {
+    self.stage_id
+  }
+
+  fn name(&self) -> &'static str {
+    "overlay_status"
+  }
+
+  fn desc(&self) -> String {
+    format!("{}({})", self.name(), self.opts())
+  }
+
+  fn opts(&self) -> String {
+    format!("{:?}", self.property)
+  }
+
+  fn inspect(&self, out: &mut HashMap<StageId, PipelineStageDescription>) {
+    insert_stage_description(out, self);
+  }
+
+  fn apply(&mut self, ctx: &Context, _: &mut Vec<Action>) -> f32 {
+    let value = self.property.read(ctx.overlay);
+    trace(self.stage_id, &value);
+    value
+  }
+
   fn reset(&mut self) {}
 }
 
-#[derive(Debug)]
-enum VarType {
-  I8, U8, I16, U16, I32, U32, I64, U64
+pub fn overlay_status_axis(property: OverlayProperty) -> PipelineRef<f32> {
+  std::rc::Rc::new(std::cell::RefCell::new(OverlayStatusAxisStage { stage_id: generate_stage_id(), property }))
 }
 
-#[derive(Debug)]
-enum Relation {
-  Eq, Ne, Gt, Lt, Ge, Le
+struct OverlayStatusButtonStage {
+  stage_id: StageId,
+  property: OverlayProperty
 }
 
-pub fn memory_probe(spec: &str) -> Result<PipelineRef<bool>, String> {
-
-  fn to_number(s: &str, pos: usize) -> Result<u64, String> {
-    (if let Some(hex) = s.strip_prefix("0x") { u64::from_str_radix(hex, 16) } else { s.parse::<u64>() })
-      .map_err(|e| format!("unexpected input at pos {}: {}", pos, e))
-  }
-
-  enum Phase {
-    PointerSize,
-    EntryPointer,
-    Offsets,
-    Relation,
-    VarType,
-    Value,
-    End
-  }
-
-  let mut stage = MemoryInputStage {
-    stage_id: generate_stage_id(),
-    usize:    0,
-    address:  0,
-    offsets:  vec![],
-    relation: Relation::Eq,
-    var_type: VarType::U64,
-    values:   vec![]
-  };
-
-  let mut phase = Phase::PointerSize;
-
-  let parts = spec.split(';').collect::<Vec<_>>();
-  for i in 0..parts.len() {
-    let str = parts[i];
-    match phase {
-      Phase::PointerSize => {
-        match str {
-          "32" => stage.usize = 32,
-          "64" => stage.usize = 64,
-          x => return Err(format!("expected either 32 or 64 at pos {}, got {}", i + 1, x))
-        };
-        phase = Phase::EntryPointer;
-      },
-      Phase::EntryPointer => {
-        stage.address = to_number(str, i + 1)?;
-        phase = Phase::Offsets;
-      },
-      Phase::Offsets => {
-        match str.split_at(1) {
-          ("+", s) => stage.offsets.push(  to_number(s, i + 1)? as i32),
-          ("-", s) => stage.offsets.push(-(to_number(s, i + 1)? as i32)),
-          (x, _) => return Err(format!("expected +|- at pos {}, got {}", i + 1, x))
-        };
-
-        if i + 1 == parts.len() {
-          return Err(format!("unexpected end of input at pos {}", i + 2));
-        }
-
-        if !(parts[i + 1].starts_with('+') || parts[i + 1].starts_with('-')) {
-          phase = Phase::Relation;
-        }
-      },
-      Phase::Relation => {
-        match str {
-          "eq" | "==" => stage.relation = Relation::Eq,
-          "ne" | "!=" => stage.relation = Relation::Ne,
-          "gt" | ">"  => stage.relation = Relation::Gt,
-          "lt" | "<"  => stage.relation = Relation::Lt,
-          "ge" | ">=" => stage.relation = Relation::Ge,
-          "le" | "<=" => stage.relation = Relation::Le,
-          x => return Err(format!("expected eq|ne|gt|lt|ge|le at pos {}, got {}", i + 1, x))
-        }
-        phase = Phase::VarType;
-      },
-      Phase::VarType => {
-        match str {
-          "i8"  => stage.var_type = VarType::I8,
-          "u8"  => stage.var_type = VarType::U8,
-          "i16" => stage.var_type = VarType::I16,
-          "u16" => stage.var_type = VarType::U16,
-          "i32" => stage.var_type = VarType::I32,
-          "u32" => stage.var_type = VarType::U32,
-          "i64" => stage.var_type = VarType::I64,
-          "u64" => stage.var_type = VarType::U64,
-          x => return Err(format!("expected i8|u8|i16|u16|i32|u32|i64|u64 at pos {}, got {}", i + 1, x))
-        }
-        phase = Phase::Value;
-      },
-      Phase::Value => {
-        for s in str.split(',') {
-          stage.values.push(to_number(s, i + 1)?);
-        }
-        phase = Phase::End;
-      },
-      Phase::End => {
-        return Err(format!("unexpected input at pos {}: {}", i + 1, str));
-      }
-    }
+impl Pipeline<bool> for OverlayStatusButtonStage {
+
+  fn stage_id(&self) -> StageId {
+    self.stage_id
+  }
+
+  fn name(&self) -> &'static str {
+    "overlay_status"
+  }
+
+  fn desc(&self) -> String {
+    format!("{}({})", self.name(), self.opts())
+  }
+
+  fn opts(&self) -> String {
+    format!("{:?}", self.property)
+  }
+
+  fn inspect(&self, out: &mut HashMap<StageId, PipelineStageDescription>) {
+    insert_stage_description(out, self);
   }
 
-  Ok(std::rc::Rc::new(std::cell::RefCell::new(stage)))
+  fn apply(&mut self, ctx: &Context, _: &mut Vec<Action>) -> bool {
+    let value = self.property.read(ctx.overlay) != 0.0;
+    trace(self.stage_id, &value);
+    value
+  }
+
+  fn reset(&mut self) {}
+}
+
+pub fn overlay_status_button(property: OverlayProperty) -> PipelineRef<bool> {
+  std::rc::Rc::new(std::cell::RefCell::new(OverlayStatusButtonStage { stage_id: generate_stage_id(), property }))
 }
@@ -0,0 +1,463 @@
+use pest::iterators::Pair;
+use pest::Parser;
+use pest_derive::Parser;
+
+use super::*;
+
+#[derive(Parser)]
+#[grammar = "stages/memory_probe.pest"]
+struct MemoryProbeParser;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum VarType {
+  I8, U8, I16, U16, I32, U32, I64, U64, F32, F64
+}
+
+impl VarType {
+  fn is_float(&self) -> bool {
+    matches!(self, VarType::F32 | VarType::F64)
+  }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Relation {
+  Eq, Ne, Gt, Lt, Ge, Le
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Number {
+  Int(i128),
+  Float(f64)
+}
+
+impl Number {
+  fn as_f64(&self) -> f64 {
+    match self {
+      Number::Int(n)   => *n as f64,
+      Number::Float(n) => *n
+    }
+  }
+
+  fn compare(&self, other: &Number) -> Option<std::cmp::Ordering> {
+    match (self, other) {
+      (Number::Int(a), Number::Int(b)) => Some(a.cmp(b)),
+      (a, b) => a.as_f64().partial_cmp(&b.as_f64())
+    }
+  }
+}
+
+#[derive(Clone, Debug)]
+struct MemoryRead {
+  usize:    u8,
+  module:   Option<String>,
+  address:  u64,
+  offsets:  Vec<i32>,
+  var_type: VarType
+}
+
+impl MemoryRead {
+
+  fn probe(&self) -> Probe {
+    Probe::Memory { usize: self.usize, module: self.module.clone(), address: self.address, offsets: self.offsets.clone() }
+  }
+
+  // memory probes report a pointer-sized value, only the lower bytes are relevant
+  fn decode(&self, raw: u64) -> Number {
+    match self.var_type {
+      VarType::I8  => Number::Int(raw as  i8 as i128),
+      VarType::U8  => Number::Int(raw as  u8 as i128),
+      VarType::I16 => Number::Int(raw as i16 as i128),
+      VarType::U16 => Number::Int(raw as u16 as i128),
+      VarType::I32 => Number::Int(raw as i32 as i128),
+      VarType::U32 => Number::Int(raw as u32 as i128),
+      VarType::I64 => Number::Int(raw as i64 as i128),
+      VarType::U64 => Number::Int(raw as i128),
+      VarType::F32 => Number::Float(f32::from_bits(raw as u32) as f64),
+      VarType::F64 => Number::Float(f64::from_bits(raw))
+    }
+  }
+
+  fn desc(&self) -> String {
+    let offsets = self.offsets.iter()
+      .map(|offset| if *offset < 0 { format!(" -0x{:x}", -(*offset as i64)) } else { format!(" +0x{:x}", offset) })
+      .collect::<String>();
+    let module = self.module.as_ref().map(|module| format!("{}+", module)).unwrap_or_default();
+    format!("{}[{}: {}0x{:x}{}]", format!("{:?}", self.var_type).to_lowercase(), self.usize, module, self.address, offsets)
+  }
+}
+
+#[derive(Clone, Debug)]
+enum MemoryTest {
+  Relation(Relation, Number),
+  OneOf(Vec<Number>),
+  InRange(Number, Number),
+  AnyBits(u64)
+}
+
+impl MemoryTest {
+  fn check(&self, value: Number) -> bool {
+    use std::cmp::Ordering::*;
+    match self {
+      MemoryTest::Relation(relation, n) => {
+        let ord = value.compare(n);
+        match relation {
+          Relation::Eq => ord == Some(Equal),
+          Relation::Ne => ord != Some(Equal),
+          Relation::Gt => ord == Some(Greater),
+          Relation::Lt => ord == Some(Less),
+          Relation::Ge => matches!(ord, Some(Greater | Equal)),
+          Relation::Le => matches!(ord, Some(Less | Equal))
+        }
+      },
+      MemoryTest::OneOf(values) => values.iter().any(|n| value.compare(n) == Some(Equal)),
+      MemoryTest::InRange(from, to) => {
+        matches!(value.compare(from), Some(Greater | Equal)) && matches!(value.compare(to), Some(Less | Equal))
+      },
+      MemoryTest::AnyBits(mask) => match value {
+        Number::Int(n)   => (n as u64) & mask != 0,
+        Number::Float(_) => unreachable!()
+      }
+    }
+  }
+}
+
+struct MemoryConditionStage {
+  stage_id: StageId,
+  read:     MemoryRead,
+  test:     MemoryTest
+}
+
+impl Pipeline<bool> for MemoryConditionStage {
+
+  fn stage_id(&self) -> StageId {
+    self.stage_id
+  }
+
+  fn name(&self) -> &'static str {
+    "memory_probe"
+  }
+
+  fn desc(&self) -> String {
+    format!("{}({})", self.name(), self.opts())
+  }
+
+  fn opts(&self) -> String {
+    format!("{}, {:?}", self.read.desc(), self.test)
+  }
+
+  fn probe(&self) -> Option<Probe> {
+    Some(self.read.probe())
+  }
+
+  fn inspect(&self, out: &mut HashMap<StageId, PipelineStageDescription>) {
+    insert_stage_description(out, self);
+  }
+
+  fn apply(&mut self, ctx: &Context, _: &mut Vec<Action>) -> bool {
+    let value = forced_probe(self.stage_id).unwrap_or_else(|| {
+      let raw = unsafe { ctx.probe_values[&self.stage_id].u64 };
+      self.test.check(self.read.decode(raw))
+    });
+    trace(self.stage_id, &value);
+    value
+  }
+
+  fn reset(&mut self) {}
+}
+
+struct MemoryValueStage {
+  stage_id: StageId,
+  read:     MemoryRead
+}
+
+impl Pipeline<f32> for MemoryValueStage {
+
+  fn stage_id(&self) -> StageId {
+    self.stage_id
+  }
+
+  fn name(&self) -> &'static str {
+    "memory_probe"
+  }
+
+  fn desc(&self) -> String {
+    format!("{}({})", self.name(), self.opts())
+  }
+
+  fn opts(&self) -> String {
+    self.read.desc()
+  }
+
+  fn probe(&self) -> Option<Probe> {
+    Some(self.read.probe())
+  }
+
+  fn inspect(&self, out: &mut HashMap<StageId, PipelineStageDescription>) {
+    insert_stage_description(out, self);
+  }
+
+  fn apply(&mut self, ctx: &Context, _: &mut Vec<Action>) -> f32 {
+    let raw   = unsafe { ctx.probe_values[&self.stage_id].u64 };
+    let value = self.read.decode(raw).as_f64() as f32;
+    trace(self.stage_id, &value);
+    value
+  }
+
+  fn reset(&mut self) {}
+}
+
+pub enum MemoryProbe {
+  Value(PipelineRef<f32>),
+  Condition(PipelineRef<bool>)
+}
+
+fn parse_u64(s: &str) -> Result<u64, String> {
+  (if let Some(hex) = s.strip_prefix("0x") { u64::from_str_radix(hex, 16) } else { s.parse::<u64>() })
+    .map_err(|e| format!("{}: {}", s, e))
+}
+
+fn parse_number(pair: Pair<Rule>) -> Result<Number, String> {
+  let s = pair.as_str();
+  let (negative, abs) = if let Some(abs) = s.strip_prefix('-') { (true, abs) } else { (false, s) };
+  if abs.starts_with("0x") || !abs.contains('.') {
+    let n = parse_u64(abs)? as i128;
+    Ok(Number::Int(if negative { -n } else { n }))
+  } else {
+    s.parse::<f64>().map(Number::Float).map_err(|e| format!("{}: {}", s, e))
+  }
+}
+
+fn parse_read(pair: Pair<Rule>) -> Result<MemoryRead, String> {
+  let pair_str  = pair.as_str().to_string();
+  let mut pairs = pair.into_inner();
+
+  let var_type = match pairs.next().unwrap().as_str() {
+    "i8"  => VarType::I8,
+    "u8"  => VarType::U8,
+    "i16" => VarType::I16,
+    "u16" => VarType::U16,
+    "i32" => VarType::I32,
+    "u32" => VarType::U32,
+    "i64" => VarType::I64,
+    "u64" => VarType::U64,
+    "f32" => VarType::F32,
+    "f64" => VarType::F64,
+    _ => unreachable!()
+  };
+
+  let usize = pairs.next().unwrap().as_str().parse::<u8>().unwrap();
+
+  let mut pair = pairs.next().unwrap();
+  let module = if pair.as_rule() == Rule::module {
+    let module = pair.as_str().to_string();
+    pair = pairs.next().unwrap();
+    Some(module)
+  } else {
+    None
+  };
+
+  let address = parse_u64(pair.as_str())?;
+
+  let mut offsets = vec![];
+  for offset in pairs {
+    let (sign, abs) = offset.as_str().split_at(1);
+    let abs = i32::try_from(parse_u64(abs)?).map_err(|_| format!("offset {} is too large", offset.as_str()))?;
+    offsets.push(if sign == "-" { -abs } else { abs });
+  }
+
+  // probes report pointer-sized values, so a 32-bit target can't provide 64-bit ones
+  if usize == 32 && matches!(var_type, VarType::I64 | VarType::U64 | VarType::F64) {
+    return Err(format!("{} reads require a 64-bit target, got {}", format!("{:?}", var_type).to_lowercase(), pair_str));
+  }
+
+  Ok(MemoryRead { usize, module, address, offsets, var_type })
+}
+
+fn build_condition(pair: Pair<Rule>) -> Result<PipelineRef<bool>, String> {
+
+  fn combine(name: &'static str, p1: PipelineRef<bool>, p2: PipelineRef<bool>, and: bool) -> PipelineRef<bool> {
+    let fun = Box::new(move |a: bool, b: bool, _, _, _: &mut Vec<Action>| if and { a && b } else { a || b });
+    std::rc::Rc::new(std::cell::RefCell::new(BiFnStage::from(name, "".to_string(), p1, p2, fun)))
+  }
+
+  let rule = pair.as_rule();
+  match rule {
+    Rule::condition | Rule::and => {
+      let mut pairs = pair.into_inner();
+      let mut p = build_condition(pairs.next().unwrap())?;
+      for next in pairs {
+        p = if rule == Rule::and {
+          combine("and", p, build_condition(next)?, true)
+        } else {
+          combine("or", p, build_condition(next)?, false)
+        };
+      }
+      Ok(p)
+    },
+    Rule::not => {
+      Ok(invert(build_condition(pair.into_inner().next().unwrap())?))
+    },
+    Rule::compare | Rule::one_of | Rule::range | Rule::any_bits => {
+      let mut pairs = pair.into_inner();
+      let read = parse_read(pairs.next().unwrap())?;
+
+      let test = match rule {
+        Rule::compare => {
+          let relation = match pairs.next().unwrap().as_str() {
+            "==" => Relation::Eq,
+            "!=" => Relation::Ne,
+            ">"  => Relation::Gt,
+            "<"  => Relation::Lt,
+            ">=" => Relation::Ge,
+            "<=" => Relation::Le,
+            _ => unreachable!()
+          };
+          MemoryTest::Relation(relation, parse_number(pairs.next().unwrap())?)
+        },
+        Rule::one_of => {
+          MemoryTest::OneOf(pairs.map(parse_number).collect::<Result<Vec<_>, _>>()?)
+        },
+        Rule::range => {
+          let from = parse_number(pairs.next().unwrap())?;
+          let to   = parse_number(pairs.next().unwrap())?;
+          if from.compare(&to) == Some(std::cmp::Ordering::Greater) {
+            return Err(format!("empty range [{}, {}]", from.as_f64(), to.as_f64()));
+          }
+          MemoryTest::InRange(from, to)
+        },
+        _ => {
+          if read.var_type.is_float() {
+            return Err(format!("bit tests require an integer type, got {:?}", read.var_type).to_lowercase());
+          }
+          match parse_number(pairs.next().unwrap())? {
+            Number::Int(mask) if mask > 0 && mask <= u64::MAX as i128 => MemoryTest::AnyBits(mask as u64),
+            _ => return Err("bit mask should be a positive integer".to_string())
+          }
+        }
+      };
+
+      Ok(std::rc::Rc::new(std::cell::RefCell::new(MemoryConditionStage { stage_id: generate_stage_id(), read, test })))
+    },
+    _ => unreachable!()
+  }
+}
+
+// the spec format before expressions: pointer size;address;offsets (one per part);relation;type;values
+fn translate_legacy_spec(spec: &str) -> Option<String> {
+  let parts = spec.split(';').collect::<Vec<_>>();
+  if parts.len() < 6 {
+    return None;
+  }
+
+  let (usize, address, offsets) = (parts[0], parts[1], &parts[2..(parts.len() - 3)]);
+  let (relation, var_type, values) = (parts[parts.len() - 3], parts[parts.len() - 2], parts[parts.len() - 1]);
+
+  let relation = match relation {
+    "eq" => "==", "ne" => "!=", "gt" => ">", "lt" => "<", "ge" => ">=", "le" => "<=",
+    other => other
+  };
+
+  let read   = format!("{}[{}: {} {}]", var_type, usize, address, offsets.join(" "));
+  let values = values.split(',').collect::<Vec<_>>();
+  match (relation, values.as_slice()) {
+    (_,    [value]) => Some(format!("{} {} {}", read, relation, value)),
+    ("==", values)  => Some(format!("{} in {{{}}}", read, values.join(", "))),
+    ("!=", values)  => Some(format!("!({} in {{{}}})", read, values.join(", "))),
+    _ => None
+  }
+}
+
+pub fn memory_probe(spec: &str) -> Result<MemoryProbe, String> {
+
+  if spec.contains(';') {
+    return Err(match translate_legacy_spec(spec) {
+      Some(translated) => format!("semicolon separated specs are no longer supported, this one reads as \"{}\"", translated),
+      None             => "semicolon separated specs are no longer supported, see mapper/src/stages/memory_probe.pest".to_string()
+    });
+  }
+
+  let pair = MemoryProbeParser::parse(Rule::spec, spec)
+    .map_err(|e| format!("invalid spec\n{}", e))?
+    .next()
+    .unwrap();
+
+  if pair.as_rule() == Rule::read {
+    let read = parse_read(pair)?;
+    Ok(MemoryProbe::Value(std::rc::Rc::new(std::cell::RefCell::new(MemoryValueStage { stage_id: generate_stage_id(), read }))))
+  } else {
+    Ok(MemoryProbe::Condition(build_condition(pair)?))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  fn probe_values<T: Copy + 'static>(p: &PipelineRef<T>, values: &[u64]) -> HashMap<StageId, ProbeValue> {
+    let mut meta = HashMap::new();
+    p.borrow().inspect(&mut meta);
+
+    let mut ids = meta.values().filter(|stage| stage.probe.is_some()).map(|stage| stage.id).collect::<Vec<_>>();
+    ids.sort();
+    assert_eq!(ids.len(), values.len());
+
+    ids.into_iter().zip(values).map(|(id, value)| (id, ProbeValue { u64: *value })).collect()
+  }
+
+  fn check(spec: &str, values: &[u64]) -> bool {
+    if let Ok(MemoryProbe::Condition(p)) = memory_probe(spec) {
+      let state = crate::controllers::ControllerState::empty();
+      let probe_values = probe_values(&p, values);
+      let ctx = Context { state: &state, time: Timestamp::from_millis(0), layers: LayerMask::EMPTY, probe_values: &probe_values, overlay: &OverlayStatus::default() };
+      let result = p.borrow_mut().apply(&ctx, &mut vec![]);
+      result
+    } else {
+      panic!("{}", spec);
+    }
+  }
+
+  #[test]
+  fn memory_probe_test() {
+    assert!( check("u16[32: 0x00C0FEE0 +0x22] != 0",                     &[5]));
+    assert!(!check("u16[32: 0x00C0FEE0 +0x22] != 0",                     &[0x10000]));
+    assert!( check("i8[32: 0x1000] < 0",                                 &[0xff]));
+    assert!( check("u16[32: 0x1000 +0x4 -0x8] in {460, 464, 476}",       &[464]));
+    assert!(!check("u16[32: 0x1000] in {460, 464, 476}",                 &[465]));
+    assert!( check("f32[64: 0x1000] in [0, 50.5]",                       &[50.5f32.to_bits() as u64]));
+    assert!(!check("f32[64: 0x1000] in [0, 50.5]",                       &[(-1.0f32).to_bits() as u64]));
+    assert!( check("u8[32: 0x1000] & 0x4",                               &[0x6]));
+    assert!( check("u8[32: 0x1000] & 0x4 && u8[32: 0x2000] == 1",        &[0x4, 1]));
+    assert!(!check("u8[32: 0x1000] & 0x4 && u8[32: 0x2000] == 1",        &[0x4, 2]));
+    assert!( check("u8[32: 0x1000] == 1 || u8[32: 0x2000] == 1",         &[0, 1]));
+    assert!( check("!(u8[32: 0x1000] == 1 || u8[32: 0x2000] == 1)",      &[0, 0]));
+    assert!( check("u64[64: 0x1000] == 0xffffffffffffffff",              &[u64::MAX]));
+    assert!( check("u8[32: gta-sa.exe+0x1000 +0x4] == 1",                &[1]));
+
+    match memory_probe("f64[64: 0x1000 +0x10]") {
+      Ok(MemoryProbe::Value(p)) => {
+        let state = crate::controllers::ControllerState::empty();
+        let probe_values = probe_values(&p, &[42.5f64.to_bits()]);
+        let ctx = Context { state: &state, time: Timestamp::from_millis(0), layers: LayerMask::EMPTY, probe_values: &probe_values, overlay: &OverlayStatus::default() };
+        assert_eq!(p.borrow_mut().apply(&ctx, &mut vec![]), 42.5);
+      },
+      _ => panic!()
+    }
+
+    assert!(memory_probe("u16[16: 0x1000] == 1").is_err());
+    assert!(memory_probe("u16[32: 0x1000] ==").is_err());
+    assert!(memory_probe("f32[32: 0x1000] & 0x1").is_err());
+    assert!(memory_probe("u8[32: 0x1000] in [5, 1]").is_err());
+    assert_eq!(memory_probe("u64[32: 0x1000] == 1").err().unwrap(), "u64 reads require a 64-bit target, got u64[32: 0x1000]");
+    assert!(memory_probe("f64[32: 0x1000]").is_err());
+
+    assert_eq!(translate_legacy_spec("32;0x00C0FEE0;+0x22;ne;u16;0").unwrap(), "u16[32: 0x00C0FEE0 +0x22] != 0");
+    assert_eq!(translate_legacy_spec("32;0x1000;+0x4;-0x8;eq;u16;460,464").unwrap(), "u16[32: 0x1000 +0x4 -0x8] in {460, 464}");
+    assert_eq!(translate_legacy_spec("64;0x1000;+0x4;ne;u8;1,2").unwrap(), "!(u8[64: 0x1000 +0x4] in {1, 2})");
+    for spec in ["32;0x00C0FEE0;+0x22;ne;u16;0", "32;0x1000;+0x4;-0x8;eq;u16;460,464", "64;0x1000;+0x4;ne;u8;1,2"] {
+      let translated = translate_legacy_spec(spec).unwrap();
+      assert!(matches!(memory_probe(&translated), Ok(MemoryProbe::Condition(_))), "{}", translated);
+      assert!(memory_probe(spec).err().unwrap().ends_with(&format!("reads as \"{}\"", translated)));
+    }
+  }
+}
@@ -18,33 +18,29 @@ pub enum Action {
   ToggleShapes { stage_id: StageId, layer: u8, mask: u64 },
   ToggleOverlayUI,
   HapticFeedback(HapticFeedbackTarget, HapticFeedbackEffect),
+  Rumble(HapticFeedbackTarget, f32),
   SendOverlayMenuCommand(OverlayMenuCommand)
 }
 
-#[cfg(not(test))]
+// time since the start of crate::clock::Clock
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
-pub struct Timestamp(pub std::time::Instant);
+pub struct Timestamp(pub Duration);
 
-#[cfg(test)]
-#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
-pub struct Timestamp(pub u64);
+impl Timestamp {
+  #[cfg(test)]
+  pub fn from_millis(millis: u64) -> Self {
+    Timestamp(Duration::from_millis(millis))
+  }
+}
 
 impl std::ops::Sub<Timestamp> for Timestamp {
 
   type Output = Duration;
 
-  #[cfg(not(test))]
-  fn sub(self, other: Timestamp) -> Duration {
-    let Timestamp(instant1) = self;
-    let Timestamp(instant2) = other;
-    instant1 - instant2
-  }
-
-  #[cfg(test)]
   fn sub(self, other: Timestamp) -> Duration {
     let Timestamp(t1) = self;
     let Timestamp(t2) = other;
-    Duration::from_millis(t1 - t2)
+    t1.saturating_sub(t2)
   }
 }
 
@@ -69,18 +65,72 @@ pub fn generate_stage_id() -> StageId {
   STAGE_ID_COUNTER.fetch_add(1, std::sync::atomic::Ordering::SeqCst)
 }
 
+thread_local! {
+  // debug hook: stage outputs of the current tick, only recorded after enable_tracing
+  static STAGE_TRACE: std::cell::RefCell<Option<HashMap<StageId, String>>> = const { std::cell::RefCell::new(None) };
+}
+
+pub fn enable_tracing() {
+  STAGE_TRACE.with(|trace| *trace.borrow_mut() = Some(HashMap::new()));
+}
+
+// called by the mapper at the start of every tick
+pub fn clear_trace() {
+  STAGE_TRACE.with(|trace| if let Some(values) = trace.borrow_mut().as_mut() { values.clear() });
+}
+
+pub fn traced_values() -> HashMap<StageId, String> {
+  STAGE_TRACE.with(|trace| trace.borrow().clone().unwrap_or_default())
+}
+
+fn trace<R: std::fmt::Debug>(stage_id: StageId, value: &R) {
+  STAGE_TRACE.with(|trace| if let Some(values) = trace.borrow_mut().as_mut() { values.insert(stage_id, format!("{:?}", value)); });
+}
+
+thread_local! {
+  // fuzzing hook: probe conditions forced on or off regardless of probe values
+  static FORCED_PROBES: std::cell::RefCell<HashMap<StageId, bool>> = std::cell::RefCell::new(HashMap::new());
+}
+
+pub fn force_probe(stage_id: StageId, value: bool) {
+  FORCED_PROBES.with(|forced| forced.borrow_mut().insert(stage_id, value));
+}
+
+pub fn clear_forced_probes() {
+  FORCED_PROBES.with(|forced| forced.borrow_mut().clear());
+}
+
+fn forced_probe(stage_id: StageId) -> Option<bool> {
+  FORCED_PROBES.with(|forced| forced.borrow().get(&stage_id).copied())
+}
+
 #[repr(C)]
 pub union ProbeValue {
-  pub u64:  u64,
-  pub f64:  f64,
-  pub ff32: (f32, f32)
+  pub u64:   u64,
+  pub f64:   f64,
+  pub f32:   f32,
+  pub ff32:  (f32, f32),
+  pub fff32: (f32, f32, f32)
+}
+
+// as reported by overlay events
+#[derive(Clone, Debug, Default)]
+pub struct OverlayStatus {
+  pub connected:          bool,
+  pub focused:            bool,
+  pub hud_visible:        bool,
+  pub knobs_menu_visible: bool,
+  pub screen_width:       u32,
+  pub screen_height:      u32,
+  pub frame_time:         f32
 }
 
 pub struct Context<'a> {
   pub state:        ControllerState<'a>,
   pub time:         Timestamp, //TODO: replace with tick duration?
   pub layers:       LayerMask,
-  pub probe_values: &'a HashMap<StageId, ProbeValue>
+  pub probe_values: &'a HashMap<StageId, ProbeValue>,
+  pub overlay:      &'a OverlayStatus
 }
 
 pub trait Pipeline<R: Copy> {
@@ -100,6 +150,11 @@ pub trait Pipeline<R: Copy> {
     None
   }
 
+  // the controller axis read by input stages
+  fn axis(&self) -> Option<Axis> {
+    None
+  }
+
   fn shapes(&self) -> Vec<Vec<overlay_ipc::Shape>> {
     vec![]
   }
@@ -112,7 +167,8 @@ pub trait Pipeline<R: Copy> {
 #[derive(Clone, Debug)]
 pub enum Probe {
   Screen { target: overlay_ipc::ScreenScrapingArea },
-  Memory { usize: u8, address: u64, offsets: Vec<i32> }
+  // the address is relative to the module's load address if the module is specified
+  Memory { usize: u8, module: Option<String>, address: u64, offsets: Vec<i32> }
 }
 
 #[derive(Clone, Debug)]
@@ -122,6 +178,7 @@ pub struct PipelineStageDescription {
   pub opts:   String,
   pub inputs: Vec<StageId>, //TODO: named inputs?
   pub probe:  Option<Probe>,
+  pub axis:   Option<Axis>,
   pub shapes: Vec<Vec<overlay_ipc::Shape>>
 }
 
@@ -133,6 +190,7 @@ fn insert_stage_description<T: Copy + 'static>(out: &mut HashMap<StageId, Pipeli
       opts:   p.opts(),
       inputs: p.inputs(),
       probe:  p.probe(),
+      axis:   p.axis(),
       shapes: p.shapes()
     });
     true
@@ -157,7 +215,7 @@ impl<T: Copy + 'static, R: Copy + 'static> FnStage<T, R> {
   }
 }
 
-impl<T: Copy + 'static, R: Copy + 'static> Pipeline<R> for FnStage<T, R> {
+impl<T: Copy + 'static, R: Copy + std::fmt::Debug + 'static> Pipeline<R> for FnStage<T, R> {
 
   fn stage_id(&self) -> StageId {
     self.stage_id
@@ -188,6 +246,7 @@ impl<T: Copy + 'static, R: Copy + 'static> Pipeline<R> for FnStage<T, R> {
   fn apply(&mut self, ctx: &Context, actions: &mut Vec<Action>) -> R {
     if self.out.is_none() {
       self.out = Some((self.fun)(self.pipeline.borrow_mut().apply(ctx, actions), ctx.time, ctx.layers, actions));
+      trace(self.stage_id, &self.out.unwrap());
     }
     self.out.unwrap()
   }
@@ -224,7 +283,7 @@ impl<T: Copy + 'static, U: Copy + 'static, R: Copy + 'static> BiFnStage<T, U, R>
   }
 }
 
-impl<T: Copy + 'static, U: Copy + 'static, R: Copy + 'static> Pipeline<R> for BiFnStage<T, U, R> {
+impl<T: Copy + 'static, U: Copy + 'static, R: Copy + std::fmt::Debug + 'static> Pipeline<R> for BiFnStage<T, U, R> {
 
   fn stage_id(&self) -> StageId {
     self.stage_id
@@ -262,6 +321,7 @@ impl<T: Copy + 'static, U: Copy + 'static, R: Copy + 'static> Pipeline<R> for Bi
       let v1 = self.pipeline1.borrow_mut().apply(ctx, actions);
       let v2 = self.pipeline2.borrow_mut().apply(ctx, actions);
       self.out = Some((self.fun)(v1, v2, ctx.time, ctx.layers, actions));
+      trace(self.stage_id, &self.out.unwrap());
     }
     self.out.unwrap()
   }
@@ -335,6 +395,9 @@ pub use self::as_axis_input::*;
 mod cartesian;
 pub use self::cartesian::*;
 
+mod curve;
+pub use self::curve::*;
+
 mod cutoff;
 pub use self::cutoff::*;
 
@@ -356,6 +419,9 @@ pub use self::invert::*;
 mod line_segment_button;
 pub use self::line_segment_button::*;
 
+mod memory_probe;
+pub use self::memory_probe::*;
+
 mod menu_item;
 pub use self::menu_item::*;
 
@@ -28,7 +28,9 @@ impl Pipeline<bool> for ModeCheckStage {
   }
 
   fn apply(&mut self, ctx: &Context, _: &mut Vec<Action>) -> bool {
-    ctx.layers == self.target
+    let value = ctx.layers == self.target;
+    trace(self.stage_id, &value);
+    value
   }
 
   fn reset(&mut self) {}
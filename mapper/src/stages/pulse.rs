@@ -115,6 +115,7 @@ impl Pipeline<bool> for AxisPulseStage {
       }
 
       self.out_value = Some(self.value);
+      trace(self.stage_id, &self.value);
     }
 
     self.out_value.unwrap()
@@ -1,4 +1,4 @@
-use overlay_ipc::{Color, Length, Point, Shape};
+use overlay_ipc::{Angle, Color, Label, Length, Point, Shape};
 
 use super::*;
 use crate::controllers::{HapticFeedbackEffect, HapticFeedbackTarget};
@@ -63,7 +63,84 @@ impl TouchPoint {
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum TouchMenuOpts {
   Radial  { inner_radius: f32, outer_radius: f32, margin: f32 },
-  HexGrid { margin: f32 }
+  HexGrid { margin: f32 },
+  Grid    { columns: usize, margin: f32 } // a list is a grid with a single column
+}
+
+#[derive(Clone, Debug)]
+pub struct TouchMenuItem {
+  pub label:   String,
+  pub icon:    Option<String>,
+  pub color:   Option<Color>,
+  pub submenu: Vec<TouchMenuItem>
+}
+
+impl TouchMenuItem {
+  pub fn new(label: String) -> Self {
+    Self { label, icon: None, color: None, submenu: vec![] }
+  }
+}
+
+#[derive(Copy, Clone, Debug)]
+enum TouchMenuEntry {
+  Item(u8),      // index among all leaf items of the menu tree in depth-first order
+  Submenu(usize) // index of the level to open
+}
+
+type TouchMenuLevel = Vec<(TouchMenuItem, TouchMenuEntry)>;
+
+type ItemShapeFn<'a> = Box<dyn Fn(usize, Color) -> Shape + 'a>;
+
+fn flatten_menu(items: Vec<TouchMenuItem>, levels: &mut Vec<TouchMenuLevel>, number_of_items: &mut usize) -> usize {
+
+  let level = levels.len();
+  levels.push(vec![]);
+
+  for mut item in items {
+    let submenu = std::mem::take(&mut item.submenu);
+    let entry   = if submenu.is_empty() {
+      *number_of_items += 1;
+      TouchMenuEntry::Item((*number_of_items - 1) as u8)
+    } else {
+      TouchMenuEntry::Submenu(flatten_menu(submenu, levels, number_of_items))
+    };
+    levels[level].push((item, entry));
+  }
+
+  level
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TouchMenuHaptics {
+  pub targets:   Vec<HapticFeedbackTarget>,
+  pub intensity: f32
+}
+
+impl TouchMenuHaptics {
+
+  // menus driven by a single touchpad give feedback on that side only
+  pub fn for_position(position: &PipelineRef<(f32, f32)>) -> Self {
+
+    let mut stages = HashMap::new();
+    position.borrow().inspect(&mut stages);
+
+    let uses_pad = |x: Axis, y: Axis| stages.values().any(|stage| matches!(stage.axis, Some(axis) if axis == x || axis == y));
+
+    let targets = match (uses_pad(Axis::LPadX, Axis::LPadY), uses_pad(Axis::RPadX, Axis::RPadY)) {
+      (true, false) => vec![HapticFeedbackTarget::LeftSide],
+      (false, true) => vec![HapticFeedbackTarget::RightSide],
+      _             => vec![HapticFeedbackTarget::LeftSide, HapticFeedbackTarget::RightSide]
+    };
+
+    Self { targets, intensity: 1.0 }
+  }
+
+  fn feedback(&self, effect: HapticFeedbackEffect, actions: &mut Vec<Action>) {
+    let effect = if self.intensity == 1.0 { effect } else { effect.scaled(self.intensity) };
+    for target in &self.targets {
+      actions.push(Action::HapticFeedback(*target, effect));
+    }
+  }
 }
 
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -78,8 +155,12 @@ struct TouchMenuStage {
   position:        PipelineRef<(f32, f32)>,
   toggle:          PipelineRef<bool>,
   select:          PipelineRef<bool>,
+  select_bstate:   Box<dyn FnMut(bool) -> ButtonState>,
   opts:            TouchMenuOpts,
-  items:           Vec<String>,
+  haptics:         TouchMenuHaptics,
+  sticky:          bool,
+  levels:          Vec<TouchMenuLevel>,
+  level:           usize,
   selected_option: Option<(u8, Timestamp)>,
   mode:            TouchMenuMode,
   out_value:       Option<Option<u8>>
@@ -252,161 +333,175 @@ fn number_of_hex_grid_circles(number_of_cells: usize) -> usize {
   }
 }
 
-//TODO: determine haptic feedback target automatically or make it configurable
-impl Pipeline<Option<u8>> for TouchMenuStage {
+fn grid_cell_on_touchpad(x: f32, y: f32, columns: usize, rows: usize, margin: f32) -> Option<(usize, usize)> {
 
-  fn stage_id(&self) -> StageId {
-    self.stage_id
-  }
+  let cell_width  = 2.0 / columns as f32;
+  let cell_height = 2.0 / rows    as f32;
 
-  fn name(&self) -> &'static str {
-    "touch_menu"
-  }
+  // row 0 is at the top
+  let x = x.clamp(-1.0, 1.0) + 1.0;
+  let y = 1.0 - y.clamp(-1.0, 1.0);
 
-  fn desc(&self) -> String {
-    format!("{}(...)", self.name())
-  }
+  let column = ((x / cell_width ) as usize).min(columns - 1);
+  let row    = ((y / cell_height) as usize).min(rows    - 1);
 
-  fn inputs(&self) -> Vec<StageId> {
-    vec![self.position.borrow().stage_id(), self.toggle.borrow().stage_id(), self.select.borrow().stage_id()]
+  let dx = x - column as f32 * cell_width;
+  let dy = y - row    as f32 * cell_height;
+
+  // the margin only applies to borders between cells
+  if (column > 0 && dx < margin) || (column + 1 < columns && dx > cell_width  - margin)
+    || (row  > 0 && dy < margin) || (row    + 1 < rows    && dy > cell_height - margin)
+  {
+    None
+  } else {
+    Some((column, row))
   }
+}
 
-  fn shapes(&self) -> Vec<Vec<overlay_ipc::Shape>> {
+fn number_of_grid_rows(number_of_cells: usize, columns: usize) -> usize {
+  number_of_cells.div_ceil(columns)
+}
 
-    let center      = Point::vwh(50.0, 50.0);
-    let menu_height = Length::vh(50.0);
+impl TouchMenuStage {
 
-    match self.opts {
-      TouchMenuOpts::Radial { inner_radius, outer_radius, .. } => {
+  fn level_shapes(&self, items: &[(TouchMenuItem, TouchMenuEntry)]) -> Vec<Vec<Shape>> {
 
-        let sector_width = std::f32::consts::PI * 2.0 / self.items.len() as f32;
+    let center      = Point::vwh(50.0, 50.0);
+    let menu_height = Length::vh(50.0);
 
-        let mut layers = vec![];
+    let label = |i: usize| Some(Label { text: items[i].0.label.clone(), color: Color::rgb(1.0, 1.0, 1.0), icon: items[i].0.icon.clone() });
 
-        // background
-        layers.push(vec![
-          Shape::Ring {
-            center,
-            inner_radius: menu_height * 0.5 * inner_radius,
-            outer_radius: menu_height * 0.5 * outer_radius,
-            color:        Color::rgba(0.0, 0.2, 0.0, 0.4)
-          }
-        ]);
-
-        // normal items
-        let mut direction = std::f32::consts::PI / 2.0;
-        let mut v = Vec::with_capacity(self.items.len());
-        for item in &self.items {
-          v.push(overlay_ipc::Shape::RingSector {
-            center,
-            direction:    overlay_ipc::Angle::Rad(-direction),
-            width:        overlay_ipc::Angle::Rad(sector_width),
-            inner_radius: menu_height * 0.5 * inner_radius,
-            outer_radius: menu_height * 0.5 * outer_radius,
-            color:        overlay_ipc::Color::rgba(0.0, 0.0, 0.0, 0.0),
-            label:        Some((item.clone(), overlay_ipc::Color::rgb(1.0, 1.0, 1.0)))
-          });
-          direction -= sector_width;
-        }
-        layers.push(v);
-
-        // selected items
-        let mut direction = std::f32::consts::PI / 2.0;
-        let mut v = Vec::with_capacity(self.items.len());
-        for item in &self.items {
-          v.push(overlay_ipc::Shape::RingSector {
-            center,
-            direction:    overlay_ipc::Angle::Rad(-direction),
-            width:        overlay_ipc::Angle::Rad(sector_width),
-            inner_radius: menu_height * 0.5 * inner_radius,
-            outer_radius: menu_height * 0.5 * outer_radius,
-            color:        overlay_ipc::Color::rgba(0.0, 0.8, 0.0, 0.8),
-            label:        Some((item.clone(), overlay_ipc::Color::rgb(1.0, 1.0, 1.0)))
-          });
-          direction -= sector_width;
-        }
-        layers.push(v);
-
-        // selected and locked items
-        let mut direction = std::f32::consts::PI / 2.0;
-        let mut v = Vec::with_capacity(self.items.len());
-        for item in &self.items {
-          v.push(overlay_ipc::Shape::RingSector {
-            center,
-            direction:    overlay_ipc::Angle::Rad(-direction),
-            width:        overlay_ipc::Angle::Rad(sector_width),
-            inner_radius: menu_height * 0.5 * inner_radius,
-            outer_radius: menu_height * 0.5 * outer_radius,
-            color:        overlay_ipc::Color::rgba(0.8, 0.8, 0.0, 0.8),
-            label:        Some((item.clone(), overlay_ipc::Color::rgb(1.0, 1.0, 1.0)))
-          });
-          direction -= sector_width;
-        }
-        layers.push(v);
+    let (background, normal_color, item_shape): (Shape, Color, ItemShapeFn) = match self.opts {
+      TouchMenuOpts::Radial { inner_radius, outer_radius, .. } => {
 
-        layers
+        let sector_width = std::f32::consts::PI * 2.0 / items.len() as f32;
+
+        let background = Shape::Ring {
+          center,
+          inner_radius: menu_height * 0.5 * inner_radius,
+          outer_radius: menu_height * 0.5 * outer_radius,
+          color:        Color::rgba(0.0, 0.2, 0.0, 0.4)
+        };
+
+        (background, Color::rgba(0.0, 0.0, 0.0, 0.0), Box::new(move |i, color| Shape::RingSector {
+          center,
+          direction:    Angle::Rad(-(std::f32::consts::PI / 2.0 - sector_width * i as f32)),
+          width:        Angle::Rad(sector_width),
+          inner_radius: menu_height * 0.5 * inner_radius,
+          outer_radius: menu_height * 0.5 * outer_radius,
+          color,
+          label:        label(i)
+        }))
       },
       TouchMenuOpts::HexGrid { .. } => {
 
-        let mut layers = vec![];
+        let circumradius = menu_height / (number_of_hex_grid_circles(items.len()) * 2 + 1) as f32 / 3f32.sqrt();
+        let points       = hex_grid_positions_on_screen(center, circumradius, items.len());
+
+        let background = Shape::Ring {
+          center,
+          inner_radius: circumradius,
+          outer_radius: menu_height * 0.5,
+          color:        Color::rgba(0.0, 0.2, 0.0, 0.4)
+        };
+
+        (background, Color::rgba(0.1, 0.1, 0.1, 0.3), Box::new(move |i, color| Shape::RegularHexagon {
+          center: points[i],
+          circumradius,
+          color,
+          label:  label(i)
+        }))
+      },
+      TouchMenuOpts::Grid { columns, .. } => {
+
+        let rows        = number_of_grid_rows(items.len(), columns);
+        let cell_width  = menu_height / columns as f32;
+        let cell_height = menu_height / rows    as f32;
+
+        let background = Shape::Rectangle {
+          center,
+          width:  menu_height,
+          height: menu_height,
+          color:  Color::rgba(0.0, 0.2, 0.0, 0.4),
+          label:  None
+        };
+
+        (background, Color::rgba(0.1, 0.1, 0.1, 0.3), Box::new(move |i, color| Shape::Rectangle {
+          center: Point {
+            x: center.x + cell_width  * ((i % columns) as f32 + 0.5 - columns as f32 / 2.0),
+            y: center.y + cell_height * ((i / columns) as f32 + 0.5 - rows    as f32 / 2.0)
+          },
+          width:  cell_width  * 0.95,
+          height: cell_height * 0.95,
+          color,
+          label:  label(i)
+        }))
+      }
+    };
+
+    vec![
+      vec![background],
+      // normal items
+      (0..items.len()).map(|i| item_shape(i, items[i].0.color.clone().unwrap_or(normal_color.clone()))).collect(),
+      // selected items
+      (0..items.len()).map(|i| item_shape(i, Color::rgba(0.0, 0.8, 0.0, 0.8))).collect(),
+      // selected and locked items
+      (0..items.len()).map(|i| item_shape(i, Color::rgba(0.8, 0.8, 0.0, 0.8))).collect()
+    ]
+  }
 
-        let circumradius = menu_height / (number_of_hex_grid_circles(self.items.len()) * 2 + 1) as f32 / 3f32.sqrt();
-        let points       = hex_grid_positions_on_screen(center, circumradius, self.items.len());
+  fn open_level(&mut self, level: usize) {
+    self.level           = level;
+    self.selected_option = None;
+    self.mode            = TouchMenuMode::Unlocked;
+  }
 
-        // background
-        layers.push(vec![
-          Shape::Ring {
-            center,
-            inner_radius: circumradius,
-            outer_radius: menu_height * 0.5,
-            color:        Color::rgba(0.0, 0.2, 0.0, 0.4)
-          }
-        ]);
-
-        // normal items
-        let mut v = Vec::with_capacity(self.items.len());
-        #[allow(clippy::needless_range_loop)]
-        for i in 0..self.items.len() {
-          v.push(Shape::RegularHexagon {
-            center:       points[i],
-            circumradius,
-            color:        Color::rgba(0.1, 0.1, 0.1, 0.3),
-            label:        Some((self.items[i].clone(), Color::rgb(1.0, 1.0, 1.0)))
-          });
-        }
-        layers.push(v);
-
-        // selected items
-        let mut v = Vec::with_capacity(self.items.len());
-        #[allow(clippy::needless_range_loop)]
-        for i in 0..self.items.len() {
-          v.push(Shape::RegularHexagon {
-            center:       points[i],
-            circumradius,
-            color:        Color::rgba(0.0, 0.8, 0.0, 0.8),
-            label:        Some((self.items[i].clone(), Color::rgb(1.0, 1.0, 1.0)))
-          });
-        }
-        layers.push(v);
-
-        // selected and locked items
-        let mut v = Vec::with_capacity(self.items.len());
-        #[allow(clippy::needless_range_loop)]
-        for i in 0..self.items.len() {
-          v.push(Shape::RegularHexagon {
-            center:       points[i],
-            circumradius,
-            color:        Color::rgba(0.8, 0.8, 0.0, 0.8),
-            label:        Some((self.items[i].clone(), Color::rgb(1.0, 1.0, 1.0)))
-          });
-        }
-        layers.push(v);
+  fn hover_item(&mut self, i: usize, ctx: &Context, actions: &mut Vec<Action>) {
+    if self.selected_option.map(|option| option.0) != Some(i as u8) {
+      self.haptics.feedback(HapticFeedbackEffect::SlightBump, actions);
+      self.selected_option = Some((i as u8, ctx.time));
+    }
+  }
 
-        layers
+  fn lock_hovered_item(&mut self, position: (f32, f32), ctx: &Context, actions: &mut Vec<Action>) {
+    if let Some((_, t)) = self.selected_option {
+      if ctx.time - t >= Duration::from_millis(500) {
+        self.mode = TouchMenuMode::Locked { position };
+        self.haptics.feedback(HapticFeedbackEffect::ModerateBump, actions);
       }
     }
   }
 
+  fn leave_items(&mut self) {
+    if !self.sticky {
+      self.selected_option = None;
+    }
+  }
+}
+
+impl Pipeline<Option<u8>> for TouchMenuStage {
+
+  fn stage_id(&self) -> StageId {
+    self.stage_id
+  }
+
+  fn name(&self) -> &'static str {
+    "touch_menu"
+  }
+
+  fn desc(&self) -> String {
+    format!("{}(...)", self.name())
+  }
+
+  fn inputs(&self) -> Vec<StageId> {
+    vec![self.position.borrow().stage_id(), self.toggle.borrow().stage_id(), self.select.borrow().stage_id()]
+  }
+
+  // each menu level takes 4 consecutive shape layers
+  fn shapes(&self) -> Vec<Vec<overlay_ipc::Shape>> {
+    self.levels.iter().flat_map(|items| self.level_shapes(items)).collect()
+  }
+
   fn inspect(&self, out: &mut HashMap<StageId, PipelineStageDescription>) {
     if insert_stage_description(out, self) {
       self.position.borrow().inspect(out);
@@ -421,21 +516,45 @@ impl Pipeline<Option<u8>> for TouchMenuStage {
 
       // we effectively process menu selection with a delay of one tick
       // in order to handle touchpad release events
-      if self.select.borrow_mut().apply(ctx, actions) {
-        self.out_value = Some(match self.mode {
-          TouchMenuMode::Locked { .. } => self.selected_option.map(|option| option.0),
-          TouchMenuMode::Unlocked      => None
-        });
-      } else {
-        self.out_value = Some(None);
+      let select       = self.select.borrow_mut().apply(ctx, actions);
+      let select_state = (self.select_bstate)(select);
+
+      self.out_value = Some(None);
+
+      if select {
+
+        let selected = match self.mode {
+          TouchMenuMode::Locked { .. } => self.selected_option,
+          TouchMenuMode::Unlocked      => if self.sticky { self.selected_option } else { None }
+        };
+
+        match selected.map(|option| self.levels[self.level][option.0 as usize].1) {
+          Some(TouchMenuEntry::Item(item)) => {
+            self.out_value = Some(Some(item));
+            if self.level != 0 {
+              self.open_level(0);
+            }
+          },
+          Some(TouchMenuEntry::Submenu(level)) => {
+            self.open_level(level);
+          },
+          None => {
+            // selecting nothing closes submenus
+            if select_state == ButtonState::Pressed && self.level != 0 {
+              self.open_level(0);
+            }
+          }
+        }
       }
 
       if self.toggle.borrow_mut().apply(ctx, actions) {
 
+        let number_of_items = self.levels[self.level].len();
+
         match self.opts {
           TouchMenuOpts::Radial { inner_radius, outer_radius, margin } => {
 
-            let sector_width = std::f32::consts::PI * 2.0 / self.items.len() as f32;
+            let sector_width = std::f32::consts::PI * 2.0 / number_of_items as f32;
 
             let (x, y) = self.position.borrow_mut().apply(ctx, actions);
             let p      = TouchPoint::new(x, y, margin);
@@ -450,51 +569,25 @@ impl Pipeline<Option<u8>> for TouchMenuStage {
               TouchMenuMode::Unlocked => {
                 if p.inside_ring(inner_radius, outer_radius * 1.2 /* ? */) {
                   let mut direction = std::f32::consts::PI / 2.0;
-                  for i in 0..self.items.len() {
+                  for i in 0..number_of_items {
                     if p.inside_sector(sector_width, direction) {
-
-                      if self.selected_option.map(|option| option.0) != Some(i as u8) {
-                        //println!("selecting radial menu item {}", i);
-                        actions.push(Action::HapticFeedback(
-                          HapticFeedbackTarget::LeftSide,
-                          HapticFeedbackEffect::SlightBump
-                        ));
-                        actions.push(Action::HapticFeedback(
-                          HapticFeedbackTarget::RightSide,
-                          HapticFeedbackEffect::SlightBump
-                        ));
-
-                        self.selected_option = Some((i as u8, ctx.time));
-                      }
-
+                      self.hover_item(i, ctx, actions);
                       break;
                     }
                     direction -= sector_width;
                   }
 
-                  if let Some((_, t)) = self.selected_option {
-                    if ctx.time - t >= Duration::from_millis(500) {
-                      self.mode = TouchMenuMode::Locked { position: (x, y) };
-                      actions.push(Action::HapticFeedback(
-                        HapticFeedbackTarget::LeftSide,
-                        HapticFeedbackEffect::ModerateBump
-                      ));
-                      actions.push(Action::HapticFeedback(
-                        HapticFeedbackTarget::RightSide,
-                        HapticFeedbackEffect::ModerateBump
-                      ));
-                    }
-                  }
+                  self.lock_hovered_item((x, y), ctx, actions);
 
                 } else {
-                  self.selected_option = None;
+                  self.leave_items();
                 }
               }
             }
           },
           TouchMenuOpts::HexGrid { margin } => {
 
-            let circumradius = 2.0 / (number_of_hex_grid_circles(self.items.len()) * 2 + 1) as f32 / 3f32.sqrt();
+            let circumradius = 2.0 / (number_of_hex_grid_circles(number_of_items) * 2 + 1) as f32 / 3f32.sqrt();
             let inradius     = 3f32.sqrt() / 2.0 * circumradius;
             assert!(inradius > margin);
 
@@ -510,52 +603,52 @@ impl Pipeline<Option<u8>> for TouchMenuStage {
               },
               TouchMenuMode::Unlocked => {
                 if distance_from_center(x, y) > trigger_distance {
-                  let points = hex_grid_positions_on_touchpad((0.0, 0.0), circumradius, self.items.len()); //TODO: cache this
+                  let points = hex_grid_positions_on_touchpad((0.0, 0.0), circumradius, number_of_items); //TODO: cache this
                   #[allow(clippy::needless_range_loop)]
-                  for i in 0..self.items.len() {
+                  for i in 0..number_of_items {
                     if distance_from_point(x, y, points[i].0, points[i].1) < trigger_distance {
-
-                      if self.selected_option.map(|option| option.0) != Some(i as u8) {
-                        //println!("selecting hex menu item {}", i);
-                        actions.push(Action::HapticFeedback(
-                          HapticFeedbackTarget::LeftSide,
-                          HapticFeedbackEffect::SlightBump
-                        ));
-                        actions.push(Action::HapticFeedback(
-                          HapticFeedbackTarget::RightSide,
-                          HapticFeedbackEffect::SlightBump
-                        ));
-
-                        self.selected_option = Some((i as u8, ctx.time));
-                      }
-
+                      self.hover_item(i, ctx, actions);
                       break;
                     }
                   }
 
-                  if let Some((_, t)) = self.selected_option {
-                    if ctx.time - t >= Duration::from_millis(500) {
-                      self.mode = TouchMenuMode::Locked { position: (x, y) };
-                      actions.push(Action::HapticFeedback(
-                        HapticFeedbackTarget::LeftSide,
-                        HapticFeedbackEffect::ModerateBump
-                      ));
-                      actions.push(Action::HapticFeedback(
-                        HapticFeedbackTarget::RightSide,
-                        HapticFeedbackEffect::ModerateBump
-                      ));
-                    }
-                  }
+                  self.lock_hovered_item((x, y), ctx, actions);
+
+                } else {
+                  self.leave_items();
+                }
+              }
+            }
+          },
+          TouchMenuOpts::Grid { columns, margin } => {
 
+            let rows   = number_of_grid_rows(number_of_items, columns);
+            let (x, y) = self.position.borrow_mut().apply(ctx, actions);
+            let cell   = grid_cell_on_touchpad(x, y, columns, rows, margin)
+              .map(|(column, row)| row * columns + column)
+              .filter(|i| *i < number_of_items);
+
+            match self.mode {
+              TouchMenuMode::Locked { .. } => {
+                if cell.is_some() && cell != self.selected_option.map(|option| option.0 as usize) {
+                  self.mode = TouchMenuMode::Unlocked;
+                }
+              },
+              TouchMenuMode::Unlocked => {
+                if let Some(i) = cell {
+                  self.hover_item(i, ctx, actions);
+                  self.lock_hovered_item((x, y), ctx, actions);
                 } else {
-                  self.selected_option = None;
+                  self.leave_items();
                 }
               }
             }
           }
         }
 
-        actions.push(Action::ToggleShapes { stage_id: self.stage_id, layer: 0, mask: u64::MAX });
+        let first_layer = (self.level * 4) as u8;
+
+        actions.push(Action::ToggleShapes { stage_id: self.stage_id, layer: first_layer, mask: u64::MAX });
 
         let mut selected_items = 0;
 
@@ -563,14 +656,14 @@ impl Pipeline<Option<u8>> for TouchMenuStage {
           selected_items |= 1 << i;
         }
 
-        actions.push(Action::ToggleShapes { stage_id: self.stage_id, layer: 1, mask: u64::MAX & !selected_items });
+        actions.push(Action::ToggleShapes { stage_id: self.stage_id, layer: first_layer + 1, mask: u64::MAX & !selected_items });
 
         match self.mode {
           TouchMenuMode::Locked { .. } => {
-            actions.push(Action::ToggleShapes { stage_id: self.stage_id, layer: 3, mask: selected_items });
+            actions.push(Action::ToggleShapes { stage_id: self.stage_id, layer: first_layer + 3, mask: selected_items });
           },
           TouchMenuMode::Unlocked => {
-            actions.push(Action::ToggleShapes { stage_id: self.stage_id, layer: 2, mask: selected_items });
+            actions.push(Action::ToggleShapes { stage_id: self.stage_id, layer: first_layer + 2, mask: selected_items });
           }
         }
 
@@ -578,6 +671,7 @@ impl Pipeline<Option<u8>> for TouchMenuStage {
         self.selected_option = None;
         self.mode = TouchMenuMode::Unlocked;
       }
+      trace(self.stage_id, &self.out_value.unwrap());
     }
 
     self.out_value.unwrap()
@@ -593,29 +687,154 @@ impl Pipeline<Option<u8>> for TouchMenuStage {
   }
 }
 
-pub fn touch_menu(position: PipelineRef<(f32, f32)>, toggle: PipelineRef<bool>, select: PipelineRef<bool>, items: Vec<String>, opts: TouchMenuOpts) -> PipelineRef<Option<u8>> {
-
-  assert!(items.len() <= 60);
+pub fn touch_menu(
+  position: PipelineRef<(f32, f32)>,
+  toggle:   PipelineRef<bool>,
+  select:   PipelineRef<bool>,
+  items:    Vec<TouchMenuItem>,
+  opts:     TouchMenuOpts,
+  haptics:  TouchMenuHaptics,
+  sticky:   bool
+) -> Result<PipelineRef<Option<u8>>, String> {
+
+  let mut levels          = vec![];
+  let mut number_of_items = 0;
+  flatten_menu(items, &mut levels, &mut number_of_items);
+
+  // items are reported as u8, each level gets 4 shape layers
+  if number_of_items > u8::MAX as usize + 1 {
+    return Err(format!("Menus can have at most {} items, got {}", u8::MAX as usize + 1, number_of_items));
+  }
+  if levels.len() * 4 > u8::MAX as usize + 1 {
+    return Err(format!("Menus can have at most {} submenus, got {}", (u8::MAX as usize + 1) / 4 - 1, levels.len() - 1));
+  }
+  if let Some(level) = levels.iter().find(|level| level.len() > 60) {
+    return Err(format!("Menus and submenus can have at most 60 items, got {}", level.len()));
+  }
 
   match opts {
     TouchMenuOpts::Radial { inner_radius, outer_radius, .. } => {
-      assert!(inner_radius > 0.0);
-      assert!(outer_radius > inner_radius);
+      if inner_radius <= 0.0 || outer_radius <= inner_radius {
+        return Err("Radial menu radii should be positive, the outer one larger than the inner one".to_string());
+      }
     },
     TouchMenuOpts::HexGrid { .. } => {
       // ?
+    },
+    TouchMenuOpts::Grid { columns, .. } => {
+      if columns == 0 {
+        return Err("Grid menus should have at least one column".to_string());
+      }
     }
   }
 
-  std::rc::Rc::new(std::cell::RefCell::new(TouchMenuStage {
+  Ok(std::rc::Rc::new(std::cell::RefCell::new(TouchMenuStage {
     stage_id: generate_stage_id(),
     position,
     toggle,
     select,
+    select_bstate: to_button_state(),
     opts,
-    items,
+    haptics,
+    sticky,
+    levels,
+    level: 0,
     mode: TouchMenuMode::Unlocked,
     selected_option: None,
     out_value: None
-  }))
+  })))
+}
+
+#[cfg(test)]
+mod tests {
+
+  use std::rc::Rc;
+
+  use super::*;
+
+  #[test]
+  fn haptic_target_detection_test() {
+
+    let targets = |x, y| TouchMenuHaptics::for_position(&merge(axis_input(x), axis_input(y))).targets;
+
+    assert_eq!(targets(Axis::LPadX, Axis::LPadY), vec![HapticFeedbackTarget::LeftSide]);
+    assert_eq!(targets(Axis::RPadX, Axis::RPadY), vec![HapticFeedbackTarget::RightSide]);
+    assert_eq!(targets(Axis::LJoyX, Axis::LJoyY), vec![HapticFeedbackTarget::LeftSide, HapticFeedbackTarget::RightSide]);
+  }
+
+  #[test]
+  fn menu_limits_test() {
+
+    let items = |count: usize| (0..count).map(|i| TouchMenuItem::new(i.to_string())).collect::<Vec<_>>();
+    let submenu = |submenu: Vec<TouchMenuItem>| {
+      let mut item = TouchMenuItem::new("more".to_string());
+      item.submenu = submenu;
+      item
+    };
+
+    let menu = |items: Vec<TouchMenuItem>, opts: TouchMenuOpts| touch_menu(
+      merge(axis_input(Axis::RPadX), axis_input(Axis::RPadY)),
+      button_input(Button::RPadTouch),
+      button_input(Button::RPad),
+      items,
+      opts,
+      TouchMenuHaptics { targets: vec![], intensity: 1.0 },
+      false).err();
+
+    let grid = TouchMenuOpts::Grid { columns: 2, margin: 0.03 };
+
+    assert_eq!(menu(items(60), grid), None);
+    assert_eq!(menu(items(61), grid), Some("Menus and submenus can have at most 60 items, got 61".to_string()));
+    assert_eq!(menu((0..5).map(|_| submenu(items(60))).collect(), grid), Some("Menus can have at most 256 items, got 300".to_string()));
+    assert_eq!(menu(vec![(0..63).fold(submenu(items(1)), |menu, _| submenu(vec![menu]))], grid),
+      Some("Menus can have at most 63 submenus, got 64".to_string()));
+
+    assert!(menu(items(2), TouchMenuOpts::Grid { columns: 0, margin: 0.03 }).is_some());
+    assert!(menu(items(2), TouchMenuOpts::Radial { inner_radius: 0.5, outer_radius: 0.25, margin: 0.03 }).is_some());
+  }
+
+  #[test]
+  fn submenu_test() {
+
+    let mut submenu = TouchMenuItem::new("B".to_string());
+    submenu.submenu = vec![TouchMenuItem::new("C".to_string()), TouchMenuItem::new("D".to_string())];
+
+    let items  = vec![TouchMenuItem::new("A".to_string()), submenu];
+    let toggle = button_input(Button::RPadTouch);
+    let menu   = touch_menu(
+      merge(axis_input(Axis::RPadX), axis_input(Axis::RPadY)),
+      Rc::clone(&toggle),
+      invert(toggle),
+      items,
+      TouchMenuOpts::Grid { columns: 1, margin: 0.03 },
+      TouchMenuHaptics { targets: vec![], intensity: 1.0 },
+      false).unwrap();
+
+    let mut state   = crate::controllers::ControllerState::empty();
+    let mut actions = vec![];
+
+    let mut apply = |touch: Option<f32>, time: u64| {
+      state.buttons.rpad_touch = touch.is_some();
+      state.axes.rpad_y        = touch.unwrap_or(0.0);
+      let ctx = Context { state: &state, time: Timestamp::from_millis(time), layers: LayerMask::EMPTY, probe_values: &HashMap::new(), overlay: &OverlayStatus::default() };
+      menu.borrow_mut().reset();
+      menu.borrow_mut().apply(&ctx, &mut actions)
+    };
+
+    // hold the second item until it locks, then release to open the submenu
+    assert_eq!(apply(Some(-0.5),   0), None);
+    assert_eq!(apply(Some(-0.5), 600), None);
+    assert_eq!(apply(None,       700), None);
+    assert_eq!(apply(None,       800), None);
+
+    // the first item of the submenu is the second leaf
+    assert_eq!(apply(Some(0.5),  900), None);
+    assert_eq!(apply(Some(0.5), 1500), None);
+    assert_eq!(apply(None,      1600), Some(1));
+
+    // back at the root level
+    assert_eq!(apply(Some(0.5), 1700), None);
+    assert_eq!(apply(Some(0.5), 2300), None);
+    assert_eq!(apply(None,      2400), Some(0));
+  }
 }
@@ -0,0 +1,289 @@
+use std::collections::VecDeque;
+use std::io::Write;
+use std::sync::mpsc;
+
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::{cursor, queue, terminal};
+use strum::IntoEnumIterator;
+
+use overlay_ipc::Knob;
+
+use crate::controllers::{Axis, Button, ControllerState};
+use crate::frontend::{self, Link, Shared, Snapshot, UPDATE_INTERVAL};
+use crate::mapper::{Frontend, FrontendRequest, Mode};
+use crate::output::{MapperIO, RecordingOutput};
+
+const MAX_EVENTS: usize = 12;
+
+// everything the terminal shows
+struct TuiState {
+  snapshot: Snapshot,
+  events:   VecDeque<String> // output events and log messages
+}
+
+impl AsMut<Snapshot> for TuiState {
+
+  fn as_mut(&mut self) -> &mut Snapshot {
+    &mut self.snapshot
+  }
+}
+
+// the terminal side, runs on the main thread
+pub struct Tui {
+  state: Shared<TuiState>,
+  edits: mpsc::Sender<FrontendRequest>
+}
+
+// attached to every mapper instance in turn, outlives config reloads
+pub struct TuiFrontend {
+  link: Link<TuiState>
+}
+
+pub fn tui() -> (Tui, TuiFrontend) {
+  let (state, edits, link) = frontend::link(TuiState { snapshot: Snapshot::default(), events: VecDeque::new() });
+  (Tui { state, edits }, TuiFrontend { link })
+}
+
+impl TuiFrontend {
+
+  pub fn set_layers(&mut self, layers: Vec<String>) {
+    self.link.set_layers(layers);
+  }
+
+  pub fn record_output(&self, output: Box<dyn MapperIO>) -> Box<dyn MapperIO> {
+    let state = self.link.state.clone();
+    Box::new(RecordingOutput::new(output, move |event| state.lock().unwrap().push_output(event)))
+  }
+
+  // see crate::log::set_sink
+  pub fn log_sink(&self) -> crate::log::Sink {
+    let state = self.link.state.clone();
+    Box::new(move |message| {
+      for line in message.lines() {
+        state.lock().unwrap().push_event(line.to_string());
+      }
+    })
+  }
+}
+
+impl Frontend for TuiFrontend {
+
+  fn update(&mut self, state: &ControllerState, mode: &Mode, knobs: &[Knob], knobs_edited: &[Knob]) {
+    self.link.update(state, mode, knobs, knobs_edited);
+  }
+
+  fn requests(&mut self) -> Vec<FrontendRequest> {
+    self.link.requests()
+  }
+}
+
+impl TuiState {
+
+  fn push_event(&mut self, event: String) {
+    if self.events.len() == MAX_EVENTS {
+      self.events.pop_front();
+    }
+    self.events.push_back(event);
+  }
+
+  // consecutive moves are merged, otherwise they'd push everything else out
+  fn push_output(&mut self, event: String) {
+    let moves = |event: &str| -> Option<(i32, i32)> {
+      let mut numbers = event.strip_prefix("mouse move ")?.split(' ').map(|n| n.parse::<i32>().ok());
+      Some((numbers.next()??, numbers.next()??))
+    };
+    match (self.events.back().and_then(|last| moves(last)), moves(&event)) {
+      (Some((last_x, last_y)), Some((x, y))) => *self.events.back_mut().unwrap() = format!("mouse move {} {}", last_x + x, last_y + y),
+      _ => self.push_event(event)
+    }
+  }
+}
+
+fn knob_value(knob: &Knob) -> String {
+  match knob {
+    Knob::Flag { value, .. } => (if *value { "Y" } else { "N" }).to_string(),
+    Knob::Enum { index, options, .. } | Knob::Key { index, options, .. } | Knob::Layer { index, options, .. } => {
+      format!("{} {} {}", if *index > 0 { "◀" } else { " " }, options[*index], if *index < options.len() - 1 { "▶" } else { " " })
+    },
+    Knob::Number  { value, .. } => format!("{}", value),
+    Knob::Integer { value, .. } => format!("{}", value),
+    Knob::Curve   { points, .. } => points.iter().map(|(x, y)| format!("{}:{}", x, y)).collect::<Vec<_>>().join(" ")
+  }
+}
+
+fn render(tui: &TuiState, selected: usize) -> Vec<String> {
+  let snapshot = &tui.snapshot;
+  let mut lines = vec![];
+
+  let layers = snapshot.layers.iter()
+    .map(|layer| if snapshot.mode.contains(layer) { format!("[{}]", layer) } else { layer.clone() })
+    .collect::<Vec<_>>();
+  lines.push(format!("layers: {}", layers.join(" ")));
+  lines.push(String::new());
+
+  lines.push("knobs (↑↓ select, ←→ change, enter apply, q quit):".to_string());
+  for (i, knob) in snapshot.knobs_edited.iter().enumerate() {
+    let edited = snapshot.knobs.iter().any(|k| k.name() == knob.name() && !k.compare_value(knob));
+    lines.push(format!("{} {:<24} {}{}", if i == selected { "▶" } else { " " }, knob.name(), knob_value(knob), if edited { " *" } else { "" }));
+  }
+  lines.push(String::new());
+
+  let buttons = Button::iter().filter(|button| snapshot.controller.read_button(*button)).map(|button| format!("{:?}", button)).collect::<Vec<_>>();
+  lines.push(format!("buttons: {}", buttons.join(" ")));
+
+  let axes = [Axis::LJoyX, Axis::LJoyY, Axis::RJoyX, Axis::RJoyY, Axis::LPadX, Axis::LPadY, Axis::RPadX, Axis::RPadY,
+              Axis::LTrig, Axis::RTrig, Axis::Pitch, Axis::Roll, Axis::Yaw];
+  for row in axes.chunks(4) {
+    let row = row.iter().map(|axis| format!("{:<6} {:>7.3}", format!("{:?}", axis), snapshot.controller.read_axis(*axis))).collect::<Vec<_>>();
+    lines.push(format!("  {}", row.join("  ")));
+  }
+  lines.push(String::new());
+
+  lines.push("events:".to_string());
+  for event in &tui.events {
+    lines.push(format!("  {}", event));
+  }
+
+  lines
+}
+
+impl Tui {
+
+  fn draw(&self, out: &mut impl Write, selected: usize) -> std::io::Result<()> {
+    let (width, height) = terminal::size()?;
+    let lines = render(&self.state.lock().unwrap(), selected);
+
+    queue!(out, cursor::MoveTo(0, 0), terminal::Clear(terminal::ClearType::All))?;
+    for line in lines.iter().take(height as usize) {
+      write!(out, "{}\r\n", line.chars().take(width as usize).collect::<String>())?;
+    }
+    out.flush()
+  }
+
+  fn edit_knob(&self, selected: usize, next: bool) {
+    let mut tui = self.state.lock().unwrap();
+    if let Some(knob) = tui.snapshot.knobs_edited.get_mut(selected) {
+      if next {
+        knob.select_next_value();
+      } else {
+        knob.select_prev_value();
+      }
+      // the mapper reports the edit back later, further edits shouldn't start from a stale value
+      let _ = self.edits.send(FrontendRequest::EditKnob(knob.clone()));
+    }
+  }
+
+  fn event_loop(&self, out: &mut impl Write) -> std::io::Result<()> {
+    let mut selected = 0;
+    loop {
+      self.draw(out, selected)?;
+
+      if !event::poll(UPDATE_INTERVAL)? {
+        continue;
+      }
+
+      if let Event::Key(KeyEvent { code, modifiers, kind: KeyEventKind::Press, .. }) = event::read()? {
+        let knob_count = self.state.lock().unwrap().snapshot.knobs_edited.len();
+        match code {
+          KeyCode::Up    => selected = selected.saturating_sub(1),
+          KeyCode::Down  => selected = (selected + 1).min(knob_count.saturating_sub(1)),
+          KeyCode::Left  => self.edit_knob(selected, false),
+          KeyCode::Right => self.edit_knob(selected, true),
+          KeyCode::Enter => { let _ = self.edits.send(FrontendRequest::CommitKnobs); },
+          KeyCode::Esc | KeyCode::Char('q') => return Ok(()),
+          KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => return Ok(()),
+          _ => ()
+        }
+      }
+    }
+  }
+
+  // returns once the user quits
+  pub fn run(&self) -> Result<(), String> {
+    let mut out = std::io::stdout();
+
+    terminal::enable_raw_mode().map_err(|e| format!("{}", e))?;
+    queue!(out, terminal::EnterAlternateScreen, cursor::Hide).map_err(|e| format!("{}", e))?;
+
+    let result = self.event_loop(&mut out);
+
+    let _ = queue!(out, cursor::Show, terminal::LeaveAlternateScreen);
+    let _ = out.flush();
+    let _ = terminal::disable_raw_mode();
+
+    result.map_err(|e| format!("{}", e))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  #[test]
+  fn render_test() {
+    let knob = Knob::Flag { name: "invert y".to_string(), value: false };
+    let mut controller = ControllerState::empty();
+    controller.set_button(Button::A, true);
+    controller.set_axis(Axis::LPadX, 0.5);
+
+    let tui = TuiState {
+      snapshot: Snapshot {
+        knobs:        vec![knob.clone(), Knob::Integer { name: "burst".to_string(), value: 3, min_value: 1, max_value: 5, step: 1 }],
+        knobs_edited: vec![Knob::Flag { name: "invert y".to_string(), value: true }, Knob::Integer { name: "burst".to_string(), value: 3, min_value: 1, max_value: 5, step: 1 }],
+        layers:       vec!["foo".to_string(), "bar".to_string()],
+        mode:         vec!["bar".to_string()],
+        controller
+      },
+      events: VecDeque::from(vec!["key A down".to_string()])
+    };
+
+    let lines = render(&tui, 1);
+    assert_eq!(lines[0], "layers: foo [bar]");
+    assert_eq!(lines[3], format!("  {:<24} Y *", "invert y"));
+    assert_eq!(lines[4], format!("▶ {:<24} 3", "burst"));
+    assert_eq!(lines[6], "buttons: A");
+    assert!(lines[8].starts_with("  LPadX    0.500"), "{}", lines[8]);
+    assert_eq!(&lines[lines.len() - 2..], ["events:", "  key A down"]);
+  }
+
+  #[test]
+  fn frontend_test() {
+    let (tui, mut frontend) = tui();
+
+    // log messages and output share the events pane
+    let mut output = frontend.record_output(Box::new(crate::output::DummyOutput));
+    output.mouse_cursor_rel_xy(1, 2);
+    output.mouse_cursor_rel_xy(3, 4);
+    frontend.log_sink()("Can't load config:\nfoo".to_string());
+    output.keyboard_key_down(crate::output::KeyboardKey::A);
+    assert_eq!(tui.state.lock().unwrap().events, ["mouse move 4 6", "Can't load config:", "foo", "key A down"]);
+
+    for _ in 0..MAX_EVENTS {
+      output.keyboard_key_up(crate::output::KeyboardKey::A);
+    }
+    assert_eq!(tui.state.lock().unwrap().events.len(), MAX_EVENTS);
+
+    let config = crate::config::load_config(r#"
+      let speed = knob("speed", 2, min_value = 1, max_value = 4);
+      layer foo { input(LJoyX).scale(speed).bind(Ms.X) },
+      input(true).set_mode(foo)
+    "#, None, false).unwrap();
+    frontend.set_layers(config.layers.clone());
+
+    let clock = crate::clock::VirtualClock::default();
+    let mut mapper = crate::mapper::Mapper::new(None, None, None, config, &mut *output, &clock, 0);
+    mapper.attach_frontend(&mut frontend);
+
+    // the first update isn't throttled
+    let state = ControllerState::empty();
+    mapper.step(&state);
+    mapper.step(&state);
+    assert_eq!(render(&tui.state.lock().unwrap(), 0)[0], "layers: [foo]");
+
+    tui.edit_knob(0, true);
+    tui.edits.send(FrontendRequest::CommitKnobs).unwrap();
+    mapper.step(&state);
+    assert!(matches!(mapper.knobs(), [Knob::Number { value, .. }] if *value > 2.0));
+  }
+}
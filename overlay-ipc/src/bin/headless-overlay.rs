@@ -0,0 +1,94 @@
+// Registers a headless overlay on the session bus for testing the mapper without a GPU, e.g.
+//   dbus-run-session -- sh -c 'headless-overlay < script & mapper load examples/gta-sa.cfg'
+//
+// The script (stdin) is a list of commands, one per line:
+//   probe <id> memory <value>
+//   probe <id> template <correlation>
+//   probe <id> histogram <distance>
+//   probe <id> hsv <pixels in range> <uniformity score>
+//   probe <id> color <r> <g> <b>
+//   focus <true|false>
+//   resize <width> <height>
+//   frame <seconds>
+//   menu <open|prev-item|next-item|prev-value|next-value|close>
+//   sleep <milliseconds>
+// Probe ids are printed by the overlay-ipc server as commands arrive.
+
+use std::io::BufRead;
+
+use overlay_ipc::headless::HeadlessOverlay;
+use overlay_ipc::{Capabilities, Color, OverlayMenuCommand, ProbeResult, ScreenScrapingResult};
+
+fn number<T: std::str::FromStr>(arg: Option<&str>) -> Result<T, String> {
+  let arg = arg.ok_or("missing argument")?;
+  arg.parse::<T>().map_err(|_| format!("invalid number: {}", arg))
+}
+
+fn run_line(overlay: &HeadlessOverlay, line: &str) -> Result<(), String> {
+  let mut args = line.split_whitespace();
+
+  match args.next() {
+    None => {},
+    Some("probe") => {
+      let probe_id = number::<u64>(args.next())?;
+      let result = match args.next() {
+        Some("memory")    => ProbeResult::Memory(number(args.next())?),
+        Some("template")  => ProbeResult::Screen(ScreenScrapingResult::Template { correlation: number(args.next())? }),
+        Some("histogram") => ProbeResult::Screen(ScreenScrapingResult::Histogram { distance: number(args.next())? }),
+        Some("hsv")       => ProbeResult::Screen(ScreenScrapingResult::HsvRange {
+          pixels_in_range:  number(args.next())?,
+          uniformity_score: number(args.next())?
+        }),
+        Some("color")     => ProbeResult::Screen(ScreenScrapingResult::MeanColor(
+          Color::rgb(number(args.next())?, number(args.next())?, number(args.next())?))),
+        _ => return Err("expected memory, template, histogram, hsv or color".to_string())
+      };
+      overlay.set_probe_result(probe_id, result);
+    },
+    Some("focus") => {
+      overlay.set_focused(args.next().ok_or("missing argument")?.parse::<bool>().map_err(|e| format!("{}", e))?);
+    },
+    Some("resize") => {
+      overlay.resize(number(args.next())?, number(args.next())?);
+    },
+    Some("frame") => {
+      overlay.present_frame(number(args.next())?);
+    },
+    Some("menu") => {
+      overlay.menu_command(match args.next() {
+        Some("open")       => OverlayMenuCommand::OpenKnobsMenu,
+        Some("prev-item")  => OverlayMenuCommand::SelectPrevMenuItem,
+        Some("next-item")  => OverlayMenuCommand::SelectNextMenuItem,
+        Some("prev-value") => OverlayMenuCommand::SelectPrevValue,
+        Some("next-value") => OverlayMenuCommand::SelectNextValue,
+        Some("close")      => OverlayMenuCommand::CloseKnobsMenu,
+        _ => return Err("expected open, prev-item, next-item, prev-value, next-value or close".to_string())
+      });
+    },
+    Some("sleep") => {
+      std::thread::sleep(std::time::Duration::from_millis(number(args.next())?));
+    },
+    Some(command) => return Err(format!("unknown command: {}", command))
+  }
+
+  Ok(())
+}
+
+fn main() {
+  let overlay_name = std::env::var("STWGS_OVERLAY_NAME").unwrap_or_else(|_| "headless".to_string());
+  let overlay = HeadlessOverlay::register(&overlay_name, Capabilities::ALL);
+  eprintln!("[headless] registered as {:?} (pid {})", overlay_name, std::process::id());
+
+  for (i, line) in std::io::stdin().lock().lines().enumerate() {
+    let line = line.unwrap();
+    let line = line.split('#').next().unwrap();
+    if let Err(e) = run_line(&overlay, line) {
+      eprintln!("[headless] line {}: {}", i + 1, e);
+    }
+  }
+
+  // keep serving after the script is over
+  loop {
+    std::thread::park();
+  }
+}
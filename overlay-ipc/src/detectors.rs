@@ -0,0 +1,349 @@
+use crate::{histogram_bin, luma, Color, Image, ScreenScrapingArea, ScreenScrapingDetector, ScreenScrapingResult, HISTOGRAM_BINS};
+
+// must match Target in overlay's pixelcount.wgsl
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PackedTarget {
+  pub kind:        u32,
+  pub x1:          u32,
+  pub y1:          u32,
+  pub x2:          u32,
+  pub y2:          u32,
+  pub data_offset: u32,
+  pub data_width:  u32,
+  pub data_height: u32,
+  pub min_hue:     f32,
+  pub max_hue:     f32,
+  pub min_sat:     f32,
+  pub max_sat:     f32,
+  pub min_val:     f32,
+  pub max_val:     f32
+}
+
+pub const KIND_HSV_RANGE:  u32 = 0;
+pub const KIND_TEMPLATE:   u32 = 1;
+pub const KIND_MEAN_COLOR: u32 = 2;
+pub const KIND_HISTOGRAM:  u32 = 3;
+
+// must match Result in overlay's pixelcount.wgsl
+pub const RESULT_LEN: usize = 4;
+
+impl PackedTarget {
+  pub fn to_words(self) -> [u32; 14] {
+    [
+      self.kind,
+      self.x1,
+      self.y1,
+      self.x2,
+      self.y2,
+      self.data_offset,
+      self.data_width,
+      self.data_height,
+      self.min_hue.to_bits(),
+      self.max_hue.to_bits(),
+      self.min_sat.to_bits(),
+      self.max_sat.to_bits(),
+      self.min_val.to_bits(),
+      self.max_val.to_bits()
+    ]
+  }
+}
+
+// targets + reference data (template luma, histograms) for the compute shader
+pub fn pack(targets: &[ScreenScrapingArea], screen_width: u32, screen_height: u32) -> (Vec<PackedTarget>, Vec<f32>) {
+
+  let mut packed = vec![];
+  let mut data   = vec![];
+
+  for target in targets {
+
+    let mut t = PackedTarget {
+      x1: target.bounds.min.x.to_px(screen_width, screen_height) as u32,
+      y1: target.bounds.min.y.to_px(screen_width, screen_height) as u32,
+      x2: target.bounds.max.x.to_px(screen_width, screen_height) as u32,
+      y2: target.bounds.max.y.to_px(screen_width, screen_height) as u32,
+      data_offset: data.len() as u32,
+      ..Default::default()
+    };
+
+    // stay within the screen
+    t.x2 = t.x2.min(screen_width);
+    t.y2 = t.y2.min(screen_height);
+    t.x1 = t.x1.min(t.x2);
+    t.y1 = t.y1.min(t.y2);
+
+    match &target.detector {
+      ScreenScrapingDetector::HsvRange { min_hue, max_hue, min_sat, max_sat, min_val, max_val } => {
+        t.kind    = KIND_HSV_RANGE;
+        t.min_hue = *min_hue;
+        t.max_hue = *max_hue;
+        t.min_sat = *min_sat;
+        t.max_sat = *max_sat;
+        t.min_val = *min_val;
+        t.max_val = *max_val;
+      },
+      ScreenScrapingDetector::Template(image) => {
+        t.kind        = KIND_TEMPLATE;
+        t.data_width  = image.width;
+        t.data_height = image.height;
+        data.extend(image.luma());
+      },
+      ScreenScrapingDetector::MeanColor => {
+        t.kind = KIND_MEAN_COLOR;
+      },
+      ScreenScrapingDetector::Histogram(reference) => {
+        assert_eq!(reference.len(), HISTOGRAM_BINS * 3);
+        t.kind       = KIND_HISTOGRAM;
+        t.data_width = reference.len() as u32;
+        data.extend(reference);
+      }
+    }
+
+    packed.push(t);
+  }
+
+  (packed, data)
+}
+
+pub fn unpack(targets: &[ScreenScrapingArea], results: &[f32]) -> Vec<ScreenScrapingResult> {
+  targets.iter()
+    .zip(results.chunks_exact(RESULT_LEN))
+    .map(|(target, r)| match target.detector {
+      ScreenScrapingDetector::HsvRange { .. } => ScreenScrapingResult::HsvRange { pixels_in_range: r[0], uniformity_score: r[1] },
+      ScreenScrapingDetector::Template(_)     => ScreenScrapingResult::Template { correlation: r[0] },
+      ScreenScrapingDetector::MeanColor       => ScreenScrapingResult::MeanColor(Color::rgb(r[0], r[1], r[2])),
+      ScreenScrapingDetector::Histogram(_)    => ScreenScrapingResult::Histogram { distance: r[0] }
+    })
+    .collect()
+}
+
+fn hsv(r: f32, g: f32, b: f32) -> (f32, f32, f32) {
+  let max = r.max(g.max(b));
+  let min = r.min(g.min(b));
+  if max == 0.0 {
+    return (0.0, 0.0, 0.0);
+  }
+  let chroma = max - min;
+  if chroma != 0.0 {
+    if max == r {
+      return (60.0 * ((g - b) / chroma).rem_euclid(6.0), chroma / max, max);
+    }
+    if max == g {
+      return (60.0 * ((b - r) / chroma + 2.0), chroma / max, max);
+    }
+    return (60.0 * ((r - g) / chroma + 4.0), chroma / max, max);
+  }
+  (0.0, 0.0, max)
+}
+
+fn in_hsv_range(t: &PackedTarget, r: f32, g: f32, b: f32) -> bool {
+  let (hue, sat, val) = hsv(r, g, b);
+  hue >= t.min_hue && hue <= t.max_hue
+    && sat >= t.min_sat && sat <= t.max_sat
+    && val >= t.min_val && val <= t.max_val
+}
+
+// CPU reference implementation of overlay's pixelcount.wgsl
+pub fn compute_on_cpu(screen: &Image, targets: &[PackedTarget], data: &[f32]) -> Vec<f32> {
+
+  let mut results = vec![0.0; targets.len() * RESULT_LEN];
+
+  for (t, result) in targets.iter().zip(results.chunks_exact_mut(RESULT_LEN)) {
+
+    let pixel_count = ((t.x2 - t.x1) * (t.y2 - t.y1)) as f32;
+    if pixel_count == 0.0 {
+      continue;
+    }
+
+    match t.kind {
+      KIND_HSV_RANGE => {
+        let mut pixels_in_range_count = 0;
+        let mut seq_matches_count     = 0;
+        let mut uniformity_score      = 0.0;
+
+        for x in t.x1..t.x2 {
+          for y in t.y1..t.y2 {
+            let (r, g, b) = screen.rgb(x, y);
+            if in_hsv_range(t, r, g, b) {
+              pixels_in_range_count += 1;
+              seq_matches_count     += 1;
+            } else if seq_matches_count > 0 {
+              uniformity_score  += (seq_matches_count as f32).powi(2);
+              seq_matches_count  = 0;
+            }
+          }
+        }
+
+        if seq_matches_count > 0 {
+          uniformity_score += (seq_matches_count as f32).powi(2);
+        }
+
+        result[0] = pixels_in_range_count as f32 / pixel_count;
+        result[1] = uniformity_score / pixel_count.powi(2);
+      },
+      KIND_TEMPLATE => {
+        let (w, h) = (t.x2 - t.x1, t.y2 - t.y1);
+
+        let (mut sum_a, mut sum_b, mut sum_aa, mut sum_bb, mut sum_ab) = (0.0, 0.0, 0.0, 0.0, 0.0);
+        for ty in 0..t.data_height {
+          for tx in 0..t.data_width {
+            let (r, g, b) = screen.rgb(t.x1 + tx * w / t.data_width, t.y1 + ty * h / t.data_height);
+            let a = luma(r, g, b);
+            let b = data[(t.data_offset + ty * t.data_width + tx) as usize];
+            sum_a  += a;
+            sum_b  += b;
+            sum_aa += a * a;
+            sum_bb += b * b;
+            sum_ab += a * b;
+          }
+        }
+
+        let n     = (t.data_width * t.data_height) as f32;
+        let cov   = sum_ab - sum_a * sum_b / n;
+        let var_a = sum_aa - sum_a * sum_a / n;
+        let var_b = sum_bb - sum_b * sum_b / n;
+        let denom = (var_a * var_b).max(0.0).sqrt();
+
+        result[0] = if denom > 0.0 { cov / denom } else { 0.0 };
+      },
+      KIND_MEAN_COLOR => {
+        for x in t.x1..t.x2 {
+          for y in t.y1..t.y2 {
+            let (r, g, b) = screen.rgb(x, y);
+            result[0] += r;
+            result[1] += g;
+            result[2] += b;
+          }
+        }
+
+        for c in &mut result[0..3] {
+          *c /= pixel_count;
+        }
+      },
+      _ => {
+        let mut histogram = [0.0; HISTOGRAM_BINS * 3];
+        for x in t.x1..t.x2 {
+          for y in t.y1..t.y2 {
+            let (r, g, b) = screen.rgb(x, y);
+            histogram[histogram_bin(r)]                      += 1.0;
+            histogram[histogram_bin(g) + HISTOGRAM_BINS]     += 1.0;
+            histogram[histogram_bin(b) + HISTOGRAM_BINS * 2] += 1.0;
+          }
+        }
+
+        let mut distance = 0.0;
+        for (i, bin) in histogram.iter().enumerate() {
+          distance += (bin / pixel_count - data[t.data_offset as usize + i]).abs();
+        }
+
+        // each of the three histograms contributes up to 2.0
+        result[0] = distance / 6.0;
+      }
+    }
+  }
+
+  results
+}
+
+// areas outlined in green, pixels matched by HSV range detectors painted magenta
+pub fn debug_image(screen: &Image, targets: &[PackedTarget]) -> Image {
+
+  let mut image = screen.clone();
+
+  for t in targets {
+    if t.kind == KIND_HSV_RANGE {
+      for x in t.x1..t.x2 {
+        for y in t.y1..t.y2 {
+          let (r, g, b) = screen.rgb(x, y);
+          if in_hsv_range(t, r, g, b) {
+            image.set_rgb(x, y, [255, 0, 255]);
+          }
+        }
+      }
+    }
+
+    if t.x1 < t.x2 && t.y1 < t.y2 {
+      for x in t.x1..t.x2 {
+        image.set_rgb(x, t.y1,     [0, 255, 0]);
+        image.set_rgb(x, t.y2 - 1, [0, 255, 0]);
+      }
+      for y in t.y1..t.y2 {
+        image.set_rgb(t.x1,     y, [0, 255, 0]);
+        image.set_rgb(t.x2 - 1, y, [0, 255, 0]);
+      }
+    }
+  }
+
+  image
+}
+
+#[cfg(test)]
+mod tests {
+
+  use crate::{Length, Point, Rect};
+
+  use super::*;
+
+  fn area(x1: f32, y1: f32, x2: f32, y2: f32, detector: ScreenScrapingDetector) -> ScreenScrapingArea {
+    ScreenScrapingArea {
+      bounds: Rect {
+        min: Point { x: Length::px(x1), y: Length::px(y1) },
+        max: Point { x: Length::px(x2), y: Length::px(y2) }
+      },
+      detector
+    }
+  }
+
+  fn run(screen: &Image, targets: &[ScreenScrapingArea]) -> Vec<ScreenScrapingResult> {
+    let (packed, data) = pack(targets, screen.width, screen.height);
+    unpack(targets, &compute_on_cpu(screen, &packed, &data))
+  }
+
+  #[test]
+  fn detectors_test() {
+    // 32x32: red 8x8 square at (8, 8) over a grey background, blue 8x8 square at (20, 4)
+    let screen = Image::from_png(include_bytes!("fixtures/screen.png")).unwrap();
+    // the red square with a 2px margin
+    let marker = Image::from_png(include_bytes!("fixtures/marker.png")).unwrap();
+
+    let results = run(&screen, &[
+      area( 8.0, 8.0, 16.0, 16.0, ScreenScrapingDetector::HsvRange {
+        min_hue: 0.0, max_hue: 10.0, min_sat: 0.5, max_sat: 1.0, min_val: 0.5, max_val: 1.0 }),
+      area( 6.0, 6.0, 18.0, 18.0, ScreenScrapingDetector::Template(marker.clone())),
+      area(20.0, 4.0, 28.0, 12.0, ScreenScrapingDetector::Template(marker.clone())),
+      area(20.0, 4.0, 28.0, 12.0, ScreenScrapingDetector::MeanColor),
+      area( 6.0, 6.0, 18.0, 18.0, ScreenScrapingDetector::Histogram(marker.histogram())),
+      area(20.0, 4.0, 28.0, 12.0, ScreenScrapingDetector::Histogram(marker.histogram()))
+    ]);
+
+    match results[0] {
+      ScreenScrapingResult::HsvRange { pixels_in_range, uniformity_score } => {
+        assert_eq!(pixels_in_range, 1.0);
+        assert_eq!(uniformity_score, 1.0);
+      },
+      _ => panic!()
+    }
+
+    match (&results[1], &results[2]) {
+      (ScreenScrapingResult::Template { correlation: hit }, ScreenScrapingResult::Template { correlation: miss }) => {
+        assert!(*hit > 0.99, "{}", hit);
+        assert!(*miss < 0.5, "{}", miss);
+      },
+      _ => panic!()
+    }
+
+    match &results[3] {
+      ScreenScrapingResult::MeanColor(color) => {
+        assert_eq!((color.r, color.g, color.b), (0.0, 0.0, 1.0));
+      },
+      _ => panic!()
+    }
+
+    match (&results[4], &results[5]) {
+      (ScreenScrapingResult::Histogram { distance: hit }, ScreenScrapingResult::Histogram { distance: miss }) => {
+        assert!(*hit < 0.01, "{}", hit);
+        assert!(*miss > 0.5, "{}", miss);
+      },
+      _ => panic!()
+    }
+  }
+}
@@ -0,0 +1,303 @@
+// A stand-in for the Vulkan layer, which mirrors the overlay's state handling without rendering anything.
+// Every command is recorded, probe values and overlay events are scripted by the caller.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use ipc_channel::ipc::IpcSender;
+
+use crate::*;
+
+pub struct HeadlessState {
+  pub commands:                 Vec<OverlayCommand>, // everything except for the handshake, in order of arrival
+  pub hud_visible:              bool,
+  pub knobs_menu_visible:       bool,
+  pub knobs_menu_selected_item: usize,
+  pub focused:                  bool,
+  pub screen_size:              (u32, u32),
+  pub layer_names:              Vec<String>,
+  pub mode:                     u128,
+  pub status_text:              Option<String>,
+  pub shapes:                   HashMap<u64 /* stage id */, Vec<(Vec<Shape>, u64 /* visibility mask */)>>,
+  pub knobs:                    Vec<Knob>,
+  processed:                    usize,
+  probes:                       HashMap<u64, IpcSender<ProbeReport>>,
+  probe_results:                HashMap<u64, ProbeResult>,
+  event_senders:                Vec<IpcSender<OverlayEvent>>
+}
+
+impl HeadlessState {
+
+  fn new() -> Self {
+    Self {
+      commands:                 vec![],
+      hud_visible:              true,
+      knobs_menu_visible:       false,
+      knobs_menu_selected_item: 0,
+      focused:                  true,
+      screen_size:              (1920, 1080),
+      layer_names:              vec![],
+      mode:                     0,
+      status_text:              None,
+      shapes:                   HashMap::new(),
+      knobs:                    vec![],
+      processed:                0,
+      probes:                   HashMap::new(),
+      probe_results:            HashMap::new(),
+      event_senders:            vec![]
+    }
+  }
+
+  // same as OverlayState::reset, scripted probe results are kept for the next connection
+  fn reset(&mut self) {
+    self.hud_visible = true;
+    self.layer_names.clear();
+    self.mode = 0;
+    self.status_text = None;
+    self.shapes.clear();
+    self.knobs.clear();
+    self.probes.clear();
+    self.event_senders.clear();
+  }
+
+  fn emit(&mut self, event: OverlayEvent) {
+    self.event_senders.retain(|sender| sender.send(event.clone()).is_ok());
+  }
+
+  fn emit_ui_visibility(&mut self) {
+    self.emit(OverlayEvent::UIVisibilityChanged { hud: self.hud_visible, knobs_menu: self.knobs_menu_visible });
+  }
+
+  fn report_probe(&mut self, probe_id: u64) {
+    if let (Some(sender), Some(result)) = (self.probes.get(&probe_id), self.probe_results.get(&probe_id)) {
+      if sender.send((probe_id, result.clone())).is_err() {
+        self.probes.remove(&probe_id);
+      }
+    }
+  }
+
+  fn menu_command(&mut self, command: OverlayMenuCommand) {
+    match command {
+      OverlayMenuCommand::OpenKnobsMenu => {
+        self.knobs_menu_visible       = true;
+        self.knobs_menu_selected_item = 0;
+        self.emit_ui_visibility();
+      },
+      OverlayMenuCommand::SelectPrevMenuItem => {
+        self.knobs_menu_selected_item = crate::prev_menu_item(self.knobs_menu_selected_item, self.knobs.len());
+      },
+      OverlayMenuCommand::SelectNextMenuItem => {
+        self.knobs_menu_selected_item = crate::next_menu_item(self.knobs_menu_selected_item, self.knobs.len());
+      },
+      OverlayMenuCommand::SelectPrevValue | OverlayMenuCommand::SelectNextValue => {
+        if let Some(knob) = self.knobs.get_mut(self.knobs_menu_selected_item) {
+          if command == OverlayMenuCommand::SelectPrevValue {
+            knob.select_prev_value();
+          } else {
+            knob.select_next_value();
+          }
+          let knob = knob.clone();
+          self.emit(OverlayEvent::KnobChanged(knob));
+        }
+      },
+      OverlayMenuCommand::CloseKnobsMenu => {
+        self.knobs_menu_visible = false;
+        self.emit_ui_visibility();
+      },
+      _ => eprintln!("[headless] ignored menu command: {:?}", command)
+    }
+  }
+
+  fn process(&mut self, command: OverlayCommand) {
+    match &command {
+      OverlayCommand::Handshake(..) => {
+        // handled by overlay_ipc
+      },
+      OverlayCommand::AddMemoryCheck(probe_id, _, _, _, _, sender) | OverlayCommand::AddScreenScrapingArea(probe_id, _, sender) => {
+        self.probes.insert(*probe_id, sender.clone());
+        self.report_probe(*probe_id);
+      },
+      OverlayCommand::MenuCommand(menu_command) => {
+        self.menu_command(*menu_command);
+      },
+      OverlayCommand::RegisterKnobs(knobs) => {
+        self.knobs = knobs.clone();
+      },
+      OverlayCommand::RegisterShapes { stage_id, shapes } => {
+        self.shapes.insert(*stage_id, shapes.iter().map(|s| (s.clone(), 0)).collect());
+      },
+      OverlayCommand::ResetOverlay => {
+        self.reset();
+      },
+      OverlayCommand::SetLayerNames(names) => {
+        self.layer_names = names.clone();
+      },
+      OverlayCommand::SetMode(mode) => {
+        self.mode = *mode;
+      },
+      OverlayCommand::SetStatusText(text) => {
+        self.status_text = text.clone();
+      },
+      OverlayCommand::SubscribeToEvents(sender) => {
+        self.event_senders.push(sender.clone());
+
+        let (focused, (width, height)) = (self.focused, self.screen_size);
+        self.emit(OverlayEvent::FocusChanged(focused));
+        self.emit(OverlayEvent::SwapchainResized { width, height });
+        self.emit_ui_visibility();
+      },
+      OverlayCommand::ToggleShapes { stage_id, layer, mask } => {
+        match self.shapes.get_mut(stage_id).and_then(|layers| layers.get_mut(*layer as usize)) {
+          Some((_, m)) => *m = *mask,
+          None         => eprintln!("[headless] layer {} for stage {} doesn't exist", layer, stage_id)
+        }
+      },
+      OverlayCommand::ToggleUI => {
+        self.hud_visible = !self.hud_visible;
+        self.emit_ui_visibility();
+      }
+    }
+
+    self.commands.push(command);
+    self.processed += 1;
+  }
+}
+
+pub struct HeadlessOverlay {
+  state: Arc<Mutex<HeadlessState>>
+}
+
+impl HeadlessOverlay {
+
+  // commands are processed in a background thread until the receiver is disconnected
+  pub fn new(receiver: CommandReceiver) -> Self {
+    let state = Arc::new(Mutex::new(HeadlessState::new()));
+
+    let thread_state = state.clone();
+    thread::spawn(move || {
+      while let Ok(command) = receiver.recv() {
+        thread_state.lock().unwrap().process(command);
+      }
+    });
+
+    Self { state }
+  }
+
+  // registers stwgs.Overlay on the session bus, just like the Vulkan layer
+  pub fn register(overlay_name: &str, capabilities: Capabilities) -> Self {
+    Self::new(process_incoming_commands(overlay_name, capabilities))
+  }
+
+  // no D-Bus involved, connect with connect_to_ipc_server
+  pub fn serve(capabilities: Capabilities) -> (Self, String) {
+    let (server_name, receiver) = start_ipc_server(capabilities);
+    (Self::new(receiver), server_name)
+  }
+
+  pub fn state(&self) -> MutexGuard<'_, HeadlessState> {
+    self.state.lock().unwrap()
+  }
+
+  // waits until every command sent by the client so far is processed
+  pub fn sync(&self, client: &CommandSender, timeout: Duration) -> bool {
+    let sent = client.sent();
+    self.wait_for(timeout, |state| state.processed >= sent)
+  }
+
+  pub fn take_commands(&self) -> Vec<OverlayCommand> {
+    std::mem::take(&mut self.state().commands)
+  }
+
+  // commands arrive asynchronously, returns false on timeout
+  pub fn wait_for(&self, timeout: Duration, condition: impl Fn(&HeadlessState) -> bool) -> bool {
+    let started_at = Instant::now();
+    while !condition(&self.state()) {
+      if started_at.elapsed() > timeout {
+        return false;
+      }
+      thread::sleep(Duration::from_millis(1));
+    }
+    true
+  }
+
+  // reported right away if the probe is registered, otherwise as soon as it is
+  pub fn set_probe_result(&self, probe_id: u64, result: ProbeResult) {
+    let mut state = self.state();
+    state.probe_results.insert(probe_id, result);
+    state.report_probe(probe_id);
+  }
+
+  pub fn set_focused(&self, focused: bool) {
+    let mut state = self.state();
+    state.focused = focused;
+    state.emit(OverlayEvent::FocusChanged(focused));
+  }
+
+  pub fn resize(&self, width: u32, height: u32) {
+    let mut state = self.state();
+    state.screen_size = (width, height);
+    state.emit(OverlayEvent::SwapchainResized { width, height });
+  }
+
+  pub fn present_frame(&self, frame_time: f32) {
+    self.state().emit(OverlayEvent::FramePresented { frame_time });
+  }
+
+  // as if sent by the mapper, handy for driving the knobs menu
+  pub fn menu_command(&self, command: OverlayMenuCommand) {
+    self.state().menu_command(command);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  #[test]
+  fn headless_overlay_test() {
+    let (overlay, server_name) = HeadlessOverlay::serve(Capabilities { knobs_ui: false, ..Capabilities::ALL });
+    let client = connect_to_ipc_server(server_name).unwrap();
+    assert!(!client.capabilities.knobs_ui);
+
+    let (probe_sender, probe_receiver) = ipc::channel().unwrap();
+    let (event_sender, event_receiver) = ipc::channel().unwrap();
+
+    overlay.set_probe_result(7, ProbeResult::Memory(42));
+    client.send(OverlayCommand::AddMemoryCheck(7, 32, None, 0x1000, vec![], probe_sender)).unwrap();
+    client.send(OverlayCommand::SubscribeToEvents(event_sender)).unwrap();
+    client.send(OverlayCommand::RegisterKnobs(vec![Knob::Flag { name: "foo".to_string(), value: false }])).unwrap();
+    client.send(OverlayCommand::MenuCommand(OverlayMenuCommand::OpenKnobsMenu)).unwrap();
+    client.send(OverlayCommand::MenuCommand(OverlayMenuCommand::SelectNextValue)).unwrap();
+
+    assert!(overlay.wait_for(Duration::from_secs(5), |state| state.commands.len() == 5));
+    assert!(matches!(probe_receiver.recv().unwrap(), (7, ProbeResult::Memory(42))));
+    assert!(overlay.state().knobs_menu_visible);
+
+    let events = std::iter::from_fn(|| event_receiver.try_recv().ok()).collect::<Vec<_>>();
+    assert!(matches!(events.last(), Some(OverlayEvent::KnobChanged(Knob::Flag { value: true, .. }))));
+  }
+
+  #[test]
+  fn menu_selection_test() {
+    let (overlay, _) = HeadlessOverlay::serve(Capabilities::ALL);
+    overlay.state().knobs = vec![
+      Knob::Flag { name: "foo".to_string(), value: false },
+      Knob::Flag { name: "bar".to_string(), value: false }
+    ];
+
+    // the selection wraps around at either end, as in the overlay
+    overlay.menu_command(OverlayMenuCommand::SelectPrevMenuItem);
+    assert_eq!(overlay.state().knobs_menu_selected_item, 1);
+    overlay.menu_command(OverlayMenuCommand::SelectNextMenuItem);
+    assert_eq!(overlay.state().knobs_menu_selected_item, 0);
+    overlay.menu_command(OverlayMenuCommand::SelectNextMenuItem);
+    assert_eq!(overlay.state().knobs_menu_selected_item, 1);
+
+    overlay.state().knobs.clear();
+    overlay.menu_command(OverlayMenuCommand::SelectNextMenuItem);
+    assert_eq!(overlay.state().knobs_menu_selected_item, 0);
+  }
+}
@@ -0,0 +1,228 @@
+use serde::{Deserialize, Serialize};
+
+// per channel
+pub const HISTOGRAM_BINS: usize = 16;
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+// 8-bit RGB
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub struct Image {
+  pub width:  u32,
+  pub height: u32,
+  pub pixels: Vec<u8>
+}
+
+impl std::fmt::Debug for Image {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "Image({}x{})", self.width, self.height)
+  }
+}
+
+impl Image {
+
+  pub fn new(width: u32, height: u32, rgb: [u8; 3]) -> Self {
+    Self { width, height, pixels: rgb.repeat((width * height) as usize) }
+  }
+
+  // PNG or binary PPM, told apart by the signature
+  pub fn load(path: &str) -> Result<Self, String> {
+    let bytes = std::fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+    if bytes.starts_with(PNG_SIGNATURE) {
+      Self::from_png(&bytes).map_err(|e| format!("{}: {}", path, e))
+    } else {
+      Self::from_ppm(&bytes).map_err(|e| format!("{}: {}", path, e))
+    }
+  }
+
+  // binary PPM for .ppm paths, PNG otherwise
+  pub fn save(&self, path: &str) -> Result<(), String> {
+    let bytes = if path.ends_with(".ppm") { self.to_ppm() } else { self.to_png() };
+    std::fs::write(path, bytes).map_err(|e| format!("{}: {}", path, e))
+  }
+
+  // any colour type and bit depth, alpha is dropped
+  pub fn from_png(bytes: &[u8]) -> Result<Self, String> {
+    let mut decoder = png::Decoder::new(bytes);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+
+    let mut reader = decoder.read_info().map_err(|e| format!("{}", e))?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer).map_err(|e| format!("{}", e))?;
+
+    let pixels = match info.color_type {
+      png::ColorType::Rgb            => buffer[..info.buffer_size()].to_vec(),
+      png::ColorType::Rgba           => buffer[..info.buffer_size()].chunks(4).flat_map(|p| [p[0], p[1], p[2]]).collect(),
+      png::ColorType::Grayscale      => buffer[..info.buffer_size()].iter().flat_map(|&v| [v, v, v]).collect(),
+      png::ColorType::GrayscaleAlpha => buffer[..info.buffer_size()].chunks(2).flat_map(|p| [p[0], p[0], p[0]]).collect(),
+      png::ColorType::Indexed        => return Err("palette wasn't expanded".to_string())
+    };
+
+    Ok(Self { width: info.width, height: info.height, pixels })
+  }
+
+  // binary PPM (P6) with 8-bit samples
+  pub fn from_ppm(bytes: &[u8]) -> Result<Self, String> {
+
+    let mut pos = 0;
+
+    let mut next_token = || -> Result<String, String> {
+      loop {
+        match bytes.get(pos) {
+          Some(b'#') => {
+            while pos < bytes.len() && bytes[pos] != b'\n' {
+              pos += 1;
+            }
+          },
+          Some(c) if c.is_ascii_whitespace() => pos += 1,
+          Some(_) => break,
+          None => return Err("unexpected end of file".to_string())
+        }
+      }
+
+      let start = pos;
+      while pos < bytes.len() && !bytes[pos].is_ascii_whitespace() {
+        pos += 1;
+      }
+
+      Ok(String::from_utf8_lossy(&bytes[start..pos]).to_string())
+    };
+
+    if next_token()? != "P6" {
+      return Err("not a binary PPM file".to_string());
+    }
+
+    let mut number = || -> Result<u32, String> {
+      let token = next_token()?;
+      token.parse::<u32>().map_err(|_| format!("invalid number: {}", token))
+    };
+
+    let width  = number()?;
+    let height = number()?;
+    let maxval = number()?;
+
+    if maxval != 255 {
+      return Err(format!("unsupported maxval: {}", maxval));
+    }
+
+    if width == 0 || height == 0 {
+      return Err("empty image".to_string());
+    }
+
+    // a single whitespace character separates the header from the pixel data
+    let start = pos + 1;
+    let size  = (width * height * 3) as usize;
+
+    if bytes.len() < start + size {
+      return Err("truncated pixel data".to_string());
+    }
+
+    Ok(Self { width, height, pixels: bytes[start..(start + size)].to_vec() })
+  }
+
+  pub fn to_ppm(&self) -> Vec<u8> {
+    let mut bytes = format!("P6\n{} {}\n255\n", self.width, self.height).into_bytes();
+    bytes.extend(&self.pixels);
+    bytes
+  }
+
+  pub fn to_png(&self) -> Vec<u8> {
+    let mut bytes = vec![];
+    let mut encoder = png::Encoder::new(&mut bytes, self.width, self.height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    // writing into memory fails only on a pixel buffer of the wrong size
+    encoder.write_header().and_then(|mut writer| writer.write_image_data(&self.pixels)).unwrap();
+    bytes
+  }
+
+  pub fn set_rgb(&mut self, x: u32, y: u32, rgb: [u8; 3]) {
+    let i = ((y * self.width + x) * 3) as usize;
+    self.pixels[i..(i + 3)].copy_from_slice(&rgb);
+  }
+
+  pub fn rgb(&self, x: u32, y: u32) -> (f32, f32, f32) {
+    let i = ((y * self.width + x) * 3) as usize;
+    (self.pixels[i] as f32 / 255.0, self.pixels[i + 1] as f32 / 255.0, self.pixels[i + 2] as f32 / 255.0)
+  }
+
+  pub fn luma(&self) -> Vec<f32> {
+    let mut v = Vec::with_capacity((self.width * self.height) as usize);
+    for y in 0..self.height {
+      for x in 0..self.width {
+        let (r, g, b) = self.rgb(x, y);
+        v.push(luma(r, g, b));
+      }
+    }
+    v
+  }
+
+  // normalized R, G and B histograms, one after another
+  pub fn histogram(&self) -> Vec<f32> {
+    let mut v = vec![0.0; HISTOGRAM_BINS * 3];
+    for y in 0..self.height {
+      for x in 0..self.width {
+        let (r, g, b) = self.rgb(x, y);
+        v[histogram_bin(r)]                      += 1.0;
+        v[histogram_bin(g) + HISTOGRAM_BINS]     += 1.0;
+        v[histogram_bin(b) + HISTOGRAM_BINS * 2] += 1.0;
+      }
+    }
+
+    let pixel_count = (self.width * self.height) as f32;
+    for bin in &mut v {
+      *bin /= pixel_count;
+    }
+    v
+  }
+}
+
+pub fn luma(r: f32, g: f32, b: f32) -> f32 {
+  0.299 * r + 0.587 * g + 0.114 * b
+}
+
+pub fn histogram_bin(value: f32) -> usize {
+  ((value * HISTOGRAM_BINS as f32) as usize).min(HISTOGRAM_BINS - 1)
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  #[test]
+  fn ppm_test() {
+    let mut bytes = b"P6\n# comment\n2 1\n255\n".to_vec();
+    bytes.extend([255, 0, 0, 0, 0, 255]);
+
+    let image = Image::from_ppm(&bytes).unwrap();
+    assert_eq!((image.width, image.height), (2, 1));
+    assert_eq!(image.rgb(0, 0), (1.0, 0.0, 0.0));
+    assert_eq!(image.rgb(1, 0), (0.0, 0.0, 1.0));
+
+    let histogram = image.histogram();
+    assert_eq!(histogram[HISTOGRAM_BINS - 1], 0.5);
+    assert_eq!(histogram[0], 0.5);
+
+    assert_eq!(Image::from_ppm(&image.to_ppm()).unwrap(), image);
+
+    assert!(Image::from_ppm(&bytes[..bytes.len() - 1]).is_err());
+    assert!(Image::from_ppm(b"P3\n2 1\n255\n").is_err());
+  }
+
+  #[test]
+  fn png_test() {
+    let image = Image { width: 2, height: 1, pixels: vec![255, 0, 0, 0, 0, 255] };
+    let png   = image.to_png();
+    assert_eq!(&png[0..8], b"\x89PNG\r\n\x1a\n");
+    assert_eq!(&png[(png.len() - 12)..], b"\0\0\0\0IEND\xae\x42\x60\x82");
+    assert_eq!(Image::from_png(&png).unwrap(), image);
+
+    // compressed and filtered, with alpha
+    let marker = Image::from_png(include_bytes!("fixtures/marker.png")).unwrap();
+    assert_eq!((marker.width, marker.height), (12, 12));
+    assert_eq!(marker.rgb(6, 6), (1.0, 0.0, 0.0));
+
+    assert!(Image::from_png(&png[..png.len() - 20]).is_err());
+  }
+}
@@ -1,3 +1,4 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{mpsc, Mutex};
 use std::thread;
 
@@ -6,38 +7,188 @@ use ipc_channel::ipc::{IpcError, IpcOneShotServer, IpcSender};
 
 use lazy_static::lazy_static;
 
-pub type CommandSender   = IpcSender<OverlayCommand>;
+pub mod detectors;
+pub mod headless;
+mod image;
+pub mod proc_maps;
+pub mod raster;
+
+pub use image::*;
+
 pub type CommandReceiver = mpsc::Receiver<OverlayCommand>;
 
-//TODO: filter by overlay name
-pub fn connect_to_overlay() -> Result<Option<CommandSender>, Box<dyn std::error::Error>> {
+// bump on any change to OverlayCommand or the types it carries
+pub const PROTOCOL_VERSION: u32 = 5;
+
+const HANDSHAKE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Capabilities {
+  pub screen_probes: bool,
+  pub memory_probes: bool,
+  pub shapes:        bool,
+  pub knobs_ui:      bool
+}
+
+impl Capabilities {
+  pub const ALL: Self = Self { screen_probes: true, memory_probes: true, shapes: true, knobs_ui: true };
 
-  use zbus::{proxy, Connection};
+  const NAMES: [&'static str; 4] = ["screen_probes", "memory_probes", "shapes", "knobs_ui"];
+
+  fn flags(&mut self) -> [&mut bool; 4] {
+    [&mut self.screen_probes, &mut self.memory_probes, &mut self.shapes, &mut self.knobs_ui]
+  }
 
-  #[proxy(interface = "stwgs.Overlay", default_path = "/overlay")]
-  trait SCOverlay {
-    fn overlay_name(&self)    -> zbus::fdo::Result<String>;
-    fn ipc_server_name(&self) -> zbus::fdo::Result<String>;
+  pub fn to_names(mut self) -> Vec<String> {
+    Self::NAMES.iter().zip(self.flags()).filter(|(_, flag)| **flag).map(|(name, _)| name.to_string()).collect()
   }
 
-  let connection = futures::executor::block_on(Connection::session())?;
-  let dbus_proxy = futures::executor::block_on(zbus::fdo::DBusProxy::new(&connection))?;
+  // names from newer versions are ignored
+  pub fn from_names(names: &[String]) -> Self {
+    let mut capabilities = Self { screen_probes: false, memory_probes: false, shapes: false, knobs_ui: false };
+    for (name, flag) in Self::NAMES.iter().zip(capabilities.flags()) {
+      *flag = names.iter().any(|n| n == name);
+    }
+    capabilities
+  }
+}
+
+// capabilities are sent as names, so that the reply decodes whatever the other side's version is
+// and a version mismatch can be reported
+#[derive(Serialize, Deserialize, Debug)]
+pub struct HandshakeReply {
+  pub version:      u32,
+  pub capabilities: Vec<String>
+}
+
+pub struct CommandSender {
+  sender:           IpcSender<OverlayCommand>,
+  sent:             AtomicUsize,
+  pub capabilities: Capabilities
+}
+
+impl CommandSender {
+  pub fn send(&self, command: OverlayCommand) -> Result<(), String> {
+    self.sent.fetch_add(1, Ordering::Relaxed);
+    self.sender.send(command).map_err(|e| format!("{}", e))
+  }
+
+  // number of commands sent after the handshake
+  pub fn sent(&self) -> usize {
+    self.sent.load(Ordering::Relaxed)
+  }
+}
+
+#[derive(Clone, Debug)]
+pub struct OverlayInfo {
+  pub bus_name:     String,
+  pub pid:          u32,
+  pub overlay_name: String,
+  pub executable:   String
+}
+
+impl OverlayInfo {
+  // either a pid, an overlay name or an executable name (case insensitive)
+  pub fn matches(&self, selector: &str) -> bool {
+    selector.parse::<u32>().map(|pid| pid == self.pid).unwrap_or(false)
+      || self.overlay_name.eq_ignore_ascii_case(selector)
+      || self.executable.rsplit(['/', '\\']).next().unwrap().eq_ignore_ascii_case(selector)
+  }
+}
+
+#[zbus::proxy(interface = "stwgs.Overlay", default_path = "/overlay")]
+trait SCOverlay {
+  fn overlay_name(&self)    -> zbus::fdo::Result<String>;
+  fn ipc_server_name(&self) -> zbus::fdo::Result<String>;
+  fn pid(&self)             -> zbus::fdo::Result<u32>;
+  fn executable(&self)      -> zbus::fdo::Result<String>;
+}
+
+// skipped overlays are reported through log
+fn overlay_proxies<'c>(connection: &'c zbus::Connection, log: &dyn Fn(String)) -> Result<Vec<(OverlayInfo, SCOverlayProxy<'c>)>, Box<dyn std::error::Error>> {
+
+  let dbus_proxy = futures::executor::block_on(zbus::fdo::DBusProxy::new(connection))?;
   let services   = futures::executor::block_on(dbus_proxy.list_names())?;
 
+  let mut overlays = vec![];
+
   for name in services {
     if name.starts_with("stwgs.Overlay") {
-      let overlay_proxy =
-        futures::executor::block_on(SCOverlayProxy::builder(&connection).destination(&name).unwrap().build())?;
-      //println!("{:?}", overlay_proxy);
+      // an overlay might be exiting or stuck, that shouldn't hide the others
+      let overlay = || -> Result<(OverlayInfo, SCOverlayProxy<'_>), Box<dyn std::error::Error>> {
+        let overlay_proxy =
+          futures::executor::block_on(SCOverlayProxy::builder(connection).destination(name.to_string())?.build())?;
+
+        let info = OverlayInfo {
+          bus_name:     name.to_string(),
+          pid:          futures::executor::block_on(overlay_proxy.pid())?,
+          overlay_name: futures::executor::block_on(overlay_proxy.overlay_name())?,
+          executable:   futures::executor::block_on(overlay_proxy.executable())?
+        };
+
+        Ok((info, overlay_proxy))
+      };
+
+      match overlay() {
+        Ok(overlay) => overlays.push(overlay),
+        Err(e)      => log(format!("Skipping overlay {}: {}", name, e))
+      }
+    }
+  }
 
-      let overlay_name = futures::executor::block_on(overlay_proxy.overlay_name())?;
-      println!("[client] found overlay: {:?}", overlay_name);
-      let server_name  = futures::executor::block_on(overlay_proxy.ipc_server_name())?;
-      println!("[client] connecting to ipc server {:?}", server_name);
+  overlays.sort_by_key(|(info, _)| info.pid);
+  Ok(overlays)
+}
 
-      let sender = IpcSender::connect(server_name)?;
+pub fn list_overlays() -> Result<Vec<OverlayInfo>, Box<dyn std::error::Error>> {
+  let connection = futures::executor::block_on(zbus::Connection::session())?;
+  Ok(overlay_proxies(&connection, &|message| eprintln!("{}", message))?.into_iter().map(|(info, _)| info).collect())
+}
 
-      return Ok(Some(sender));
+// performs the handshake with an ipc server obtained via D-Bus (see connect_to_overlay) or start_ipc_server
+pub fn connect_to_ipc_server(server_name: String) -> Result<CommandSender, Box<dyn std::error::Error>> {
+  handshake(server_name, PROTOCOL_VERSION)
+}
+
+fn handshake(server_name: String, version: u32) -> Result<CommandSender, Box<dyn std::error::Error>> {
+  let sender = IpcSender::connect(server_name)?;
+
+  let (reply_sender, reply_receiver) = ipc::channel()?;
+  sender.send(OverlayCommand::Handshake(version, reply_sender))?;
+
+  let started_at = std::time::Instant::now();
+  let reply = loop {
+    match reply_receiver.try_recv() {
+      Ok(reply) => break reply,
+      Err(ipc::TryRecvError::Empty) if started_at.elapsed() < HANDSHAKE_TIMEOUT => {
+        thread::sleep(std::time::Duration::from_millis(10));
+      },
+      Err(ipc::TryRecvError::Empty) => return Err("overlay handshake timed out".into()),
+      Err(ipc::TryRecvError::IpcError(e)) => return Err(format!("overlay handshake failed: {:?}", e).into())
+    }
+  };
+
+  if reply.version != version {
+    return Err(format!("overlay protocol version mismatch: expected {}, got {} (rebuild the overlay)",
+      version, reply.version).into());
+  }
+
+  Ok(CommandSender { sender, sent: AtomicUsize::new(0), capabilities: Capabilities::from_names(&reply.capabilities) })
+}
+
+// connects to the first overlay matching the selector (see OverlayInfo::matches) or just the first one found,
+// progress is reported through log
+pub fn connect_to_overlay(selector: Option<&str>, log: &dyn Fn(String)) -> Result<Option<CommandSender>, Box<dyn std::error::Error>> {
+
+  let connection = futures::executor::block_on(zbus::Connection::session())?;
+
+  for (info, overlay_proxy) in overlay_proxies(&connection, log)? {
+    if selector.map(|selector| info.matches(selector)).unwrap_or(true) {
+      log(format!("[client] found overlay: {:?} (pid {})", info.overlay_name, info.pid));
+      let server_name = futures::executor::block_on(overlay_proxy.ipc_server_name())?;
+      log(format!("[client] connecting to ipc server {:?}", server_name));
+
+      return Ok(Some(connect_to_ipc_server(server_name)?));
     }
   }
 
@@ -48,44 +199,87 @@ lazy_static! {
   static ref DBUS_CONNECTION: Mutex<Option<zbus::Connection>> = Mutex::new(None);
 }
 
-pub fn process_incoming_commands(overlay_name: &str) -> CommandReceiver {
-  let (mpsc_sender, mpsc_receiver) = mpsc::channel();
-  let _ = mpsc_sender.send(OverlayCommand::SetStatusText(Some("waiting for connection".to_string())));
+// Wine rewrites the command line to point to the Windows executable, unlike current_exe (the preloader)
+fn executable() -> String {
+  std::fs::read("/proc/self/cmdline").ok()
+    .and_then(|cmdline| cmdline.split(|&c| c == 0).next().map(|argv0| String::from_utf8_lossy(argv0).to_string()))
+    .filter(|argv0| !argv0.is_empty())
+    .or_else(|| std::env::current_exe().ok().map(|path| path.to_string_lossy().to_string()))
+    .unwrap_or_default()
+}
 
-  fn start_ipc_thread(mpsc_sender: mpsc::Sender<OverlayCommand>) -> (String, thread::JoinHandle<()>) {
-    let (server, server_name) = IpcOneShotServer::<OverlayCommand>::new().unwrap();
+fn start_ipc_thread(mpsc_sender: mpsc::Sender<OverlayCommand>, capabilities: Capabilities) -> (String, thread::JoinHandle<()>) {
+  let (server, server_name) = IpcOneShotServer::<OverlayCommand>::new().unwrap();
 
-    let thread_handle = thread::spawn(move || {
-      let (receiver, command) = server.accept().unwrap();
-      eprintln!("[server] received first: {:?}", command);
-      let _ = mpsc_sender.send(command);
+  let thread_handle = thread::spawn(move || {
+    let (receiver, command) = match server.accept() {
+      Ok(accepted) => accepted,
+      Err(err) => {
+        eprintln!("[server] can't accept connection: {:?}", err);
+        return;
+      }
+    };
+
+    eprintln!("[server] received first: {:?}", command);
+    match command {
+      OverlayCommand::Handshake(version, reply_sender) => {
+        let _ = reply_sender.send(HandshakeReply { version: PROTOCOL_VERSION, capabilities: capabilities.to_names() });
+        if version != PROTOCOL_VERSION {
+          eprintln!("[server] protocol version mismatch: expected {}, got {}", PROTOCOL_VERSION, version);
+          return;
+        }
+      },
+      _ => {
+        eprintln!("[server] expected handshake");
+        return;
+      }
+    }
 
-      loop {
-        match receiver.recv() {
-          Ok(command) => {
-            eprintln!("[server] received: {:?}", command);
-            let _ = mpsc_sender.send(command);
+    loop {
+      match receiver.recv() {
+        Ok(command) => {
+          eprintln!("[server] received: {:?}", command);
+          let _ = mpsc_sender.send(command);
+        },
+        Err(err) => match err {
+          IpcError::Disconnected => {
+            eprintln!("[server] client disconnected");
+            let _ = mpsc_sender.send(OverlayCommand::ResetOverlay);
+            let _ = mpsc_sender.send(OverlayCommand::SetStatusText(Some("waiting for connection".to_string())));
+            break;
           },
-          Err(err) => match err {
-            IpcError::Disconnected => {
-              eprintln!("[server] client disconnected");
-              let _ = mpsc_sender.send(OverlayCommand::ResetOverlay);
-              let _ = mpsc_sender.send(OverlayCommand::SetStatusText(Some("waiting for connection".to_string())));
-              break;
-            },
-            _ => panic!("[server] received err: {:?}", err)
+          // most likely a client built from a different version
+          _ => {
+            eprintln!("[server] received err: {:?}", err);
+            let _ = mpsc_sender.send(OverlayCommand::ResetOverlay);
+            let _ = mpsc_sender.send(OverlayCommand::SetStatusText(Some("waiting for connection".to_string())));
+            break;
           }
         }
       }
-    });
+    }
+  });
 
-    (server_name, thread_handle)
-  }
+  (server_name, thread_handle)
+}
+
+// a single connection without D-Bus registration, e.g. for running the mapper against a headless overlay
+pub fn start_ipc_server(capabilities: Capabilities) -> (String, CommandReceiver) {
+  let (mpsc_sender, mpsc_receiver) = mpsc::channel();
+  let (server_name, _) = start_ipc_thread(mpsc_sender, capabilities);
+  (server_name, mpsc_receiver)
+}
+
+pub fn process_incoming_commands(overlay_name: &str, capabilities: Capabilities) -> CommandReceiver {
+  let (mpsc_sender, mpsc_receiver) = mpsc::channel();
+  let _ = mpsc_sender.send(OverlayCommand::SetStatusText(Some("waiting for connection".to_string())));
 
   use zbus::{interface, ConnectionBuilder};
 
   struct SCOverlay {
     overlay_name: String,
+    executable:   String,
+    capabilities: Capabilities,
     ipc_server:   Option<(String, thread::JoinHandle<()>)>,
     mpsc_sender:  Mutex<mpsc::Sender<OverlayCommand>>
   }
@@ -96,6 +290,14 @@ pub fn process_incoming_commands(overlay_name: &str) -> CommandReceiver {
       &self.overlay_name
     }
 
+    fn pid(&self) -> u32 {
+      std::process::id()
+    }
+
+    fn executable(&self) -> &String {
+      &self.executable
+    }
+
     fn ipc_server_name(&mut self) -> String {
       if let Some((ipc_server_name, ipc_thread)) = &self.ipc_server {
         if !ipc_thread.is_finished() {
@@ -103,7 +305,7 @@ pub fn process_incoming_commands(overlay_name: &str) -> CommandReceiver {
         }
       }
 
-      let (ipc_server_name, ipc_thread) = start_ipc_thread(self.mpsc_sender.lock().unwrap().clone());
+      let (ipc_server_name, ipc_thread) = start_ipc_thread(self.mpsc_sender.lock().unwrap().clone(), self.capabilities);
       self.ipc_server = Some((ipc_server_name.clone(), ipc_thread));
       ipc_server_name
     }
@@ -113,7 +315,13 @@ pub fn process_incoming_commands(overlay_name: &str) -> CommandReceiver {
   assert!(connection.is_none());
 
   let overlay_dbus_object =
-    SCOverlay { overlay_name: overlay_name.to_string(), ipc_server: None, mpsc_sender: Mutex::new(mpsc_sender) };
+    SCOverlay {
+      overlay_name: overlay_name.to_string(),
+      executable:   executable(),
+      capabilities,
+      ipc_server:   None,
+      mpsc_sender:  Mutex::new(mpsc_sender)
+    };
 
   *connection = Some(
     futures::executor::block_on(
@@ -234,13 +442,26 @@ impl Angle {
   }
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Label {
+  pub text:  String,
+  pub color: Color,
+  pub icon:  Option<String> // drawn above the text, expected to be a single glyph
+}
+
+impl Label {
+  pub fn new(text: String, color: Color) -> Self {
+    Self { text, color, icon: None }
+  }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum Shape {
   Circle {
     center: Point,
     radius: Length,
     color:  Color,
-    label:  Option<(String, Color)>
+    label:  Option<Label>
   },
   Ring {
     center:       Point,
@@ -255,40 +476,133 @@ pub enum Shape {
     inner_radius: Length,
     outer_radius: Length,
     color:        Color,
-    label:        Option<(String, Color)>
+    label:        Option<Label>
   },
   RegularHexagon {
     center:       Point,
     circumradius: Length,
     color:        Color,
-    label:        Option<(String, Color)>
+    label:        Option<Label>
+  },
+  Rectangle {
+    center:       Point,
+    width:        Length,
+    height:       Length,
+    color:        Color,
+    label:        Option<Label>
   }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum Knob {
-  Flag   { name: String, value: bool },
-  Enum   { name: String, index: usize, options: Vec<String> },
-  Number { name: String, value: f32,   min_value: f32, max_value: f32 }
+  Flag    { name: String, value: bool },
+  Enum    { name: String, index: usize, options: Vec<String> },
+  Number  { name: String, value: f32,   min_value: f32, max_value: f32 },
+  Integer { name: String, value: i32,   min_value: i32, max_value: i32, step: i32 },
+  Key     { name: String, index: usize, options: Vec<String> }, // keyboard key names
+  Layer   { name: String, index: usize, options: Vec<String> }, // layer or mode names, like "foo | bar"
+  Curve   { name: String, points: Vec<(f32, f32)> }              // sorted by x, both coordinates are in [0, 1]
 }
 
 impl Knob {
   pub fn name(&self) -> String {
     match self {
-      Knob::Flag   { name, .. } => name.clone(),
-      Knob::Enum   { name, .. } => name.clone(),
-      Knob::Number { name, .. } => name.clone()
+      Knob::Flag    { name, .. } => name.clone(),
+      Knob::Enum    { name, .. } => name.clone(),
+      Knob::Number  { name, .. } => name.clone(),
+      Knob::Integer { name, .. } => name.clone(),
+      Knob::Key     { name, .. } => name.clone(),
+      Knob::Layer   { name, .. } => name.clone(),
+      Knob::Curve   { name, .. } => name.clone()
     }
   }
 
   pub fn compare_value(&self, other: &Knob) -> bool {
     match (self, other) {
-      (Knob::Flag   { value: v1, .. },              Knob::Flag { value: v2, .. })              => *v1 == *v2,
-      (Knob::Enum   { index: i1, options: o1, .. }, Knob::Enum { index: i2, options: o2, .. }) => *o1 == *o2 && *i1 == *i2,
-      (Knob::Number { value: v1, .. },              Knob::Number { value: v2, .. })            => (*v2 - *v1).abs() < f32::EPSILON,
+      (Knob::Flag    { value: v1, .. },              Knob::Flag    { value: v2, .. })              => *v1 == *v2,
+      (Knob::Enum    { index: i1, options: o1, .. }, Knob::Enum    { index: i2, options: o2, .. }) => *o1 == *o2 && *i1 == *i2,
+      (Knob::Number  { value: v1, .. },              Knob::Number  { value: v2, .. })              => (*v2 - *v1).abs() < f32::EPSILON,
+      (Knob::Integer { value: v1, .. },              Knob::Integer { value: v2, .. })              => *v1 == *v2,
+      (Knob::Key     { index: i1, options: o1, .. }, Knob::Key     { index: i2, options: o2, .. }) => *o1 == *o2 && *i1 == *i2,
+      (Knob::Layer   { index: i1, options: o1, .. }, Knob::Layer   { index: i2, options: o2, .. }) => *o1 == *o2 && *i1 == *i2,
+      (Knob::Curve   { points: p1, .. },             Knob::Curve   { points: p2, .. })             => *p1 == *p2,
       _ => false
     }
   }
+
+  // knobs menu: left
+  pub fn select_prev_value(&mut self) {
+    match self {
+      Knob::Flag { value, .. } => {
+        *value = !*value;
+      },
+      Knob::Enum { index, .. } | Knob::Key { index, .. } | Knob::Layer { index, .. } => {
+        if *index > 0 {
+          *index -= 1;
+        }
+      },
+      Knob::Number { value, min_value, max_value, .. } => {
+        assert!(*max_value > *min_value);
+        *value -= (*max_value - *min_value) * 0.05;
+        if *value < *min_value {
+          *value = *min_value;
+        }
+      },
+      Knob::Integer { value, min_value, step, .. } => {
+        *value = (*value - *step).max(*min_value);
+      },
+      Knob::Curve { points, .. } => {
+        bend_curve(points, -0.05);
+      }
+    }
+  }
+
+  // knobs menu: right
+  pub fn select_next_value(&mut self) {
+    match self {
+      Knob::Flag { value, .. } => {
+        *value = !*value;
+      },
+      Knob::Enum { index, options, .. } | Knob::Key { index, options, .. } | Knob::Layer { index, options, .. } => {
+        if *index < options.len() - 1 {
+          *index += 1;
+        }
+      },
+      Knob::Number { value, min_value, max_value, .. } => {
+        assert!(*max_value > *min_value);
+        *value += (*max_value - *min_value) * 0.05;
+        if *value > *max_value {
+          *value = *max_value;
+        }
+      },
+      Knob::Integer { value, max_value, step, .. } => {
+        *value = (*value + *step).min(*max_value);
+      },
+      Knob::Curve { points, .. } => {
+        bend_curve(points, 0.05);
+      }
+    }
+  }
+}
+
+// knobs menu: up, wraps around to the last knob
+pub fn prev_menu_item(selected: usize, knob_count: usize) -> usize {
+  if selected > 0 { selected - 1 } else { knob_count.saturating_sub(1) }
+}
+
+// knobs menu: down, wraps around to the first knob
+pub fn next_menu_item(selected: usize, knob_count: usize) -> usize {
+  if selected + 1 < knob_count { selected + 1 } else { 0 }
+}
+
+// moves inner points up or down, end points stay in place
+fn bend_curve(points: &mut [(f32, f32)], delta: f32) {
+  let len = points.len();
+  for (i, (_, y)) in points.iter_mut().enumerate() {
+    if i > 0 && i < len - 1 {
+      *y = (*y + delta).clamp(0.0, 1.0);
+    }
+  }
 }
 
 #[derive(Serialize, Deserialize, Copy, Clone, Debug, PartialEq)]
@@ -299,41 +613,65 @@ pub enum OverlayMenuCommand {
   SelectPrevValue,
   SelectNextValue,
   SetValuePercentage, // ?
-  CloseKnobsMenu
+  CloseKnobsMenu,
+  SelectPrevPreset, // knob presets are handled by the mapper, overlays never receive these
+  SelectNextPreset
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum OverlayCommand {
+  // has to stay the first variant with the same fields, so that any version is able to decode it
+  Handshake(u32, IpcSender<HandshakeReply>),
   //GetScreenDimensions(IpcSender<(u32, u32)>),
   //ResetOverlay(IpcSender<OverlayEvent>),
   //ResetScreenScraping(IpcSender<ScreenScrapingResult>),
   //SetShapeEffect { stage_id: u64, mask: u64, effect: Effect } // ?
-  AddMemoryCheck(u8, u64, Vec<i32>, IpcSender<u64>),
-  AddScreenScrapingArea(ScreenScrapingArea, IpcSender<ScreenScrapingResult>),
-  GetKnobs(IpcSender<Vec<Knob>>),
+  AddMemoryCheck(u64, u8, Option<String>, u64, Vec<i32>, IpcSender<ProbeReport>),
+  AddScreenScrapingArea(u64, ScreenScrapingArea, IpcSender<ProbeReport>),
   MenuCommand(OverlayMenuCommand),
   RegisterKnobs(Vec<Knob>),
   RegisterShapes { stage_id: u64, shapes: Vec<Vec<Shape>> },
   ResetOverlay,
   SetLayerNames(Vec<String>),
-  SetMode(u64),
+  SetMode(u128),
   SetStatusText(Option<String>),
+  SubscribeToEvents(IpcSender<OverlayEvent>),
   ToggleShapes { stage_id: u64, layer: u8, mask: u64 },
   ToggleUI
 }
 
-/*#[derive(Serialize, Deserialize, Debug)]
-pub enum OverlayEvent {}*/
+// the current state is sent right after subscription, changes are sent as they happen
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum OverlayEvent {
+  FocusChanged(bool),
+  FramePresented { frame_time: f32 }, // mean seconds per frame, reported a few times per second
+  KnobChanged(Knob),
+  SwapchainResized { width: u32, height: u32 },
+  UIVisibilityChanged { hud: bool, knobs_menu: bool }
+}
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct ScreenScrapingArea {
-  pub bounds:  Rect,
-  pub min_hue: f32,
-  pub max_hue: f32,
-  pub min_sat: f32,
-  pub max_sat: f32,
-  pub min_val: f32,
-  pub max_val: f32
+  pub bounds:   Rect,
+  pub detector: ScreenScrapingDetector
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum ScreenScrapingDetector {
+  // share of pixels within the HSV box
+  HsvRange {
+    min_hue: f32,
+    max_hue: f32,
+    min_sat: f32,
+    max_sat: f32,
+    min_val: f32,
+    max_val: f32
+  },
+  // normalized cross-correlation with the reference image stretched over the area
+  Template(Image),
+  MeanColor,
+  // see Image::histogram
+  Histogram(Vec<f32>)
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -343,7 +681,42 @@ pub struct Rect {
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
-pub struct ScreenScrapingResult {
-  pub pixels_in_range:  f32,
-  pub uniformity_score: f32 // ?
+pub enum ScreenScrapingResult {
+  HsvRange { pixels_in_range: f32, uniformity_score: f32 /* ? */ },
+  Template { correlation: f32 },
+  MeanColor(Color),
+  Histogram { distance: f32 }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum ProbeResult {
+  Screen(ScreenScrapingResult),
+  Memory(u64)
+}
+
+// probe id + result, so that all probes can share a single channel
+pub type ProbeReport = (u64, ProbeResult);
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  #[test]
+  fn handshake_test() {
+    let (server_name, _) = start_ipc_server(Capabilities { shapes: false, ..Capabilities::ALL });
+    let client = handshake(server_name, PROTOCOL_VERSION).unwrap();
+    assert_eq!(client.capabilities, Capabilities { shapes: false, ..Capabilities::ALL });
+
+    let (server_name, _) = start_ipc_server(Capabilities::ALL);
+    match handshake(server_name, PROTOCOL_VERSION + 1) {
+      Err(e) => assert_eq!(e.to_string(), format!("overlay protocol version mismatch: expected {}, got {} (rebuild the overlay)",
+        PROTOCOL_VERSION + 1, PROTOCOL_VERSION)),
+      Ok(_) => panic!()
+    }
+
+    let names = vec!["knobs_ui".to_string(), "added_later".to_string()];
+    assert_eq!(Capabilities::from_names(&names), Capabilities { screen_probes: false, memory_probes: false, shapes: false, knobs_ui: true });
+    assert_eq!(Capabilities::from_names(&Capabilities::ALL.to_names()), Capabilities::ALL);
+  }
 }
@@ -0,0 +1,49 @@
+// Linux /proc/<pid>/maps format:
+// 00400000-00452000 r-xp 00000000 08:02 173521 /usr/bin/dbus-daemon
+
+// load address of the first mapping backed by a file with the given name (case insensitive)
+pub fn module_base(maps: &str, module: &str) -> Option<u64> {
+  for line in maps.lines() {
+    // range, permissions, offset, device, inode, path (padded, might contain spaces)
+    let mut fields = line.splitn(6, char::is_whitespace);
+    if let (Some(range), Some(path)) = (fields.next(), fields.nth(4).map(str::trim)) {
+      // Wine processes map PE images under dosdevices paths
+      let file_name = path.rsplit(['/', '\\']).next().unwrap();
+      if file_name.eq_ignore_ascii_case(module) {
+        let start = range.split('-').next()?;
+        return u64::from_str_radix(start, 16).ok();
+      }
+    }
+  }
+  None
+}
+
+pub fn module_base_of_current_process(module: &str) -> Result<u64, String> {
+  let maps = std::fs::read_to_string("/proc/self/maps").map_err(|e| format!("/proc/self/maps: {}", e))?;
+  module_base(&maps, module).ok_or_else(|| format!("module {} is not loaded", module))
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+
+  #[test]
+  fn module_base_test() {
+    let maps = "\
+00010000-00011000 r--p 00000000 00:00 0
+00400000-00401000 r--p 00000000 00:2a 1234 /home/user/.wine/dosdevices/c:/Games/gta-sa.exe
+00401000-00800000 r-xp 00001000 00:2a 1234 /home/user/.wine/dosdevices/c:/Games/gta-sa.exe
+7f0000000000-7f0000001000 r--p 00000000 00:2a 5678                       /usr/lib/libc.so.6
+7f0000100000-7f0000101000 r--p 00000000 00:2a 9012 /home/user/.wine/drive_c/Program Files/Game/game engine.dll
+7ffc00000000-7ffc00021000 rw-p 00000000 00:00 0 [stack]
+";
+
+    assert_eq!(module_base(maps, "gta-sa.exe"),      Some(0x00400000));
+    assert_eq!(module_base(maps, "GTA-SA.EXE"),      Some(0x00400000));
+    assert_eq!(module_base(maps, "libc.so.6"),       Some(0x7f0000000000));
+    assert_eq!(module_base(maps, "game engine.dll"), Some(0x7f0000100000));
+    assert_eq!(module_base(maps, "libm.so.6"),       None);
+    assert_eq!(module_base(maps, "Files"),           None);
+  }
+}
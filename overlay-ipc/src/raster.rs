@@ -0,0 +1,142 @@
+// CPU counterpart of the overlay's shape rendering (see overlay/src/gui.rs) for previews and snapshots,
+// labels aren't drawn since there is no font rasterizer around
+
+use std::f32::consts::{PI, TAU};
+
+use crate::{Color, Image, Shape};
+
+// 2x2 samples per pixel
+const SAMPLE_OFFSETS: [(f32, f32); 4] = [(0.25, 0.25), (0.75, 0.25), (0.25, 0.75), (0.75, 0.75)];
+
+fn blend(image: &mut Image, x: u32, y: u32, color: &Color, coverage: f32) {
+  let alpha = color.a.clamp(0.0, 1.0) * coverage;
+  let i = ((y * image.width + x) * 3) as usize;
+  for (c, value) in [color.r, color.g, color.b].into_iter().enumerate() {
+    let dst = image.pixels[i + c] as f32 / 255.0;
+    image.pixels[i + c] = ((dst * (1.0 - alpha) + value.clamp(0.0, 1.0) * alpha) * 255.0).round() as u8;
+  }
+}
+
+// bounds are min x, min y, max x, max y in pixels
+fn fill(image: &mut Image, bounds: (f32, f32, f32, f32), color: &Color, contains: impl Fn(f32, f32) -> bool) {
+  if color.a <= 0.0 {
+    return;
+  }
+
+  let (min_x, min_y, max_x, max_y) = bounds;
+  let x0 = min_x.floor().max(0.0) as u32;
+  let y0 = min_y.floor().max(0.0) as u32;
+  let x1 = (max_x.ceil().max(0.0) as u32).min(image.width);
+  let y1 = (max_y.ceil().max(0.0) as u32).min(image.height);
+
+  for y in y0..y1 {
+    for x in x0..x1 {
+      let hits = SAMPLE_OFFSETS.iter().filter(|(dx, dy)| contains(x as f32 + dx, y as f32 + dy)).count();
+      if hits > 0 {
+        blend(image, x, y, color, hits as f32 / SAMPLE_OFFSETS.len() as f32);
+      }
+    }
+  }
+}
+
+// absolute difference between two angles, [0, PI]
+fn angle_distance(a: f32, b: f32) -> f32 {
+  let d = (a - b).rem_euclid(TAU);
+  d.min(TAU - d)
+}
+
+pub fn draw_shape(image: &mut Image, shape: &Shape) {
+
+  let (width, height) = (image.width, image.height);
+
+  match shape {
+    Shape::Circle { center, radius, color, .. } => {
+      let (cx, cy) = (center.x.to_px(width, height), center.y.to_px(width, height));
+      let r = radius.to_px(width, height);
+      fill(image, (cx - r, cy - r, cx + r, cy + r), color, |x, y| (x - cx).hypot(y - cy) <= r);
+    },
+    Shape::Ring { center, inner_radius, outer_radius, color } => {
+      let (cx, cy) = (center.x.to_px(width, height), center.y.to_px(width, height));
+      let (r0, r1) = (inner_radius.to_px(width, height), outer_radius.to_px(width, height));
+      fill(image, (cx - r1, cy - r1, cx + r1, cy + r1), color, |x, y| {
+        let r = (x - cx).hypot(y - cy);
+        r0 <= r && r <= r1
+      });
+    },
+    Shape::RingSector { center, direction, width: angle, inner_radius, outer_radius, color, .. } => {
+      let (cx, cy) = (center.x.to_px(width, height), center.y.to_px(width, height));
+      let (r0, r1) = (inner_radius.to_px(width, height), outer_radius.to_px(width, height));
+      let (direction, half_angle) = (direction.to_rad(), angle.to_rad() / 2.0);
+      fill(image, (cx - r1, cy - r1, cx + r1, cy + r1), color, |x, y| {
+        let r = (x - cx).hypot(y - cy);
+        r0 <= r && r <= r1 && angle_distance((y - cy).atan2(x - cx), direction) <= half_angle
+      });
+    },
+    Shape::RegularHexagon { center, circumradius, color, .. } => {
+      let (cx, cy) = (center.x.to_px(width, height), center.y.to_px(width, height));
+      let r = circumradius.to_px(width, height);
+      // the first corner points right, so edge normals are at 30 + 60 * i degrees
+      let apothem = r * (PI / 6.0).cos();
+      fill(image, (cx - r, cy - r, cx + r, cy + r), color, |x, y| {
+        (0..6).all(|i| {
+          let normal = PI / 6.0 + PI / 3.0 * i as f32;
+          (x - cx) * normal.cos() + (y - cy) * normal.sin() <= apothem
+        })
+      });
+    },
+    Shape::Rectangle { center, width: w, height: h, color, .. } => {
+      let (cx, cy) = (center.x.to_px(width, height), center.y.to_px(width, height));
+      let (hw, hh) = (w.to_px(width, height) / 2.0, h.to_px(width, height) / 2.0);
+      fill(image, (cx - hw, cy - hh, cx + hw, cy + hh), color, |x, y| (x - cx).abs() <= hw && (y - cy).abs() <= hh);
+    }
+  }
+}
+
+// layers are drawn in order, each one only with shapes enabled by its visibility mask (see OverlayCommand::ToggleShapes)
+pub fn draw_shape_layers(image: &mut Image, layers: &[(Vec<Shape>, u64)]) {
+  for (shapes, mask) in layers {
+    for (i, shape) in shapes.iter().enumerate() {
+      if mask & (1 << i) != 0 {
+        draw_shape(image, shape);
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+
+  use super::*;
+  use crate::{Angle, Length, Point};
+
+  #[test]
+  fn draw_shape_layers_test() {
+    let mut image = Image::new(100, 100, [0, 0, 0]);
+
+    let sector = |direction: f32| Shape::RingSector {
+      center:       Point::px(50.0, 50.0),
+      direction:    Angle::Deg(direction),
+      width:        Angle::Deg(90.0),
+      inner_radius: Length::px(20.0),
+      outer_radius: Length::px(40.0),
+      color:        Color::rgb(1.0, 0.0, 0.0),
+      label:        None
+    };
+
+    let hexagon = Shape::RegularHexagon {
+      center:       Point::px(50.0, 50.0),
+      circumradius: Length::px(10.0),
+      color:        Color::rgba(0.0, 0.0, 1.0, 0.5),
+      label:        None
+    };
+
+    // the second sector is hidden, y axis points down
+    draw_shape_layers(&mut image, &[(vec![sector(90.0), sector(-90.0)], 0b01), (vec![hexagon], 0b1)]);
+
+    assert_eq!(image.rgb(50, 80), (1.0, 0.0, 0.0));
+    assert_eq!(image.rgb(50, 20), (0.0, 0.0, 0.0));
+    assert_eq!(image.rgb(80, 50), (0.0, 0.0, 0.0));
+    assert_eq!(image.rgb(50, 50), (0.0, 0.0, 128.0 / 255.0));
+    assert_eq!(image.rgb(50, 40), (0.0, 0.0, 0.0)); // outside of the hexagon's flat top
+  }
+}
@@ -0,0 +1,94 @@
+use std::time::Duration;
+
+const POLLING_INTERVAL: Duration = Duration::from_millis(500);
+
+struct FocusWatcher {
+  connection:        xcb::Connection,
+  root:              xcb::x::Window,
+  net_active_window: xcb::x::Atom,
+  net_wm_pid:        xcb::x::Atom
+}
+
+impl FocusWatcher {
+
+  fn new() -> Result<Self, String> {
+    let (connection, screen_num) = xcb::Connection::connect(None).map_err(|e| format!("{}", e))?;
+
+    let root = connection.get_setup().roots().nth(screen_num as usize).ok_or("No screen found")?.root();
+
+    let intern_atom = |name: &str| -> Result<xcb::x::Atom, String> {
+      let cookie = connection.send_request(&xcb::x::InternAtom { only_if_exists: true, name: name.as_bytes() });
+      let atom   = connection.wait_for_reply(cookie).map_err(|e| format!("{}", e))?.atom();
+      if atom == xcb::x::ATOM_NONE {
+        Err(format!("{} is not supported by the window manager", name))
+      } else {
+        Ok(atom)
+      }
+    };
+
+    let net_active_window = intern_atom("_NET_ACTIVE_WINDOW")?;
+    let net_wm_pid        = intern_atom("_NET_WM_PID")?;
+
+    Ok(Self { connection, root, net_active_window, net_wm_pid })
+  }
+
+  fn get_property(&self, window: xcb::x::Window, property: xcb::x::Atom, r#type: xcb::x::Atom) -> Result<xcb::x::GetPropertyReply, String> {
+    let cookie = self.connection.send_request(&xcb::x::GetProperty {
+      delete: false,
+      window,
+      property,
+      r#type,
+      long_offset: 0,
+      long_length: 1
+    });
+    self.connection.wait_for_reply(cookie).map_err(|e| format!("{}", e))
+  }
+
+  // Wine sets _NET_WM_PID to the pid of the process running the game (and this layer)
+  fn is_focused(&self) -> Result<bool, String> {
+    let reply = self.get_property(self.root, self.net_active_window, xcb::x::ATOM_WINDOW)?;
+    match reply.value::<xcb::x::Window>().first() {
+      Some(&window) if !xcb::Xid::is_none(&window) => {
+        let reply = self.get_property(window, self.net_wm_pid, xcb::x::ATOM_CARDINAL)?;
+        Ok(reply.value::<u32>().first() == Some(&std::process::id()))
+      },
+      _ => Ok(false)
+    }
+  }
+}
+
+// X11 only, the game is assumed to be focused otherwise
+pub fn watch_focus(on_change: impl Fn(bool)) {
+
+  let watcher = match FocusWatcher::new() {
+    Ok(watcher) => watcher,
+    Err(e) => {
+      eprintln!("Can't watch focus: {}", e);
+      return;
+    }
+  };
+
+  let mut focused    = true;
+  let mut last_error = None;
+
+  loop {
+    match watcher.is_focused() {
+      Ok(value) => {
+        last_error = None;
+        if value != focused {
+          focused = value;
+          on_change(focused);
+        }
+      },
+      // e.g. the focused window closing between requests, the focus stays as it was
+      Err(e) => {
+        if last_error.as_ref() != Some(&e) {
+          eprintln!("Can't check focus: {}", e);
+        }
+        last_error = Some(e);
+      }
+    }
+
+    std::thread::sleep(POLLING_INTERVAL);
+  }
+}
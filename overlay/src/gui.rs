@@ -1,9 +1,41 @@
 use egui::{pos2, vec2, Align2, Color32, Pos2, Rect, Vec2};
-use overlay_ipc::{Color, Knob, Point, ScreenScrapingResult, Shape};
+use overlay_ipc::{Color, Knob, Label, Point, ScreenScrapingResult, Shape};
 
 use crate::{follow_pointer_chain, OverlayState};
 
-pub fn draw_ui(overlay: &OverlayState, ctx: &egui::Context, screen: (u32, u32), scraping_result: Option<ScreenScrapingResult>) -> egui::FullOutput {
+fn to_pos2(p: &Point, screen_width: u32, screen_height: u32) -> Pos2 {
+  pos2(p.x.to_px(screen_width, screen_height), p.y.to_px(screen_width, screen_height))
+}
+
+fn to_color32(color: &Color) -> Color32 {
+  Color32::from_rgba_unmultiplied(
+    (color.r * 255.0) as u8,
+    (color.g * 255.0) as u8,
+    (color.b * 255.0) as u8,
+    (color.a * 255.0) as u8)
+}
+
+fn draw_label(painter: &egui::Painter, pos: Pos2, label: &Label, font_size: f32) {
+
+  let font  = egui::FontId::new(font_size, egui::FontFamily::Monospace);
+  let color = to_color32(&label.color);
+
+  match &label.icon {
+    Some(icon) if !icon.is_empty() => {
+      painter.text(pos - vec2(0.0, font_size * 0.5), Align2::CENTER_CENTER, icon, font.clone(), color);
+      if !label.text.is_empty() {
+        painter.text(pos + vec2(0.0, font_size * 0.5), Align2::CENTER_CENTER, &label.text, font, color);
+      }
+    },
+    _ => {
+      if !label.text.is_empty() {
+        painter.text(pos, Align2::CENTER_CENTER, &label.text, font, color);
+      }
+    }
+  }
+}
+
+pub fn draw_ui(overlay: &OverlayState, ctx: &egui::Context, screen: (u32, u32), scraping_results: &[ScreenScrapingResult]) -> egui::FullOutput {
 
   let (screen_width, screen_height) = screen;
 
@@ -41,18 +73,6 @@ pub fn draw_ui(overlay: &OverlayState, ctx: &egui::Context, screen: (u32, u32),
             for (i, shape) in shape_set.iter().enumerate() {
               if mask & (1 << i) != 0 {
 
-                fn to_pos2(p: &Point, screen_width: u32, screen_height: u32) -> Pos2 {
-                  pos2(p.x.to_px(screen_width, screen_height), p.y.to_px(screen_width, screen_height))
-                }
-
-                fn to_color32(color: &Color) -> Color32 {
-                  Color32::from_rgba_unmultiplied(
-                    (color.r * 255.0) as u8,
-                    (color.g * 255.0) as u8,
-                    (color.b * 255.0) as u8,
-                    (color.a * 255.0) as u8)
-                }
-
                 match shape {
                   Shape::Circle { center, radius, color, label } => {
 
@@ -62,11 +82,8 @@ pub fn draw_ui(overlay: &OverlayState, ctx: &egui::Context, screen: (u32, u32),
                       painter.circle_filled(center, radius.to_px(screen_width, screen_height), to_color32(color));
                     }
 
-                    if let Some((text, color)) = label {
-                      if !text.is_empty() {
-                        let font = egui::FontId::new(screen_height as f32 * 0.05, egui::FontFamily::Monospace);
-                        painter.text(center, Align2::CENTER_CENTER, text, font, to_color32(color));
-                      }
+                    if let Some(label) = label {
+                      draw_label(&painter, center, label, screen_height as f32 * 0.05);
                     }
                   },
                   Shape::Ring { center, inner_radius, outer_radius, color } => {
@@ -103,14 +120,11 @@ pub fn draw_ui(overlay: &OverlayState, ctx: &egui::Context, screen: (u32, u32),
                       painter.add(egui::Shape::line(points, egui::Stroke::new(ring_width, to_color32(color))));
                     }
 
-                    if let Some((text, color)) = label {
-                      if !text.is_empty() {
-                        let r    = inner_radius + ring_width * (3.0 / 4.0);
-                        let x    = center[0] + direction.to_rad().cos() * r;
-                        let y    = center[1] + direction.to_rad().sin() * r;
-                        let font = egui::FontId::new(width.to_rad() * r * 0.6, egui::FontFamily::Monospace);
-                        painter.text(pos2(x, y), Align2::CENTER_CENTER, text, font, to_color32(color));
-                      }
+                    if let Some(label) = label {
+                      let r = inner_radius + ring_width * (3.0 / 4.0);
+                      let x = center[0] + direction.to_rad().cos() * r;
+                      let y = center[1] + direction.to_rad().sin() * r;
+                      draw_label(&painter, pos2(x, y), label, width.to_rad() * r * 0.6);
                     }
                   },
                   Shape::RegularHexagon { center, circumradius, color, label } => {
@@ -137,11 +151,21 @@ pub fn draw_ui(overlay: &OverlayState, ctx: &egui::Context, screen: (u32, u32),
                       painter.add(egui::Shape::convex_polygon(points, to_color32(color), egui::Stroke::NONE));
                     }
 
-                    if let Some((text, color)) = label {
-                      if !text.is_empty() {
-                        let font = egui::FontId::new(circumradius * 0.8, egui::FontFamily::Monospace);
-                        painter.text(center, Align2::CENTER_CENTER, text, font, to_color32(color));
-                      }
+                    if let Some(label) = label {
+                      draw_label(&painter, center, label, circumradius * 0.8);
+                    }
+                  },
+                  Shape::Rectangle { center, width, height, color, label } => {
+
+                    let center = to_pos2(center, screen_width, screen_height);
+                    let size   = vec2(width.to_px(screen_width, screen_height), height.to_px(screen_width, screen_height));
+
+                    if color.a > 0.0 {
+                      painter.rect_filled(Rect::from_center_size(center, size), egui::Rounding::ZERO, to_color32(color));
+                    }
+
+                    if let Some(label) = label {
+                      draw_label(&painter, center, label, (size.y * 0.5).min(size.x / 4.0));
                     }
                   }
                 };
@@ -166,7 +190,7 @@ pub fn draw_ui(overlay: &OverlayState, ctx: &egui::Context, screen: (u32, u32),
 
             for target in &overlay.memory_targets {
               ui.add(egui::Label::new(
-                egui::RichText::new(format!("mem: {:x}", unsafe { follow_pointer_chain(target.0, &target.1) }))
+                egui::RichText::new(format!("mem: {:x}", unsafe { follow_pointer_chain(target.1, &target.2) }))
                   .background_color(Color32::BLACK)
                   .color(Color32::WHITE)));
             }
@@ -181,21 +205,28 @@ pub fn draw_ui(overlay: &OverlayState, ctx: &egui::Context, screen: (u32, u32),
             }
           });
 
-          for target in &overlay.screen_scraping_targets {
+          for ((_, area, _), result) in overlay.screen_scraping_targets.iter().zip(scraping_results) {
 
-            let min_x = target.0.bounds.min.x.to_px(screen_width, screen_height);
-            let min_y = target.0.bounds.min.y.to_px(screen_width, screen_height);
-            let max_x = target.0.bounds.max.x.to_px(screen_width, screen_height);
-            let max_y = target.0.bounds.max.y.to_px(screen_width, screen_height);
+            let min_x = area.bounds.min.x.to_px(screen_width, screen_height);
+            let min_y = area.bounds.min.y.to_px(screen_width, screen_height);
+            let max_x = area.bounds.max.x.to_px(screen_width, screen_height);
+            let max_y = area.bounds.max.y.to_px(screen_width, screen_height);
 
             let rect = Rect::from_min_max(pos2(min_x, min_y), pos2(max_x, max_y));
 
             ui.allocate_ui_at_rect(Rect::from_min_size(rect.min, vec2(500.0, 500.0)), |ui| {
               ui.add(egui::Label::new(
                 egui::RichText::new(
-                  format!("{:.5}\n{:.5}",
-                    scraping_result.as_ref().unwrap().pixels_in_range,
-                    scraping_result.as_ref().unwrap().uniformity_score))
+                  match result {
+                    ScreenScrapingResult::HsvRange { pixels_in_range, uniformity_score } =>
+                      format!("{:.5}\n{:.5}", pixels_in_range, uniformity_score),
+                    ScreenScrapingResult::Template { correlation } =>
+                      format!("{:.5}", correlation),
+                    ScreenScrapingResult::MeanColor(color) =>
+                      format!("{:.3} {:.3} {:.3}", color.r, color.g, color.b),
+                    ScreenScrapingResult::Histogram { distance } =>
+                      format!("{:.5}", distance)
+                  })
                   .background_color(Color32::TRANSPARENT)
                   .color(Color32::WHITE)));
             });
@@ -221,7 +252,7 @@ pub fn draw_ui(overlay: &OverlayState, ctx: &egui::Context, screen: (u32, u32),
                       .background_color(Color32::LIGHT_GRAY)
                       .color(Color32::BLACK)));
                 },
-                Knob::Enum { name, index, options } => {
+                Knob::Enum { name, index, options } | Knob::Key { name, index, options } | Knob::Layer { name, index, options } => {
 
                   let at_start = *index == 0;
                   let at_end   = *index == options.len() - 1;
@@ -254,6 +285,38 @@ pub fn draw_ui(overlay: &OverlayState, ctx: &egui::Context, screen: (u32, u32),
                   let stroke_width = bb.height() * 0.15;
 
                   ui.painter().hline(bb.min.x..=(bb.min.x + bb.width() * f), bb.max.y - stroke_width * 0.5, egui::Stroke::new(stroke_width, Color32::BLACK));
+                },
+                Knob::Integer { name, value, min_value, max_value, .. } => {
+
+                  let resp = ui.add(egui::Label::new(
+                    egui::RichText::new(
+                      format!("{} {:<20} {:<20} ", if knob_index == overlay.knob_menu_selected_item { "▶" } else { " " }, name, value))
+                      .background_color(Color32::LIGHT_GRAY)
+                      .color(Color32::BLACK)));
+
+                  let bb = resp.rect;
+                  let f  = if max_value > min_value { (value - min_value) as f32 / (max_value - min_value) as f32 } else { 1.0 };
+
+                  let stroke_width = bb.height() * 0.15;
+
+                  ui.painter().hline(bb.min.x..=(bb.min.x + bb.width() * f), bb.max.y - stroke_width * 0.5, egui::Stroke::new(stroke_width, Color32::BLACK));
+                },
+                Knob::Curve { name, points } => {
+
+                  let resp = ui.add(egui::Label::new(
+                    egui::RichText::new(
+                      format!("{} {:<20} {:<20} ", if knob_index == overlay.knob_menu_selected_item { "▶" } else { " " }, name, ""))
+                      .background_color(Color32::LIGHT_GRAY)
+                      .color(Color32::BLACK)));
+
+                  // the curve is drawn over the empty value column
+                  let bb   = resp.rect;
+                  let side = bb.height();
+                  let min  = pos2(bb.max.x - side * 2.0, bb.min.y);
+
+                  let points = points.iter().map(|(x, y)| pos2(min.x + x * side, min.y + (1.0 - y) * side)).collect::<Vec<_>>();
+
+                  ui.painter().add(egui::Shape::line(points, egui::Stroke::new(side * 0.1, Color32::BLACK)));
                 }
               }
             }
@@ -7,11 +7,14 @@ use std::sync::Mutex;
 use lazy_static::lazy_static;
 
 mod definitions;
+mod focus;
 mod gui;
 mod wgpu_util;
 
 use definitions::*;
-use overlay_ipc::{Knob, OverlayCommand, OverlayMenuCommand, Shape};
+use overlay_ipc::{Knob, OverlayCommand, OverlayEvent, OverlayMenuCommand, Shape};
+
+const FRAME_TIME_REPORT_INTERVAL: std::time::Duration = std::time::Duration::from_millis(250);
 
 pub struct WGPUSwapchainProps<'window> {
   pub width:            u32,
@@ -29,15 +32,19 @@ pub struct WGPUSwapchainProps<'window> {
 
 pub struct OverlayState {
   pub hud_is_active: bool,
-  pub screen_scraping_targets: Vec<(overlay_ipc::ScreenScrapingArea, overlay_ipc::ipc::IpcSender<overlay_ipc::ScreenScrapingResult>)>,
-  pub memory_targets: Vec<(u64, Vec<i32>, overlay_ipc::ipc::IpcSender<u64>)>,
+  pub screen_scraping_targets: Vec<(u64, overlay_ipc::ScreenScrapingArea, overlay_ipc::ipc::IpcSender<overlay_ipc::ProbeReport>)>,
+  pub memory_targets: Vec<(u64, u64, Vec<i32>, overlay_ipc::ipc::IpcSender<overlay_ipc::ProbeReport>)>,
   pub layer_names: Vec<String>,
-  pub mode: u64,
+  pub mode: u128,
   pub status_text: Option<String>,
   pub shapes: HashMap<u64 /* stage id */, Vec<(Vec<Shape>, u64 /* visibility mask */)>>,
   pub knobs: Vec<Knob>,
   pub knob_menu_visible: bool,
-  pub knob_menu_selected_item: usize
+  pub knob_menu_selected_item: usize,
+  pub focused: bool,
+  pub screen_size: Option<(u32, u32)>,
+  pub frame_times: Option<(std::time::Instant, u32)>, // start of the current frame time report, frames presented since
+  pub event_senders: Vec<overlay_ipc::ipc::IpcSender<OverlayEvent>>
 }
 
 impl OverlayState {
@@ -53,7 +60,11 @@ impl OverlayState {
       shapes: HashMap::new(),
       knobs: vec![],
       knob_menu_visible: false,
-      knob_menu_selected_item: 0
+      knob_menu_selected_item: 0,
+      focused: true,
+      screen_size: None,
+      frame_times: None,
+      event_senders: vec![]
     }
   }
 
@@ -66,6 +77,15 @@ impl OverlayState {
     self.status_text = None;
     self.shapes.clear();
     self.knobs.clear();
+    self.event_senders.clear();
+  }
+
+  pub fn emit(&mut self, event: OverlayEvent) {
+    self.event_senders.retain(|sender| sender.send(event.clone()).is_ok());
+  }
+
+  fn emit_ui_visibility(&mut self) {
+    self.emit(OverlayEvent::UIVisibilityChanged { hud: self.hud_is_active, knobs_menu: self.knob_menu_visible });
   }
 }
 
@@ -86,25 +106,45 @@ lazy_static! {
     let overlay_name = std::env::var("STWGS_OVERLAY_NAME").unwrap_or_else(|_|
       std::env::current_exe().unwrap().file_name().unwrap().to_string_lossy().into_owned());
 
-    let receiver = overlay_ipc::process_incoming_commands(&overlay_name);
+    let receiver = overlay_ipc::process_incoming_commands(&overlay_name, overlay_ipc::Capabilities::ALL);
+
+    std::thread::spawn(|| {
+      focus::watch_focus(|focused| {
+        let mut overlay = OVERLAY_STATE.lock().unwrap();
+        overlay.focused = focused;
+        overlay.emit(OverlayEvent::FocusChanged(focused));
+      });
+    });
+
     std::thread::spawn(move || {
       loop {
         match receiver.recv() {
           Ok(command) => match command {
+            OverlayCommand::Handshake(..) => {
+              // handled by overlay_ipc
+            },
             OverlayCommand::ToggleUI => {
               let mut overlay = OVERLAY_STATE.lock().unwrap();
               overlay.hud_is_active = !overlay.hud_is_active;
+              overlay.emit_ui_visibility();
             },
-            OverlayCommand::AddScreenScrapingArea(area, sender) => {
+            OverlayCommand::AddScreenScrapingArea(probe_id, area, sender) => {
               let mut overlay = OVERLAY_STATE.lock().unwrap();
-              overlay.screen_scraping_targets.push((area, sender));
+              overlay.screen_scraping_targets.push((probe_id, area, sender));
             },
-            OverlayCommand::AddMemoryCheck(pointer_size, address, offsets, sender) => {
+            OverlayCommand::AddMemoryCheck(probe_id, pointer_size, module, address, offsets, sender) => {
               let mut overlay = OVERLAY_STATE.lock().unwrap();
               if core::mem::size_of::<usize>() * 8 == pointer_size as usize {
-                overlay.memory_targets.push((address, offsets, sender));
+                match module.as_deref().map(overlay_ipc::proc_maps::module_base_of_current_process) {
+                  None           => overlay.memory_targets.push((probe_id, address, offsets, sender)),
+                  Some(Ok(base)) => match base.checked_add(address).filter(|address| usize::try_from(*address).is_ok()) {
+                    Some(address) => overlay.memory_targets.push((probe_id, address, offsets, sender)),
+                    None          => eprintln!("{} + 0x{:x} overflows the address space", module.unwrap_or_default(), address)
+                  },
+                  Some(Err(e))   => eprintln!("{}", e)
+                }
               } else {
-                eprintln!("pointer size mismatch: {:?}", overlay_ipc::OverlayCommand::AddMemoryCheck(pointer_size, address, offsets, sender));
+                eprintln!("pointer size mismatch: {:?}", overlay_ipc::OverlayCommand::AddMemoryCheck(probe_id, pointer_size, module, address, offsets, sender));
               }
             },
             OverlayCommand::ResetOverlay => {
@@ -123,6 +163,17 @@ lazy_static! {
               let mut overlay = OVERLAY_STATE.lock().unwrap();
               overlay.status_text = str;
             },
+            OverlayCommand::SubscribeToEvents(sender) => {
+              let mut overlay = OVERLAY_STATE.lock().unwrap();
+              overlay.event_senders.push(sender);
+
+              let focused = overlay.focused;
+              overlay.emit(OverlayEvent::FocusChanged(focused));
+              if let Some((width, height)) = overlay.screen_size {
+                overlay.emit(OverlayEvent::SwapchainResized { width, height });
+              }
+              overlay.emit_ui_visibility();
+            },
             OverlayCommand::RegisterShapes { stage_id, shapes } => {
               let mut overlay = OVERLAY_STATE.lock().unwrap();
               overlay.shapes.insert(stage_id, shapes.iter().map(|s| (s.clone(), 0)).collect());
@@ -149,68 +200,35 @@ lazy_static! {
                 OverlayMenuCommand::OpenKnobsMenu => {
                   overlay.knob_menu_visible       = true;
                   overlay.knob_menu_selected_item = 0;
+                  overlay.emit_ui_visibility();
                 },
                 OverlayMenuCommand::SelectPrevMenuItem => {
-                  if overlay.knob_menu_selected_item > 0 {
-                    overlay.knob_menu_selected_item -= 1;
-                  } else {
-                    overlay.knob_menu_selected_item = overlay.knobs.len() - 1;
-                  }
+                  overlay.knob_menu_selected_item = overlay_ipc::prev_menu_item(overlay.knob_menu_selected_item, overlay.knobs.len());
                 },
                 OverlayMenuCommand::SelectNextMenuItem => {
-                  overlay.knob_menu_selected_item = (overlay.knob_menu_selected_item + 1) % overlay.knobs.len();
+                  overlay.knob_menu_selected_item = overlay_ipc::next_menu_item(overlay.knob_menu_selected_item, overlay.knobs.len());
                 },
                 OverlayMenuCommand::SelectPrevValue => {
                   let knob_menu_selected_item = overlay.knob_menu_selected_item;
-                  let knob = &mut overlay.knobs[knob_menu_selected_item];
-                  match knob {
-                    Knob::Flag { value, .. } => {
-                      *value = !*value;
-                    },
-                    Knob::Enum { index, .. } => {
-                      if *index > 0 {
-                        *index -= 1;
-                      }
-                    },
-                    Knob::Number { value, min_value, max_value, .. } => {
-                      assert!(*max_value > *min_value);
-                      *value -= (*max_value - *min_value) * 0.05;
-                      if *value < *min_value {
-                        *value = *min_value;
-                      }
-                    }
-                  }
+                  overlay.knobs[knob_menu_selected_item].select_prev_value();
+
+                  let knob = overlay.knobs[knob_menu_selected_item].clone();
+                  overlay.emit(OverlayEvent::KnobChanged(knob));
                 },
                 OverlayMenuCommand::SelectNextValue => {
                   let knob_menu_selected_item = overlay.knob_menu_selected_item;
-                  let knob = &mut overlay.knobs[knob_menu_selected_item];
-                  match knob {
-                    Knob::Flag { value, .. } => {
-                      *value = !*value;
-                    },
-                    Knob::Enum { index, options, .. } => {
-                      if *index < options.len() - 1 {
-                        *index += 1;
-                      }
-                    },
-                    Knob::Number { value, min_value, max_value, .. } => {
-                      assert!(*max_value > *min_value);
-                      *value += (*max_value - *min_value) * 0.05;
-                      if *value > *max_value {
-                        *value = *max_value;
-                      }
-                    }
-                  }
+                  overlay.knobs[knob_menu_selected_item].select_next_value();
+
+                  let knob = overlay.knobs[knob_menu_selected_item].clone();
+                  overlay.emit(OverlayEvent::KnobChanged(knob));
                 },
                 OverlayMenuCommand::SetValuePercentage => todo!(),
                 OverlayMenuCommand::CloseKnobsMenu => {
                   overlay.knob_menu_visible = false;
-                }
+                  overlay.emit_ui_visibility();
+                },
+                OverlayMenuCommand::SelectPrevPreset | OverlayMenuCommand::SelectNextPreset => {}
               }
-            },
-            OverlayCommand::GetKnobs(sender) => {
-              let overlay = OVERLAY_STATE.lock().unwrap();
-              let _ = sender.send(overlay.knobs.clone());
             }
           },
           Err(_) => {
@@ -542,6 +560,13 @@ unsafe extern "C" fn overlay_vk_create_swapchain_khr(
         egui_renderer,
         egui_ctx
       });
+
+      let (width, height) = ((*create_info).image_extent.width, (*create_info).image_extent.height);
+      let mut overlay = OVERLAY_STATE.lock().unwrap();
+      if overlay.screen_size != Some((width, height)) {
+        overlay.screen_size = Some((width, height));
+        overlay.emit(OverlayEvent::SwapchainResized { width, height });
+      }
     }
 
     // ipc init
@@ -613,28 +638,41 @@ unsafe extern "C" fn overlay_vk_queue_present_khr(queue: ash::vk::Queue, present
     let frame = wgpu_util::get_frame(&wgpu_props.surface, *pi.p_image_indices);
     //wgpu_util::draw(&frame, &wgpu_props.adapter, &wgpu_props.device, &wgpu_props.queue, &wgpu_props.surface, &wgpu_props.pipeline);
 
-    let overlay = OVERLAY_STATE.lock().unwrap();
+    let mut overlay = OVERLAY_STATE.lock().unwrap();
+
+    // the mean frame time a few times per second, subscribers don't need every frame
+    let now = std::time::Instant::now();
+    overlay.frame_times = match overlay.frame_times {
+      Some((since, frames)) if now - since >= FRAME_TIME_REPORT_INTERVAL => {
+        overlay.emit(OverlayEvent::FramePresented { frame_time: (now - since).as_secs_f32() / (frames + 1) as f32 });
+        Some((now, 0))
+      },
+      Some((since, frames)) => Some((since, frames + 1)),
+      None => Some((now, 0))
+    };
 
-    let scraping_result = {
+    let scraping_results = {
       if !overlay.screen_scraping_targets.is_empty() {
-        let targets = overlay.screen_scraping_targets.iter().map(|t| t.0.clone()).collect();
-        let scraping_result = wgpu_util::compute(
+        let targets = overlay.screen_scraping_targets.iter().map(|t| t.1.clone()).collect::<Vec<_>>();
+        let scraping_results = wgpu_util::compute(
           &frame, &wgpu_props.device, &wgpu_props.queue, &wgpu_props.compute_pipeline, &targets, screen_width, screen_height);
-        let _ = overlay.screen_scraping_targets[0].1.send(scraping_result.clone());
-        Some(scraping_result)
+        for ((probe_id, _, sender), result) in overlay.screen_scraping_targets.iter().zip(&scraping_results) {
+          let _ = sender.send((*probe_id, overlay_ipc::ProbeResult::Screen(result.clone())));
+        }
+        scraping_results
       } else {
-        None
+        vec![]
       }
     };
 
     if !overlay.memory_targets.is_empty() {
-      for target in &overlay.memory_targets {
-        let value = follow_pointer_chain(target.0, &target.1);
-        let _ = target.2.send(value as u64);
+      for (probe_id, address, offsets, sender) in &overlay.memory_targets {
+        let value = follow_pointer_chain(*address, offsets);
+        let _ = sender.send((*probe_id, overlay_ipc::ProbeResult::Memory(value as u64)));
       }
     }
 
-    let egui_output        = gui::draw_ui(&overlay, &wgpu_props.egui_ctx, (screen_width, screen_height), scraping_result);
+    let egui_output        = gui::draw_ui(&overlay, &wgpu_props.egui_ctx, (screen_width, screen_height), &scraping_results);
     let clipped_primitives = wgpu_props.egui_ctx.tessellate(egui_output.shapes, wgpu_props.egui_ctx.pixels_per_point());
 
     let mut encoder = wgpu_props.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("egui encoder") });
@@ -1,15 +1,20 @@
 
+// see overlay_ipc::detectors
 struct Target {
-  x1:      u32,
-  y1:      u32,
-  x2:      u32,
-  y2:      u32,
-  min_hue: f32,
-  max_hue: f32,
-  min_sat: f32,
-  max_sat: f32,
-  min_val: f32,
-  max_val: f32
+  kind:        u32,
+  x1:          u32,
+  y1:          u32,
+  x2:          u32,
+  y2:          u32,
+  data_offset: u32,
+  data_width:  u32,
+  data_height: u32,
+  min_hue:     f32,
+  max_hue:     f32,
+  min_sat:     f32,
+  max_sat:     f32,
+  min_val:     f32,
+  max_val:     f32
 }
 
 struct Targets {
@@ -17,14 +22,22 @@ struct Targets {
 }
 
 struct Result {
-  pixels_in_range:  f32,
-  uniformity_score: f32
+  v0: f32,
+  v1: f32,
+  v2: f32,
+  v3: f32
 }
 
 struct Results {
   values: array<Result>
 }
 
+struct Data {
+  values: array<f32>
+}
+
+const HISTOGRAM_BINS: u32 = 16u;
+
 @group(0) @binding(0)
 var screen: texture_2d<f32>;
 
@@ -34,6 +47,9 @@ var<storage, read> targets: Targets;
 @group(0) @binding(2)
 var<storage, read_write> results: Results;
 
+@group(0) @binding(3)
+var<storage, read> data: Data;
+
 fn hsv_mod(a: f32, b: f32) -> f32 {
   return a - floor(a / b) * b;
 }
@@ -59,11 +75,19 @@ fn hsv(r: f32, g: f32, b: f32) -> vec3<f32> {
   return vec3<f32>(0.0, 0.0, M);
 }
 
-@compute @workgroup_size(1)
-fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+fn luma(pix: vec4<f32>) -> f32 {
+  return 0.299 * pix[0] + 0.587 * pix[1] + 0.114 * pix[2];
+}
 
-  //TODO: iterate over multiple targets
-  let t = targets.values[0];
+fn histogram_bin(value: f32) -> u32 {
+  return min(u32(value * f32(HISTOGRAM_BINS)), HISTOGRAM_BINS - 1u);
+}
+
+fn load(x: u32, y: u32) -> vec4<f32> {
+  return textureLoad(screen, vec2<i32>(i32(x), i32(y)), 0);
+}
+
+fn hsv_range(i: u32, t: Target, pixel_count: f32) {
 
   var pixels_in_range_count: u32 = u32(0);
   var seq_matches_count:     u32 = u32(0);
@@ -72,8 +96,7 @@ fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
   for (var x: u32 = t.x1; x < t.x2; x = x + u32(1)) {
     for (var y: u32 = t.y1; y < t.y2; y = y + u32(1)) {
 
-      let pos = vec2<i32>(i32(x), i32(y));
-      let pix = textureLoad(screen, pos, 0);
+      let pix = load(x, y);
 
       let hsv_ = hsv(pix[0], pix[1], pix[2]);
       let hue  = hsv_[0];
@@ -98,10 +121,119 @@ fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
     uniformity_score = uniformity_score + pow(f32(seq_matches_count), 2.0);
   }
 
+  results.values[i].v0 = f32(pixels_in_range_count) / pixel_count;
+  results.values[i].v1 = f32(uniformity_score)      / pow(pixel_count, 2.0);
+}
+
+fn template_match(i: u32, t: Target) {
+
+  let w = t.x2 - t.x1;
+  let h = t.y2 - t.y1;
+
+  var sum_a  = 0.0;
+  var sum_b  = 0.0;
+  var sum_aa = 0.0;
+  var sum_bb = 0.0;
+  var sum_ab = 0.0;
+
+  for (var ty: u32 = 0u; ty < t.data_height; ty = ty + 1u) {
+    for (var tx: u32 = 0u; tx < t.data_width; tx = tx + 1u) {
+      let a = luma(load(t.x1 + tx * w / t.data_width, t.y1 + ty * h / t.data_height));
+      let b = data.values[t.data_offset + ty * t.data_width + tx];
+      sum_a  = sum_a  + a;
+      sum_b  = sum_b  + b;
+      sum_aa = sum_aa + a * a;
+      sum_bb = sum_bb + b * b;
+      sum_ab = sum_ab + a * b;
+    }
+  }
+
+  let n     = f32(t.data_width * t.data_height);
+  let cov   = sum_ab - sum_a * sum_b / n;
+  let var_a = sum_aa - sum_a * sum_a / n;
+  let var_b = sum_bb - sum_b * sum_b / n;
+  let denom = sqrt(max(var_a * var_b, 0.0));
+
+  if (denom > 0.0) {
+    results.values[i].v0 = cov / denom;
+  } else {
+    results.values[i].v0 = 0.0;
+  }
+}
+
+fn mean_color(i: u32, t: Target, pixel_count: f32) {
+
+  var sum = vec3<f32>(0.0, 0.0, 0.0);
+
+  for (var x: u32 = t.x1; x < t.x2; x = x + u32(1)) {
+    for (var y: u32 = t.y1; y < t.y2; y = y + u32(1)) {
+      let pix = load(x, y);
+      sum = sum + vec3<f32>(pix[0], pix[1], pix[2]);
+    }
+  }
+
+  results.values[i].v0 = sum[0] / pixel_count;
+  results.values[i].v1 = sum[1] / pixel_count;
+  results.values[i].v2 = sum[2] / pixel_count;
+}
+
+fn histogram_distance(i: u32, t: Target, pixel_count: f32) {
+
+  var histogram: array<f32, 48>; // HISTOGRAM_BINS * 3
+
+  for (var x: u32 = t.x1; x < t.x2; x = x + u32(1)) {
+    for (var y: u32 = t.y1; y < t.y2; y = y + u32(1)) {
+      let pix = load(x, y);
+      let r = histogram_bin(pix[0]);
+      let g = histogram_bin(pix[1]) + HISTOGRAM_BINS;
+      let b = histogram_bin(pix[2]) + HISTOGRAM_BINS * 2u;
+      histogram[r] = histogram[r] + 1.0;
+      histogram[g] = histogram[g] + 1.0;
+      histogram[b] = histogram[b] + 1.0;
+    }
+  }
+
+  var distance = 0.0;
+  for (var j: u32 = 0u; j < HISTOGRAM_BINS * 3u; j = j + 1u) {
+    distance = distance + abs(histogram[j] / pixel_count - data.values[t.data_offset + j]);
+  }
+
+  // each of the three histograms contributes up to 2.0
+  results.values[i].v0 = distance / 6.0;
+}
+
+// one invocation per target
+@compute @workgroup_size(1)
+fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
+
+  let i = global_id.x;
+  if (i >= arrayLength(&targets.values)) {
+    return;
+  }
+
+  let t = targets.values[i];
+
+  results.values[i] = Result(0.0, 0.0, 0.0, 0.0);
+
   let pixel_count = f32((t.x2 - t.x1) * (t.y2 - t.y1));
+  if (pixel_count == 0.0) {
+    return;
+  }
 
-  results.values[0].pixels_in_range  = f32(pixels_in_range_count) / pixel_count;
-  results.values[0].uniformity_score = f32(uniformity_score)      / pow(pixel_count, 2.0);
+  switch t.kind {
+    case 0u: { // detectors::KIND_HSV_RANGE
+      hsv_range(i, t, pixel_count);
+    }
+    case 1u: { // detectors::KIND_TEMPLATE
+      template_match(i, t);
+    }
+    case 2u: { // detectors::KIND_MEAN_COLOR
+      mean_color(i, t, pixel_count);
+    }
+    default: { // detectors::KIND_HISTOGRAM
+      histogram_distance(i, t, pixel_count);
+    }
+  }
 }
 
 //fn vec3_eq(a: vec3<f32>, b: vec3<f32>) -> bool {
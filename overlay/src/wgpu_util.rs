@@ -5,6 +5,7 @@ use std::sync::Mutex;
 
 use ash::vk::Handle;
 use lazy_static::lazy_static;
+use overlay_ipc::detectors;
 
 use crate::REGISTRY;
 
@@ -421,6 +422,16 @@ pub fn prepare_compute_pipeline(device: &wgpu::Device) -> wgpu::ComputePipeline
           min_binding_size: None
         },
         count: None
+      },
+      wgpu::BindGroupLayoutEntry {
+        binding: 3,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Buffer {
+          ty: wgpu::BufferBindingType::Storage { read_only: true },
+          has_dynamic_offset: false,
+          min_binding_size: None
+        },
+        count: None
       }
     ]
   });
@@ -445,11 +456,11 @@ pub fn compute(
   device:   &wgpu::Device,
   queue:    &wgpu::Queue,
   pipeline: &wgpu::ComputePipeline,
-  targets:  &Vec<overlay_ipc::ScreenScrapingArea>,
+  targets:  &[overlay_ipc::ScreenScrapingArea],
   screen_width:  u32,
   screen_height: u32
 )
-  -> overlay_ipc::ScreenScrapingResult
+  -> Vec<overlay_ipc::ScreenScrapingResult>
 {
   let view = frame
     .texture
@@ -465,51 +476,46 @@ pub fn compute(
       array_layer_count: Some(1) // ?
     });
 
+  let (packed_targets, data) = detectors::pack(targets, screen_width, screen_height);
+
+  let targets_bytes = packed_targets.iter().flat_map(|t| t.to_words()).collect::<Vec<u32>>();
+  let targets_bytes = bytemuck::cast_slice::<u32, u8>(&targets_bytes);
+
+  let results_size = (targets.len() * detectors::RESULT_LEN * std::mem::size_of::<f32>()) as u64;
+
   let targets_buffer = device.create_buffer(&wgpu::BufferDescriptor {
     label: None,
-    size: 1000, //TODO: how large should that buffer be?
+    size: targets_bytes.len() as u64,
     usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
     mapped_at_creation: false // ?
   });
 
   let results_buffer = device.create_buffer(&wgpu::BufferDescriptor {
     label: None,
-    size: 100, // ?
+    size: results_size,
     usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
     mapped_at_creation: false // ?
   });
 
   let results_buffer2 = device.create_buffer(&wgpu::BufferDescriptor {
     label: None,
-    size: 100, // ?
+    size: results_size,
     usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
     mapped_at_creation: false // ?
   });
 
-  let mut offset = 0;
-  for target in targets {
-    let values = &[
-      target.bounds.min.x.to_px(screen_width, screen_height) as u32,
-      target.bounds.min.y.to_px(screen_width, screen_height) as u32,
-      target.bounds.max.x.to_px(screen_width, screen_height) as u32,
-      target.bounds.max.y.to_px(screen_width, screen_height) as u32
-    ];
-    let bytes = bytemuck::cast_slice::<u32, u8>(values);
-    queue.write_buffer(&targets_buffer, offset as u64, bytes);
-    offset += bytes.len();
-
-    let values = &[
-      target.min_hue,
-      target.max_hue,
-      target.min_sat,
-      target.max_sat,
-      target.min_val,
-      target.max_val,
-    ];
-    let bytes = bytemuck::cast_slice::<f32, u8>(values);
-    queue.write_buffer(&targets_buffer, offset as u64, bytes);
-    offset += bytes.len();
-  }
+  // empty bindings are not allowed
+  let data = if data.is_empty() { vec![0.0] } else { data };
+
+  let data_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+    label: None,
+    size: (data.len() * std::mem::size_of::<f32>()) as u64,
+    usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+    mapped_at_creation: false // ?
+  });
+
+  queue.write_buffer(&targets_buffer, 0, targets_bytes);
+  queue.write_buffer(&data_buffer,    0, bytemuck::cast_slice::<f32, u8>(&data));
 
   let bind_group_layout = pipeline.get_bind_group_layout(0);
   let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
@@ -527,6 +533,10 @@ pub fn compute(
       wgpu::BindGroupEntry {
         binding: 2,
         resource: results_buffer.as_entire_binding()
+      },
+      wgpu::BindGroupEntry {
+        binding: 3,
+        resource: data_buffer.as_entire_binding()
       }
     ]
   });
@@ -537,10 +547,10 @@ pub fn compute(
     let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None, timestamp_writes: None });
     pass.set_bind_group(0, &bind_group, &[]);
     pass.set_pipeline(pipeline);
-    pass.dispatch_workgroups(1, 1, 1); // ?
+    pass.dispatch_workgroups(targets.len() as u32, 1, 1); // one invocation per target
   }
 
-  encoder.copy_buffer_to_buffer(&results_buffer, 0, &results_buffer2, 0, 100);
+  encoder.copy_buffer_to_buffer(&results_buffer, 0, &results_buffer2, 0, results_size);
 
   queue.submit(Some(encoder.finish()));
 
@@ -552,5 +562,5 @@ pub fn compute(
   let result = bytemuck::cast_slice::<u8, f32>(&buffer_slice.get_mapped_range()).to_owned();
   results_buffer2.unmap();
 
-  overlay_ipc::ScreenScrapingResult { pixels_in_range: result[0], uniformity_score: result[1] }
+  detectors::unpack(targets, &result)
 }